@@ -2,7 +2,26 @@
 //!
 //! You normally don't use this crate directly. Instead, you run the binary
 //! to use the bot. When integrating the bot into other programs, invoke
-//! [`run`] function to start the bot server.
+//! [`run`] function to start the bot server, or use [`AppBuilder`] to
+//! register custom modules before starting it.
+//!
+//! Each [`App`] owns its database, modules and dispatcher, so multiple
+//! bots (e.g. with different tokens and configs) can run in one process:
+//!
+//! ```no_run
+//! # async fn example(config_a: telegpt_core::config::SharedConfig, config_b: telegpt_core::config::SharedConfig) -> anyhow::Result<()> {
+//! use telegpt_core::app::App;
+//!
+//! let app_a = App::new(config_a).await?;
+//! let app_b = App::new(config_b).await?;
+//! tokio::join!(app_a.run(), app_b.run());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Note that bots should not share the same `databasePath`.
+
+use std::future::Future;
 
 use anyhow::Error;
 use teloxide::{
@@ -14,9 +33,14 @@ use crate::{
     config::{Config, SharedConfig},
     database::{DatabaseManager, FileDatabaseProvider, InMemDatabaseProvider},
     dispatcher::build_dispatcher,
-    module_mgr::ModuleManager,
-    modules::{admin::Admin, chat::Chat, openai::OpenAI, prefs::Prefs, stats::Stats},
-    types::HandlerResult,
+    event_bus::EventBus,
+    hooks::HookEvent,
+    module_mgr::{Module, ModuleManager},
+    modules::{
+        admin::Admin, chat::Chat, help::Help, inline::Inline, openai::OpenAI, prefs::Prefs,
+        stats::Stats,
+    },
+    types::{HandlerResult, TeloxideDispatcher},
 };
 
 async fn update_menu(bot: Bot, module_mgr: &mut ModuleManager) -> HandlerResult {
@@ -33,7 +57,10 @@ async fn update_menu(bot: Bot, module_mgr: &mut ModuleManager) -> HandlerResult
 }
 
 async fn init_bot(config: &Config, module_mgr: &mut ModuleManager) -> Result<Bot, Error> {
-    let bot = Bot::new(&config.telegram_bot_token);
+    let mut bot = Bot::new(&config.telegram_bot_token);
+    if let Some(api_url) = &config.telegram_api_url {
+        bot = bot.set_api_url(api_url.parse()?);
+    }
     bot.set_chat_menu_button()
         .menu_button(MenuButton::Commands)
         .await?;
@@ -44,39 +71,140 @@ async fn init_bot(config: &Config, module_mgr: &mut ModuleManager) -> Result<Bot
 /// Starts bot server and blocks the caller until the bot is requested
 /// to shutdown.
 pub async fn run(config: SharedConfig) {
-    debug!("Initializing database...");
-    let db_mgr = if let Some(database_path) = &config.database_path {
-        DatabaseManager::with_db_provider(FileDatabaseProvider::new(database_path))
-    } else {
-        DatabaseManager::with_db_provider(InMemDatabaseProvider)
+    AppBuilder::new(config).run().await;
+}
+
+/// A bot with its own state, which is ready to run.
+pub struct App {
+    dispatcher: TeloxideDispatcher,
+    // Keeps the database thread alive until the bot is stopped.
+    _db_mgr: DatabaseManager,
+}
+
+impl App {
+    /// Initializes the bot with the built-in modules.
+    pub async fn new(config: SharedConfig) -> Result<Self, Error> {
+        AppBuilder::new(config).build().await
+    }
+
+    /// Starts bot server and blocks the caller until the bot is requested
+    /// to shutdown.
+    pub async fn run(mut self) {
+        info!("Bot is started!");
+        self.dispatcher.dispatch().await;
     }
-    .unwrap();
-
-    debug!("Initializing modules...");
-    let mut module_mgr = ModuleManager::new();
-    module_mgr.register_module(crate::modules::config::Config::new(config.clone()));
-    module_mgr.register_module(OpenAI);
-    module_mgr.register_module(Prefs::new(db_mgr.clone()));
-    module_mgr.register_module(Admin::new(db_mgr.clone()));
-    module_mgr.register_module(Stats::new(db_mgr.clone()));
-    module_mgr.register_module(Chat);
-
-    info!("Initializing bot...");
-    let bot = match init_bot(&config, &mut module_mgr).await {
-        Ok(bot) => bot,
-        Err(err) => {
-            error!("Failed to init bot: {}", err);
-            return;
+}
+
+/// Subscribes a callback to the event bus of the bot being built.
+type SubscribeHook = Box<dyn FnOnce(&EventBus) + Send>;
+
+/// A builder to start the bot with custom modules.
+///
+/// ```no_run
+/// # async fn example(config: telegpt_core::config::SharedConfig, my_module: impl telegpt_core::Module + 'static) {
+/// use telegpt_core::app::AppBuilder;
+///
+/// AppBuilder::new(config)
+///     .register_module(my_module)
+///     .run()
+///     .await;
+/// # }
+/// ```
+pub struct AppBuilder {
+    config: SharedConfig,
+    modules: Vec<Box<dyn Module>>,
+    hooks: Vec<SubscribeHook>,
+}
+
+impl AppBuilder {
+    pub fn new(config: SharedConfig) -> Self {
+        Self {
+            config,
+            modules: vec![],
+            hooks: vec![],
         }
-    };
+    }
+
+    /// Registers an async callback, which is called with each event of
+    /// the bot. See the [`hooks`](crate::hooks) module for the events.
+    pub fn on_event<F, Fut>(mut self, mut hook: F) -> Self
+    where
+        F: FnMut(HookEvent) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.push(Box::new(move |event_bus| {
+            event_bus.subscribe(move |event| {
+                let fut = HookEvent::from_event(event).map(&mut hook);
+                async move {
+                    if let Some(fut) = fut {
+                        fut.await;
+                    }
+                }
+            });
+        }));
+        self
+    }
 
-    let mut built_dispatcher = match build_dispatcher(bot, module_mgr).await {
-        Ok(dispatcher) => dispatcher,
-        Err(err) => {
-            error!("Failed to init dispatcher: {}", err);
-            return;
+    /// Registers a custom module. Custom modules are registered after
+    /// the built-in modules they may depend on, and take precedence over
+    /// the chat handler.
+    pub fn register_module<M>(mut self, module: M) -> Self
+    where
+        M: Module + 'static,
+    {
+        self.modules.push(Box::new(module));
+        self
+    }
+
+    /// Starts bot server and blocks the caller until the bot is requested
+    /// to shutdown.
+    pub async fn run(self) {
+        match self.build().await {
+            Ok(app) => app.run().await,
+            Err(err) => error!("Failed to init bot: {}", err),
         }
-    };
-    info!("Bot is started!");
-    built_dispatcher.dispatch().await;
+    }
+
+    /// Initializes the bot with the built-in and custom modules.
+    pub async fn build(self) -> Result<App, Error> {
+        let config = self.config;
+
+        debug!("Initializing database...");
+        let db_mgr = if let Some(database_path) = &config.load().database_path {
+            DatabaseManager::with_db_provider(FileDatabaseProvider::new(database_path))
+        } else {
+            DatabaseManager::with_db_provider(InMemDatabaseProvider)
+        }?
+        .with_encryption_key(config.load().database_encryption_key.as_deref());
+
+        debug!("Initializing modules...");
+        let mut module_mgr = ModuleManager::new();
+        module_mgr.register_module(crate::modules::config::Config::new(config.clone()));
+        module_mgr.register_module(Prefs::new(db_mgr.clone()));
+        module_mgr.register_module(OpenAI::new(db_mgr.clone()));
+        module_mgr.register_module(Admin::new(db_mgr.clone()));
+        module_mgr.register_module(Stats::new(db_mgr.clone()));
+        module_mgr.register_module(Help);
+        for module in self.modules {
+            module_mgr.register_boxed_module(module);
+        }
+        module_mgr.register_module(Chat::new(db_mgr.clone()));
+        module_mgr.register_module(Inline);
+
+        // Subscribe before the modules, so that no event is missed.
+        let event_bus = EventBus::new();
+        for hook in self.hooks {
+            hook(&event_bus);
+        }
+
+        info!("Initializing bot...");
+        let bot = init_bot(&config.load(), &mut module_mgr).await?;
+        let dispatcher = build_dispatcher(bot, module_mgr, event_bus)
+            .await
+            .map_err(|err| anyhow!("Failed to init dispatcher: {}", err))?;
+        Ok(App {
+            dispatcher,
+            _db_mgr: db_mgr,
+        })
+    }
 }
@@ -0,0 +1,167 @@
+//! Administration of the bot without Telegram.
+//!
+//! These operations work on the database and the config directly, so they
+//! can be run while the bot is stopped, e.g. to bootstrap the members of a
+//! new deployment:
+//!
+//! ```shell
+//! $ /path/to/telegpt -c your_config.json member add alice
+//! ```
+
+use std::fmt::Write;
+
+use anyhow::Error;
+use chrono::{TimeZone, Utc};
+
+use crate::{
+    config::SharedConfig,
+    database::{DatabaseManager, FileDatabaseProvider},
+    modules::{admin::MemberManager, prefs::PreferencesManager, stats::StatsManager},
+};
+
+const LIST_PAGE_SIZE: u64 = 100;
+
+/// The managers of the bot, opened on the database from config.
+pub struct AdminTool {
+    member_mgr: MemberManager,
+    stats_mgr: StatsManager,
+    config: SharedConfig,
+    // Keep the database open until the tool is dropped.
+    _db_mgr: DatabaseManager,
+}
+
+impl AdminTool {
+    /// Opens the database from config, which is migrated to the latest
+    /// schema if needed.
+    pub async fn open(config: SharedConfig) -> Result<Self, Error> {
+        let database_path = config
+            .load()
+            .database_path
+            .clone()
+            .ok_or_else(|| anyhow!("`databasePath` is not set, there is nothing to manage"))?;
+        let db_mgr = DatabaseManager::with_db_provider(FileDatabaseProvider::new(database_path))?
+            .with_encryption_key(config.load().database_encryption_key.as_deref());
+
+        let prefs_mgr = PreferencesManager::with_db_manager(db_mgr.clone()).await?;
+        let member_mgr = MemberManager::new(db_mgr.clone(), prefs_mgr, config.clone()).await?;
+        let stats_mgr = StatsManager::with_db_manager(db_mgr.clone(), config.clone()).await?;
+        Ok(Self {
+            member_mgr,
+            stats_mgr,
+            config,
+            _db_mgr: db_mgr,
+        })
+    }
+
+    /// Adds the member, which does nothing if the user is already a member.
+    pub async fn add_member(&self, username: &str) -> Result<(), Error> {
+        let username = username.trim_start_matches('@').to_owned();
+        if !self.member_mgr.add_member(username).await? {
+            bail!("Failed to add the member");
+        }
+        Ok(())
+    }
+
+    /// Removes the member, returns `false` if the user is not a member.
+    pub async fn remove_member(&self, username: &str) -> Result<bool, Error> {
+        let username = username.trim_start_matches('@').to_owned();
+        self.member_mgr.delete_member(username).await
+    }
+
+    /// Returns the members as lines of text.
+    pub async fn list_members(&self) -> Result<String, Error> {
+        let timezone = self.config.load().timezone;
+        let mut text = String::new();
+        let mut offset = 0;
+        loop {
+            let members = self.member_mgr.list_members(offset, LIST_PAGE_SIZE).await?;
+            for member in &members {
+                let added_at = Utc
+                    .timestamp_opt(member.created_at, 0)
+                    .single()
+                    .map(|t| {
+                        t.with_timezone(&timezone)
+                            .format("%Y-%m-%d %H:%M")
+                            .to_string()
+                    })
+                    .unwrap_or_default();
+                writeln!(
+                    &mut text,
+                    "{}\t{}{}",
+                    member.username,
+                    added_at,
+                    if member.disabled { "\t(banned)" } else { "" }
+                )?;
+            }
+            if (members.len() as u64) < LIST_PAGE_SIZE {
+                break;
+            }
+            offset += LIST_PAGE_SIZE;
+        }
+        Ok(text)
+    }
+
+    /// Exports the hourly token usage of each user, as CSV or JSON lines.
+    pub async fn export_usage(&self, csv: bool) -> Result<String, Error> {
+        let timezone = self.config.load().timezone;
+        let records = self.stats_mgr.export_usage().await?;
+
+        let mut text = String::new();
+        if csv {
+            writeln!(
+                &mut text,
+                "user,time,prompt_tokens,completion_tokens,tokens,cost"
+            )?;
+        }
+        for record in records {
+            let time = Utc
+                .timestamp_opt(record.time, 0)
+                .single()
+                .map(|t| t.with_timezone(&timezone).to_rfc3339())
+                .unwrap_or_default();
+            if csv {
+                writeln!(
+                    &mut text,
+                    "{},{},{},{},{},{}",
+                    csv_field(&record.user_id),
+                    time,
+                    record.prompt_tokens,
+                    record.completion_tokens,
+                    record.tokens,
+                    record.cost
+                )?;
+            } else {
+                let json = serde_json::json!({
+                    "user": record.user_id,
+                    "time": time,
+                    "promptTokens": record.prompt_tokens,
+                    "completionTokens": record.completion_tokens,
+                    "tokens": record.tokens,
+                    "cost": record.cost,
+                });
+                writeln!(&mut text, "{}", json)?;
+            }
+        }
+        Ok(text)
+    }
+}
+
+/// Quotes the field if it contains the special characters of CSV.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("alice"), "alice");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+}
@@ -18,60 +18,216 @@
 //!
 //! See [`Config`] for more detailed descriptions.
 
-use std::collections::HashSet;
-use std::ops::Deref;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 
+use anyhow::Error;
+use chrono::NaiveTime;
+use chrono_tz::Tz;
 use paste::paste;
 use serde::Deserialize;
 
+/// The maximum length in bytes of the names of parameter presets, which
+/// are put in the data of buttons, i.e. `/preset:<name>` in 64 bytes.
+const PRESET_NAME_MAX_LEN: usize = 56;
+
 /// A thread-safe reference-counting object that represents
-/// a [`Config`] instance.
+/// a [`Config`] instance, which can be replaced at runtime.
+///
+/// Clones of a `SharedConfig` share the same instance, so a replaced
+/// config is visible to all of them.
 #[derive(Debug, Clone)]
 pub struct SharedConfig {
-    config: Arc<Config>,
+    config: Arc<RwLock<Arc<Config>>>,
+    path: Option<PathBuf>,
 }
 
 impl SharedConfig {
     /// Constructs a new `SharedConfig`.
     pub fn new(config: Config) -> Self {
         Self {
-            config: Arc::new(config),
+            config: Arc::new(RwLock::new(Arc::new(config))),
+            path: None,
         }
     }
-}
 
-impl Deref for SharedConfig {
-    type Target = Config;
+    /// Constructs a new `SharedConfig` from a JSON file, which can be
+    /// reloaded later with [`reload`](Self::reload).
+    pub fn from_file<P>(path: P) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        Ok(Self {
+            path: Some(path.to_owned()),
+            ..Self::new(read_config_file(path)?)
+        })
+    }
+
+    /// Returns a snapshot of the current config. Hold the snapshot for
+    /// values that need to be consistent within an operation.
+    pub fn load(&self) -> Arc<Config> {
+        Arc::clone(&self.config.read().unwrap())
+    }
+
+    /// Replaces the current config.
+    pub fn store(&self, config: Config) {
+        *self.config.write().unwrap() = Arc::new(config);
+    }
 
-    fn deref(&self) -> &Self::Target {
-        return self.config.as_ref();
+    /// Re-reads the file this config was loaded from, and replaces the
+    /// current config with it. The current config is kept if the file
+    /// is invalid.
+    ///
+    /// Note that some settings (e.g. the bot token, API keys and the
+    /// database path) only take effect after restarting the bot.
+    pub fn reload(&self) -> Result<(), Error> {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or_else(|| anyhow!("The config was not loaded from a file"))?;
+        self.store(read_config_file(path)?);
+        Ok(())
     }
 }
 
+fn read_config_file(path: &Path) -> Result<Config, Error> {
+    let config_buf = fs::read(path)?;
+    let config_json_str = String::from_utf8(config_buf)?;
+    Ok(serde_json::from_str(&config_json_str)?)
+}
+
 /// Top-level config type fot the bot.
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     /// The API key of your OpenAI account.
     /// JSON key: `openaiAPIKey`
-    #[serde(rename = "openaiAPIKey")]
+    #[serde(default, rename = "openaiAPIKey")]
     pub openai_api_key: String,
+    /// A pool of API keys, possibly from different accounts. When specified,
+    /// requests are distributed among these keys instead of using
+    /// `openaiAPIKey`.
+    /// JSON key: `openaiAPIKeys`
+    #[serde(default, rename = "openaiAPIKeys")]
+    pub openai_api_keys: Vec<OpenAIKeyConfig>,
+    /// The organization that the requests to the OpenAI API are billed to,
+    /// [`None`] for the default organization of the keys.
+    /// JSON key: `openaiOrganization`
+    #[serde(default, rename = "openaiOrganization")]
+    pub openai_organization: Option<String>,
+    /// The project that the requests to the OpenAI API are billed to,
+    /// [`None`] for the default project of the keys.
+    /// JSON key: `openaiProject`
+    #[serde(default, rename = "openaiProject")]
+    pub openai_project: Option<String>,
+    /// The service that serves the chat model, default to the OpenAI API.
+    /// Transcription and speech are always served by the OpenAI API.
+    /// JSON key: `provider`
+    #[serde(default)]
+    pub provider: ProviderConfig,
+    /// The ratio of the monthly budget, at which a warning is logged when
+    /// a key's spend reaches it. This is default to `0.8`.
+    /// JSON key: `keyBudgetAlertThreshold`
+    #[serde(
+        default = "default_key_budget_alert_threshold",
+        rename = "keyBudgetAlertThreshold"
+    )]
+    pub key_budget_alert_threshold: f64,
     /// The token of your Telegram bot.
     /// JSON key: `botToken`
     #[serde(rename = "botToken")]
     pub telegram_bot_token: String,
 
-    /// A timeout in seconds for waiting for the OpenAI server response.
+    /// The URL of the Bot API server, e.g. a local Bot API server or a mock
+    /// server in tests. [`None`] for `https://api.telegram.org`.
+    /// JSON key: `telegramApiUrl`
+    #[serde(default, rename = "telegramApiUrl")]
+    pub telegram_api_url: Option<String>,
+
+    /// The model used for chat completions, which can also be the id of
+    /// a fine-tuned model (e.g. `ft:gpt-3.5-turbo:my-org:custom:id`).
+    /// JSON key: `openaiGptModel`
+    #[serde(default = "default_openai_gpt_model", rename = "openaiGptModel")]
+    pub openai_gpt_model: String,
+
+    /// A boolean value that indicates whether to check that the configured
+    /// models are accessible to the API keys on startup. Disable it if the
+    /// API server doesn't provide the models endpoint. This is default
+    /// to `true`.
+    /// JSON key: `validateModels`
+    #[serde(default = "default_validate_models", rename = "validateModels")]
+    pub validate_models: bool,
+
+    /// Models that can be selected per chat with the `/model` command.
+    /// When empty, only `openaiGptModel` is available.
+    /// JSON key: `availableModels`
+    #[serde(default, rename = "availableModels")]
+    pub available_models: Vec<String>,
+
+    /// A boolean value that indicates whether only admins can switch
+    /// the model of a chat. When set to `false`, all allowed members can
+    /// switch it. This is default to `true`.
+    /// JSON key: `modelSelectionAdminOnly`
+    #[serde(
+        default = "default_model_selection_admin_only",
+        rename = "modelSelectionAdminOnly"
+    )]
+    pub model_selection_admin_only: bool,
+
+    /// Models to try in order when the model of the chat fails or times
+    /// out, before the error is shown to the user.
+    /// JSON key: `fallbackModels`
+    #[serde(default, rename = "fallbackModels")]
+    pub fallback_models: Vec<String>,
+
+    /// Two models to run against each other with the `/compare` admin
+    /// command.
+    /// JSON key: `compareModels`
+    #[serde(default, rename = "compareModels")]
+    pub compare_models: Vec<String>,
+
+    /// A timeout in seconds for waiting for the next chunk once the answer
+    /// has started streaming.
     /// JSON key: `openaiAPITimeout`
     #[serde(default = "default_openai_api_timeout", rename = "openaiAPITimeout")]
     pub openai_api_timeout: u64,
 
+    /// A timeout in seconds for waiting for the first token of the answer,
+    /// which is usually longer than `openaiAPITimeout` since the server may
+    /// take a while to process long prompts.
+    /// JSON key: `openaiFirstTokenTimeout`
+    #[serde(
+        default = "default_openai_first_token_timeout",
+        rename = "openaiFirstTokenTimeout"
+    )]
+    pub openai_first_token_timeout: u64,
+
     /// A set of usernames that represents the admin users, who can use
     /// admin commands. You must specify this field to use admin features.
     /// JSON key: `adminUsernames`
     #[serde(default, rename = "adminUsernames")]
     pub admin_usernames: HashSet<String>,
 
+    /// The maximum number of answers generated at the same time. Messages
+    /// beyond the limit wait in a queue, and their positions are shown
+    /// until they are answered. There is no limit if this is not set. It
+    /// only takes effect after restarting the bot.
+    /// JSON key: `maxConcurrentCompletions`
+    #[serde(default, rename = "maxConcurrentCompletions")]
+    pub max_concurrent_completions: Option<usize>,
+
+    /// A boolean value that indicates whether the administrators of a group
+    /// can change the settings of the group (`/group_trigger`,
+    /// `/private_answers` and `/group_report`), in addition to
+    /// `adminUsernames`. The commands that change the whole bot (e.g. the
+    /// members and the roles) still require a bot admin. This is default
+    /// to `false`.
+    /// JSON key: `groupAdminsAreBotAdmins`
+    #[serde(default, rename = "groupAdminsAreBotAdmins")]
+    pub group_admins_are_bot_admins: bool,
+
     /// The throttle interval (in milliseconds) for sending streamed
     /// chunks back to Telegram.
     /// JSON key: `streamThrottleInterval`
@@ -81,16 +237,204 @@ pub struct Config {
     )]
     pub stream_throttle_interval: u64,
 
+    /// A template of the generation stats displayed after the progress
+    /// indicator while an answer is streamed, e.g.
+    /// `"{elapsed}s · {speed} tokens/s"`. `{elapsed}` is the seconds since
+    /// the request, `{tokens}` the generated tokens, and `{speed}` the
+    /// tokens per second. Only the indicator is displayed if this is not
+    /// set.
+    /// JSON key: `progressStatsFormat`
+    #[serde(default, rename = "progressStatsFormat")]
+    pub progress_stats_format: Option<String>,
+
+    /// A text prepended to every answer, e.g. `"🤖 {model}"`. `{model}` is
+    /// the model that generated the answer, and `{tokens}` the tokens used
+    /// by the request. It's rendered as Markdown like the answer.
+    /// JSON key: `replyPrefix`
+    #[serde(default, rename = "replyPrefix")]
+    pub reply_prefix: Option<String>,
+
+    /// A text appended to every answer, e.g.
+    /// `"Powered by X • model: {model} • {tokens} tokens"`, with the same
+    /// placeholders as `replyPrefix`.
+    /// JSON key: `replySuffix`
+    #[serde(default, rename = "replySuffix")]
+    pub reply_suffix: Option<String>,
+
     /// Maximum number of messages in a single conversation.
     /// JSON key: `conversationLimit`
     #[serde(default = "default_conversation_limit", rename = "conversationLimit")]
     pub conversation_limit: u64,
 
+    /// The system message that new sessions start with, unless the chat
+    /// overrides it with `/system_prompt`.
+    /// JSON key: `defaultSystemPrompt`
+    #[serde(default, rename = "defaultSystemPrompt")]
+    pub default_system_prompt: Option<String>,
+
+    /// The system message that new sessions in groups start with, in place
+    /// of `defaultSystemPrompt`.
+    /// JSON key: `groupSystemPrompt`
+    #[serde(default, rename = "groupSystemPrompt")]
+    pub group_system_prompt: Option<String>,
+
+    /// The maximum number of tokens allowed for the prompt, including the
+    /// history messages. When set, the oldest history messages are dropped
+    /// until the prompt fits in the budget, while the system message is
+    /// always kept.
+    /// JSON key: `maxPromptTokens`
+    #[serde(default, rename = "maxPromptTokens")]
+    pub max_prompt_tokens: Option<u32>,
+
+    /// A boolean value that indicates whether to send the answers as they
+    /// are displayed (e.g. with the Markdown rendered) to the model as
+    /// history, instead of the raw outputs of the model. This is default
+    /// to `false`.
+    /// JSON key: `renderedHistory`
+    #[serde(default, rename = "renderedHistory")]
+    pub rendered_history: bool,
+
+    /// A multiplier applied to the estimated token counts, which are used
+    /// for models without a tokenizer. Increase it if the estimations turn
+    /// out to be lower than the actual usage. This is default to `1.0`.
+    /// JSON key: `tokenEstimateMultiplier`
+    #[serde(
+        default = "default_token_estimate_multiplier",
+        rename = "tokenEstimateMultiplier"
+    )]
+    pub token_estimate_multiplier: f64,
+
     /// The maximum number of tokens allowed for the generated answer.
     /// JSON key: `maxTokens`
     #[serde(default, rename = "maxTokens")]
     pub max_tokens: Option<u16>,
 
+    /// The default sampling temperature, between 0 and 2. Members can
+    /// override it per chat with the `/settings` command.
+    /// JSON key: `temperature`
+    #[serde(default = "default_temperature")]
+    pub temperature: f32,
+
+    /// The default nucleus sampling probability mass, between 0 and 1.
+    /// JSON key: `topP`
+    #[serde(default, rename = "topP")]
+    pub top_p: Option<f32>,
+
+    /// The default presence penalty, between -2 and 2.
+    /// JSON key: `presencePenalty`
+    #[serde(default, rename = "presencePenalty")]
+    pub presence_penalty: Option<f32>,
+
+    /// The default frequency penalty, between -2 and 2.
+    /// JSON key: `frequencyPenalty`
+    #[serde(default, rename = "frequencyPenalty")]
+    pub frequency_penalty: Option<f32>,
+
+    /// Custom parameter presets, which map the names to the sampling
+    /// parameters, e.g. `{"coding": {"temperature": 0.1}}`. Members can
+    /// apply a preset to the chat with the `/preset` command. A preset with
+    /// the name of a built-in one (`creative`, `precise` or `balanced`)
+    /// replaces it. Names can be up to 56 bytes long.
+    /// JSON key: `parameterPresets`
+    #[serde(default, rename = "parameterPresets")]
+    pub parameter_presets: HashMap<String, ParameterPreset>,
+
+    /// Up to 4 sequences where the model will stop generating further tokens.
+    /// JSON key: `stopSequences`
+    #[serde(default, rename = "stopSequences")]
+    pub stop_sequences: Vec<String>,
+
+    /// A map from token IDs to bias values (from -100 to 100), which
+    /// modifies the likelihood of the specified tokens appearing in the
+    /// answer.
+    /// JSON key: `logitBias`
+    #[serde(default, rename = "logitBias")]
+    pub logit_bias: HashMap<String, i32>,
+
+    /// The pricing of each model, which is used to estimate the cost of
+    /// usage. Models without pricing are counted as free.
+    /// JSON key: `modelPricing`
+    #[serde(default, rename = "modelPricing")]
+    pub model_pricing: HashMap<String, ModelPricing>,
+
+    /// The default personas, which map the names to the system prompts.
+    /// They are added to the persona library on start unless a persona of
+    /// the same name exists or was deleted by admins. Personas can be
+    /// activated with `/persona <name>` or deep links like
+    /// `https://t.me/<bot>?start=persona_<name>`.
+    /// JSON key: `personas`
+    #[serde(default)]
+    pub personas: HashMap<String, String>,
+
+    /// The sampling parameters of personas, which override `stopSequences`
+    /// and `logitBias` while a persona is active in the session, e.g.
+    /// `{"coder": {"stopSequences": ["```\n\n"]}}`.
+    /// JSON key: `personaParams`
+    #[serde(default, rename = "personaParams")]
+    pub persona_params: HashMap<String, PersonaParams>,
+
+    /// The invite codes that let users join the members with deep links
+    /// like `https://t.me/<bot>?start=invite_<code>`.
+    /// JSON key: `inviteCodes`
+    #[serde(default, rename = "inviteCodes")]
+    pub invite_codes: HashSet<String>,
+
+    /// The prompts that are asked on behalf of users with deep links like
+    /// `https://t.me/<bot>?start=prompt_<name>`, which map the names to
+    /// the prompts.
+    /// JSON key: `promptTemplates`
+    #[serde(default, rename = "promptTemplates")]
+    pub prompt_templates: HashMap<String, String>,
+
+    /// The daily quotas of each user on the features, see [`DailyQuotas`].
+    /// JSON key: `dailyQuotas`
+    #[serde(default, rename = "dailyQuotas")]
+    pub daily_quotas: DailyQuotas,
+
+    /// A boolean value that indicates whether to accept photos (with an
+    /// optional caption as the question) in chats using vision models.
+    /// This is default to `false`.
+    /// JSON key: `imageInput`
+    #[serde(default, rename = "imageInput")]
+    pub image_input: bool,
+
+    /// A boolean value that indicates whether to transcribe voice messages
+    /// and use the transcriptions as the questions. This is default to
+    /// `false`.
+    /// JSON key: `voiceInput`
+    #[serde(default, rename = "voiceInput")]
+    pub voice_input: bool,
+
+    /// The model used to transcribe voice messages. This is default to
+    /// `"whisper-1"`.
+    /// JSON key: `transcriptionModel`
+    #[serde(default = "default_transcription_model", rename = "transcriptionModel")]
+    pub transcription_model: String,
+
+    /// A boolean value that indicates whether to post the transcription of
+    /// a voice message before the answer, so that other members in the
+    /// group can see what was asked. This is default to `false`.
+    /// JSON key: `echoTranscription`
+    #[serde(default, rename = "echoTranscription")]
+    pub echo_transcription: bool,
+
+    /// The model used to speak the answers in chats with voice replies
+    /// enabled. This is default to `"tts-1"`.
+    /// JSON key: `ttsModel`
+    #[serde(default = "default_tts_model", rename = "ttsModel")]
+    pub tts_model: String,
+
+    /// The voice used to speak the answers, e.g. `"alloy"`, `"nova"`.
+    /// This is default to `"alloy"`.
+    /// JSON key: `ttsVoice`
+    #[serde(default = "default_tts_voice", rename = "ttsVoice")]
+    pub tts_voice: String,
+
+    /// Prefixes of the models that accept images.
+    /// JSON key: `visionModels`
+    #[serde(default = "default_vision_models", rename = "visionModels")]
+    pub vision_models: Vec<String>,
+
     /// A boolean value that indicates whether to parse and render the
     /// markdown contents. When set to `false`, the raw contents returned
     /// from OpenAI will be displayed. This is default to `false`.
@@ -98,18 +442,723 @@ pub struct Config {
     #[serde(default = "default_renders_markdown", rename = "rendersMarkdown")]
     pub renders_markdown: bool,
 
+    /// How the Markdown contents are rendered, see [`RenderMode`]. When
+    /// not set, it's `entities` if `rendersMarkdown` is `true`, and `plain`
+    /// otherwise. The preference of a chat can still turn rendering on or
+    /// off.
+    /// JSON key: `renderMode`
+    #[serde(default, rename = "renderMode")]
+    pub render_mode: Option<RenderMode>,
+
+    /// A threshold in characters. When the rendered answer is mostly a
+    /// code block longer than this, the code is sent as a file instead.
+    /// [`None`] to always send the code as text.
+    /// JSON key: `codeFileThreshold`
+    #[serde(default, rename = "codeFileThreshold")]
+    pub code_file_threshold: Option<usize>,
+
+    /// A directory for dumping the raw stream deltas returned from OpenAI,
+    /// one file per chat. This is only intended for debugging, and should
+    /// not be enabled in the production environment since it records
+    /// the contents of conversations.
+    /// JSON key: `streamDumpDir`
+    #[serde(default, rename = "streamDumpDir")]
+    pub stream_dump_dir: Option<String>,
+
+    /// Destinations of the conversations archived with `/archive`, [`None`]
+    /// to disable the command.
+    /// JSON key: `archive`
+    #[serde(default)]
+    pub archive: Option<ArchiveConfig>,
+
+    /// A boolean value that indicates whether to tell the user in private
+    /// chat when the bot lacks the permission to answer in a group. This
+    /// is default to `false`.
+    /// JSON key: `notifyUserOnSendFailure`
+    #[serde(default, rename = "notifyUserOnSendFailure")]
+    pub notify_user_on_send_failure: bool,
+
+    /// The number of seconds after which the bot deletes its error notices,
+    /// rejections and confirmations in groups, to keep the chats tidy. The
+    /// messages are kept if this is not set.
+    /// JSON key: `serviceMessageTtl`
+    #[serde(default, rename = "serviceMessageTtl")]
+    pub service_message_ttl: Option<u64>,
+
+    /// The maximum number of messages that each user, and each group, can
+    /// send to the bot per minute. Excess messages are rejected. Admins
+    /// are not limited, and there is no limit if this is not set.
+    /// JSON key: `rateLimitPerMinute`
+    #[serde(default, rename = "rateLimitPerMinute")]
+    pub rate_limit_per_minute: Option<u32>,
+
+    /// The limits per minute of specific members by their usernames, which
+    /// replace `rateLimitPerMinute` (0 for unlimited). Members with a limit
+    /// here are not counted in the limit of groups.
+    /// JSON key: `rateLimitOverrides`
+    #[serde(default, rename = "rateLimitOverrides")]
+    pub rate_limit_overrides: HashMap<String, u32>,
+
+    /// Checks for new releases of TeleGPT periodically and notifies the
+    /// admin chats, [`None`] to disable the check.
+    /// JSON key: `updateCheck`
+    #[serde(default, rename = "updateCheck")]
+    pub update_check: Option<UpdateCheckConfig>,
+
+    /// The address (e.g. `127.0.0.1:8080`) to serve the health check over
+    /// HTTP, [`None`] to disable it. Changes take effect after restarting.
+    /// JSON key: `healthListenAddr`
+    #[serde(default, rename = "healthListenAddr")]
+    pub health_listen_addr: Option<String>,
+
+    /// The exporter of the traces of updates, OpenAI requests and database
+    /// queries, [`None`] to disable it. Changes take effect after
+    /// restarting.
+    /// JSON key: `tracing`
+    #[serde(default)]
+    pub tracing: Option<TracingConfig>,
+
+    /// Checks the user input and the answers with the moderation endpoint
+    /// of OpenAI, see [`ModerationConfig`].
+    /// JSON key: `moderation`
+    #[serde(default)]
+    pub moderation: ModerationConfig,
+
+    /// Summarizes the messages evicted from the conversation (by
+    /// `conversationLimit` or the context window) instead of dropping
+    /// them, [`None`] to drop them.
+    /// JSON key: `historySummary`
+    #[serde(default, rename = "historySummary")]
+    pub history_summary: Option<HistorySummaryConfig>,
+
+    /// Names the sessions with short titles generated by a model, once
+    /// they have a few messages, [`None`] to leave them unnamed.
+    /// JSON key: `sessionTitles`
+    #[serde(default, rename = "sessionTitles")]
+    pub session_titles: Option<SessionTitleConfig>,
+
+    /// Answers identical prompts (with the same model and parameters) from
+    /// a cache instead of calling the API, [`None`] to disable the cache.
+    /// JSON key: `responseCache`
+    #[serde(default, rename = "responseCache")]
+    pub response_cache: Option<ResponseCacheConfig>,
+
+    /// The search API that backs the `/search` command, [`None`] to
+    /// disable the command.
+    /// JSON key: `webSearch`
+    #[serde(default, rename = "webSearch")]
+    pub web_search: Option<WebSearchConfig>,
+
+    /// Keeps the recent messages of groups in memory for the `/summarize`
+    /// command, including the ones that don't mention the bot. [`None`] to
+    /// disable the command and keep no messages.
+    /// JSON key: `groupMessageCache`
+    #[serde(default, rename = "groupMessageCache")]
+    pub group_message_cache: Option<GroupMessageCacheConfig>,
+
+    /// A threshold in seconds. When the answer in a group is posted later
+    /// than this since the question was asked, the sender will be mentioned
+    /// so that they don't miss it. [`None`] to disable the mention.
+    /// JSON key: `longWaitMentionThreshold`
+    #[serde(default, rename = "longWaitMentionThreshold")]
+    pub long_wait_mention_threshold: Option<u64>,
+
+    /// The timezone used for displaying and reporting times, in IANA name
+    /// (e.g. `Asia/Shanghai`). This is default to `UTC`.
+    /// JSON key: `timezone`
+    #[serde(default = "default_timezone")]
+    pub timezone: Tz,
+
+    /// Sessions idle for longer than this many minutes are cleared, [`None`]
+    /// to keep sessions until they are reset.
+    /// JSON key: `sessionTtlMinutes`
+    #[serde(default, rename = "sessionTtlMinutes")]
+    pub session_ttl_minutes: Option<u64>,
+
+    /// The usage recorded more than this many days ago is pruned, after
+    /// the token usage is rolled up into monthly totals. [`None`] to keep
+    /// the usage forever.
+    /// JSON key: `statsRetentionDays`
+    #[serde(default, rename = "statsRetentionDays")]
+    pub stats_retention_days: Option<u32>,
+
+    /// Posts a digest of the usage of the previous day to an admin chat
+    /// every day, [`None`] to disable the digest.
+    /// JSON key: `dailyDigest`
+    #[serde(default, rename = "dailyDigest")]
+    pub daily_digest: Option<DailyDigestConfig>,
+
+    /// A boolean value that indicates whether to notify the chat when its
+    /// session is cleared for being idle. This is default to `false`.
+    /// JSON key: `notifySessionExpiry`
+    #[serde(default, rename = "notifySessionExpiry")]
+    pub notify_session_expiry: bool,
+
+    /// A boolean value that indicates whether to tell the model the current
+    /// date and time (in the configured timezone) with each request. This is
+    /// default to `false`.
+    /// JSON key: `injectCurrentTime`
+    #[serde(default, rename = "injectCurrentTime")]
+    pub inject_current_time: bool,
+
     /// A path for storing the database, [`None`] for in-memory database.
     /// JSON key: `databasePath`
     #[serde(rename = "databasePath")]
     pub database_path: Option<String>,
 
-    /// Strings for I18N.
+    /// A secret that encrypts the sensitive data in the database, i.e. the
+    /// preferences of chats, the member list and the cached answers. Use a
+    /// long random string, and keep it, since the data can't be read
+    /// without it.
+    /// JSON key: `databaseEncryptionKey`
+    #[serde(default, rename = "databaseEncryptionKey")]
+    pub database_encryption_key: Option<String>,
+
+    /// Strings for I18N, either in a single locale or by locales, see
+    /// [`I18n`].
     /// JSON key: `i18n`
     #[serde(default)]
-    pub i18n: I18nStrings,
+    pub i18n: I18n,
+
+    /// The locale of the strings used when there are no strings in the
+    /// language of the user. This is default to `en`.
+    /// JSON key: `defaultLocale`
+    #[serde(default = "default_default_locale", rename = "defaultLocale")]
+    pub default_locale: String,
+}
+
+impl Config {
+    /// Returns the strings in the language of the user, falling back to
+    /// the default locale.
+    pub fn i18n_strings(&self, language_code: Option<&str>) -> &I18nStrings {
+        self.i18n.get(language_code, &self.default_locale)
+    }
+
+    /// Returns the render mode of the chats without a preference.
+    pub fn default_render_mode(&self) -> RenderMode {
+        match self.render_mode {
+            Some(render_mode) => render_mode,
+            None if self.renders_markdown => RenderMode::Entities,
+            None => RenderMode::Plain,
+        }
+    }
+
+    /// Checks the settings that can be parsed but won't work, and returns
+    /// the problems found. The config is valid if nothing is returned.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.telegram_bot_token.trim().is_empty() {
+            problems.push("`botToken` is empty".to_owned());
+        }
+        match &self.provider {
+            ProviderConfig::OpenAI => {
+                if self.openai_api_key.trim().is_empty() && self.openai_api_keys.is_empty() {
+                    problems.push("Neither `openaiAPIKey` nor `openaiAPIKeys` is set".to_owned());
+                }
+            }
+            ProviderConfig::OpenAICompatible(provider) => {
+                if provider.base_url.trim().is_empty() {
+                    problems.push("`provider.baseUrl` is empty".to_owned());
+                }
+            }
+        }
+        if self
+            .openai_api_keys
+            .iter()
+            .any(|key| key.key.trim().is_empty())
+        {
+            problems.push("`openaiAPIKeys` contains an empty key".to_owned());
+        }
+        if self.conversation_limit == 0 {
+            problems.push("`conversationLimit` must be greater than 0".to_owned());
+        }
+
+        for name in self.parameter_presets.keys() {
+            if name.is_empty() || name.len() > PRESET_NAME_MAX_LEN {
+                problems.push(format!(
+                    "The name of `parameterPresets.{}` must be 1 to {} bytes long",
+                    name, PRESET_NAME_MAX_LEN
+                ));
+            }
+        }
+
+        let mut check_range = |key: &str, value: Option<f32>, min: f32, max: f32| {
+            if let Some(value) = value {
+                if !(min..=max).contains(&value) {
+                    problems.push(format!("`{}` must be between {} and {}", key, min, max));
+                }
+            }
+        };
+        check_range("temperature", Some(self.temperature), 0.0, 2.0);
+        check_range("topP", self.top_p, 0.0, 1.0);
+        check_range("presencePenalty", self.presence_penalty, -2.0, 2.0);
+        check_range("frequencyPenalty", self.frequency_penalty, -2.0, 2.0);
+        for (name, preset) in &self.parameter_presets {
+            let key = |param: &str| format!("parameterPresets.{}.{}", name, param);
+            check_range(&key("temperature"), preset.temperature, 0.0, 2.0);
+            check_range(&key("topP"), preset.top_p, 0.0, 1.0);
+            check_range(&key("presencePenalty"), preset.presence_penalty, -2.0, 2.0);
+            check_range(
+                &key("frequencyPenalty"),
+                preset.frequency_penalty,
+                -2.0,
+                2.0,
+            );
+        }
+
+        if let Some(tracing) = &self.tracing {
+            if !(0.0..=1.0).contains(&tracing.sample_ratio) {
+                problems.push("`tracing.sampleRatio` must be between 0 and 1".to_owned());
+            }
+        }
+
+        if let Some(daily_digest) = &self.daily_digest {
+            if daily_digest.time_of_day().is_none() {
+                problems.push(format!(
+                    "`dailyDigest.time` ({}) is not in the HH:MM format",
+                    daily_digest.time
+                ));
+            }
+        }
+
+        if self.stop_sequences.len() > 4 {
+            problems.push("`stopSequences` has more than 4 sequences".to_owned());
+        }
+        for (name, params) in &self.persona_params {
+            if params.stop_sequences.as_ref().map_or(0, Vec::len) > 4 {
+                problems.push(format!(
+                    "`personaParams.{}.stopSequences` has more than 4 sequences",
+                    name
+                ));
+            }
+        }
+        if !self.available_models.is_empty()
+            && !self.available_models.contains(&self.openai_gpt_model)
+        {
+            problems.push(format!(
+                "`openaiGptModel` ({}) is not in `availableModels`",
+                self.openai_gpt_model
+            ));
+        }
+        problems
+    }
+}
+
+/// The service that serves the chat model.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "type")]
+pub enum ProviderConfig {
+    /// The OpenAI API, with the keys in `openaiAPIKey` or `openaiAPIKeys`.
+    #[default]
+    #[serde(rename = "openai")]
+    OpenAI,
+    /// A server with an OpenAI-compatible API, e.g. Ollama, LM Studio or
+    /// vLLM.
+    #[serde(rename = "openaiCompatible")]
+    OpenAICompatible(CompatibleProviderConfig),
+}
+
+/// Settings of an OpenAI-compatible server.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CompatibleProviderConfig {
+    /// The base URL of the API, e.g. `http://localhost:11434/v1`.
+    /// JSON key: `baseUrl`
+    #[serde(rename = "baseUrl")]
+    pub base_url: String,
+    /// The API key sent to the server, if it requires one.
+    /// JSON key: `apiKey`
+    #[serde(default, rename = "apiKey")]
+    pub api_key: Option<String>,
+    /// The names of the models on the server, by the model names used in
+    /// this config and by `/model`. Models not in the map are sent as is.
+    /// JSON key: `modelNames`
+    #[serde(default, rename = "modelNames")]
+    pub model_names: HashMap<String, String>,
+}
+
+/// An API key in the key pool.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OpenAIKeyConfig {
+    /// The API key.
+    /// JSON key: `key`
+    pub key: String,
+    /// The budget in USD of the key per month. The key is not used once
+    /// its spend exceeds the budget, unless all keys have exceeded theirs.
+    /// JSON key: `monthlyBudget`
+    #[serde(default, rename = "monthlyBudget")]
+    pub monthly_budget: Option<f64>,
+}
+
+/// The daily quotas of each user, features without quotas are unlimited.
+/// Admins are not limited by the quotas.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct DailyQuotas {
+    /// The tokens used in chats, including the prompts and the answers.
+    /// JSON key: `chatTokens`
+    #[serde(default, rename = "chatTokens")]
+    pub chat_tokens: Option<u64>,
+    /// The photos sent to vision models.
+    /// JSON key: `images`
+    #[serde(default)]
+    pub images: Option<u64>,
+    /// The voice messages to transcribe.
+    /// JSON key: `voiceMessages`
+    #[serde(default, rename = "voiceMessages")]
+    pub voice_messages: Option<u64>,
+    /// The messages of the users with the guest role, other users are not
+    /// limited by it.
+    /// JSON key: `guestMessages`
+    #[serde(default, rename = "guestMessages")]
+    pub guest_messages: Option<u64>,
+}
+
+/// Destinations of archived conversations. At least one destination
+/// should be specified.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ArchiveConfig {
+    /// A directory to write the conversations into, one Markdown file with
+    /// front matter per archive.
+    /// JSON key: `markdownDir`
+    #[serde(default, rename = "markdownDir")]
+    pub markdown_dir: Option<String>,
+    /// A URL to post the conversations to as JSON.
+    /// JSON key: `endpoint`
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// Extra HTTP headers (e.g. `Authorization`) sent to `endpoint`.
+    /// JSON key: `headers`
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+/// How the flagged contents are handled, in each direction.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct ModerationConfig {
+    /// The handling of the messages from users.
+    /// JSON key: `input`
+    #[serde(default)]
+    pub input: ModerationMode,
+    /// The handling of the answers from the model.
+    /// JSON key: `output`
+    #[serde(default)]
+    pub output: ModerationMode,
+}
+
+/// How the answers in Markdown are displayed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum RenderMode {
+    /// The Markdown is parsed and sent as the entities of the message.
+    #[default]
+    #[serde(rename = "entities")]
+    Entities,
+    /// The Markdown is parsed and sent as MarkdownV2 text, which falls back
+    /// to the entities if Telegram rejects it.
+    #[serde(rename = "markdownv2")]
+    MarkdownV2,
+    /// The raw contents are displayed.
+    #[serde(rename = "plain")]
+    Plain,
+}
+
+/// The handling of flagged contents. The flagged contents are always
+/// logged and counted in stats unless moderation is off.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum ModerationMode {
+    /// The contents are not checked.
+    #[default]
+    #[serde(rename = "off")]
+    Off,
+    /// The contents are allowed silently.
+    #[serde(rename = "logOnly")]
+    LogOnly,
+    /// The contents are allowed, and the user is warned.
+    #[serde(rename = "warn")]
+    Warn,
+    /// The contents are rejected.
+    #[serde(rename = "block")]
+    Block,
+}
+
+/// Settings of the checker for new releases.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateCheckConfig {
+    /// The chats to notify when a newer version is released, usually the
+    /// private chats of the admins.
+    /// JSON key: `notifyChatIds`
+    #[serde(rename = "notifyChatIds")]
+    pub notify_chat_ids: Vec<i64>,
+    /// The URL of the latest release in the format of GitHub releases API.
+    /// JSON key: `releaseUrl`
+    #[serde(default = "default_release_url", rename = "releaseUrl")]
+    pub release_url: String,
+    /// The interval in hours between checks.
+    /// JSON key: `intervalHours`
+    #[serde(
+        default = "default_update_check_interval_hours",
+        rename = "intervalHours"
+    )]
+    pub interval_hours: u64,
+}
+
+/// Settings of the daily digest of the usage.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DailyDigestConfig {
+    /// The chat to post the digest to, usually the private chat of an admin.
+    /// JSON key: `chatId`
+    #[serde(rename = "chatId")]
+    pub chat_id: i64,
+    /// When to post the digest every day, in the `HH:MM` format and the
+    /// configured timezone. This is default to `09:00`.
+    /// JSON key: `time`
+    #[serde(default = "default_digest_time")]
+    pub time: String,
+    /// The maximum number of chats and users listed in the digest.
+    /// JSON key: `topLimit`
+    #[serde(default = "default_digest_top_limit", rename = "topLimit")]
+    pub top_limit: u32,
+}
+
+impl DailyDigestConfig {
+    /// Returns the parsed `time`, or [`None`] if it's invalid.
+    pub fn time_of_day(&self) -> Option<NaiveTime> {
+        NaiveTime::parse_from_str(self.time.trim(), "%H:%M").ok()
+    }
+}
+
+/// Settings of the OTLP exporter of traces. Chat ids are hashed in the
+/// spans, and the contents of messages are never recorded.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TracingConfig {
+    /// The base URL of the OTLP/HTTP collector, e.g.
+    /// `http://localhost:4318`. Traces are sent to `/v1/traces` under it.
+    /// JSON key: `otlpEndpoint`
+    #[serde(rename = "otlpEndpoint")]
+    pub otlp_endpoint: String,
+    /// The `service.name` of the traces.
+    /// JSON key: `serviceName`
+    #[serde(default = "default_tracing_service_name", rename = "serviceName")]
+    pub service_name: String,
+    /// The ratio of updates to trace, from 0 to 1.
+    /// JSON key: `sampleRatio`
+    #[serde(default = "default_tracing_sample_ratio", rename = "sampleRatio")]
+    pub sample_ratio: f64,
+    /// The secret key to hash chat ids with. Without it, a random key is
+    /// used, and the hashes change when the bot restarts.
+    /// JSON key: `hashKey`
+    #[serde(default, rename = "hashKey")]
+    pub hash_key: Option<String>,
+}
+
+/// Settings of the summary of evicted messages.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HistorySummaryConfig {
+    /// The maximum number of tokens of the summary.
+    /// JSON key: `maxTokens`
+    #[serde(default = "default_summary_max_tokens", rename = "maxTokens")]
+    pub max_tokens: u16,
+    /// The model used to summarize, [`None`] to use the model of the chat.
+    /// JSON key: `model`
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// Settings of the automatic titles of sessions.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SessionTitleConfig {
+    /// The number of history messages that a session needs to be named.
+    /// JSON key: `afterMessages`
+    #[serde(default = "default_title_after_messages", rename = "afterMessages")]
+    pub after_messages: usize,
+    /// The model used to name sessions, [`None`] to use the model of the
+    /// chat.
+    /// JSON key: `model`
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// Settings of the cache of answers.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResponseCacheConfig {
+    /// How long the answers are kept, in minutes.
+    /// JSON key: `ttlMinutes`
+    #[serde(default = "default_response_cache_ttl_minutes", rename = "ttlMinutes")]
+    pub ttl_minutes: u64,
+}
+
+/// Settings of the web search.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebSearchConfig {
+    /// The search API, which is specified by `type` along with its
+    /// settings, e.g. `{"type": "brave", "apiKey": "..."}`.
+    #[serde(flatten)]
+    pub provider: SearchProviderConfig,
+    /// The maximum number of results added to the prompt.
+    /// JSON key: `maxResults`
+    #[serde(default = "default_web_search_max_results", rename = "maxResults")]
+    pub max_results: usize,
+}
+
+/// Settings of the recent messages kept for `/summarize`. The messages are
+/// only kept in memory, and are lost on restart.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GroupMessageCacheConfig {
+    /// The maximum number of messages kept for each group.
+    /// JSON key: `capacity`
+    #[serde(default = "default_group_message_cache_capacity")]
+    pub capacity: usize,
+    /// How long the messages are kept, in minutes.
+    /// JSON key: `ttlMinutes`
+    #[serde(
+        default = "default_group_message_cache_ttl_minutes",
+        rename = "ttlMinutes"
+    )]
+    pub ttl_minutes: u64,
+    /// The users (without `@`) whose messages are never kept.
+    /// JSON key: `excludedUsernames`
+    #[serde(default, rename = "excludedUsernames")]
+    pub excluded_usernames: HashSet<String>,
+}
+
+/// The service that serves the web search.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type")]
+pub enum SearchProviderConfig {
+    /// The Brave Search API.
+    #[serde(rename = "brave")]
+    Brave {
+        /// The subscription token of the API.
+        /// JSON key: `apiKey`
+        #[serde(rename = "apiKey")]
+        api_key: String,
+    },
+    /// A SearXNG instance with the JSON format enabled.
+    #[serde(rename = "searxng")]
+    Searxng {
+        /// The base URL of the instance, e.g. `http://localhost:8080`.
+        /// JSON key: `baseUrl`
+        #[serde(rename = "baseUrl")]
+        base_url: String,
+    },
+}
+
+/// The pricing of a model in USD per 1K tokens.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ModelPricing {
+    /// The price of prompt tokens.
+    /// JSON key: `prompt`
+    pub prompt: f64,
+    /// The price of completion tokens.
+    /// JSON key: `completion`
+    pub completion: f64,
+}
+
+impl ModelPricing {
+    /// Returns the cost in USD of the given token usage.
+    pub fn cost(&self, prompt_tokens: u32, completion_tokens: u32) -> f64 {
+        (prompt_tokens as f64 * self.prompt + completion_tokens as f64 * self.completion) / 1000.0
+    }
+}
+
+/// The sampling parameters of a persona. Parameters that are not set fall
+/// back to the defaults in config.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PersonaParams {
+    /// JSON key: `stopSequences`
+    #[serde(default, rename = "stopSequences")]
+    pub stop_sequences: Option<Vec<String>>,
+    /// JSON key: `logitBias`
+    #[serde(default, rename = "logitBias")]
+    pub logit_bias: Option<HashMap<String, i32>>,
+}
+
+/// A named bundle of sampling parameters. Parameters that are not set fall
+/// back to the defaults in config.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct ParameterPreset {
+    /// JSON key: `temperature`
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    /// JSON key: `topP`
+    #[serde(default, rename = "topP")]
+    pub top_p: Option<f32>,
+    /// JSON key: `presencePenalty`
+    #[serde(default, rename = "presencePenalty")]
+    pub presence_penalty: Option<f32>,
+    /// JSON key: `frequencyPenalty`
+    #[serde(default, rename = "frequencyPenalty")]
+    pub frequency_penalty: Option<f32>,
+}
+
+/// Strings for I18N in multiple locales.
+///
+/// The strings can be specified for a single locale:
+///
+/// ```json
+/// { "resetPrompt": "Session is reset!" }
+/// ```
+///
+/// or by locales, which are matched with the language codes (e.g. `en`
+/// or `pt-br`) of users:
+///
+/// ```json
+/// {
+///   "en": { "resetPrompt": "Session is reset!" },
+///   "zh": { "resetPrompt": "会话已重置！" }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(from = "I18nRepr")]
+pub struct I18n {
+    locales: HashMap<String, I18nStrings>,
+    fallback: I18nStrings,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum I18nRepr {
+    Locales(HashMap<String, I18nStrings>),
+    Strings(Box<I18nStrings>),
+}
+
+impl From<I18nRepr> for I18n {
+    fn from(repr: I18nRepr) -> Self {
+        match repr {
+            I18nRepr::Locales(locales) => Self {
+                locales: locales
+                    .into_iter()
+                    .map(|(code, strings)| (code.to_lowercase(), strings))
+                    .collect(),
+                fallback: I18nStrings::default(),
+            },
+            I18nRepr::Strings(strings) => Self {
+                locales: HashMap::new(),
+                fallback: *strings,
+            },
+        }
+    }
 }
 
-/// Strings for I18N.
+impl I18n {
+    /// Returns the strings of the language, or the default locale if there
+    /// are no strings in the language. A region-specific language (e.g.
+    /// `pt-br`) also matches the strings of its language (e.g. `pt`).
+    pub fn get(&self, language_code: Option<&str>, default_locale: &str) -> &I18nStrings {
+        let find = |code: &str| {
+            let code = code.to_lowercase();
+            self.locales.get(&code).or_else(|| {
+                code.split(['-', '_'])
+                    .next()
+                    .and_then(|language| self.locales.get(language))
+            })
+        };
+        language_code
+            .and_then(find)
+            .or_else(|| find(default_locale))
+            .unwrap_or(&self.fallback)
+    }
+}
+
+/// Strings for I18N in a locale.
 #[derive(Debug, Clone, Deserialize)]
 pub struct I18nStrings {
     /// A text to display when there are something wrong with the OpenAI service.
@@ -124,6 +1173,92 @@ pub struct I18nStrings {
     /// JSON key: `notAllowedPrompt`
     #[serde(default = "default_not_allowed_prompt", rename = "notAllowedPrompt")]
     pub not_allowed_prompt: String,
+    /// A text to mention the sender when the answer is ready after a long
+    /// wait, which is prefixed by the name of the sender.
+    /// JSON key: `answerReadyPrompt`
+    #[serde(default = "default_answer_ready_prompt", rename = "answerReadyPrompt")]
+    pub answer_ready_prompt: String,
+    /// A text to display when the session is cleared for being idle.
+    /// JSON key: `sessionExpiredPrompt`
+    #[serde(
+        default = "default_session_expired_prompt",
+        rename = "sessionExpiredPrompt"
+    )]
+    pub session_expired_prompt: String,
+    /// A text to display when a user starts the bot.
+    /// JSON key: `startPrompt`
+    #[serde(default = "default_start_prompt", rename = "startPrompt")]
+    pub start_prompt: String,
+    /// A text to display when a user sends messages too fast.
+    /// JSON key: `rateLimitedPrompt`
+    #[serde(default = "default_rate_limited_prompt", rename = "rateLimitedPrompt")]
+    pub rate_limited_prompt: String,
+    /// A text to display when a message or an answer is blocked by the
+    /// moderation.
+    /// JSON key: `moderationBlockedPrompt`
+    #[serde(
+        default = "default_moderation_blocked_prompt",
+        rename = "moderationBlockedPrompt"
+    )]
+    pub moderation_blocked_prompt: String,
+    /// A text to display when a message or an answer is flagged by the
+    /// moderation but still allowed.
+    /// JSON key: `moderationWarningPrompt`
+    #[serde(
+        default = "default_moderation_warning_prompt",
+        rename = "moderationWarningPrompt"
+    )]
+    pub moderation_warning_prompt: String,
+    /// A text to display in the group when the answer is sent to the
+    /// private chat of the sender.
+    /// JSON key: `privateAnswerPrompt`
+    #[serde(
+        default = "default_private_answer_prompt",
+        rename = "privateAnswerPrompt"
+    )]
+    pub private_answer_prompt: String,
+    /// A text to display while the message waits in the queue, where
+    /// `{position}` is replaced with its position.
+    /// JSON key: `queuedPrompt`
+    #[serde(default = "default_queued_prompt", rename = "queuedPrompt")]
+    pub queued_prompt: String,
+    /// A text to append to an answer of a fallback model, where `{model}`
+    /// is replaced with the name of the model.
+    /// JSON key: `fallbackModelPrompt`
+    #[serde(
+        default = "default_fallback_model_prompt",
+        rename = "fallbackModelPrompt"
+    )]
+    pub fallback_model_prompt: String,
+    /// A text to display when the model returns an empty answer, e.g. when
+    /// it's filtered.
+    /// JSON key: `emptyAnswerPrompt`
+    #[serde(default = "default_empty_answer_prompt", rename = "emptyAnswerPrompt")]
+    pub empty_answer_prompt: String,
+    /// A text to append to an answer that is cut off by `maxTokens`.
+    /// JSON key: `truncatedPrompt`
+    #[serde(default = "default_truncated_prompt", rename = "truncatedPrompt")]
+    pub truncated_prompt: String,
+    /// A text to display in place of the code block that is sent as a file.
+    /// JSON key: `codeFilePrompt`
+    #[serde(default = "default_code_file_prompt", rename = "codeFilePrompt")]
+    pub code_file_prompt: String,
+    /// A text to display before the commands listed by `/help`.
+    /// JSON key: `helpPrompt`
+    #[serde(default = "default_help_prompt", rename = "helpPrompt")]
+    pub help_prompt: String,
+    /// A text to display before the admin commands listed by `/help`.
+    /// JSON key: `adminHelpPrompt`
+    #[serde(default = "default_admin_help_prompt", rename = "adminHelpPrompt")]
+    pub admin_help_prompt: String,
+    /// The descriptions of commands (without the leading slash) in `/help`,
+    /// which replace the built-in ones.
+    /// JSON key: `commandDescriptions`
+    #[serde(
+        default = "default_command_descriptions",
+        rename = "commandDescriptions"
+    )]
+    pub command_descriptions: HashMap<String, String>,
 }
 
 macro_rules! define_defaults {
@@ -151,14 +1286,128 @@ macro_rules! define_defaults {
 }
 
 define_defaults! {
+    openai_gpt_model: String = "gpt-3.5-turbo".to_owned(),
     openai_api_timeout: u64 = 10,
+    openai_first_token_timeout: u64 = 30,
     stream_throttle_interval: u64 = 500,
     conversation_limit: u64 = 20,
     renders_markdown: bool = false,
+    model_selection_admin_only: bool = true,
+    validate_models: bool = true,
+    transcription_model: String = "whisper-1".to_owned(),
+    tts_model: String = "tts-1".to_owned(),
+    tts_voice: String = "alloy".to_owned(),
+    key_budget_alert_threshold: f64 = 0.8,
+    token_estimate_multiplier: f64 = 1.0,
+    temperature: f32 = 0.6,
+    vision_models: Vec<String> = vec![
+        "gpt-4o".to_owned(),
+        "gpt-4-turbo".to_owned(),
+        "gpt-4-vision-preview".to_owned(),
+    ],
+    timezone: Tz = Tz::UTC,
+    default_locale: String = "en".to_owned(),
+    release_url: String =
+        "https://api.github.com/repos/IcyStudio/TeleGPT/releases/latest".to_owned(),
+    update_check_interval_hours: u64 = 24,
+    summary_max_tokens: u16 = 300,
+    title_after_messages: usize = 4,
+    digest_time: String = "09:00".to_owned(),
+    digest_top_limit: u32 = 5,
+    response_cache_ttl_minutes: u64 = 1440,
+    web_search_max_results: usize = 5,
+    group_message_cache_capacity: usize = 200,
+    group_message_cache_ttl_minutes: u64 = 1440,
+    tracing_service_name: String = "telegpt".to_owned(),
+    tracing_sample_ratio: f64 = 1.0,
 }
 
 define_defaults!(I18nStrings {
     api_error_prompt: String = "Hmm, something went wrong...".to_owned(),
     reset_prompt: String = "\u{26A0} Session is reset!".to_owned(),
     not_allowed_prompt: String = "Sadly, you are not allowed to use this bot currently.".to_owned(),
+    answer_ready_prompt: String = "your answer is ready.".to_owned(),
+    session_expired_prompt: String =
+        "The session has been idle for a while and is cleared.".to_owned(),
+    start_prompt: String = "Hi! Send me a message to start a conversation.".to_owned(),
+    rate_limited_prompt: String =
+        "You are sending messages too fast, please wait a moment and try again.".to_owned(),
+    moderation_blocked_prompt: String =
+        "\u{26D4} This content violates the usage policies and is blocked.".to_owned(),
+    moderation_warning_prompt: String =
+        "\u{26A0} This content may violate the usage policies.".to_owned(),
+    private_answer_prompt: String = "\u{1F4EC} Answered in the private chat.".to_owned(),
+    code_file_prompt: String = "\u{1F4CE} The code is attached as a file.".to_owned(),
+    queued_prompt: String = "Queued (#{position})...".to_owned(),
+    fallback_model_prompt: String =
+        "\u{1F501} Answered by {model}, since the model of this chat is unavailable.".to_owned(),
+    empty_answer_prompt: String =
+        "The model returned an empty answer, try rephrasing the question.".to_owned(),
+    truncated_prompt: String =
+        "\u{2026}(truncated, send /continue for more)".to_owned(),
+    help_prompt: String = "Here are the commands you can use:".to_owned(),
+    admin_help_prompt: String = "Commands for admins:".to_owned(),
+    command_descriptions: HashMap<String, String> = HashMap::new(),
 });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_i18n_locales() {
+        let i18n: I18n = serde_json::from_str(r#"{"resetPrompt":"Reset"}"#).unwrap();
+        assert_eq!(i18n.get(Some("zh"), "en").reset_prompt, "Reset");
+
+        let i18n: I18n = serde_json::from_str(
+            r#"{"en":{"resetPrompt":"Reset"},"zh":{"resetPrompt":"重置"},"pt-BR":{"resetPrompt":"Redefinir"}}"#,
+        )
+        .unwrap();
+        assert_eq!(i18n.get(Some("zh-hans"), "en").reset_prompt, "重置");
+        assert_eq!(i18n.get(Some("pt-br"), "en").reset_prompt, "Redefinir");
+        assert_eq!(i18n.get(Some("fr"), "en").reset_prompt, "Reset");
+        assert_eq!(i18n.get(None, "zh").reset_prompt, "重置");
+    }
+
+    #[test]
+    fn test_validate() {
+        let config: Config =
+            serde_json::from_str(r#"{"openaiAPIKey":"sk-xxx","botToken":"123:abc"}"#).unwrap();
+        assert!(config.validate().is_empty());
+
+        let config: Config =
+            serde_json::from_str(r#"{"botToken":"","temperature":3,"topP":0.5}"#).unwrap();
+        assert_eq!(
+            config.validate(),
+            vec![
+                "`botToken` is empty",
+                "Neither `openaiAPIKey` nor `openaiAPIKeys` is set",
+                "`temperature` must be between 0 and 2",
+            ]
+        );
+
+        let config: Config = serde_json::from_str(
+            r#"{"openaiAPIKey":"sk-xxx","botToken":"123:abc","parameterPresets":{"wild":{"topP":2}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.validate(),
+            vec!["`parameterPresets.wild.topP` must be between 0 and 1"]
+        );
+
+        let name = "x".repeat(PRESET_NAME_MAX_LEN + 1);
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "openaiAPIKey": "sk-xxx",
+            "botToken": "123:abc",
+            "parameterPresets": { name.clone(): {}, "x".repeat(PRESET_NAME_MAX_LEN): {} },
+        }))
+        .unwrap();
+        assert_eq!(
+            config.validate(),
+            vec![format!(
+                "The name of `parameterPresets.{}` must be 1 to 56 bytes long",
+                name
+            )]
+        );
+    }
+}
@@ -43,7 +43,6 @@ where
         updater(&mut *state)
     }
 
-    #[allow(dead_code)]
     pub fn end(&self) {
         if let Some(owner) = self.owner.upgrade() {
             owner.end_conversation(self.chat_id, self.user_id)
@@ -96,7 +95,6 @@ impl ConversationManager {
         }
     }
 
-    #[allow(dead_code)]
     pub fn start_conversation<S>(
         &self,
         chat_id: ChatId,
@@ -139,7 +137,6 @@ impl ConversationManager {
         });
     }
 
-    #[allow(dead_code)]
     pub fn end_conversation(&self, chat_id: ChatId, user_id: Option<UserId>) {
         self.with_mut_inner(|inner| {
             if let HashMapEntry::Occupied(mut chat_entry) = inner.chats.entry(chat_id) {
@@ -3,17 +3,173 @@
 use std::fmt::Debug;
 use std::mem::ManuallyDrop;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread::{Builder as ThreadBuilder, JoinHandle};
 
 use anyhow::Error;
-use rusqlite::Connection;
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rand::rand_bytes;
+use openssl::sha::sha256;
+use openssl::sign::Signer;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Transaction};
 use tokio::runtime::Handle;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio::sync::Notify;
+use tokio::sync::{Notify, Semaphore};
+
+/// The number of read-only connections for concurrent queries.
+const READ_POOL_SIZE: usize = 4;
+
+/// The prefix of the encrypted values, which tells them from the plain
+/// values written before the encryption is enabled.
+pub(crate) const ENCRYPTED_PREFIX: &str = "enc1:";
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// A step of the schema evolution.
+enum Migration {
+    Sql(&'static str),
+    /// Adds a column to a table. Columns that exist are skipped, since they
+    /// may be added before the migrations were introduced.
+    AddColumn {
+        table: &'static str,
+        column: &'static str,
+        definition: &'static str,
+    },
+}
+
+/// The migrations of the schema, the version of a database is the number of
+/// the migrations applied. Migrations must never be edited or reordered once
+/// released, append new ones instead.
+const MIGRATIONS: &[Migration] = &[
+    // The tables created before the migrations were introduced.
+    Migration::Sql(
+        "CREATE TABLE IF NOT EXISTS preferences (pref_key TEXT NOT NULL PRIMARY KEY, value TEXT);
+        CREATE TABLE IF NOT EXISTS members (username TEXT NOT NULL PRIMARY KEY, disabled INTEGER, created_at INTEGER NOT NULL);
+        CREATE TABLE IF NOT EXISTS api_key_spend (key_id TEXT NOT NULL, month TEXT NOT NULL, cost REAL NOT NULL, PRIMARY KEY (key_id, month));
+        CREATE TABLE IF NOT EXISTS token_usage (user_id TEXT NOT NULL, time INTEGER NOT NULL, tokens INTEGER NOT NULL, PRIMARY KEY (user_id, time));
+        CREATE TABLE IF NOT EXISTS model_usage (model TEXT NOT NULL, time INTEGER NOT NULL, tokens INTEGER NOT NULL, PRIMARY KEY (model, time));
+        CREATE TABLE IF NOT EXISTS request_log (chat_id TEXT NOT NULL, user_id TEXT NOT NULL, time INTEGER NOT NULL, tokens INTEGER NOT NULL, succeeded INTEGER NOT NULL);
+        CREATE INDEX IF NOT EXISTS request_log_chat_time ON request_log (chat_id, time);
+        CREATE TABLE IF NOT EXISTS moderation_log (chat_id TEXT NOT NULL, user_id TEXT NOT NULL, time INTEGER NOT NULL, is_output INTEGER NOT NULL, categories TEXT NOT NULL, blocked INTEGER NOT NULL);
+        CREATE TABLE IF NOT EXISTS quota_usage (user_id INTEGER NOT NULL, feature TEXT NOT NULL, day INTEGER NOT NULL, amount INTEGER NOT NULL, PRIMARY KEY (user_id, feature, day));
+        CREATE TABLE IF NOT EXISTS personas (name TEXT NOT NULL PRIMARY KEY, prompt TEXT NOT NULL, created_at INTEGER NOT NULL);",
+    ),
+    Migration::AddColumn {
+        table: "token_usage",
+        column: "prompt_tokens",
+        definition: "INTEGER NOT NULL DEFAULT 0",
+    },
+    Migration::AddColumn {
+        table: "token_usage",
+        column: "completion_tokens",
+        definition: "INTEGER NOT NULL DEFAULT 0",
+    },
+    Migration::AddColumn {
+        table: "token_usage",
+        column: "cost",
+        definition: "REAL NOT NULL DEFAULT 0",
+    },
+    Migration::Sql(
+        "CREATE TABLE IF NOT EXISTS response_cache (cache_key TEXT NOT NULL PRIMARY KEY, content TEXT NOT NULL, created_at INTEGER NOT NULL);",
+    ),
+    Migration::Sql(
+        "CREATE TABLE IF NOT EXISTS feedback (chat_id TEXT NOT NULL, message_id INTEGER NOT NULL, user_id TEXT NOT NULL, rating INTEGER NOT NULL, model TEXT NOT NULL, prompt_hash TEXT NOT NULL, time INTEGER NOT NULL, PRIMARY KEY (chat_id, message_id, user_id));",
+    ),
+    Migration::AddColumn {
+        table: "request_log",
+        column: "regenerated",
+        definition: "INTEGER NOT NULL DEFAULT 0",
+    },
+    Migration::AddColumn {
+        table: "members",
+        column: "role",
+        definition: "TEXT NOT NULL DEFAULT 'member'",
+    },
+    Migration::Sql(
+        "CREATE TABLE IF NOT EXISTS usage_monthly (user_id TEXT NOT NULL, month TEXT NOT NULL, tokens INTEGER NOT NULL, prompt_tokens INTEGER NOT NULL, completion_tokens INTEGER NOT NULL, cost REAL NOT NULL, PRIMARY KEY (user_id, month));",
+    ),
+    Migration::Sql(
+        "CREATE TABLE IF NOT EXISTS pending_messages (session_key TEXT NOT NULL PRIMARY KEY, content TEXT NOT NULL, created_at INTEGER NOT NULL);",
+    ),
+    // The cached answers were keyed by the whole requests, which hold the
+    // prompts.
+    Migration::Sql("DELETE FROM response_cache;"),
+    Migration::Sql(
+        "CREATE TABLE IF NOT EXISTS deleted_personas (name TEXT NOT NULL PRIMARY KEY, deleted_at INTEGER NOT NULL);",
+    ),
+    // The usernames of members, sealed like other sensitive columns, while
+    // `username` holds their lookup keys.
+    Migration::AddColumn {
+        table: "members",
+        column: "sealed_username",
+        definition: "TEXT",
+    },
+];
+
+impl Migration {
+    fn apply(&self, tx: &Transaction) -> Result<(), Error> {
+        match self {
+            Migration::Sql(sql) => tx.execute_batch(sql)?,
+            Migration::AddColumn {
+                table,
+                column,
+                definition,
+            } => {
+                let sql = format!(
+                    "SELECT COUNT(*) FROM pragma_table_info('{}') WHERE name = ?",
+                    table
+                );
+                let count: i64 = tx.query_row(&sql, (column,), |row| row.get(0))?;
+                if count == 0 {
+                    let sql = format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition);
+                    tx.execute(&sql, ())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Applies the pending migrations, each in its own transaction.
+fn migrate(conn: &mut Connection) -> Result<(), Error> {
+    let sql = "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL PRIMARY KEY, applied_at INTEGER NOT NULL);";
+    conn.execute(sql, ())?;
+    let sql = "SELECT MAX(version) FROM schema_version";
+    let version: Option<i64> = conn
+        .query_row(sql, (), |row| row.get(0))
+        .optional()?
+        .flatten();
+    let version = version.unwrap_or(0) as usize;
+    if version > MIGRATIONS.len() {
+        return Err(anyhow!(
+            "The database (version {}) is created by a newer version of TeleGPT",
+            version
+        ));
+    }
+
+    for (idx, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        migration.apply(&tx)?;
+        let sql = "INSERT INTO schema_version VALUES (?, strftime('%s', 'now'));";
+        tx.execute(sql, (idx as i64 + 1,))?;
+        tx.commit()?;
+        debug!("Database is migrated to version {}", idx + 1);
+    }
+    Ok(())
+}
 
 pub(crate) trait DatabaseProvider {
     fn provide_db(&self) -> Result<Connection, Error>;
+
+    /// Provides a read-only connection to the same database, or [`None`]
+    /// if the database can't be shared by connections.
+    fn provide_read_db(&self) -> Result<Option<Connection>, Error> {
+        Ok(None)
+    }
 }
 
 pub(crate) struct InMemDatabaseProvider;
@@ -43,12 +199,123 @@ impl FileDatabaseProvider {
 impl DatabaseProvider for FileDatabaseProvider {
     fn provide_db(&self) -> Result<Connection, Error> {
         let conn = Connection::open(&self.path)?;
+        // Readers don't block the writer (and vice versa) in WAL mode.
+        conn.query_row("PRAGMA journal_mode = WAL", (), |_| Ok(()))?;
         Ok(conn)
     }
+
+    fn provide_read_db(&self) -> Result<Option<Connection>, Error> {
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        let conn = Connection::open_with_flags(&self.path, flags)?;
+        Ok(Some(conn))
+    }
+}
+
+/// Encrypts the values of the sensitive columns with AES-256-GCM.
+pub(crate) struct FieldCipher {
+    key: [u8; 32],
+}
+
+impl FieldCipher {
+    /// Derives the key from the secret in config.
+    pub fn new(secret: &str) -> Self {
+        Self {
+            key: sha256(secret.as_bytes()),
+        }
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<String, Error> {
+        let mut nonce = [0; NONCE_LEN];
+        rand_bytes(&mut nonce)?;
+        let mut tag = [0; TAG_LEN];
+        let ciphertext = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.key,
+            Some(&nonce),
+            &[],
+            plaintext.as_bytes(),
+            &mut tag,
+        )?;
+        let data = [&nonce[..], &ciphertext, &tag].concat();
+        Ok(format!(
+            "{}{}",
+            ENCRYPTED_PREFIX,
+            BASE64_STANDARD.encode(data)
+        ))
+    }
+
+    /// Decrypts the value, plain values are returned as is.
+    pub fn decrypt(&self, value: String) -> Result<String, Error> {
+        let encoded = match value.strip_prefix(ENCRYPTED_PREFIX) {
+            Some(encoded) => encoded,
+            None => return Ok(value),
+        };
+        let data = BASE64_STANDARD.decode(encoded)?;
+        if data.len() < NONCE_LEN + TAG_LEN {
+            bail!("The encrypted value is truncated");
+        }
+        let (nonce, rest) = data.split_at(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+        let plaintext = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.key,
+            Some(nonce),
+            &[],
+            ciphertext,
+            tag,
+        )
+        .map_err(|_| anyhow!("Failed to decrypt the value, check `databaseEncryptionKey`"))?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+
+    /// Returns a keyed hash of the value, which can be looked up without
+    /// revealing the value.
+    pub fn hash(&self, value: &str) -> Result<String, Error> {
+        let pkey = PKey::hmac(&self.key)?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &pkey)?;
+        signer.update(value.as_bytes())?;
+        let digest = signer.sign_to_vec()?;
+        Ok(digest.iter().map(|byte| format!("{:02x}", byte)).collect())
+    }
+}
+
+/// A pool of read-only connections, each query takes one of them.
+struct ReadPool {
+    conns: Mutex<Vec<Connection>>,
+    semaphore: Semaphore,
+}
+
+impl ReadPool {
+    fn new(conns: Vec<Connection>) -> Self {
+        Self {
+            semaphore: Semaphore::new(conns.len()),
+            conns: Mutex::new(conns),
+        }
+    }
+
+    async fn query<F, R>(self: &Arc<Self>, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut Connection) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        // A connection is available as long as a permit is acquired.
+        let _permit = self.semaphore.acquire().await?;
+        let mut conn = self.conns.lock().unwrap().pop().unwrap();
+        let pool = Arc::clone(self);
+        tokio::task::spawn_blocking(move || {
+            let res = f(&mut conn);
+            pool.conns.lock().unwrap().push(conn);
+            res
+        })
+        .await
+        .map_err(|err| anyhow!(err.to_string()))
+    }
 }
 
 pub(crate) struct DatabaseManager {
     inner: Arc<DatabaseManagerInner>,
+    /// Encrypts the sensitive columns, [`None`] to store them as is.
+    cipher: Option<Arc<FieldCipher>>,
 }
 
 impl DatabaseManager {
@@ -56,7 +323,18 @@ impl DatabaseManager {
     where
         P: DatabaseProvider,
     {
-        let conn = provider.provide_db()?;
+        let mut conn = provider.provide_db()?;
+        migrate(&mut conn)?;
+        // The read-only connections are opened after the migrations, so
+        // that they see the latest schema.
+        let mut read_conns = vec![];
+        for _ in 0..READ_POOL_SIZE {
+            match provider.provide_read_db()? {
+                Some(read_conn) => read_conns.push(read_conn),
+                None => break,
+            }
+        }
+        let read_pool = (!read_conns.is_empty()).then(|| Arc::new(ReadPool::new(read_conns)));
         let (work_tx, work_rx) = channel(10);
         let shutdown_notify = Arc::new(Notify::new());
 
@@ -70,25 +348,96 @@ impl DatabaseManager {
                 join_handle,
                 work_tx,
                 shutdown_notify,
+                read_pool,
+                pending_works: Arc::new(AtomicUsize::new(0)),
             }),
+            cipher: None,
         })
     }
 
+    /// Encrypts the sensitive columns with the secret, if it's given.
+    pub fn with_encryption_key(mut self, secret: Option<&str>) -> Self {
+        self.cipher = secret.map(|secret| Arc::new(FieldCipher::new(secret)));
+        self
+    }
+
+    /// Whether `databaseEncryptionKey` is set.
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// Encrypts the value of a sensitive column, if the encryption is
+    /// enabled.
+    pub fn seal(&self, value: String) -> Result<String, Error> {
+        match &self.cipher {
+            Some(cipher) => cipher.encrypt(&value),
+            None => Ok(value),
+        }
+    }
+
+    /// Decrypts the value of a sensitive column. Plain values are returned
+    /// as is, since they may be written before the encryption is enabled.
+    pub fn unseal(&self, value: String) -> Result<String, Error> {
+        match &self.cipher {
+            Some(cipher) => cipher.decrypt(value),
+            None if value.starts_with(ENCRYPTED_PREFIX) => {
+                bail!("The value is encrypted, but `databaseEncryptionKey` is not set")
+            }
+            None => Ok(value),
+        }
+    }
+
+    /// Hashes a sensitive lookup key if the encryption is enabled, so that
+    /// the key is not stored as is.
+    pub fn lookup_key(&self, key: String) -> Result<String, Error> {
+        match &self.cipher {
+            Some(cipher) => cipher.hash(&key),
+            None => Ok(key),
+        }
+    }
+
     pub async fn enqueue_work<F>(&self, f: F) -> Result<(), Error>
     where
         F: FnOnce(&mut Connection) + Send + 'static,
     {
-        let work = AnyDatabaseThreadWork::new(f);
-        self.inner
-            .work_tx
-            .send(Box::new(work))
-            .await
-            .map_err(|err| anyhow!(err.to_string()))?;
+        let pending_works = Arc::clone(&self.inner.pending_works);
+        pending_works.fetch_add(1, Ordering::SeqCst);
+        let work = AnyDatabaseThreadWork::new(move |conn: &mut Connection| {
+            f(conn);
+            pending_works.fetch_sub(1, Ordering::SeqCst);
+        });
+        let res = self.inner.work_tx.send(Box::new(work)).await;
+        if let Err(err) = res {
+            self.inner.pending_works.fetch_sub(1, Ordering::SeqCst);
+            return Err(anyhow!(err.to_string()));
+        }
 
         Ok(())
     }
 
+    /// Runs a read-only query, concurrently with other queries if the
+    /// database supports it. Use [`write`](Self::write) for modifications,
+    /// which fail here.
+    ///
+    /// Queries are run after the pending works on the dedicated thread, so
+    /// that they always see the previous modifications.
+    #[tracing::instrument(name = "db.query", skip_all)]
     pub async fn query<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut Connection) -> R + Send + 'static,
+        R: Send + Debug + 'static,
+    {
+        match &self.inner.read_pool {
+            Some(read_pool) if self.inner.pending_works.load(Ordering::SeqCst) == 0 => {
+                read_pool.query(f).await
+            }
+            _ => self.write(f).await,
+        }
+    }
+
+    /// Runs a query on the dedicated thread, which serializes modifications.
+    #[tracing::instrument(name = "db.write", skip_all)]
+    pub async fn write<F, R>(&self, f: F) -> Result<R, Error>
     where
         F: FnOnce(&mut Connection) -> R + Send + 'static,
         R: Send + Debug + 'static,
@@ -108,6 +457,7 @@ impl Clone for DatabaseManager {
     fn clone(&self) -> Self {
         DatabaseManager {
             inner: Arc::clone(&self.inner),
+            cipher: self.cipher.clone(),
         }
     }
 }
@@ -116,6 +466,9 @@ struct DatabaseManagerInner {
     join_handle: ManuallyDrop<JoinHandle<()>>,
     work_tx: Sender<Box<dyn DatabaseThreadWork>>,
     shutdown_notify: Arc<Notify>,
+    read_pool: Option<Arc<ReadPool>>,
+    /// The number of works enqueued but not performed yet.
+    pending_works: Arc<AtomicUsize>,
 }
 
 impl Drop for DatabaseManagerInner {
@@ -227,3 +580,65 @@ where
         f(conn)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column_names(conn: &Connection, table: &str) -> Vec<String> {
+        let sql = format!("SELECT name FROM pragma_table_info('{}')", table);
+        let mut stmt = conn.prepare(&sql).unwrap();
+        let names = stmt.query_map((), |row| row.get(0)).unwrap();
+        names.map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn test_migrate() {
+        // A database created before the migrations were introduced.
+        let mut conn = Connection::open_in_memory().unwrap();
+        let sql = "CREATE TABLE token_usage (user_id TEXT NOT NULL, time INTEGER NOT NULL, tokens INTEGER NOT NULL, cost REAL NOT NULL DEFAULT 0, PRIMARY KEY (user_id, time));";
+        conn.execute(sql, ()).unwrap();
+
+        migrate(&mut conn).unwrap();
+        migrate(&mut conn).unwrap();
+        let columns = column_names(&conn, "token_usage");
+        assert_eq!(
+            columns,
+            [
+                "user_id",
+                "time",
+                "tokens",
+                "cost",
+                "prompt_tokens",
+                "completion_tokens"
+            ]
+        );
+        let version: i64 = conn
+            .query_row("SELECT MAX(version) FROM schema_version", (), |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(version as usize, MIGRATIONS.len());
+    }
+
+    #[test]
+    fn test_field_cipher() {
+        let cipher = FieldCipher::new("secret");
+        let encrypted = cipher.encrypt("{\"lang\":\"en\"}").unwrap();
+        assert!(encrypted.starts_with(ENCRYPTED_PREFIX));
+        assert_ne!(encrypted, cipher.encrypt("{\"lang\":\"en\"}").unwrap());
+        assert_eq!(
+            cipher.decrypt(encrypted.clone()).unwrap(),
+            "{\"lang\":\"en\"}"
+        );
+        assert!(FieldCipher::new("wrong").decrypt(encrypted).is_err());
+
+        // Values written before the encryption is enabled.
+        assert_eq!(cipher.decrypt("true".to_owned()).unwrap(), "true");
+        assert_eq!(cipher.hash("key").unwrap(), cipher.hash("key").unwrap());
+        assert_ne!(
+            cipher.hash("key").unwrap(),
+            FieldCipher::new("wrong").hash("key").unwrap()
+        );
+    }
+}
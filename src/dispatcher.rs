@@ -4,29 +4,74 @@ use std::sync::Arc;
 
 use anyhow::Error;
 use teloxide::prelude::*;
-use teloxide::types::{Me, MediaKind, MessageCommon, MessageEntityKind, MessageKind, User};
+use teloxide::types::{
+    Me, MediaKind, MessageCommon, MessageEntityKind, MessageKind, UpdateKind, User,
+};
 use tokio::sync::Mutex;
 
 use crate::{
+    config::SharedConfig,
     conversation::ConversationManager,
+    event_bus::{Event, EventBus},
     module_mgr::ModuleManager,
+    modules::{
+        admin::is_admin,
+        chat::{GroupMessageCache, GroupTrigger, GroupTriggerCache},
+        help::CommandList,
+    },
+    rate_limiter::{RateLimitResult, RateLimiter},
+    scheduler::Scheduler,
+    telemetry::trace_update,
     types::{HandlerResult, TeloxideDispatcher},
-    utils::{dptree_ext::command_filter, HandlerExt},
+    utils::{
+        auto_delete::schedule_deletion, dptree_ext::command_filter, i18n::user_language, HandlerExt,
+    },
 };
 
-fn can_respond_group_message(me: &User, msg: &Message) -> bool {
-    if let MessageKind::Common(MessageCommon {
-        media_kind: MediaKind::Text(ref media_text),
-        ..
-    }) = msg.kind
-    {
-        let text = media_text.text.as_str();
+fn can_respond_group_message(me: &User, msg: &Message, trigger: GroupTrigger) -> bool {
+    let text_and_entities = match msg.kind {
+        MessageKind::Common(MessageCommon {
+            media_kind: MediaKind::Text(ref media_text),
+            ..
+        }) => Some((media_text.text.as_str(), &media_text.entities)),
+        MessageKind::Common(MessageCommon {
+            media_kind: MediaKind::Photo(ref media_photo),
+            ..
+        }) => Some((
+            media_photo.caption.as_deref().unwrap_or_default(),
+            &media_photo.caption_entities,
+        )),
+        MessageKind::Common(MessageCommon {
+            media_kind: MediaKind::Voice(ref media_voice),
+            ..
+        }) => Some((
+            media_voice.caption.as_deref().unwrap_or_default(),
+            &media_voice.caption_entities,
+        )),
+        _ => None,
+    };
+
+    if let Some((text, entities)) = text_and_entities {
         // Command message:
         if text.starts_with('/') {
             return true;
         }
+        match trigger {
+            GroupTrigger::All => return true,
+            GroupTrigger::CommandOnly => return false,
+            GroupTrigger::MentionOnly => {}
+        }
+        // Reply to the bot's message:
+        if msg
+            .reply_to_message()
+            .and_then(|replied| replied.from())
+            .map(|user| user.id == me.id)
+            .unwrap_or(false)
+        {
+            return true;
+        }
         // Mention message:
-        if media_text.entities.iter().any(|ent| match &ent.kind {
+        if entities.iter().any(|ent| match &ent.kind {
             MessageEntityKind::Mention => {
                 let mention_username = &text[ent.offset..(ent.offset + ent.length)];
                 if mention_username.is_empty() {
@@ -46,7 +91,19 @@ fn can_respond_group_message(me: &User, msg: &Message) -> bool {
     false
 }
 
-async fn message_filter(me: Me, msg: Message) -> bool {
+async fn message_filter(
+    me: Me,
+    msg: Message,
+    event_bus: EventBus,
+    message_cache: GroupMessageCache,
+    group_triggers: GroupTriggerCache,
+) -> bool {
+    // Keep the group messages for summaries, even if they are not for the
+    // bot. Edited messages are already kept.
+    if msg.edit_date().is_none() {
+        message_cache.record(&msg);
+    }
+
     let from = msg
         .from()
         .map(|u| {
@@ -59,8 +116,14 @@ async fn message_filter(me: Me, msg: Message) -> bool {
         })
         .unwrap_or("<unknown>".to_owned());
 
-    if !msg.chat.is_private() && !can_respond_group_message(&me.user, &msg) {
-        return true;
+    if !msg.chat.is_private() {
+        let trigger = group_triggers.get(msg.chat.id).await.unwrap_or_else(|err| {
+            error!("Failed to get the group trigger: {}", err);
+            GroupTrigger::default()
+        });
+        if !can_respond_group_message(&me.user, &msg, trigger) {
+            return true;
+        }
     }
 
     if let Some(text) = msg.text() {
@@ -69,14 +132,86 @@ async fn message_filter(me: Me, msg: Message) -> bool {
         debug!("{} sent a message: {:#?}", from, msg.kind);
     }
 
+    event_bus.publish(Event::MessageReceived {
+        chat_id: msg.chat.id.to_string(),
+        user_id: msg.from().map(|u| u.id.0),
+        username: msg.from().and_then(|u| u.username.clone()),
+        text: msg
+            .text()
+            .or_else(|| msg.caption())
+            .unwrap_or_default()
+            .to_owned(),
+    });
+
     false
 }
 
+/// Rejects the message if the sender exceeds the rate limit.
+async fn rate_limit_filter(
+    bot: Bot,
+    msg: Message,
+    rate_limiter: RateLimiter,
+    config: SharedConfig,
+) -> bool {
+    let user = match msg.from() {
+        Some(user) => user,
+        None => return false,
+    };
+    if is_admin(user, &config) {
+        return false;
+    }
+
+    let username = user.username.as_deref().unwrap_or_default();
+    let override_limit = rate_limiter
+        .get_override(username)
+        .or_else(|| config.load().rate_limit_overrides.get(username).copied());
+    let limit = match override_limit.or(config.load().rate_limit_per_minute) {
+        Some(limit) => limit,
+        None => return false,
+    };
+    let group_id = (!msg.chat.is_private()).then_some(msg.chat.id.0);
+
+    match rate_limiter.check(user.id.0, group_id, limit, override_limit.is_some()) {
+        RateLimitResult::Allowed => false,
+        RateLimitResult::Limited { notify } => {
+            debug!("Message from {} is rate limited", user.id);
+            // Only the first rejected message is replied, to not reply to
+            // the spam with more spam.
+            if notify {
+                let res = bot
+                    .send_message(
+                        msg.chat.id,
+                        &config
+                            .load()
+                            .i18n_strings(user_language(&msg))
+                            .rate_limited_prompt,
+                    )
+                    .reply_to_message_id(msg.id)
+                    .await;
+                match res {
+                    Ok(sent_msg) => schedule_deletion(&bot, &sent_msg, &config),
+                    Err(err) => error!("Failed to send the rate limit notice: {}", err),
+                }
+            }
+            true
+        }
+    }
+}
+
 async fn default_handler(upd: Update) -> HandlerResult {
     warn!("Update ({}) is not handled!", upd.id);
     Ok(())
 }
 
+/// Updates from the same chat are handled in order, except callback queries,
+/// which may stop the answer being generated in the chat.
+fn distribute_update(upd: &Update) -> Option<ChatId> {
+    match &upd.kind {
+        UpdateKind::CallbackQuery(_) => None,
+        _ => upd.chat().map(|chat| chat.id),
+    }
+}
+
 pub(crate) async fn noop_handler() -> HandlerResult {
     Ok(())
 }
@@ -84,13 +219,20 @@ pub(crate) async fn noop_handler() -> HandlerResult {
 pub(crate) async fn build_dispatcher(
     bot: Bot,
     mut module_mgr: ModuleManager,
+    event_bus: EventBus,
 ) -> Result<TeloxideDispatcher, Error> {
     // Load dependencies.
     struct DependencyMapHolder {
         dep_map: Option<DependencyMap>,
     }
+    // The bot, event bus and scheduler are available to modules as
+    // dependencies.
+    let mut dep_map = DependencyMap::new();
+    dep_map.insert(bot.clone());
+    dep_map.insert(event_bus);
+    dep_map.insert(Scheduler::start());
     let dep_map_holder = Arc::new(Mutex::new(DependencyMapHolder {
-        dep_map: Some(DependencyMap::new()),
+        dep_map: Some(dep_map),
     }));
     module_mgr
         .with_all_modules_async(|m| {
@@ -109,6 +251,8 @@ pub(crate) async fn build_dispatcher(
     let conversation_mgr = ConversationManager::new();
     let conversation_handler = conversation_mgr.make_handler();
     dep_map.insert(conversation_mgr);
+    dep_map.insert(RateLimiter::default());
+    dep_map.insert(CommandList::collect(&mut module_mgr));
 
     // Build command handler chain.
     let mut command_handler = Some(Update::filter_message());
@@ -133,14 +277,30 @@ pub(crate) async fn build_dispatcher(
                 .filter_async(message_filter)
                 .endpoint(noop_handler),
         ) // Pre-handler and filter for message updates.
+        .branch(
+            Update::filter_message()
+                .filter_async(rate_limit_filter)
+                .endpoint(noop_handler),
+        ) // Rate limiter for message updates.
+        .branch(
+            Update::filter_edited_message()
+                .filter_async(message_filter)
+                .endpoint(noop_handler),
+        )
+        .branch(
+            Update::filter_edited_message()
+                .filter_async(rate_limit_filter)
+                .endpoint(noop_handler),
+        ) // Edited messages are answered again, so they are filtered too.
         .branch(conversation_handler) // Conversation handlers.
         .branch(command_handler.unwrap()) // Command handlers.
         .branch(biz_handler.unwrap()) // Core business handlers.
         .branch(dptree::endpoint(default_handler)) // Fallback handler.
         .post_chain(dptree::endpoint(noop_handler)); // For future extensions.
 
-    let dispatcher = Dispatcher::builder(bot, handler)
+    let dispatcher = Dispatcher::builder(bot, trace_update(handler))
         .dependencies(dep_map)
+        .distribution_function(distribute_update)
         .enable_ctrlc_handler()
         .build();
     Ok(dispatcher)
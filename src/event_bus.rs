@@ -0,0 +1,131 @@
+use std::future::Future;
+
+use tokio::sync::broadcast::{self, error::RecvError};
+
+const EVENT_BUS_CAPACITY: usize = 256;
+
+/// Events published by modules, which let other modules react to them
+/// without depending on each other directly.
+// Not all the events have subscribers yet.
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub(crate) enum Event {
+    /// A member is added by the admin.
+    MemberAdded { username: String },
+    /// A message that the bot may respond to is received.
+    MessageReceived {
+        chat_id: String,
+        user_id: Option<u64>,
+        username: Option<String>,
+        text: String,
+    },
+    /// A user who is not allowed to use the bot is rejected.
+    MemberDenied {
+        chat_id: String,
+        user_id: Option<u64>,
+        username: Option<String>,
+    },
+    /// A request to the chat model is started.
+    ChatStarted {
+        chat_id: String,
+        user_id: Option<u64>,
+        model: String,
+    },
+    /// A chat completion is finished.
+    ChatCompleted {
+        chat_id: String,
+        /// The forum topic that the answer is sent to.
+        topic_id: Option<i32>,
+        user_id: Option<u64>,
+        username: Option<String>,
+        model: String,
+        prompt_tokens: u32,
+        completion_tokens: u32,
+        /// The estimated cost in USD.
+        cost: f64,
+        /// `true` if the answer replaces a previous one of the same
+        /// question.
+        is_regeneration: bool,
+    },
+    /// A request to the model failed.
+    ModelErrored {
+        chat_id: String,
+        username: Option<String>,
+        model: String,
+        error: String,
+    },
+    /// A message or an answer is flagged by the moderation.
+    ContentFlagged {
+        chat_id: String,
+        username: Option<String>,
+        /// `true` if the answer of the model is flagged.
+        is_output: bool,
+        categories: Vec<String>,
+        blocked: bool,
+    },
+    /// The bot lacks the permission to send messages to the chat.
+    SendForbidden {
+        chat_id: String,
+        /// The forum topic that the answer fails to be sent to.
+        topic_id: Option<i32>,
+        /// The user whose message failed to be answered.
+        user_id: Option<u64>,
+        reason: String,
+    },
+    /// A user rates an answer with the feedback buttons.
+    FeedbackReceived {
+        chat_id: String,
+        message_id: i32,
+        user_id: u64,
+        /// `1` for thumbs up, `-1` for thumbs down.
+        rating: i8,
+        model: String,
+        prompt_hash: String,
+    },
+    /// The spend of an API key reaches its monthly budget.
+    QuotaExceeded {
+        masked_key: String,
+        spend: f64,
+        monthly_budget: f64,
+    },
+}
+
+/// A publish/subscribe event bus shared by all modules.
+#[derive(Clone)]
+pub(crate) struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+        Self { sender }
+    }
+
+    /// Publishes the event to the current subscribers.
+    pub fn publish(&self, event: Event) {
+        // It's fine that nobody is listening.
+        let _ = self.sender.send(event);
+    }
+
+    /// Spawns a task that calls the handler with each event published
+    /// after this call.
+    pub fn subscribe<F, Fut>(&self, mut handler: F)
+    where
+        F: FnMut(Event) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        let mut receiver = self.sender.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => handler(event).await,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Event subscriber lagged, {} events are skipped", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+}
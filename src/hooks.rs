@@ -0,0 +1,139 @@
+//! Events of the bot for library integrators.
+//!
+//! Register an async callback with [`AppBuilder::on_event`] to do custom
+//! logging, billing or analytics, without writing a [`Module`]:
+//!
+//! ```no_run
+//! # async fn example(config: telegpt_core::config::SharedConfig) {
+//! use telegpt_core::{app::AppBuilder, hooks::HookEvent};
+//!
+//! AppBuilder::new(config)
+//!     .on_event(|event| async move {
+//!         if let HookEvent::CompletionFinished { user_id, cost, .. } = event {
+//!             println!("{:?} spent ${:.4}", user_id, cost);
+//!         }
+//!     })
+//!     .run()
+//!     .await;
+//! # }
+//! ```
+//!
+//! Callbacks are called in the order of the events, and don't block the
+//! bot. Events are skipped if a callback falls too far behind.
+//!
+//! [`AppBuilder::on_event`]: crate::app::AppBuilder::on_event
+//! [`Module`]: crate::Module
+
+use crate::event_bus::Event;
+
+/// An event observed by the callbacks.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum HookEvent {
+    /// A message that the bot may respond to is received.
+    MessageReceived {
+        chat_id: String,
+        user_id: Option<u64>,
+        username: Option<String>,
+        /// The text or caption of the message.
+        text: String,
+    },
+    /// A request to the chat model is started.
+    CompletionStarted {
+        chat_id: String,
+        user_id: Option<u64>,
+        model: String,
+    },
+    /// A chat completion is finished.
+    CompletionFinished {
+        chat_id: String,
+        user_id: Option<u64>,
+        username: Option<String>,
+        model: String,
+        prompt_tokens: u32,
+        completion_tokens: u32,
+        /// The estimated cost in USD.
+        cost: f64,
+    },
+    /// A request to the chat model failed.
+    CompletionFailed {
+        chat_id: String,
+        username: Option<String>,
+        model: String,
+        error: String,
+    },
+    /// A user who is not allowed to use the bot is rejected.
+    MemberDenied {
+        chat_id: String,
+        user_id: Option<u64>,
+        username: Option<String>,
+    },
+}
+
+impl HookEvent {
+    /// Converts the internal event, `None` if it's not exposed.
+    pub(crate) fn from_event(event: Event) -> Option<Self> {
+        let event = match event {
+            Event::MessageReceived {
+                chat_id,
+                user_id,
+                username,
+                text,
+            } => Self::MessageReceived {
+                chat_id,
+                user_id,
+                username,
+                text,
+            },
+            Event::ChatStarted {
+                chat_id,
+                user_id,
+                model,
+            } => Self::CompletionStarted {
+                chat_id,
+                user_id,
+                model,
+            },
+            Event::ChatCompleted {
+                chat_id,
+                user_id,
+                username,
+                model,
+                prompt_tokens,
+                completion_tokens,
+                cost,
+                ..
+            } => Self::CompletionFinished {
+                chat_id,
+                user_id,
+                username,
+                model,
+                prompt_tokens,
+                completion_tokens,
+                cost,
+            },
+            Event::ModelErrored {
+                chat_id,
+                username,
+                model,
+                error,
+            } => Self::CompletionFailed {
+                chat_id,
+                username,
+                model,
+                error,
+            },
+            Event::MemberDenied {
+                chat_id,
+                user_id,
+                username,
+            } => Self::MemberDenied {
+                chat_id,
+                user_id,
+                username,
+            },
+            _ => return None,
+        };
+        Some(event)
+    }
+}
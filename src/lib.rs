@@ -15,15 +15,17 @@
 //! $ /path/to/telegpt -c your_config.json
 //! ```
 //!
-//! The configuration is described in [`config`] module.
+//! The configuration is described in [`config`] module. Members and stats can also be
+//! managed without starting the bot, see the [`cli`] module.
 //!
 //! ### Using via library
 //!
 //! TeleGPT can also be used as a library, therefore you can run it along with your code in
 //! the same process. Checkout the [`app`] module to learn more about it.
 //!
-//! *Note: Currently there are no APIs to let you interact with the bot, and it's a planned
-//! feature to implement soon.*
+//! Custom commands and handlers can be added by implementing the [`Module`] trait and
+//! registering it with [`app::AppBuilder::register_module`]. To observe what the bot does
+//! (e.g. for billing), register a callback with [`app::AppBuilder::on_event`] instead.
 //!
 //! ## Further Readings
 //!
@@ -37,11 +39,22 @@ extern crate anyhow;
 extern crate async_trait;
 
 pub mod app;
+pub mod cli;
 pub mod config;
 mod conversation;
 mod database;
 mod dispatcher;
+mod event_bus;
+pub mod hooks;
 mod module_mgr;
 mod modules;
+mod rate_limiter;
+mod scheduler;
+pub mod telemetry;
+#[cfg(feature = "test-harness")]
+pub mod testing;
 mod types;
 mod utils;
+
+pub use module_mgr::{Command, Module};
+pub use types::{HandlerResult, TeloxideHandler};
@@ -1,23 +1,120 @@
 #[macro_use]
 extern crate log;
 
-use std::fs;
-
 use anyhow::Error;
-use clap::Parser;
-use telegpt_core::{app, config::SharedConfig};
+use clap::{Parser, Subcommand};
+use telegpt_core::{app, cli::AdminTool, config::SharedConfig, telemetry::init_tracing};
+
+/// Reloads the config when receiving `SIGHUP`.
+#[cfg(unix)]
+fn watch_reload_signal(config: SharedConfig) {
+    use tokio::signal::unix::{signal, SignalKind};
 
-fn init_config(config_path: &str) -> Result<SharedConfig, Error> {
-    let config_buf = fs::read(config_path)?;
-    let config_json_str = String::from_utf8(config_buf)?;
-    let config = serde_json::from_str(&config_json_str)?;
-    Ok(SharedConfig::new(config))
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            error!("Failed to listen to SIGHUP: {}", err);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match config.reload() {
+                Ok(_) => info!("Config is reloaded"),
+                Err(err) => error!("Failed to reload config: {}", err),
+            }
+        }
+    });
 }
 
 #[derive(Parser)]
 struct Args {
-    #[arg(short = 'c', long = "config", default_value = "telegpt.config.json")]
+    #[arg(
+        short = 'c',
+        long = "config",
+        default_value = "telegpt.config.json",
+        global = true
+    )]
     pub config_path: String,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Serves the bot, which is the default.
+    Serve,
+    /// Manages the members.
+    #[command(subcommand)]
+    Member(MemberCommand),
+    /// Manages the stats.
+    #[command(subcommand)]
+    Stats(StatsCommand),
+    /// Manages the config.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Subcommand)]
+enum MemberCommand {
+    /// Adds a member by the username.
+    Add { username: String },
+    /// Removes a member by the username.
+    Remove { username: String },
+    /// Lists all the members.
+    List,
+}
+
+#[derive(Subcommand)]
+enum StatsCommand {
+    /// Prints the hourly token usage of each user, as JSON lines by default.
+    Export {
+        /// Prints as CSV instead.
+        #[arg(long)]
+        csv: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Checks the config for problems.
+    Validate,
+}
+
+/// Runs the administration command, and returns the exit code.
+async fn run_admin_command(config: SharedConfig, command: Command) -> Result<i32, Error> {
+    if let Command::Config(ConfigCommand::Validate) = command {
+        let problems = config.load().validate();
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        if problems.is_empty() {
+            println!("The config is valid");
+            return Ok(0);
+        }
+        return Ok(1);
+    }
+
+    let tool = AdminTool::open(config).await?;
+    match command {
+        Command::Member(MemberCommand::Add { username }) => {
+            tool.add_member(&username).await?;
+            println!("Added {}", username);
+        }
+        Command::Member(MemberCommand::Remove { username }) => {
+            if tool.remove_member(&username).await? {
+                println!("Removed {}", username);
+            } else {
+                println!("{} is not a member", username);
+                return Ok(1);
+            }
+        }
+        Command::Member(MemberCommand::List) => print!("{}", tool.list_members().await?),
+        Command::Stats(StatsCommand::Export { csv }) => print!("{}", tool.export_usage(csv).await?),
+        Command::Serve | Command::Config(_) => unreachable!(),
+    }
+    Ok(0)
 }
 
 #[tokio::main]
@@ -32,7 +129,7 @@ async fn main() {
     }
 
     let args = Args::parse();
-    let config = match init_config(&args.config_path) {
+    let config = match SharedConfig::from_file(&args.config_path) {
         Ok(config) => config,
         Err(err) => {
             error!("Failed to load config: {}", err);
@@ -40,6 +137,34 @@ async fn main() {
         }
     };
 
+    match args.command {
+        None | Some(Command::Serve) => {}
+        Some(command) => {
+            let code = match run_admin_command(config, command).await {
+                Ok(code) => code,
+                Err(err) => {
+                    error!("{}", err);
+                    1
+                }
+            };
+            std::process::exit(code);
+        }
+    }
+
+    #[cfg(unix)]
+    watch_reload_signal(config.clone());
+
+    let _tracing_guard = match &config.load().tracing {
+        Some(tracing_config) => match init_tracing(tracing_config) {
+            Ok(guard) => Some(guard),
+            Err(err) => {
+                error!("Failed to init tracing: {}", err);
+                None
+            }
+        },
+        None => None,
+    };
+
     app::run(config).await;
 
     info!("Bye");
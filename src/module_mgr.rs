@@ -1,43 +1,76 @@
-#![doc(hidden)]
-
 use std::future::Future;
 
 use anyhow::Error;
 use teloxide::prelude::*;
 
-use crate::types::TeloxideHandler;
+use crate::{modules::admin::MemberRole, types::TeloxideHandler};
 
+/// A bot command provided by a module.
 pub struct Command {
     pub command: String,
     pub description: String,
     pub handler: TeloxideHandler,
     pub is_hidden: bool,
+    pub is_admin_only: bool,
+    /// The lowest role listed with the command in the help, see
+    /// [`Command::moderator_only`].
+    pub(crate) role: MemberRole,
 }
 
 impl Command {
+    /// Creates a command, `command` is the name without the leading slash.
     pub fn new(command: &str, description: &str, handler: TeloxideHandler) -> Self {
         Self {
             command: command.to_owned(),
             description: description.to_owned(),
             handler,
             is_hidden: false,
+            is_admin_only: false,
+            role: MemberRole::Member,
         }
     }
 
+    /// Hides the command from the bot menu.
     pub fn hidden(mut self) -> Self {
         self.is_hidden = true;
         self
     }
+
+    /// Marks the command as an admin command, which is hidden from the bot
+    /// menu and only listed in the help of admins. The handler still needs
+    /// to check the sender.
+    pub fn admin_only(mut self) -> Self {
+        self.is_hidden = true;
+        self.is_admin_only = true;
+        self.role = MemberRole::Admin;
+        self
+    }
+
+    /// Marks the command as an admin command that moderators can run too,
+    /// which is also listed in the help of moderators.
+    pub(crate) fn moderator_only(mut self) -> Self {
+        self = self.admin_only();
+        self.role = MemberRole::Moderator;
+        self
+    }
 }
 
+/// A module extends the bot with dependencies, handlers and commands.
+///
+/// Register custom modules with [`AppBuilder::register_module`](crate::app::AppBuilder::register_module).
 #[async_trait]
 pub trait Module {
+    /// Inserts the dependencies provided by this module. Dependencies of
+    /// the built-in modules (e.g. [`SharedConfig`](crate::config::SharedConfig))
+    /// are available when this is called.
     async fn register_dependency(&mut self, dep_map: &mut DependencyMap) -> Result<(), Error>;
 
+    /// Returns the handler of the updates other than commands.
     fn filter_handler(&self) -> TeloxideHandler {
         dptree::entry()
     }
 
+    /// Returns the commands provided by this module.
     fn commands(&self) -> Vec<Command> {
         vec![]
     }
@@ -59,6 +92,10 @@ impl ModuleManager {
         self.modules.push(Box::new(module));
     }
 
+    pub fn register_boxed_module(&mut self, module: Box<dyn Module>) {
+        self.modules.push(module);
+    }
+
     pub fn with_all_modules<F>(&mut self, mut f: F)
     where
         F: FnMut(&mut dyn Module),
@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use teloxide::prelude::*;
+
+/// How long the administrators of a group are cached, so that changes in
+/// the group take effect within minutes without calling the API for each
+/// command.
+const GROUP_ADMINS_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// The administrators of a group, and when they are fetched.
+type CachedAdmins = (Instant, Vec<UserId>);
+
+/// Caches the administrators of groups, fetched with
+/// `getChatAdministrators`.
+#[derive(Clone, Default)]
+pub(crate) struct GroupAdminCache {
+    admins: Arc<Mutex<HashMap<ChatId, CachedAdmins>>>,
+}
+
+impl GroupAdminCache {
+    /// Returns `true` if the user is an administrator of the group. Users
+    /// are not administrators if the list can't be fetched.
+    pub async fn is_group_admin(&self, bot: &Bot, chat_id: ChatId, user_id: UserId) -> bool {
+        let cached = self
+            .admins
+            .lock()
+            .unwrap()
+            .get(&chat_id)
+            .filter(|(fetched_at, _)| fetched_at.elapsed() < GROUP_ADMINS_TTL)
+            .map(|(_, admins)| admins.contains(&user_id));
+        if let Some(is_admin) = cached {
+            return is_admin;
+        }
+
+        let admins: Vec<_> = match bot.get_chat_administrators(chat_id).await {
+            Ok(members) => members.into_iter().map(|member| member.user.id).collect(),
+            Err(err) => {
+                error!("Failed to get the administrators of {}: {}", chat_id, err);
+                return false;
+            }
+        };
+        let is_admin = admins.contains(&user_id);
+        self.admins
+            .lock()
+            .unwrap()
+            .insert(chat_id, (Instant::now(), admins));
+        is_admin
+    }
+}
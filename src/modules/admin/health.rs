@@ -0,0 +1,224 @@
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use serde_json::{json, Map, Value};
+use teloxide::prelude::*;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+
+use crate::{database::DatabaseManager, modules::openai::OpenAIClient};
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the checks are reused by the health endpoint, so that frequent
+/// requests don't call Telegram and OpenAI each time.
+const CHECK_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// The result of a dependency check.
+#[derive(Clone)]
+struct CheckResult {
+    name: &'static str,
+    latency: Duration,
+    error: Option<String>,
+}
+
+/// The results of the self-diagnostics.
+pub(crate) struct HealthReport {
+    uptime: Duration,
+    checks: Vec<CheckResult>,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.checks.iter().all(|check| check.error.is_none())
+    }
+
+    pub fn to_json(&self) -> Value {
+        let checks: Map<_, _> = self
+            .checks
+            .iter()
+            .map(|check| {
+                let value = json!({
+                    "ok": check.error.is_none(),
+                    "latencyMs": check.latency.as_millis() as u64,
+                    "error": check.error,
+                });
+                (check.name.to_owned(), value)
+            })
+            .collect();
+        json!({
+            "healthy": self.is_healthy(),
+            "uptimeSecs": self.uptime.as_secs(),
+            "checks": checks,
+        })
+    }
+
+    pub fn render_text(&self) -> Result<String, Error> {
+        let mut text = String::new();
+        let uptime_mins = self.uptime.as_secs() / 60;
+        writeln!(
+            &mut text,
+            "Status: {} (up {}h {}m)",
+            if self.is_healthy() {
+                "healthy"
+            } else {
+                "unhealthy"
+            },
+            uptime_mins / 60,
+            uptime_mins % 60
+        )?;
+        for check in &self.checks {
+            match &check.error {
+                None => writeln!(
+                    &mut text,
+                    "\u{2705} {}: {} ms",
+                    check.name,
+                    check.latency.as_millis()
+                )?,
+                Some(err) => writeln!(&mut text, "\u{274C} {}: {}", check.name, err)?,
+            }
+        }
+        Ok(text.trim_end().to_owned())
+    }
+}
+
+/// The checks and when they are done.
+type TimedChecks = (Instant, Vec<CheckResult>);
+
+/// Checks the services that the bot depends on.
+#[derive(Clone)]
+pub(crate) struct HealthChecker {
+    bot: Bot,
+    openai_client: OpenAIClient,
+    db_mgr: DatabaseManager,
+    started_at: Instant,
+    last_checks: Arc<Mutex<Option<TimedChecks>>>,
+}
+
+impl HealthChecker {
+    pub fn new(bot: Bot, openai_client: OpenAIClient, db_mgr: DatabaseManager) -> Self {
+        Self {
+            bot,
+            openai_client,
+            db_mgr,
+            started_at: Instant::now(),
+            last_checks: Default::default(),
+        }
+    }
+
+    pub async fn run(&self) -> HealthReport {
+        HealthReport {
+            uptime: self.started_at.elapsed(),
+            checks: self.check_all().await,
+        }
+    }
+
+    /// Like [`run`](Self::run), but reuses the checks done in the last
+    /// [`CHECK_CACHE_TTL`]. Concurrent calls wait for the same checks.
+    pub async fn run_cached(&self) -> HealthReport {
+        let mut last_checks = self.last_checks.lock().await;
+        let checks = match &*last_checks {
+            Some((checked_at, checks)) if checked_at.elapsed() < CHECK_CACHE_TTL => checks.clone(),
+            _ => {
+                let checks = self.check_all().await;
+                *last_checks = Some((Instant::now(), checks.clone()));
+                checks
+            }
+        };
+        HealthReport {
+            uptime: self.started_at.elapsed(),
+            checks,
+        }
+    }
+
+    async fn check_all(&self) -> Vec<CheckResult> {
+        let (telegram, openai, database) = tokio::join!(
+            check("telegram", async {
+                self.bot.get_me().await?;
+                Ok(())
+            }),
+            check("openai", self.openai_client.check_connectivity()),
+            // Goes through the database thread, which is blocked by slow
+            // works.
+            check("database", self.db_mgr.write(|_| ())),
+        );
+        vec![telegram, openai, database]
+    }
+}
+
+async fn check<F>(name: &'static str, fut: F) -> CheckResult
+where
+    F: Future<Output = Result<(), Error>>,
+{
+    let start = Instant::now();
+    let error = match tokio::time::timeout(CHECK_TIMEOUT, fut).await {
+        Ok(Ok(_)) => None,
+        Ok(Err(err)) => Some(err.to_string()),
+        Err(_) => Some("Timed out".to_owned()),
+    };
+    CheckResult {
+        name,
+        latency: start.elapsed(),
+        error,
+    }
+}
+
+/// Serves the health report as JSON over HTTP, with the status code 200 if
+/// the bot is healthy, or 503 otherwise.
+pub(crate) fn start_health_server(addr: String, checker: HealthChecker) {
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind(&addr).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!("Failed to serve the health check on {}: {}", addr, err);
+                return;
+            }
+        };
+        info!("Serving the health check on {}", addr);
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    warn!("Failed to accept the health check connection: {}", err);
+                    continue;
+                }
+            };
+            let checker = checker.clone();
+            tokio::spawn(async move {
+                if let Err(err) = serve_health_check(stream, &checker).await {
+                    debug!("Failed to serve the health check: {}", err);
+                }
+            });
+        }
+    });
+}
+
+async fn serve_health_check(mut stream: TcpStream, checker: &HealthChecker) -> Result<(), Error> {
+    // Only the request line matters, e.g. `GET /health HTTP/1.1`.
+    let mut buf = [0; 1024];
+    let len = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let (status, body) = if request.starts_with("GET ") {
+        let report = checker.run_cached().await;
+        let status = if report.is_healthy() {
+            "200 OK"
+        } else {
+            "503 Service Unavailable"
+        };
+        (status, report.to_json().to_string())
+    } else {
+        ("405 Method Not Allowed", String::new())
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
@@ -3,7 +3,11 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use anyhow::Error;
 use serde::{Deserialize, Serialize};
 
-use crate::{config::SharedConfig, database::DatabaseManager, modules::prefs::PreferencesManager};
+use crate::{
+    config::SharedConfig,
+    database::{DatabaseManager, ENCRYPTED_PREFIX},
+    modules::prefs::PreferencesManager,
+};
 
 const PUBLIC_USABLE_PREF_KEY: &str = "PublicUsable";
 
@@ -16,6 +20,61 @@ impl Default for PublicUsableValue {
     }
 }
 
+#[derive(Clone, Debug)]
+pub(crate) struct MemberInfo {
+    pub username: String,
+    pub disabled: bool,
+    pub created_at: i64,
+}
+
+/// A row of the members table, whose username may be sealed.
+#[derive(Debug)]
+struct MemberRow {
+    username: String,
+    sealed_username: Option<String>,
+    disabled: bool,
+    created_at: i64,
+}
+
+impl MemberRow {
+    const COLUMNS: &'static str = "username, sealed_username, disabled, created_at";
+
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            username: row.get(0)?,
+            sealed_username: row.get(1)?,
+            disabled: row.get::<_, Option<bool>>(2)?.unwrap_or(false),
+            created_at: row.get(3)?,
+        })
+    }
+
+    fn into_member_info(self, db_mgr: &DatabaseManager) -> Result<MemberInfo, Error> {
+        // The rows written before the usernames were sealed.
+        let username = match self.sealed_username {
+            Some(sealed_username) => db_mgr.unseal(sealed_username)?,
+            None => self.username,
+        };
+        Ok(MemberInfo {
+            username,
+            disabled: self.disabled,
+            created_at: self.created_at,
+        })
+    }
+}
+
+/// Seals the username for the `sealed_username` column, which is left
+/// empty until the encryption is enabled, so that the row is sealed and
+/// re-keyed by [`MemberManager::seal_usernames`] then.
+pub(crate) fn sealed_username(
+    db_mgr: &DatabaseManager,
+    username: &str,
+) -> Result<Option<String>, Error> {
+    if !db_mgr.is_encrypted() {
+        return Ok(None);
+    }
+    db_mgr.seal(username.to_owned()).map(Some)
+}
+
 #[derive(Clone)]
 pub(crate) struct MemberManager {
     db_mgr: DatabaseManager,
@@ -29,16 +88,7 @@ impl MemberManager {
         pref_mgr: PreferencesManager,
         config: SharedConfig,
     ) -> Result<Self, Error> {
-        // Initialize the database table before returning.
-        let ok = db_mgr.query(|conn| {
-            let sql = "CREATE TABLE IF NOT EXISTS members (username TEXT NOT NULL PRIMARY KEY, disabled INTEGER, created_at INTEGER NOT NULL);";
-            conn.execute(sql, ()).unwrap();
-            true
-        }).await?;
-        if !ok {
-            return Err(anyhow!("Failed to initialize database table"));
-        }
-
+        Self::seal_usernames(&db_mgr).await?;
         Ok(Self {
             db_mgr,
             pref_mgr,
@@ -46,18 +96,65 @@ impl MemberManager {
         })
     }
 
+    /// Seals the usernames that are stored as is, and keys their rows by
+    /// the lookup keys, see [`DatabaseManager::lookup_key`]. It's done once
+    /// the encryption is enabled, the rows added before are keyed by the
+    /// usernames themselves.
+    async fn seal_usernames(db_mgr: &DatabaseManager) -> Result<(), Error> {
+        if !db_mgr.is_encrypted() {
+            return Ok(());
+        }
+
+        let usernames = db_mgr
+            .query(|conn| {
+                let sql = format!(
+                    "SELECT username FROM members WHERE sealed_username IS NULL OR sealed_username NOT LIKE '{}%'",
+                    ENCRYPTED_PREFIX
+                );
+                let mut stmt = conn.prepare(&sql)?;
+                let rows = stmt.query_map((), |row| row.get::<_, String>(0))?;
+                rows.collect::<Result<Vec<_>, _>>()
+            })
+            .await??;
+        if usernames.is_empty() {
+            return Ok(());
+        }
+
+        let rows = usernames
+            .into_iter()
+            .map(|username| {
+                let key = db_mgr.lookup_key(username.clone())?;
+                let sealed_username = db_mgr.seal(username.clone())?;
+                Ok((username, key, sealed_username))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        db_mgr
+            .write(move |conn| {
+                let tx = conn.transaction()?;
+                for (username, key, sealed_username) in rows {
+                    let sql = "UPDATE OR IGNORE members SET username = ?, sealed_username = ? WHERE username = ?";
+                    tx.execute(sql, (key, sealed_username, username))?;
+                }
+                tx.commit()
+            })
+            .await??;
+        Ok(())
+    }
+
     pub async fn add_member(&self, username: String) -> Result<bool, Error> {
         let unix_timestamp_secs = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs();
+        let key = self.db_mgr.lookup_key(username.clone())?;
+        let sealed_username = sealed_username(&self.db_mgr, &username)?;
         let result = self
             .db_mgr
-            .query(move |conn| {
-                let sql = "INSERT OR IGNORE INTO members VALUES (?, 0, ?);";
+            .write(move |conn| {
+                let sql = "INSERT OR IGNORE INTO members (username, sealed_username, disabled, created_at) VALUES (?, ?, 0, ?);";
                 let mut stmt = conn.prepare(sql).unwrap();
 
-                match stmt.execute((&username, unix_timestamp_secs)) {
+                match stmt.execute((&key, &sealed_username, unix_timestamp_secs)) {
                     Ok(1) => {
                         info!("User \"{}\" is added", username);
                     }
@@ -78,13 +175,14 @@ impl MemberManager {
     }
 
     pub async fn delete_member(&self, username: String) -> Result<bool, Error> {
+        let key = self.db_mgr.lookup_key(username.clone())?;
         let result = self
             .db_mgr
-            .query(move |conn| {
+            .write(move |conn| {
                 let sql = "DELETE FROM members WHERE username = ?";
                 let mut stmt = conn.prepare(sql).unwrap();
 
-                match stmt.execute((&username,)) {
+                match stmt.execute((&key,)) {
                     Ok(1) => {
                         info!("User \"{}\" is deleted", username);
                         return true;
@@ -104,32 +202,134 @@ impl MemberManager {
         Ok(result)
     }
 
-    pub async fn is_member_allowed(&self, username: String) -> Result<bool, Error> {
-        let public_usable: PublicUsableValue =
-            self.pref_mgr.get_value(PUBLIC_USABLE_PREF_KEY).await?;
-        if public_usable.0 {
-            return Ok(true);
+    pub async fn set_member_disabled(
+        &self,
+        username: String,
+        disabled: bool,
+    ) -> Result<bool, Error> {
+        let key = self.db_mgr.lookup_key(username.clone())?;
+        let result = self
+            .db_mgr
+            .write(move |conn| {
+                let sql = "UPDATE members SET disabled = ? WHERE username = ?";
+                let mut stmt = conn.prepare(sql).unwrap();
+
+                match stmt.execute((disabled, &key)) {
+                    Ok(1) => {
+                        info!(
+                            "User \"{}\" is {}",
+                            username,
+                            if disabled { "disabled" } else { "enabled" }
+                        );
+                        return true;
+                    }
+                    Ok(_) => {
+                        warn!("User \"{}\" is not found", username);
+                    }
+                    Err(err) => {
+                        error!("Failed to update row: {}", err);
+                    }
+                }
+
+                false
+            })
+            .await?;
+
+        Ok(result)
+    }
+
+    pub async fn count_members(&self) -> Result<u64, Error> {
+        let result = self
+            .db_mgr
+            .query(|conn| {
+                let sql = "SELECT COUNT(*) FROM members";
+                conn.query_row(sql, (), |row| row.get(0))
+                    .map_err(|err| anyhow!(err))
+            })
+            .await??;
+
+        Ok(result)
+    }
+
+    pub async fn list_members(&self, offset: u64, limit: u64) -> Result<Vec<MemberInfo>, Error> {
+        let rows = self
+            .db_mgr
+            .query(move |conn| {
+                let sql = format!(
+                    "SELECT {} FROM members ORDER BY created_at, username LIMIT ? OFFSET ?",
+                    MemberRow::COLUMNS
+                );
+                let mut stmt = conn.prepare(&sql)?;
+                let rows = stmt.query_map((limit, offset), MemberRow::from_row)?;
+                rows.collect::<Result<Vec<_>, _>>()
+                    .map_err(|err| anyhow!(err))
+            })
+            .await??;
+
+        rows.into_iter()
+            .map(|row| row.into_member_info(&self.db_mgr))
+            .collect()
+    }
+
+    /// Returns the members whose usernames start with the keyword. The
+    /// usernames may be sealed, so they are matched after being read.
+    pub async fn search_members(
+        &self,
+        keyword: String,
+        limit: u64,
+    ) -> Result<Vec<MemberInfo>, Error> {
+        let rows = self
+            .db_mgr
+            .query(move |conn| {
+                let sql = format!("SELECT {} FROM members", MemberRow::COLUMNS);
+                let mut stmt = conn.prepare(&sql)?;
+                let rows = stmt.query_map((), MemberRow::from_row)?;
+                rows.collect::<Result<Vec<_>, _>>()
+                    .map_err(|err| anyhow!(err))
+            })
+            .await??;
+
+        let mut members = vec![];
+        for row in rows {
+            let member = row.into_member_info(&self.db_mgr)?;
+            if member.username.starts_with(&keyword) {
+                members.push(member);
+            }
         }
+        members.sort_by(|a, b| a.username.cmp(&b.username));
+        members.truncate(limit as usize);
+        Ok(members)
+    }
 
-        if self.config.admin_usernames.contains(&username) {
+    pub async fn is_member_allowed(&self, username: String) -> Result<bool, Error> {
+        if self.config.load().admin_usernames.contains(&username) {
             return Ok(true);
         }
 
-        let result = self
+        // `None` if the user is not a member.
+        let key = self.db_mgr.lookup_key(username)?;
+        let disabled = self
             .db_mgr
             .query(move |conn| {
                 let sql = "SELECT username, disabled FROM members WHERE username = ?";
-                let disabled_result: Result<bool, _> =
-                    conn.query_row(sql, (&username,), |row| row.get(1));
-
-                match disabled_result {
-                    Ok(disabled) => !disabled,
-                    Err(_) => false,
-                }
+                conn.query_row(sql, (&key,), |row| row.get::<_, Option<bool>>(1))
+                    .ok()
+                    .map(|disabled| disabled.unwrap_or(false))
             })
             .await?;
 
-        Ok(result)
+        // Disabled members are not allowed even if the bot is public.
+        if disabled == Some(true) {
+            return Ok(false);
+        }
+
+        let public_usable: PublicUsableValue =
+            self.pref_mgr.get_value(PUBLIC_USABLE_PREF_KEY).await?;
+        if public_usable.0 {
+            return Ok(true);
+        }
+
+        Ok(disabled.is_some())
     }
 
     pub async fn set_public_usable(&self, public_usable: bool) -> Result<(), Error> {
@@ -139,3 +339,120 @@ impl MemberManager {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::InMemDatabaseProvider;
+    use crate::modules::admin::{MemberRole, RoleManager};
+
+    #[tokio::test]
+    async fn test_sealed_usernames() {
+        let config = SharedConfig::new(serde_json::from_str(r#"{"botToken": ""}"#).unwrap());
+        let db_mgr = DatabaseManager::with_db_provider(InMemDatabaseProvider)
+            .unwrap()
+            .with_encryption_key(Some("secret"));
+        // A member added before the usernames were sealed.
+        db_mgr
+            .write(|conn| {
+                let sql =
+                    "INSERT INTO members (username, disabled, created_at) VALUES ('alice', 0, 1)";
+                conn.execute(sql, ())
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        let prefs_mgr = PreferencesManager::with_db_manager(db_mgr.clone())
+            .await
+            .unwrap();
+        let member_mgr = MemberManager::new(db_mgr.clone(), prefs_mgr, config)
+            .await
+            .unwrap();
+        member_mgr.set_public_usable(false).await.unwrap();
+        assert!(member_mgr.add_member("bob".to_owned()).await.unwrap());
+
+        let usernames: Vec<String> = db_mgr
+            .query(|conn| {
+                let sql = "SELECT username || sealed_username FROM members";
+                let mut stmt = conn.prepare(sql).unwrap();
+                let rows = stmt.query_map((), |row| row.get(0)).unwrap();
+                rows.collect::<Result<Vec<_>, _>>().unwrap()
+            })
+            .await
+            .unwrap();
+        assert_eq!(usernames.len(), 2);
+        assert!(usernames
+            .iter()
+            .all(|username| !username.contains("alice") && !username.contains("bob")));
+
+        let members = member_mgr.list_members(0, 10).await.unwrap();
+        let usernames: Vec<_> = members.iter().map(|m| m.username.as_str()).collect();
+        assert_eq!(usernames, ["alice", "bob"]);
+        let members = member_mgr.search_members("b".to_owned(), 10).await.unwrap();
+        assert_eq!(members.len(), 1);
+        assert!(member_mgr
+            .is_member_allowed("alice".to_owned())
+            .await
+            .unwrap());
+        assert!(!member_mgr
+            .is_member_allowed("carol".to_owned())
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_members_added_before_encryption() {
+        let config = SharedConfig::new(serde_json::from_str(r#"{"botToken": ""}"#).unwrap());
+        let db_mgr = DatabaseManager::with_db_provider(InMemDatabaseProvider).unwrap();
+        let prefs_mgr = PreferencesManager::with_db_manager(db_mgr.clone())
+            .await
+            .unwrap();
+        let member_mgr = MemberManager::new(db_mgr.clone(), prefs_mgr, config.clone())
+            .await
+            .unwrap();
+        member_mgr.set_public_usable(false).await.unwrap();
+        assert!(member_mgr.add_member("alice".to_owned()).await.unwrap());
+        RoleManager::new(db_mgr.clone(), config.clone())
+            .set_role("bob".to_owned(), MemberRole::Moderator)
+            .await
+            .unwrap();
+
+        // Restarts with `databaseEncryptionKey` set.
+        let db_mgr = db_mgr.with_encryption_key(Some("secret"));
+        let prefs_mgr = PreferencesManager::with_db_manager(db_mgr.clone())
+            .await
+            .unwrap();
+        let member_mgr = MemberManager::new(db_mgr.clone(), prefs_mgr, config.clone())
+            .await
+            .unwrap();
+        let role_mgr = RoleManager::new(db_mgr.clone(), config);
+
+        let sealed_usernames: Vec<String> = db_mgr
+            .query(|conn| {
+                let sql = "SELECT sealed_username FROM members";
+                let mut stmt = conn.prepare(sql).unwrap();
+                let rows = stmt.query_map((), |row| row.get(0)).unwrap();
+                rows.collect::<Result<Vec<_>, _>>().unwrap()
+            })
+            .await
+            .unwrap();
+        assert_eq!(sealed_usernames.len(), 2);
+        assert!(sealed_usernames
+            .iter()
+            .all(|username| username.starts_with(ENCRYPTED_PREFIX)));
+
+        assert!(member_mgr
+            .is_member_allowed("alice".to_owned())
+            .await
+            .unwrap());
+        assert!(member_mgr
+            .is_member_allowed("bob".to_owned())
+            .await
+            .unwrap());
+        assert_eq!(
+            role_mgr.role_of_username("bob".to_owned()).await.unwrap(),
+            MemberRole::Moderator
+        );
+    }
+}
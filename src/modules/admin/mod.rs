@@ -1,20 +1,46 @@
+mod group_admins;
+mod health;
 mod member_mgr;
+mod role_mgr;
+mod update_checker;
 
+use std::fmt::Write;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::Error;
+use async_openai::types::{ChatCompletionRequestMessageArgs, Role};
+use chrono::{TimeZone, Utc};
+use futures::StreamExt;
+use teloxide::dispatching::DpHandlerDescription;
 use teloxide::dptree::di::DependencySupplier;
 use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, User};
 
 use crate::{
     config::SharedConfig,
     database::DatabaseManager,
+    dispatcher::noop_handler,
+    event_bus::{Event, EventBus},
     module_mgr::{Command, Module},
+    modules::chat::{
+        is_valid_persona_name, DegradedChats, GroupTrigger, GroupTriggerCache, PersonaManager,
+        PRIVATE_ANSWERS_PREF_KEY,
+    },
+    modules::openai::{ChatModelParams, ChatModelResult, CompletionScheduler, OpenAIClient},
     modules::prefs::PreferencesManager,
-    types::HandlerResult,
-    utils::dptree_ext::CommandArgs,
+    modules::stats::{ChatReport, FeedbackReport, ModerationReport, StatsManager},
+    rate_limiter::RateLimiter,
+    types::{HandlerResult, TeloxideHandler},
+    utils::{
+        dptree_ext::{command_with_args, next_word, ArgsError, CommandArg, CommandArgs, Rest},
+        sender::RetryExt,
+    },
 };
+use group_admins::GroupAdminCache;
+use health::HealthChecker;
 pub(crate) use member_mgr::MemberManager;
+pub(crate) use role_mgr::{MemberRole, RoleManager};
 
 pub(crate) struct Admin {
     db_mgr: DatabaseManager,
@@ -26,31 +52,174 @@ impl Admin {
     }
 }
 
-fn check_admin(msg: &Message, config: &SharedConfig) -> bool {
-    if let Some(user) = msg.from() {
-        if let Some(username) = &user.username {
-            return config.admin_usernames.contains(username);
-        }
+pub(crate) fn is_admin(user: &User, config: &SharedConfig) -> bool {
+    if let Some(username) = &user.username {
+        return config.load().admin_usernames.contains(username);
     }
     false
 }
 
-macro_rules! check_admin {
-    ($bot:expr, $msg:expr, $conf:expr) => {
-        if !check_admin(&$msg, &$conf) {
-            let _ = $bot
-                .send_message(
+/// Checks if the sender has the role (or a higher one), see
+/// [`RoleManager::role_of`].
+pub(crate) async fn check_role(
+    msg: &Message,
+    role_mgr: &RoleManager,
+    required: MemberRole,
+) -> bool {
+    let user = match msg.from() {
+        Some(user) => user,
+        None => return false,
+    };
+    let role = role_mgr.role_of(user).await.unwrap_or_else(|err| {
+        error!("Failed to get the role of {}: {}", user.id, err);
+        MemberRole::default()
+    });
+    role >= required
+}
+
+/// Checks if the sender has the role (or a higher one) for the settings
+/// of this chat, see [`RoleManager::role_in_chat`].
+async fn check_chat_role(
+    bot: &Bot,
+    msg: &Message,
+    role_mgr: &RoleManager,
+    required: MemberRole,
+) -> bool {
+    role_mgr.role_in_chat(bot, msg).await >= required
+}
+
+/// Returns `true` if the sender can ban, delete or set the role of the user,
+/// who must have a lower role than the sender. The users in
+/// `adminUsernames` can manage everyone else, and can't be managed.
+async fn can_manage_user(
+    msg: &Message,
+    username: &str,
+    role_mgr: &RoleManager,
+    config: &SharedConfig,
+) -> Result<bool, Error> {
+    let sender = match msg.from() {
+        Some(sender) => sender,
+        None => return Ok(false),
+    };
+    if config.load().admin_usernames.iter().any(|u| u == username) {
+        return Ok(false);
+    }
+    if is_admin(sender, config) {
+        return Ok(true);
+    }
+    let sender_role = role_mgr.role_of(sender).await?;
+    let target_role = role_mgr.role_of_username(username.to_owned()).await?;
+    Ok(sender_role.can_manage(target_role))
+}
+
+/// The username of a member, with or without the leading `@`.
+#[derive(Clone, Debug)]
+struct Username(String);
+
+impl CommandArg for Username {
+    fn placeholder() -> String {
+        "<username>".to_owned()
+    }
+
+    fn parse(input: &mut &str) -> Result<Self, ArgsError> {
+        let word = next_word::<Self>(input)?;
+        Ok(Self(word.trim_start_matches('@').to_owned()))
+    }
+}
+
+/// A rate limit per minute (0 for unlimited), or `default` for [`None`].
+#[derive(Clone, Debug)]
+struct RateLimitArg(Option<u32>);
+
+impl CommandArg for RateLimitArg {
+    fn placeholder() -> String {
+        "<limit|default>".to_owned()
+    }
+
+    fn parse(input: &mut &str) -> Result<Self, ArgsError> {
+        match next_word::<Self>(input)? {
+            "default" => Ok(Self(None)),
+            word => word.parse().map(|limit| Self(Some(limit))).map_err(|_| {
+                ArgsError(format!(
+                    "Invalid limit \"{}\", use messages per minute (0 for unlimited) or \"default\"",
+                    word
+                ))
+            }),
+        }
+    }
+}
+
+/// Replies that the sender doesn't have the right to execute the command.
+async fn reject_admin_command(bot: &Bot, msg: &Message) {
+    let _ = bot
+        .send_message(
+            msg.chat.id,
+            "You don't have the right to execute admin commands!",
+        )
+        .send_retrying()
+        .await;
+    warn!(
+        "Non-admin user \"{}\" tried to execute admin commands",
+        msg.from()
+            .and_then(|u| u.username.clone())
+            .unwrap_or("<unknown>".to_owned())
+    );
+}
+
+/// Runs the handler of the command if the sender has the role (or a higher
+/// one), see [`check_role`]. The arguments are parsed after the check, so
+/// that the usages of admin commands are not replied to others.
+fn with_role(role: MemberRole, handler: TeloxideHandler) -> TeloxideHandler {
+    let check = move |bot: Bot, msg: Message, role_mgr: RoleManager| async move {
+        let allowed = check_role(&msg, &role_mgr, role).await;
+        if !allowed {
+            reject_admin_command(&bot, &msg).await;
+        }
+        !allowed
+    };
+    dptree::entry()
+        .branch(dptree::filter_async(check).endpoint(noop_handler))
+        .branch(handler)
+}
+
+/// Like [`with_role`], but group administrators count as admins for the
+/// settings of the group, see [`check_chat_role`].
+fn with_chat_role(role: MemberRole, handler: TeloxideHandler) -> TeloxideHandler {
+    let check = move |bot: Bot, msg: Message, role_mgr: RoleManager| async move {
+        let allowed = check_chat_role(&bot, &msg, &role_mgr, role).await;
+        if !allowed {
+            reject_admin_command(&bot, &msg).await;
+        }
+        !allowed
+    };
+    dptree::entry()
+        .branch(dptree::filter_async(check).endpoint(noop_handler))
+        .branch(handler)
+}
+
+macro_rules! check_manage {
+    ($bot:expr, $msg:expr, $username:expr, $role_mgr:expr, $config:expr) => {
+        match can_manage_user(&$msg, &$username, &$role_mgr, &$config).await {
+            Ok(true) => {}
+            Ok(false) => {
+                $bot.send_message(
                     $msg.chat.id,
-                    "You don't have the right to execute admin commands!",
+                    "You can only manage the users whose roles are lower than yours",
                 )
-                .await;
-            warn!(
-                "Non-admin user \"{}\" tried to execute admin commands",
-                $msg.from()
-                    .and_then(|u| u.username.clone())
-                    .unwrap_or("<unknown>".to_owned())
-            );
-            return Ok(());
+                .send_retrying()
+                .await?;
+                return Ok(());
+            }
+            Err(err) => {
+                error!("Failed to check the role of \"{}\": {}", $username, err);
+                $bot.send_message(
+                    $msg.chat.id,
+                    "Failed to check the role, internal error occurred",
+                )
+                .send_retrying()
+                .await?;
+                return Ok(());
+            }
         }
     };
 }
@@ -58,28 +227,13 @@ macro_rules! check_admin {
 async fn set_public(
     bot: Bot,
     msg: Message,
-    args: CommandArgs,
+    (value,): (bool,),
     member_mgr: MemberManager,
-    config: SharedConfig,
 ) -> HandlerResult {
-    check_admin!(bot, msg, config);
-
-    let value = match args.0.as_str() {
-        "yes" | "on" | "true" | "1" => true,
-        "no" | "off" | "false" | "0" => false,
-        _ => {
-            bot.send_message(
-                msg.chat.id,
-                "Invalid value, possible values are \"yes\", \"no\"",
-            )
-            .await?;
-            return Ok(());
-        }
-    };
-
     match member_mgr.set_public_usable(value).await {
         Ok(_) => {
             bot.send_message(msg.chat.id, format!("Success, current status: {}", value))
+                .send_retrying()
                 .await?;
         }
         Err(err) => {
@@ -88,6 +242,7 @@ async fn set_public(
                 msg.chat.id,
                 "Failed to set public usability, internal error occurred",
             )
+            .send_retrying()
             .await?;
         }
     }
@@ -95,23 +250,56 @@ async fn set_public(
     Ok(())
 }
 
-async fn add_member(
+async fn set_role(
     bot: Bot,
     msg: Message,
-    args: CommandArgs,
-    member_mgr: MemberManager,
+    (Username(username), role): (Username, MemberRole),
+    role_mgr: RoleManager,
     config: SharedConfig,
 ) -> HandlerResult {
-    check_admin!(bot, msg, config);
-
-    let username = args.0;
-    if username.is_empty() || username.contains(' ') {
-        bot.send_message(msg.chat.id, "Invalid username").await?;
+    if config.load().admin_usernames.contains(&username) {
+        bot.send_message(
+            msg.chat.id,
+            "The users in adminUsernames are always admins, change the config instead",
+        )
+        .send_retrying()
+        .await?;
         return Ok(());
     }
+    check_manage!(bot, msg, username, role_mgr, config);
 
-    match member_mgr.add_member(username).await {
+    match role_mgr.set_role(username.clone(), role).await {
+        Ok(_) => {
+            bot.send_message(
+                msg.chat.id,
+                format!("Success, \"{}\" is now a {}", username, role.name()),
+            )
+            .send_retrying()
+            .await?;
+        }
+        Err(err) => {
+            error!("Failed to set role: {}", err);
+            bot.send_message(msg.chat.id, "Failed to set role, internal error occurred")
+                .send_retrying()
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn add_member(
+    bot: Bot,
+    msg: Message,
+    (Username(username),): (Username,),
+    member_mgr: MemberManager,
+    event_bus: EventBus,
+) -> HandlerResult {
+    match member_mgr.add_member(username.clone()).await {
         Ok(value) => {
+            if value {
+                event_bus.publish(Event::MemberAdded { username });
+            }
             bot.send_message(
                 msg.chat.id,
                 if value {
@@ -120,11 +308,13 @@ async fn add_member(
                     "Failed to add member, maybe it's already added"
                 },
             )
+            .send_retrying()
             .await?;
         }
         Err(err) => {
             error!("Failed to add member: {}", err);
             bot.send_message(msg.chat.id, "Failed to add member, internal error occurred")
+                .send_retrying()
                 .await?;
         }
     }
@@ -135,17 +325,12 @@ async fn add_member(
 async fn delete_member(
     bot: Bot,
     msg: Message,
-    args: CommandArgs,
+    (Username(username),): (Username,),
     member_mgr: MemberManager,
+    role_mgr: RoleManager,
     config: SharedConfig,
 ) -> HandlerResult {
-    check_admin!(bot, msg, config);
-
-    let username = args.0;
-    if username.is_empty() || username.contains(' ') {
-        bot.send_message(msg.chat.id, "Invalid username").await?;
-        return Ok(());
-    }
+    check_manage!(bot, msg, username, role_mgr, config);
 
     match member_mgr.delete_member(username).await {
         Ok(value) => {
@@ -157,6 +342,7 @@ async fn delete_member(
                     "The member is not existed."
                 },
             )
+            .send_retrying()
             .await?;
         }
         Err(err) => {
@@ -165,10 +351,809 @@ async fn delete_member(
                 msg.chat.id,
                 "Failed to delete member, internal error occurred",
             )
+            .send_retrying()
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn set_member_disabled(
+    bot: &Bot,
+    msg: &Message,
+    username: String,
+    disabled: bool,
+    member_mgr: &MemberManager,
+) -> HandlerResult {
+    match member_mgr.set_member_disabled(username, disabled).await {
+        Ok(value) => {
+            bot.send_message(
+                msg.chat.id,
+                if value {
+                    "Success"
+                } else {
+                    "The member is not existed."
+                },
+            )
+            .send_retrying()
+            .await?;
+        }
+        Err(err) => {
+            error!("Failed to update member: {}", err);
+            bot.send_message(
+                msg.chat.id,
+                "Failed to update member, internal error occurred",
+            )
+            .send_retrying()
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn ban_member(
+    bot: Bot,
+    msg: Message,
+    (Username(username),): (Username,),
+    member_mgr: MemberManager,
+    role_mgr: RoleManager,
+    config: SharedConfig,
+) -> HandlerResult {
+    check_manage!(bot, msg, username, role_mgr, config);
+
+    set_member_disabled(&bot, &msg, username, true, &member_mgr).await
+}
+
+async fn unban_member(
+    bot: Bot,
+    msg: Message,
+    (Username(username),): (Username,),
+    member_mgr: MemberManager,
+    role_mgr: RoleManager,
+    config: SharedConfig,
+) -> HandlerResult {
+    check_manage!(bot, msg, username, role_mgr, config);
+
+    set_member_disabled(&bot, &msg, username, false, &member_mgr).await
+}
+
+const MEMBERS_PAGE_SIZE: u64 = 20;
+
+async fn render_members_page(
+    member_mgr: &MemberManager,
+    page: u64,
+) -> Result<(String, InlineKeyboardMarkup), Error> {
+    let total = member_mgr.count_members().await?;
+    let total_pages = total.div_ceil(MEMBERS_PAGE_SIZE).max(1);
+    let page = page.min(total_pages - 1);
+
+    let offset = page * MEMBERS_PAGE_SIZE;
+    let members = member_mgr.list_members(offset, MEMBERS_PAGE_SIZE).await?;
+
+    let mut text = format!(
+        "Members (page {}/{}, {} in total):\n",
+        page + 1,
+        total_pages,
+        total
+    );
+    if members.is_empty() {
+        text.push_str("No members yet.");
+    }
+    for (idx, member) in members.iter().enumerate() {
+        let created_at = Utc
+            .timestamp_opt(member.created_at, 0)
+            .single()
+            .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default();
+        writeln!(
+            &mut text,
+            "{}. {}{} - added at {}",
+            offset + idx as u64 + 1,
+            member.username,
+            if member.disabled { " (disabled)" } else { "" },
+            created_at
+        )?;
+    }
+
+    let mut buttons = vec![];
+    if page > 0 {
+        buttons.push(InlineKeyboardButton::callback(
+            "« Prev",
+            format!("/list_members:{}", page - 1),
+        ));
+    }
+    if page + 1 < total_pages {
+        buttons.push(InlineKeyboardButton::callback(
+            "Next »",
+            format!("/list_members:{}", page + 1),
+        ));
+    }
+    let keyboard = InlineKeyboardMarkup::default().append_row(buttons);
+
+    Ok((text.trim_end().to_owned(), keyboard))
+}
+
+async fn list_members(bot: Bot, msg: Message, member_mgr: MemberManager) -> HandlerResult {
+    match render_members_page(&member_mgr, 0).await {
+        Ok((text, keyboard)) => {
+            bot.send_message(msg.chat.id, text)
+                .reply_markup(keyboard)
+                .send_retrying()
+                .await?;
+        }
+        Err(err) => {
+            error!("Failed to list members: {}", err);
+            bot.send_message(
+                msg.chat.id,
+                "Failed to list members, internal error occurred",
+            )
+            .send_retrying()
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn handle_list_members_action(
+    bot: Bot,
+    query: CallbackQuery,
+    member_mgr: MemberManager,
+    role_mgr: RoleManager,
+) -> bool {
+    let page: Option<u64> = query
+        .data
+        .as_ref()
+        .and_then(|data| data.strip_prefix("/list_members:"))
+        .and_then(|page_str| page_str.parse().ok());
+    let page = match page {
+        Some(page) => page,
+        None => return false,
+    };
+
+    let message = match query.message {
+        Some(message) => message,
+        None => return false,
+    };
+
+    let role = role_mgr.role_of(&query.from).await.unwrap_or_else(|err| {
+        error!("Failed to get the role of {}: {}", query.from.id, err);
+        MemberRole::default()
+    });
+    if role < MemberRole::Moderator {
+        let _ = bot
+            .answer_callback_query(query.id)
+            .text("You don't have the right to execute admin commands!")
+            .await;
+        return true;
+    }
+
+    match render_members_page(&member_mgr, page).await {
+        Ok((text, keyboard)) => {
+            let _ = bot
+                .edit_message_text(message.chat.id, message.id, text)
+                .reply_markup(keyboard)
+                .send_retrying()
+                .await;
+        }
+        Err(err) => {
+            error!("Failed to list members: {}", err);
+        }
+    }
+
+    true
+}
+
+/// Maximum characters of each answer displayed in the comparison result,
+/// so that both answers can fit in a single message.
+const COMPARE_ANSWER_MAX_CHARS: usize = 1500;
+
+async fn run_model_for_comparison(
+    openai_client: &OpenAIClient,
+    model: &str,
+    prompt: &str,
+) -> Result<(ChatModelResult, Duration), Error> {
+    let msg = ChatCompletionRequestMessageArgs::default()
+        .role(Role::User)
+        .content(prompt)
+        .build()?;
+    let params = ChatModelParams {
+        model: Some(model.to_owned()),
+        ..Default::default()
+    };
+
+    let start = Instant::now();
+    let stream = openai_client
+        .request_chat_model(None, vec![msg], params)
+        .await?;
+    let mut result = stream
+        .fold(None, |_, item| async move { Some(item) })
+        .await
+        .filter(|res| !res.content.is_empty())
+        .ok_or_else(|| anyhow!("Server returned empty response"))?;
+    let elapsed = start.elapsed();
+
+    result.prompt_tokens = openai_client.count_tokens(&result.model, prompt);
+    result.completion_tokens = openai_client.count_tokens(&result.model, &result.content);
+    openai_client.record_usage(&result).await;
+    Ok((result, elapsed))
+}
+
+async fn compare_models(
+    bot: Bot,
+    msg: Message,
+    args: CommandArgs,
+    openai_client: OpenAIClient,
+    config: SharedConfig,
+) -> HandlerResult {
+    let prompt = args.0.trim();
+    if prompt.is_empty() {
+        bot.send_message(msg.chat.id, "Usage: /compare <prompt>")
+            .send_retrying()
+            .await?;
+        return Ok(());
+    }
+
+    let (model_a, model_b) = match config.load().compare_models.as_slice() {
+        [model_a, model_b, ..] => (model_a.clone(), model_b.clone()),
+        _ => {
+            bot.send_message(
+                msg.chat.id,
+                "Two models must be specified in `compareModels` to use this command",
+            )
+            .send_retrying()
             .await?;
+            return Ok(());
         }
+    };
+
+    let progress_msg = bot
+        .send_message(
+            msg.chat.id,
+            format!("Comparing {} and {}...", model_a, model_b),
+        )
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+
+    let (result_a, result_b) = tokio::join!(
+        run_model_for_comparison(&openai_client, &model_a, prompt),
+        run_model_for_comparison(&openai_client, &model_b, prompt),
+    );
+
+    let mut reply_text = String::new();
+    for (model, result) in [(model_a, result_a), (model_b, result_b)] {
+        match result {
+            Ok((result, elapsed)) => {
+                writeln!(
+                    &mut reply_text,
+                    "[{}] {:.2}s, ~{} tokens",
+                    model,
+                    elapsed.as_secs_f64(),
+                    result.token_usage()
+                )?;
+                let mut content: String = result
+                    .content
+                    .chars()
+                    .take(COMPARE_ANSWER_MAX_CHARS)
+                    .collect();
+                if content.len() < result.content.len() {
+                    content.push_str("...");
+                }
+                writeln!(&mut reply_text, "{}\n", content)?;
+            }
+            Err(err) => {
+                error!("Failed to request model \"{}\": {}", model, err);
+                writeln!(&mut reply_text, "[{}] failed: {}\n", model, err)?;
+            }
+        }
+    }
+
+    bot.edit_message_text(msg.chat.id, progress_msg.id, reply_text.trim_end())
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+async fn show_keys(
+    bot: Bot,
+    msg: Message,
+    openai_client: OpenAIClient,
+    config: SharedConfig,
+) -> HandlerResult {
+    let mut reply_text = String::from("API keys (spend of this month):\n");
+    for (idx, status) in openai_client.key_statuses().iter().enumerate() {
+        let budget_text = match (status.monthly_budget, status.budget_usage()) {
+            (Some(budget), Some(usage)) => {
+                let warning = if usage >= config.load().key_budget_alert_threshold {
+                    " \u{26A0}"
+                } else {
+                    ""
+                };
+                format!(" / ${:.2} ({:.0}%){}", budget, usage * 100.0, warning)
+            }
+            _ => "".to_owned(),
+        };
+        writeln!(
+            &mut reply_text,
+            "{}. {} - ${:.2}{}",
+            idx + 1,
+            status.masked_key,
+            status.spend,
+            budget_text
+        )?;
     }
 
+    bot.send_message(msg.chat.id, reply_text.trim_end())
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+async fn add_persona(
+    bot: Bot,
+    msg: Message,
+    (name, Rest(prompt)): (String, Rest),
+    persona_mgr: PersonaManager,
+) -> HandlerResult {
+    if !is_valid_persona_name(&name) {
+        bot.send_message(
+            msg.chat.id,
+            "Invalid name, only letters, digits, \"_\" and \"-\" are allowed (up to 32 characters)",
+        )
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+        return Ok(());
+    }
+
+    let reply_text = match persona_mgr.set_persona(name.clone(), prompt).await {
+        Ok(_) => format!("Persona \"{}\" is saved", name),
+        Err(err) => {
+            error!("Failed to save persona: {}", err);
+            "Failed to save persona, internal error occurred".to_owned()
+        }
+    };
+    bot.send_message(msg.chat.id, reply_text)
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+async fn delete_persona(
+    bot: Bot,
+    msg: Message,
+    args: CommandArgs,
+    persona_mgr: PersonaManager,
+) -> HandlerResult {
+    let name = args.0.trim().to_owned();
+    let reply_text = match persona_mgr.delete_persona(name.clone()).await {
+        Ok(true) => format!("Persona \"{}\" is deleted", name),
+        Ok(false) => format!("Persona \"{}\" is not found", name),
+        Err(err) => {
+            error!("Failed to delete persona: {}", err);
+            "Failed to delete persona, internal error occurred".to_owned()
+        }
+    };
+    bot.send_message(msg.chat.id, reply_text)
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+async fn set_private_answers(
+    bot: Bot,
+    msg: Message,
+    (value,): (Option<bool>,),
+    prefs_mgr: PreferencesManager,
+) -> HandlerResult {
+    if msg.chat.is_private() {
+        bot.send_message(msg.chat.id, "This command can only be used in groups")
+            .send_retrying()
+            .await?;
+        return Ok(());
+    }
+
+    let chat_id = msg.chat.id.to_string();
+    let value = match value {
+        Some(value) => value,
+        None => {
+            let current: bool = prefs_mgr
+                .get_chat_value(&chat_id, PRIVATE_ANSWERS_PREF_KEY)
+                .await?;
+            bot.send_message(
+                msg.chat.id,
+                format!(
+                    "Private answers are {}, use \"/private_answers yes\" or \"/private_answers no\" to change it",
+                    if current { "on" } else { "off" }
+                ),
+            )
+            .reply_to_message_id(msg.id)
+            .send_retrying()
+            .await?;
+            return Ok(());
+        }
+    };
+
+    let reply_text = match prefs_mgr
+        .set_chat_value(&chat_id, PRIVATE_ANSWERS_PREF_KEY, &value)
+        .await
+    {
+        Ok(_) if value => "Success, answers will be sent to the private chats of the senders",
+        Ok(_) => "Success, answers will be sent in this group",
+        Err(err) => {
+            error!("Failed to set private answers: {}", err);
+            "Failed to set private answers, internal error occurred"
+        }
+    };
+    bot.send_message(msg.chat.id, reply_text)
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+async fn set_group_trigger(
+    bot: Bot,
+    msg: Message,
+    (trigger,): (Option<GroupTrigger>,),
+    group_triggers: GroupTriggerCache,
+) -> HandlerResult {
+    if msg.chat.is_private() {
+        bot.send_message(msg.chat.id, "This command can only be used in groups")
+            .send_retrying()
+            .await?;
+        return Ok(());
+    }
+
+    let trigger = match trigger {
+        Some(trigger) => trigger,
+        None => {
+            let current = group_triggers.get(msg.chat.id).await?;
+            bot.send_message(
+                msg.chat.id,
+                format!(
+                    "The bot answers {} in this group, use \"/group_trigger all\", \"/group_trigger mentionOnly\" or \"/group_trigger commandOnly\" to change it",
+                    describe_group_trigger(current)
+                ),
+            )
+            .reply_to_message_id(msg.id)
+            .send_retrying()
+            .await?;
+            return Ok(());
+        }
+    };
+
+    let reply_text = match group_triggers.set(msg.chat.id, trigger).await {
+        Ok(_) => format!(
+            "Success, the bot will answer {} in this group",
+            describe_group_trigger(trigger)
+        ),
+        Err(err) => {
+            error!("Failed to set the group trigger: {}", err);
+            "Failed to set the group trigger, internal error occurred".to_owned()
+        }
+    };
+    bot.send_message(msg.chat.id, reply_text)
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+fn describe_group_trigger(trigger: GroupTrigger) -> String {
+    let messages = match trigger {
+        GroupTrigger::All => "every message",
+        GroupTrigger::MentionOnly => "mentions and replies",
+        GroupTrigger::CommandOnly => "only /ask",
+    };
+    format!("{} ({})", messages, trigger.name())
+}
+
+async fn show_health(bot: Bot, msg: Message, health_checker: HealthChecker) -> HandlerResult {
+    let report = health_checker.run().await;
+    bot.send_message(msg.chat.id, report.render_text()?)
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+async fn clear_response_cache(
+    bot: Bot,
+    msg: Message,
+    openai_client: OpenAIClient,
+) -> HandlerResult {
+    let reply_text = match openai_client.clear_response_cache().await {
+        Ok(count) => format!("Success, {} cached answers are cleared", count),
+        Err(err) => {
+            error!("Failed to clear the response cache: {}", err);
+            "Failed to clear the response cache, internal error occurred".to_owned()
+        }
+    };
+    bot.send_message(msg.chat.id, reply_text)
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+async fn show_status(
+    bot: Bot,
+    msg: Message,
+    degraded_chats: DegradedChats,
+    scheduler: CompletionScheduler,
+    config: SharedConfig,
+) -> HandlerResult {
+    let chats = degraded_chats.list();
+    let mut reply_text = if chats.is_empty() {
+        "All chats are healthy.".to_owned()
+    } else {
+        let mut text = String::from("Degraded chats:\n");
+        for chat in chats {
+            writeln!(
+                &mut text,
+                "\n{} (for {} min): {}",
+                chat.chat_id,
+                chat.since.elapsed().as_secs() / 60,
+                chat.reason
+            )?;
+        }
+        text
+    };
+    if config.load().max_concurrent_completions.is_some() {
+        write!(
+            &mut reply_text,
+            "\n\nQueued requests: {}",
+            scheduler.queue_len()
+        )?;
+    }
+    bot.send_message(msg.chat.id, reply_text.trim_end())
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+async fn set_rate_limit(
+    bot: Bot,
+    msg: Message,
+    (Username(username), RateLimitArg(limit)): (Username, RateLimitArg),
+    rate_limiter: RateLimiter,
+) -> HandlerResult {
+    rate_limiter.set_override(username.clone(), limit);
+    let reply_text = match limit {
+        Some(0) => format!("{} is not rate limited until restart", username),
+        Some(limit) => format!(
+            "{} can send {} messages per minute until restart",
+            username, limit
+        ),
+        None => format!("The rate limit of {} is reset", username),
+    };
+    bot.send_message(msg.chat.id, reply_text)
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+async fn reload_config(bot: Bot, msg: Message, config: SharedConfig) -> HandlerResult {
+    let reply_text = match config.reload() {
+        Ok(_) => {
+            info!("Config is reloaded");
+            "Config is reloaded. Note that the bot token, API keys and database path only take effect after restarting.".to_owned()
+        }
+        Err(err) => {
+            error!("Failed to reload config: {}", err);
+            format!("Failed to reload config: {}", err)
+        }
+    };
+    bot.send_message(msg.chat.id, reply_text)
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+const DEFAULT_REPORT_DAYS: u32 = 7;
+const REPORT_TOP_ASKERS_LIMIT: u32 = 5;
+
+fn render_group_report(title: &str, days: u32, report: &ChatReport) -> Result<String, Error> {
+    let error_rate = if report.requests > 0 {
+        report.failed_requests as f64 / report.requests as f64 * 100.0
+    } else {
+        0.0
+    };
+
+    let mut text = String::new();
+    writeln!(
+        &mut text,
+        "Report of \"{}\" in the last {} days:",
+        title, days
+    )?;
+    writeln!(&mut text, "Active users: {}", report.active_users)?;
+    writeln!(&mut text, "Messages handled: {}", report.requests)?;
+    writeln!(&mut text, "Tokens used: {}", report.tokens)?;
+    writeln!(
+        &mut text,
+        "Error rate: {:.1}% ({} failed)",
+        error_rate, report.failed_requests
+    )?;
+    if !report.top_askers.is_empty() {
+        writeln!(&mut text, "\nTop askers:")?;
+        for (idx, (user_id, requests)) in report.top_askers.iter().enumerate() {
+            let user_id = if user_id.is_empty() {
+                "<unknown>"
+            } else {
+                user_id
+            };
+            writeln!(
+                &mut text,
+                "{}. {} - {} messages",
+                idx + 1,
+                user_id,
+                requests
+            )?;
+        }
+    }
+
+    Ok(text.trim_end().to_owned())
+}
+
+async fn group_report(
+    bot: Bot,
+    msg: Message,
+    (days,): (Option<u32>,),
+    stats_mgr: StatsManager,
+) -> HandlerResult {
+    if msg.chat.is_private() {
+        bot.send_message(msg.chat.id, "This command can only be used in groups")
+            .send_retrying()
+            .await?;
+        return Ok(());
+    }
+
+    let days = days.filter(|days| *days > 0).unwrap_or(DEFAULT_REPORT_DAYS);
+    let report = stats_mgr
+        .query_chat_report(msg.chat.id.to_string(), days, REPORT_TOP_ASKERS_LIMIT)
+        .await;
+    let reply_text = match report {
+        Ok(report) => {
+            let title = msg.chat.title().unwrap_or("this group");
+            render_group_report(title, days, &report)?
+        }
+        Err(err) => {
+            error!("Failed to query group report: {}", err);
+            "Failed to generate the report, internal error occurred".to_owned()
+        }
+    };
+    bot.send_message(msg.chat.id, reply_text)
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+fn render_moderation_report(days: u32, report: &ModerationReport) -> Result<String, Error> {
+    let mut text = String::new();
+    writeln!(&mut text, "Flagged contents in the last {} days:", days)?;
+    writeln!(&mut text, "Messages: {}", report.flagged_inputs)?;
+    writeln!(&mut text, "Answers: {}", report.flagged_outputs)?;
+    writeln!(&mut text, "Blocked: {}", report.blocked)?;
+    if !report.categories.is_empty() {
+        writeln!(&mut text, "\nCategories:")?;
+        for (category, count) in &report.categories {
+            writeln!(&mut text, "{}: {}", category, count)?;
+        }
+    }
+    if !report.top_users.is_empty() {
+        writeln!(&mut text, "\nTop users:")?;
+        for (idx, (user_id, count)) in report.top_users.iter().enumerate() {
+            let user_id = if user_id.is_empty() {
+                "<unknown>"
+            } else {
+                user_id
+            };
+            writeln!(&mut text, "{}. {} - {} flags", idx + 1, user_id, count)?;
+        }
+    }
+    Ok(text.trim_end().to_owned())
+}
+
+fn render_feedback_report(days: u32, report: &FeedbackReport) -> Result<String, Error> {
+    let satisfaction = |up: i64, down: i64| {
+        if up + down == 0 {
+            "-".to_owned()
+        } else {
+            format!("{:.0}%", up as f64 * 100.0 / (up + down) as f64)
+        }
+    };
+    let mut text = String::new();
+    writeln!(&mut text, "Feedback in the last {} days:", days)?;
+    writeln!(
+        &mut text,
+        "\u{1F44D} {} / \u{1F44E} {} (satisfaction: {})",
+        report.thumbs_up,
+        report.thumbs_down,
+        satisfaction(report.thumbs_up, report.thumbs_down)
+    )?;
+    if !report.models.is_empty() {
+        writeln!(&mut text, "\nModels:")?;
+        for (model, up, down) in &report.models {
+            writeln!(
+                &mut text,
+                "{}: \u{1F44D} {} / \u{1F44E} {} ({})",
+                model,
+                up,
+                down,
+                satisfaction(*up, *down)
+            )?;
+        }
+    }
+    Ok(text.trim_end().to_owned())
+}
+
+async fn feedback_stats(
+    bot: Bot,
+    msg: Message,
+    (days,): (Option<u32>,),
+    stats_mgr: StatsManager,
+) -> HandlerResult {
+    let days = days.filter(|days| *days > 0).unwrap_or(DEFAULT_REPORT_DAYS);
+    let reply_text = match stats_mgr.query_feedback_report(days).await {
+        Ok(report) => render_feedback_report(days, &report)?,
+        Err(err) => {
+            error!("Failed to query feedback report: {}", err);
+            "Failed to generate the report, internal error occurred".to_owned()
+        }
+    };
+    bot.send_message(msg.chat.id, reply_text)
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+async fn moderation_report(
+    bot: Bot,
+    msg: Message,
+    (days,): (Option<u32>,),
+    stats_mgr: StatsManager,
+) -> HandlerResult {
+    let days = days.filter(|days| *days > 0).unwrap_or(DEFAULT_REPORT_DAYS);
+    let reply_text = match stats_mgr
+        .query_moderation_report(days, REPORT_TOP_ASKERS_LIMIT)
+        .await
+    {
+        Ok(report) => render_moderation_report(days, &report)?,
+        Err(err) => {
+            error!("Failed to query moderation report: {}", err);
+            "Failed to generate the report, internal error occurred".to_owned()
+        }
+    };
+    bot.send_message(msg.chat.id, reply_text)
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+
     Ok(())
 }
 
@@ -177,6 +1162,21 @@ impl Module for Admin {
     async fn register_dependency(&mut self, dep_map: &mut DependencyMap) -> Result<(), Error> {
         let prefs_mgr: Arc<PreferencesManager> = dep_map.get();
         let config: Arc<SharedConfig> = dep_map.get();
+        let bot: Arc<Bot> = dep_map.get();
+
+        let openai_client: Arc<OpenAIClient> = dep_map.get();
+
+        update_checker::start_update_check_task(bot.as_ref().clone(), config.as_ref().clone());
+
+        let health_checker = HealthChecker::new(
+            bot.as_ref().clone(),
+            openai_client.as_ref().clone(),
+            self.db_mgr.clone(),
+        );
+        if let Some(addr) = &config.load().health_listen_addr {
+            health::start_health_server(addr.clone(), health_checker.clone());
+        }
+        dep_map.insert(health_checker);
 
         let member_mgr = MemberManager::new(
             self.db_mgr.clone(),
@@ -185,15 +1185,192 @@ impl Module for Admin {
         )
         .await?;
         dep_map.insert(member_mgr);
+        dep_map.insert(RoleManager::new(
+            self.db_mgr.clone(),
+            config.as_ref().clone(),
+        ));
         Ok(())
     }
 
+    fn filter_handler(
+        &self,
+    ) -> Handler<'static, DependencyMap, HandlerResult, DpHandlerDescription> {
+        Update::filter_callback_query()
+            .branch(dptree::filter_async(handle_list_members_action).endpoint(noop_handler))
+    }
+
     fn commands(&self) -> Vec<Command> {
-        // Don't reveal admin commands to other users.
+        // Admin commands are only listed in the help of admins.
         vec![
-            Command::new("set_public", "", dptree::endpoint(set_public)).hidden(),
-            Command::new("add_member", "", dptree::endpoint(add_member)).hidden(),
-            Command::new("del_member", "", dptree::endpoint(delete_member)).hidden(),
+            Command::new(
+                "set_public",
+                "Make the bot public or private (on or off)",
+                with_role(
+                    MemberRole::Admin,
+                    command_with_args::<(bool,)>("set_public").endpoint(set_public),
+                ),
+            )
+            .admin_only(),
+            Command::new(
+                "add_member",
+                "Allow a user to use the bot",
+                with_role(
+                    MemberRole::Moderator,
+                    command_with_args::<(Username,)>("add_member").endpoint(add_member),
+                ),
+            )
+            .moderator_only(),
+            Command::new(
+                "del_member",
+                "Remove a member",
+                with_role(
+                    MemberRole::Moderator,
+                    command_with_args::<(Username,)>("del_member").endpoint(delete_member),
+                ),
+            )
+            .moderator_only(),
+            Command::new(
+                "ban_member",
+                "Disable a member without removing it",
+                with_role(
+                    MemberRole::Moderator,
+                    command_with_args::<(Username,)>("ban_member").endpoint(ban_member),
+                ),
+            )
+            .moderator_only(),
+            Command::new(
+                "unban_member",
+                "Enable a disabled member",
+                with_role(
+                    MemberRole::Moderator,
+                    command_with_args::<(Username,)>("unban_member").endpoint(unban_member),
+                ),
+            )
+            .moderator_only(),
+            Command::new(
+                "set_role",
+                "Set the role of a user (admin, moderator, member or guest)",
+                with_role(
+                    MemberRole::Admin,
+                    command_with_args::<(Username, MemberRole)>("set_role").endpoint(set_role),
+                ),
+            )
+            .admin_only(),
+            Command::new(
+                "list_members",
+                "List the members",
+                with_role(MemberRole::Moderator, dptree::endpoint(list_members)),
+            )
+            .moderator_only(),
+            Command::new(
+                "compare",
+                "Compare the answers of the models in compareModels",
+                with_role(MemberRole::Admin, dptree::endpoint(compare_models)),
+            )
+            .admin_only(),
+            Command::new(
+                "group_report",
+                "Show the activities of this group, optionally of the last N days",
+                with_chat_role(
+                    MemberRole::Admin,
+                    command_with_args::<(Option<u32>,)>("group_report").endpoint(group_report),
+                ),
+            )
+            .admin_only(),
+            Command::new(
+                "keys",
+                "Show the status of the API keys",
+                with_role(MemberRole::Admin, dptree::endpoint(show_keys)),
+            )
+            .admin_only(),
+            Command::new(
+                "reload_config",
+                "Reload the config file",
+                with_role(MemberRole::Admin, dptree::endpoint(reload_config)),
+            )
+            .admin_only(),
+            Command::new(
+                "status",
+                "Show the status of the bot",
+                with_role(MemberRole::Admin, dptree::endpoint(show_status)),
+            )
+            .admin_only(),
+            Command::new(
+                "health",
+                "Check the connections to Telegram, OpenAI and the database",
+                with_role(MemberRole::Admin, dptree::endpoint(show_health)),
+            )
+            .admin_only(),
+            Command::new(
+                "clear_cache",
+                "Drop all cached answers",
+                with_role(MemberRole::Admin, dptree::endpoint(clear_response_cache)),
+            )
+            .admin_only(),
+            Command::new(
+                "private_answers",
+                "Answer in the private chats of the askers in this group (yes or no)",
+                with_chat_role(
+                    MemberRole::Admin,
+                    command_with_args::<(Option<bool>,)>("private_answers")
+                        .endpoint(set_private_answers),
+                ),
+            )
+            .admin_only(),
+            Command::new(
+                "group_trigger",
+                "Choose the messages answered in this group (all, mentionOnly or commandOnly)",
+                with_chat_role(
+                    MemberRole::Admin,
+                    command_with_args::<(Option<GroupTrigger>,)>("group_trigger")
+                        .endpoint(set_group_trigger),
+                ),
+            )
+            .admin_only(),
+            Command::new(
+                "moderation_report",
+                "Show the flagged contents, optionally of the last N days",
+                with_role(
+                    MemberRole::Admin,
+                    command_with_args::<(Option<u32>,)>("moderation_report")
+                        .endpoint(moderation_report),
+                ),
+            )
+            .admin_only(),
+            Command::new(
+                "feedback_stats",
+                "Show the ratings of the models, optionally of the last N days",
+                with_role(
+                    MemberRole::Admin,
+                    command_with_args::<(Option<u32>,)>("feedback_stats").endpoint(feedback_stats),
+                ),
+            )
+            .admin_only(),
+            Command::new(
+                "set_rate_limit",
+                "Set the rate limit of a member (a number or default)",
+                with_role(
+                    MemberRole::Admin,
+                    command_with_args::<(Username, RateLimitArg)>("set_rate_limit")
+                        .endpoint(set_rate_limit),
+                ),
+            )
+            .admin_only(),
+            Command::new(
+                "add_persona",
+                "Add a persona with its system prompt",
+                with_role(
+                    MemberRole::Admin,
+                    command_with_args::<(String, Rest)>("add_persona").endpoint(add_persona),
+                ),
+            )
+            .admin_only(),
+            Command::new(
+                "del_persona",
+                "Delete a persona",
+                with_role(MemberRole::Admin, dptree::endpoint(delete_persona)),
+            )
+            .admin_only(),
         ]
     }
 }
@@ -0,0 +1,189 @@
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use rusqlite::OptionalExtension;
+use teloxide::prelude::*;
+use teloxide::types::User;
+
+use super::{member_mgr::sealed_username, GroupAdminCache};
+use crate::{
+    config::SharedConfig,
+    database::DatabaseManager,
+    utils::dptree_ext::{next_word, ArgsError, CommandArg},
+};
+
+/// The role of a user, which decides the commands they can run. Roles are
+/// ordered by their rights, each role has the rights of the lower ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum MemberRole {
+    /// A member limited by `dailyQuotas.guestMessages`.
+    Guest,
+    #[default]
+    Member,
+    /// Can manage the members, but not the settings of the bot.
+    Moderator,
+    Admin,
+}
+
+impl MemberRole {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MemberRole::Guest => "guest",
+            MemberRole::Member => "member",
+            MemberRole::Moderator => "moderator",
+            MemberRole::Admin => "admin",
+        }
+    }
+
+    /// Returns `true` if users of this role can ban, delete or set the role
+    /// of users of the other role, which must be a lower one.
+    pub fn can_manage(&self, other: MemberRole) -> bool {
+        *self > other
+    }
+}
+
+impl FromStr for MemberRole {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "guest" => Ok(MemberRole::Guest),
+            "member" => Ok(MemberRole::Member),
+            "moderator" => Ok(MemberRole::Moderator),
+            "admin" => Ok(MemberRole::Admin),
+            _ => Err(()),
+        }
+    }
+}
+
+impl CommandArg for MemberRole {
+    fn placeholder() -> String {
+        "<admin|moderator|member|guest>".to_owned()
+    }
+
+    fn parse(input: &mut &str) -> Result<Self, ArgsError> {
+        let word = next_word::<Self>(input)?;
+        word.parse().map_err(|_| {
+            ArgsError(format!(
+                "\"{}\" is not one of {}",
+                word,
+                Self::placeholder()
+            ))
+        })
+    }
+}
+
+/// Looks up and assigns the roles of users. The roles are kept in the
+/// members table, and users who are not members have the member role.
+#[derive(Clone)]
+pub(crate) struct RoleManager {
+    db_mgr: DatabaseManager,
+    group_admins: GroupAdminCache,
+    config: SharedConfig,
+}
+
+impl RoleManager {
+    pub fn new(db_mgr: DatabaseManager, config: SharedConfig) -> Self {
+        Self {
+            db_mgr,
+            group_admins: GroupAdminCache::default(),
+            config,
+        }
+    }
+
+    /// Returns the role of the user. The users in `adminUsernames` are
+    /// always admins.
+    pub async fn role_of(&self, user: &User) -> Result<MemberRole, Error> {
+        match &user.username {
+            Some(username) => self.role_of_username(username.clone()).await,
+            None => Ok(MemberRole::default()),
+        }
+    }
+
+    /// Returns the role of the user with the username, see
+    /// [`RoleManager::role_of`].
+    pub async fn role_of_username(&self, username: String) -> Result<MemberRole, Error> {
+        if self.config.load().admin_usernames.contains(&username) {
+            return Ok(MemberRole::Admin);
+        }
+
+        let key = self.db_mgr.lookup_key(username)?;
+        let role: Option<String> = self
+            .db_mgr
+            .query(move |conn| {
+                let sql = "SELECT role FROM members WHERE username = ?";
+                conn.query_row(sql, (&key,), |row| row.get(0)).optional()
+            })
+            .await??;
+        Ok(role.and_then(|role| role.parse().ok()).unwrap_or_default())
+    }
+
+    /// Returns the role of the sender for the settings of the chat of the
+    /// message, which counts the group administrators as admins when
+    /// `groupAdminsAreBotAdmins` is enabled. Only use it for the settings
+    /// that are scoped to that chat, the other commands check
+    /// [`RoleManager::role_of`]. Errors are treated as the member role.
+    pub async fn role_in_chat(&self, bot: &Bot, msg: &Message) -> MemberRole {
+        let user = match msg.from() {
+            Some(user) => user,
+            None => return MemberRole::default(),
+        };
+        let role = self.role_of(user).await.unwrap_or_else(|err| {
+            error!("Failed to get the role of {}: {}", user.id, err);
+            MemberRole::default()
+        });
+        if role == MemberRole::Admin
+            || !self.config.load().group_admins_are_bot_admins
+            || msg.chat.is_private()
+        {
+            return role;
+        }
+        if self
+            .group_admins
+            .is_group_admin(bot, msg.chat.id, user.id)
+            .await
+        {
+            return MemberRole::Admin;
+        }
+        role
+    }
+
+    /// Sets the role of the user, who is added as a member if not yet.
+    pub async fn set_role(&self, username: String, role: MemberRole) -> Result<(), Error> {
+        let unix_timestamp_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let key = self.db_mgr.lookup_key(username.clone())?;
+        let sealed_username = sealed_username(&self.db_mgr, &username)?;
+        self.db_mgr
+            .write(move |conn| {
+                let sql = "INSERT INTO members (username, sealed_username, disabled, created_at, role) VALUES (?1, ?2, 0, ?3, ?4) \
+                    ON CONFLICT (username) DO UPDATE SET role = excluded.role;";
+                conn.execute(
+                    sql,
+                    (&key, &sealed_username, unix_timestamp_secs, role.name()),
+                )?;
+                info!("The role of \"{}\" is set to {}", username, role.name());
+                Ok::<_, Error>(())
+            })
+            .await??;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_manage() {
+        assert!(MemberRole::Admin.can_manage(MemberRole::Moderator));
+        assert!(MemberRole::Moderator.can_manage(MemberRole::Member));
+        assert!(MemberRole::Moderator.can_manage(MemberRole::Guest));
+        assert!(!MemberRole::Moderator.can_manage(MemberRole::Moderator));
+        assert!(!MemberRole::Moderator.can_manage(MemberRole::Admin));
+        assert!(!MemberRole::Admin.can_manage(MemberRole::Admin));
+    }
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use anyhow::Error;
+use serde::Deserialize;
+use teloxide::prelude::*;
+
+use crate::{config::SharedConfig, utils::http};
+
+const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+const CHANGELOG_EXCERPT_LEN: usize = 800;
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    html_url: String,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+/// Parses versions like `v1.2.3` or `1.2.3-beta` into comparable numbers.
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let version = version.trim().trim_start_matches('v');
+    let version = version.split(['-', '+']).next()?;
+    let mut parts = version.split('.').map(|part| part.parse::<u64>());
+    let major = parts.next()?.ok()?;
+    let minor = parts.next().unwrap_or(Ok(0)).ok()?;
+    let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+    Some((major, minor, patch))
+}
+
+fn is_newer_version(latest: &str, current: &str) -> bool {
+    match (parse_version(latest), parse_version(current)) {
+        (Some(latest), Some(current)) => latest > current,
+        _ => false,
+    }
+}
+
+fn changelog_excerpt(body: &str) -> String {
+    let body = body.trim();
+    if body.chars().count() <= CHANGELOG_EXCERPT_LEN {
+        return body.to_owned();
+    }
+    let excerpt: String = body.chars().take(CHANGELOG_EXCERPT_LEN).collect();
+    format!("{}…", excerpt.trim_end())
+}
+
+async fn fetch_latest_release(url: &str) -> Result<Release, Error> {
+    // GitHub rejects API requests without a user agent.
+    let resp = http::client()
+        .get(url)
+        .header("User-Agent", format!("TeleGPT/{}", CURRENT_VERSION))
+        .header("Accept", "application/vnd.github+json")
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(resp.json().await?)
+}
+
+/// Starts a background task that checks for new releases with the
+/// `updateCheck` config. Each new version is notified once per run.
+pub(crate) fn start_update_check_task(bot: Bot, shared_config: SharedConfig) {
+    tokio::spawn(async move {
+        let mut notified_version: Option<String> = None;
+        loop {
+            // Read the config on each check, since it may be reloaded.
+            let update_check = match &shared_config.load().update_check {
+                Some(update_check) => update_check.clone(),
+                None => {
+                    tokio::time::sleep(Duration::from_secs(60 * 60)).await;
+                    continue;
+                }
+            };
+
+            match fetch_latest_release(&update_check.release_url).await {
+                Ok(release)
+                    if is_newer_version(&release.tag_name, CURRENT_VERSION)
+                        && notified_version.as_ref() != Some(&release.tag_name) =>
+                {
+                    info!("A newer version {} is available", release.tag_name);
+                    let mut text = format!(
+                        "TeleGPT {} is available (current version: {}).\n{}",
+                        release.tag_name, CURRENT_VERSION, release.html_url
+                    );
+                    let changelog = changelog_excerpt(release.body.as_deref().unwrap_or_default());
+                    if !changelog.is_empty() {
+                        text.push_str("\n\n");
+                        text.push_str(&changelog);
+                    }
+                    for chat_id in &update_check.notify_chat_ids {
+                        if let Err(err) = bot.send_message(ChatId(*chat_id), &text).await {
+                            error!("Failed to notify the new version: {}", err);
+                        }
+                    }
+                    notified_version = Some(release.tag_name);
+                }
+                Ok(_) => debug!("TeleGPT is up to date"),
+                Err(err) => warn!("Failed to check for new releases: {}", err),
+            }
+
+            let interval = Duration::from_secs(update_check.interval_hours.max(1) * 60 * 60);
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_newer_version() {
+        assert!(is_newer_version("v0.2.0", "0.1.0"));
+        assert!(is_newer_version("1.0", "0.9.9"));
+        assert!(!is_newer_version("v0.1.0", "0.1.0"));
+        assert!(!is_newer_version("v0.1.0-beta", "0.1.0"));
+        assert!(!is_newer_version("nightly", "0.1.0"));
+    }
+}
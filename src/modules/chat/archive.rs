@@ -0,0 +1,233 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use anyhow::Error;
+use async_openai::types::{ChatCompletionRequestMessage, Role};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::ArchiveConfig, utils::http};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct ArchivedMessage {
+    pub role: String,
+    pub content: String,
+}
+
+/// A conversation to be archived, which is also the JSON body posted to
+/// the archive endpoint, and the document accepted by `/import`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Archive {
+    #[serde(default)]
+    pub chat_id: String,
+    #[serde(default)]
+    pub chat_title: String,
+    #[serde(default)]
+    pub model: String,
+    /// The title generated for the session, if it's named.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub archived_at: DateTime<Utc>,
+    pub messages: Vec<ArchivedMessage>,
+}
+
+impl Archive {
+    pub fn new(
+        chat_id: String,
+        chat_title: String,
+        model: String,
+        msgs: Vec<ChatCompletionRequestMessage>,
+    ) -> Self {
+        let messages = msgs
+            .into_iter()
+            .map(|msg| ArchivedMessage {
+                role: msg.role.to_string(),
+                content: msg.content,
+            })
+            .collect();
+        Self {
+            chat_id,
+            chat_title,
+            model,
+            title: None,
+            archived_at: Utc::now(),
+            messages,
+        }
+    }
+
+    /// Parses an archive in JSON, and returns its messages. The messages
+    /// are validated so that they can be sent to the model. System
+    /// messages are rejected unless `allows_system` is `true`, since they
+    /// replace the system prompt of the session.
+    pub fn parse_messages(
+        data: &[u8],
+        allows_system: bool,
+    ) -> Result<Vec<ChatCompletionRequestMessage>, Error> {
+        let archive: Archive =
+            serde_json::from_slice(data).map_err(|err| anyhow!("Invalid archive: {}", err))?;
+        if archive.messages.is_empty() {
+            return Err(anyhow!("The archive has no messages"));
+        }
+
+        archive
+            .messages
+            .into_iter()
+            .enumerate()
+            .map(|(idx, msg)| {
+                let role = match msg.role.as_str() {
+                    "system" if allows_system => Role::System,
+                    "system" => {
+                        return Err(anyhow!(
+                            "Message {} is a system message, only admins can import them",
+                            idx + 1
+                        ))
+                    }
+                    "user" => Role::User,
+                    "assistant" => Role::Assistant,
+                    role => {
+                        return Err(anyhow!(
+                            "Message {} has an unknown role \"{}\"",
+                            idx + 1,
+                            role
+                        ))
+                    }
+                };
+                if msg.content.trim().is_empty() {
+                    return Err(anyhow!("Message {} is empty", idx + 1));
+                }
+                Ok(ChatCompletionRequestMessage {
+                    role,
+                    content: msg.content,
+                    name: None,
+                })
+            })
+            .collect()
+    }
+
+    /// Renders the conversation as a Markdown document with front matter.
+    pub fn to_markdown(&self) -> Result<String, Error> {
+        // JSON strings are also valid YAML scalars.
+        let mut text = String::from("---\n");
+        writeln!(
+            &mut text,
+            "chat_id: {}",
+            serde_json::to_string(&self.chat_id)?
+        )?;
+        writeln!(
+            &mut text,
+            "chat_title: {}",
+            serde_json::to_string(&self.chat_title)?
+        )?;
+        writeln!(&mut text, "model: {}", serde_json::to_string(&self.model)?)?;
+        if let Some(title) = &self.title {
+            writeln!(&mut text, "title: {}", serde_json::to_string(title)?)?;
+        }
+        writeln!(&mut text, "archived_at: {}", self.archived_at.to_rfc3339())?;
+        writeln!(&mut text, "messages: {}", self.messages.len())?;
+        writeln!(&mut text, "---\n")?;
+
+        writeln!(
+            &mut text,
+            "# {}",
+            self.title.as_deref().unwrap_or(&self.chat_title)
+        )?;
+        for msg in &self.messages {
+            let role = match msg.role.as_str() {
+                "system" => "System",
+                "assistant" => "Assistant",
+                _ => "User",
+            };
+            write!(&mut text, "\n**{}**\n\n{}\n", role, msg.content)?;
+        }
+        Ok(text)
+    }
+}
+
+/// Exports the conversation to the configured destinations, and returns
+/// the descriptions of where it is exported to.
+pub(crate) async fn export(
+    config: &ArchiveConfig,
+    archive: &Archive,
+) -> Result<Vec<String>, Error> {
+    let mut destinations = vec![];
+
+    if let Some(dir) = &config.markdown_dir {
+        let dir = Path::new(dir);
+        tokio::fs::create_dir_all(dir).await?;
+        let path = dir.join(format!(
+            "{}-{}.md",
+            archive.chat_id,
+            archive.archived_at.format("%Y%m%d-%H%M%S")
+        ));
+        tokio::fs::write(&path, archive.to_markdown()?).await?;
+        destinations.push(path.display().to_string());
+    }
+
+    if let Some(endpoint) = &config.endpoint {
+        let mut req = http::client().post(endpoint).json(archive);
+        for (name, value) in &config.headers {
+            req = req.header(name, value);
+        }
+        req.send().await?.error_for_status()?;
+        destinations.push(endpoint.to_owned());
+    }
+
+    if destinations.is_empty() {
+        return Err(anyhow!("No archive destination is configured"));
+    }
+    Ok(destinations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_markdown() {
+        let msg = |role, content: &str| ChatCompletionRequestMessage {
+            role,
+            content: content.to_owned(),
+            name: None,
+        };
+        let mut archive = Archive::new(
+            "-100".to_owned(),
+            "Team \"A\"".to_owned(),
+            "gpt-3.5-turbo".to_owned(),
+            vec![msg(Role::User, "Hi"), msg(Role::Assistant, "Hello!")],
+        );
+
+        let markdown = archive.to_markdown().unwrap();
+        assert!(markdown.starts_with("---\nchat_id: \"-100\"\nchat_title: \"Team \\\"A\\\"\"\n"));
+        assert!(markdown.contains("messages: 2\n---\n\n# Team \"A\"\n"));
+        assert!(markdown.ends_with("\n**User**\n\nHi\n\n**Assistant**\n\nHello!\n"));
+
+        let json = serde_json::to_vec(&archive).unwrap();
+        let msgs = Archive::parse_messages(&json, false).unwrap();
+        assert_eq!(msgs.len(), 2);
+        assert!(matches!(msgs[1].role, Role::Assistant));
+
+        archive.title = Some("Greetings".to_owned());
+        let markdown = archive.to_markdown().unwrap();
+        assert!(markdown.contains("title: \"Greetings\"\n"));
+        assert!(markdown.contains("---\n\n# Greetings\n"));
+    }
+
+    #[test]
+    fn test_parse_invalid_messages() {
+        let parse = |json: &str| Archive::parse_messages(json.as_bytes(), false);
+        assert!(parse(r#"{"messages":[]}"#).is_err());
+        assert!(parse(r#"{"messages":[{"role":"tool","content":"Hi"}]}"#).is_err());
+        assert!(parse(r#"{"messages":[{"role":"user","content":" "}]}"#).is_err());
+        assert!(parse(r#"{"messages":[{"role":"user","content":"Hi"}]}"#).is_ok());
+    }
+
+    #[test]
+    fn test_parse_system_messages() {
+        let json = br#"{"messages":[{"role":"system","content":"Be rude"},{"role":"user","content":"Hi"}]}"#;
+        assert!(Archive::parse_messages(json, false).is_err());
+        let msgs = Archive::parse_messages(json, true).unwrap();
+        assert!(matches!(msgs[0].role, Role::System));
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 mod symbols {
     pub(super) const BLANK: u16 = 0x2800;
     pub(super) const DOTS: [[u16; 2]; 4] = [
@@ -15,6 +17,7 @@ pub struct BrailleProgress {
     length: usize,
     current: usize,
     label: Option<String>,
+    status: Option<String>,
 }
 
 impl BrailleProgress {
@@ -25,9 +28,15 @@ impl BrailleProgress {
             length,
             current: 0,
             label,
+            status: None,
         }
     }
 
+    /// Sets the text displayed after the label, e.g. the generation speed.
+    pub fn set_status(&mut self, status: Option<String>) {
+        self.status = status;
+    }
+
     pub fn advance_progress(&mut self) {
         self.current = (self.current + 1) % self.pixel_length();
     }
@@ -60,7 +69,7 @@ impl BrailleProgress {
         counter -= 1;
 
         // Right
-        for idx in (0..self.height).map(move |y| (y * self.width + self.width - 1)) {
+        for idx in (0..self.height).map(move |y| y * self.width + self.width - 1) {
             for i in 0..4 {
                 if range.contains(&counter) || counter <= out_of_range {
                     let ch = chars.get_mut(idx).unwrap();
@@ -113,6 +122,10 @@ impl BrailleProgress {
             result.push(' ');
             result.push_str(label);
         }
+        if let Some(status) = &self.status {
+            result.push(' ');
+            result.push_str(status);
+        }
 
         result
     }
@@ -124,11 +137,32 @@ impl BrailleProgress {
     }
 }
 
+/// Formats the stats of the generation with the template, where
+/// `{elapsed}` is replaced with the seconds since the request, `{tokens}`
+/// with the generated tokens, and `{speed}` with the tokens per second
+/// since the first token.
+pub fn format_generation_stats(
+    template: &str,
+    elapsed: Duration,
+    tokens: u32,
+    generating: Duration,
+) -> String {
+    let speed = if generating.is_zero() {
+        0.0
+    } else {
+        tokens as f64 / generating.as_secs_f64()
+    };
+    template
+        .replace("{elapsed}", &format!("{:.1}", elapsed.as_secs_f64()))
+        .replace("{tokens}", &tokens.to_string())
+        .replace("{speed}", &format!("{:.1}", speed))
+}
+
 #[cfg(test)]
 mod tests {
     use std::thread::sleep;
 
-    use super::BrailleProgress;
+    use super::*;
 
     #[test]
     fn test_update() {
@@ -138,4 +172,18 @@ mod tests {
             sleep(std::time::Duration::from_millis(150));
         }
     }
+
+    #[test]
+    fn test_generation_stats() {
+        let mut progress = BrailleProgress::new(1, 1, 3, Some("Thinking...".to_owned()));
+        progress.set_status(Some(format_generation_stats(
+            "{elapsed}s, {tokens} tokens, {speed} tok/s",
+            Duration::from_millis(3500),
+            50,
+            Duration::from_secs(2),
+        )));
+        assert!(progress
+            .current_string()
+            .ends_with(" Thinking... 3.5s, 50 tokens, 25.0 tok/s"));
+    }
 }
@@ -0,0 +1,130 @@
+use teloxide::types::MessageEntityKind;
+
+use super::markdown::ParsedString;
+
+/// A code block taken out of an answer, to be sent as a file.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct CodeFile {
+    pub file_name: String,
+    pub code: String,
+}
+
+/// Takes the largest code block out of the parsed answer if it's at least
+/// `threshold` characters long and makes up most of the answer. The block
+/// is replaced with `placeholder`, and the entities are moved accordingly.
+pub(crate) fn extract_code_file(
+    parsed: &mut ParsedString,
+    threshold: usize,
+    placeholder: &str,
+) -> Option<CodeFile> {
+    let (idx, language) = parsed
+        .entities
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, entity)| match &entity.kind {
+            MessageEntityKind::Pre { language } => Some((idx, language.clone())),
+            _ => None,
+        })
+        .max_by_key(|(idx, _)| parsed.entities[*idx].length)?;
+    let entity = parsed.entities[idx].clone();
+
+    // The offsets of entities are in UTF-16 code units.
+    let start = byte_index(&parsed.content, entity.offset)?;
+    let end = byte_index(&parsed.content, entity.offset + entity.length)?;
+    let code = &parsed.content[start..end];
+    let code_len = code.chars().count();
+    if code_len < threshold || code_len * 2 < parsed.content.chars().count() {
+        return None;
+    }
+
+    let code_file = CodeFile {
+        file_name: format!("answer.{}", file_extension(language.as_deref())),
+        // Files are expected to end with a newline.
+        code: format!("{}\n", code.trim_end_matches('\n')),
+    };
+    parsed.content.replace_range(start..end, placeholder);
+
+    let placeholder_len = placeholder.encode_utf16().count();
+    parsed.entities.remove(idx);
+    parsed.entities.retain_mut(|other| {
+        if other.offset >= entity.offset + entity.length {
+            other.offset = other.offset - entity.length + placeholder_len;
+            true
+        } else {
+            // Drop the entities nested in the code block.
+            other.offset + other.length <= entity.offset
+        }
+    });
+    Some(code_file)
+}
+
+fn byte_index(s: &str, utf16_offset: usize) -> Option<usize> {
+    let mut utf16_count = 0;
+    for (idx, ch) in s.char_indices() {
+        if utf16_count == utf16_offset {
+            return Some(idx);
+        }
+        utf16_count += ch.len_utf16();
+    }
+    (utf16_count == utf16_offset).then_some(s.len())
+}
+
+/// Maps the language of a code block to the extension of its file.
+fn file_extension(language: Option<&str>) -> String {
+    let language = match language {
+        Some(language) => language.trim().to_lowercase(),
+        None => return "txt".to_owned(),
+    };
+    let extension = match language.as_str() {
+        "rust" => "rs",
+        "python" | "python3" => "py",
+        "javascript" | "node" => "js",
+        "typescript" => "ts",
+        "ruby" => "rb",
+        "kotlin" => "kt",
+        "csharp" | "c#" => "cs",
+        "c++" => "cpp",
+        "golang" => "go",
+        "shell" | "bash" | "zsh" => "sh",
+        "markdown" => "md",
+        "yml" => "yaml",
+        "text" | "plaintext" => "txt",
+        language if !language.is_empty() && language.chars().all(|c| c.is_ascii_alphanumeric()) => {
+            language
+        }
+        _ => "txt",
+    };
+    extension.to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::markdown;
+    use super::*;
+
+    #[test]
+    fn test_extract_code_file() {
+        let raw = "Here is **the** code:\n```rust\nfn main() {\n    println!(\"Hello, 世界\");\n}\n```\nRun it with *cargo*.";
+        let mut parsed = markdown::parse(raw);
+        let code_file = extract_code_file(&mut parsed, 20, "[answer.rs]").unwrap();
+
+        assert_eq!(code_file.file_name, "answer.rs");
+        assert_eq!(
+            code_file.code,
+            "fn main() {\n    println!(\"Hello, 世界\");\n}\n"
+        );
+        assert_eq!(
+            parsed.content,
+            "Here is the code:\n\n[answer.rs]\n\nRun it with cargo."
+        );
+        let last_entity = parsed.entities.last().unwrap();
+        assert!(matches!(last_entity.kind, MessageEntityKind::Italic));
+        assert_eq!(
+            last_entity.offset,
+            parsed.content.encode_utf16().count() - 6
+        );
+
+        let mut parsed = markdown::parse(raw);
+        assert!(extract_code_file(&mut parsed, 100, "").is_none());
+    }
+}
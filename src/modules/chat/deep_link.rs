@@ -0,0 +1,61 @@
+/// The payload of a `/start` deep link, e.g.
+/// `https://t.me/<bot>?start=persona_translator`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum StartPayload {
+    /// Activates the persona of the given name.
+    Persona(String),
+    /// Joins the members with the given invite code.
+    Invite(String),
+    /// Asks the prompt template of the given name.
+    Prompt(String),
+    /// Continues a group conversation with the given fork token.
+    Fork(String),
+    Unknown(String),
+}
+
+impl StartPayload {
+    pub fn parse(payload: &str) -> Option<Self> {
+        let payload = payload.trim();
+        if payload.is_empty() {
+            return None;
+        }
+        let parsed = match payload.split_once('_') {
+            Some(("persona", name)) => StartPayload::Persona(name.to_owned()),
+            Some(("invite", code)) => StartPayload::Invite(code.to_owned()),
+            Some(("prompt", name)) => StartPayload::Prompt(name.to_owned()),
+            Some(("fork", token)) => StartPayload::Fork(token.to_owned()),
+            _ => StartPayload::Unknown(payload.to_owned()),
+        };
+        Some(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_start_payload() {
+        assert_eq!(StartPayload::parse(""), None);
+        assert_eq!(
+            StartPayload::parse("persona_code_reviewer"),
+            Some(StartPayload::Persona("code_reviewer".to_owned()))
+        );
+        assert_eq!(
+            StartPayload::parse("invite_Xy12"),
+            Some(StartPayload::Invite("Xy12".to_owned()))
+        );
+        assert_eq!(
+            StartPayload::parse("prompt_daily"),
+            Some(StartPayload::Prompt("daily".to_owned()))
+        );
+        assert_eq!(
+            StartPayload::parse("fork_0a1b"),
+            Some(StartPayload::Fork("0a1b".to_owned()))
+        );
+        assert_eq!(
+            StartPayload::parse("hello"),
+            Some(StartPayload::Unknown("hello".to_owned()))
+        );
+    }
+}
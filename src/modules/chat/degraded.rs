@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use teloxide::{ApiError, RequestError};
+
+/// Messages in a degraded chat are ignored for this long, before trying
+/// to answer in the chat again.
+const DEGRADED_RETRY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// The status of a chat that the bot can't send messages to.
+#[derive(Clone, Debug)]
+pub(crate) struct DegradedChat {
+    /// The session key of the chat, or of the topic in forums, since a
+    /// closed topic doesn't affect the other topics.
+    pub chat_id: String,
+    pub reason: String,
+    pub since: Instant,
+    last_failed_at: Instant,
+}
+
+/// Tracks the chats that the bot lacks the permission to send messages
+/// to, so that they are not answered (and erroring) on every message.
+#[derive(Clone, Default)]
+pub(crate) struct DegradedChats {
+    chats: Arc<Mutex<HashMap<String, DegradedChat>>>,
+}
+
+impl DegradedChats {
+    /// Marks the chat as degraded, returns `true` if it was not degraded.
+    pub fn mark(&self, key: &str, reason: String) -> bool {
+        let mut chats = self.chats.lock().unwrap();
+        let now = Instant::now();
+        match chats.get_mut(key) {
+            Some(chat) => {
+                chat.reason = reason;
+                chat.last_failed_at = now;
+                false
+            }
+            None => {
+                chats.insert(
+                    key.to_owned(),
+                    DegradedChat {
+                        chat_id: key.to_owned(),
+                        reason,
+                        since: now,
+                        last_failed_at: now,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    /// Marks the chat as healthy, returns `true` if it was degraded.
+    pub fn clear(&self, key: &str) -> bool {
+        self.chats.lock().unwrap().remove(key).is_some()
+    }
+
+    /// Returns `true` if messages in the chat should be ignored for now.
+    pub fn should_skip(&self, key: &str) -> bool {
+        self.chats
+            .lock()
+            .unwrap()
+            .get(key)
+            .map(|chat| chat.last_failed_at.elapsed() < DEGRADED_RETRY_INTERVAL)
+            .unwrap_or(false)
+    }
+
+    pub fn list(&self) -> Vec<DegradedChat> {
+        let mut chats: Vec<_> = self.chats.lock().unwrap().values().cloned().collect();
+        chats.sort_by_key(|chat| chat.since);
+        chats
+    }
+}
+
+/// Returns `true` if the error is caused by missing the rights to send
+/// messages to the chat, e.g. the bot is muted or the topic is closed.
+pub(crate) fn is_permission_error(err: &RequestError) -> bool {
+    let api_err = match err {
+        RequestError::Api(api_err) => api_err,
+        _ => return false,
+    };
+    match api_err {
+        ApiError::BotKicked
+        | ApiError::BotKickedFromSupergroup
+        | ApiError::NotEnoughRightsToPostMessages => true,
+        ApiError::Unknown(description) => {
+            const PATTERNS: [&str; 4] = [
+                "not enough rights to send",
+                "have no rights to send",
+                "CHAT_WRITE_FORBIDDEN",
+                "TOPIC_CLOSED",
+            ];
+            PATTERNS.iter().any(|pattern| description.contains(pattern))
+        }
+        _ => false,
+    }
+}
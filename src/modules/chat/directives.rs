@@ -0,0 +1,87 @@
+/// The prefix of the directives at the start of a message.
+const DIRECTIVE_PREFIX: char = '!';
+/// The temperatures accepted by the OpenAI API.
+const TEMPERATURE_RANGE: std::ops::RangeInclusive<f32> = 0.0..=2.0;
+
+/// One-off overrides of a single request, given at the start of a message,
+/// e.g. `!gpt-4o !t=0.9 explain monads`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Directives {
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+}
+
+impl Directives {
+    /// Parses the leading directives of the text, and returns them with the
+    /// rest of the text. `!<model>` selects one of the available models,
+    /// and `!t=<temperature>` sets the temperature. Parsing stops at the
+    /// first word that is not a directive, so that other words starting
+    /// with `!` are kept in the text.
+    pub fn parse<'a>(
+        text: &'a str,
+        available_models: &[String],
+    ) -> Result<(Self, &'a str), String> {
+        let mut directives = Self::default();
+        let mut rest = text.trim_start();
+        loop {
+            let (word, next_rest) = match rest.split_once(char::is_whitespace) {
+                Some((word, next_rest)) => (word, next_rest.trim_start()),
+                None => (rest, ""),
+            };
+            let directive = match word.strip_prefix(DIRECTIVE_PREFIX) {
+                Some(directive) => directive,
+                None => break,
+            };
+
+            if let Some(value) = directive.strip_prefix("t=") {
+                let temperature = value
+                    .parse()
+                    .ok()
+                    .filter(|temperature| TEMPERATURE_RANGE.contains(temperature))
+                    .ok_or_else(|| {
+                        format!(
+                            "Invalid temperature \"{}\", it should be between {} and {}",
+                            value,
+                            TEMPERATURE_RANGE.start(),
+                            TEMPERATURE_RANGE.end()
+                        )
+                    })?;
+                directives.temperature = Some(temperature);
+            } else if available_models.iter().any(|model| model == directive) {
+                directives.model = Some(directive.to_owned());
+            } else {
+                break;
+            }
+            rest = next_rest;
+        }
+        Ok((directives, rest))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.model.is_none() && self.temperature.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_directives() {
+        let models = vec!["gpt-4o".to_owned(), "gpt-3.5-turbo".to_owned()];
+
+        let (directives, rest) =
+            Directives::parse("!gpt-4o !t=0.9 explain monads", &models).unwrap();
+        assert_eq!(directives.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(directives.temperature, Some(0.9));
+        assert_eq!(rest, "explain monads");
+
+        // Unknown directives and the words after them are kept.
+        let (directives, rest) = Directives::parse("!important !t=1 read this", &models).unwrap();
+        assert!(directives.is_empty());
+        assert_eq!(rest, "!important !t=1 read this");
+
+        assert!(Directives::parse("!t=3 hi", &models).is_err());
+        assert!(Directives::parse("!t=warm hi", &models).is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use teloxide::types::InlineKeyboardButton;
+
+const FEEDBACK_PREFIX: &str = "/feedback:";
+/// Telegram limits the callback data to 64 bytes.
+const MAX_CALLBACK_DATA_LEN: usize = 64;
+
+/// A rating of an answer, carried by the callback data of the feedback
+/// buttons so that it can be recorded even after the session is gone.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Feedback {
+    /// `1` for thumbs up, `-1` for thumbs down.
+    pub rating: i8,
+    pub prompt_hash: String,
+    pub model: String,
+}
+
+impl Feedback {
+    fn to_callback_data(&self) -> String {
+        let mut data = format!(
+            "{}{}:{}:{}",
+            FEEDBACK_PREFIX,
+            if self.rating > 0 { "up" } else { "down" },
+            self.prompt_hash,
+            self.model
+        );
+        // Overlong model names are truncated to fit.
+        if data.len() > MAX_CALLBACK_DATA_LEN {
+            let mut end = MAX_CALLBACK_DATA_LEN;
+            while !data.is_char_boundary(end) {
+                end -= 1;
+            }
+            data.truncate(end);
+        }
+        data
+    }
+
+    pub fn from_callback_data(data: &str) -> Option<Self> {
+        let mut parts = data.strip_prefix(FEEDBACK_PREFIX)?.splitn(3, ':');
+        let rating = match parts.next()? {
+            "up" => 1,
+            "down" => -1,
+            _ => return None,
+        };
+        Some(Self {
+            rating,
+            prompt_hash: parts.next()?.to_owned(),
+            model: parts.next()?.to_owned(),
+        })
+    }
+}
+
+/// Returns the thumbs up and down buttons of the answer.
+pub(crate) fn feedback_buttons(model: &str, prompt: &str) -> Vec<InlineKeyboardButton> {
+    let prompt_hash = prompt_hash(prompt);
+    [(1, "\u{1F44D}"), (-1, "\u{1F44E}")]
+        .into_iter()
+        .map(|(rating, text)| {
+            let feedback = Feedback {
+                rating,
+                prompt_hash: prompt_hash.clone(),
+                model: model.to_owned(),
+            };
+            InlineKeyboardButton::callback(text, feedback.to_callback_data())
+        })
+        .collect()
+}
+
+/// Hashes the prompt with 64-bit FNV-1a, which is stable across releases,
+/// so that the feedbacks on the same prompt can be grouped.
+fn prompt_hash(prompt: &str) -> String {
+    let hash = prompt
+        .trim()
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feedback_callback_data() {
+        let feedback = Feedback {
+            rating: -1,
+            prompt_hash: prompt_hash("Hello"),
+            model: "gpt-3.5-turbo".to_owned(),
+        };
+        let data = feedback.to_callback_data();
+        assert_eq!(Feedback::from_callback_data(&data), Some(feedback));
+        assert_eq!(prompt_hash("Hello"), prompt_hash(" Hello\n"));
+
+        let feedback = Feedback {
+            rating: 1,
+            prompt_hash: prompt_hash("Hello"),
+            model: "a-very-long-model-name-served-by-some-provider".to_owned(),
+        };
+        let data = feedback.to_callback_data();
+        assert_eq!(data.len(), MAX_CALLBACK_DATA_LEN);
+        assert_eq!(Feedback::from_callback_data(&data).unwrap().rating, 1);
+    }
+}
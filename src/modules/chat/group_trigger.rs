@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+use teloxide::types::ChatId;
+
+use crate::{
+    modules::prefs::PreferencesManager,
+    utils::dptree_ext::{next_word, ArgsError, CommandArg},
+};
+
+const GROUP_TRIGGER_PREF_KEY: &str = "GroupTrigger";
+
+/// The messages that the bot answers in a group. Commands (including
+/// `/ask`) are always accepted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum GroupTrigger {
+    /// Every message in the group.
+    All,
+    /// The messages that mention the bot or reply to its messages.
+    #[default]
+    MentionOnly,
+    /// Only `/ask` and other commands.
+    CommandOnly,
+}
+
+impl GroupTrigger {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GroupTrigger::All => "all",
+            GroupTrigger::MentionOnly => "mentionOnly",
+            GroupTrigger::CommandOnly => "commandOnly",
+        }
+    }
+}
+
+impl FromStr for GroupTrigger {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(GroupTrigger::All),
+            "mentionOnly" => Ok(GroupTrigger::MentionOnly),
+            "commandOnly" => Ok(GroupTrigger::CommandOnly),
+            _ => Err(()),
+        }
+    }
+}
+
+impl CommandArg for GroupTrigger {
+    fn placeholder() -> String {
+        "<all|mentionOnly|commandOnly>".to_owned()
+    }
+
+    fn parse(input: &mut &str) -> Result<Self, ArgsError> {
+        let word = next_word::<Self>(input)?;
+        word.parse().map_err(|_| {
+            ArgsError(format!(
+                "\"{}\" is not one of {}",
+                word,
+                Self::placeholder()
+            ))
+        })
+    }
+}
+
+/// Caches the group triggers, which are read for every group message. The
+/// triggers are set through the cache, so that it's always up to date.
+#[derive(Clone)]
+pub(crate) struct GroupTriggerCache {
+    prefs_mgr: PreferencesManager,
+    triggers: Arc<Mutex<HashMap<ChatId, GroupTrigger>>>,
+}
+
+impl GroupTriggerCache {
+    pub fn new(prefs_mgr: PreferencesManager) -> Self {
+        Self {
+            prefs_mgr,
+            triggers: Default::default(),
+        }
+    }
+
+    /// Returns the group trigger of the chat.
+    pub async fn get(&self, chat_id: ChatId) -> Result<GroupTrigger, Error> {
+        if let Some(trigger) = self.triggers.lock().unwrap().get(&chat_id) {
+            return Ok(*trigger);
+        }
+
+        let trigger = self
+            .prefs_mgr
+            .get_chat_value(&chat_id.to_string(), GROUP_TRIGGER_PREF_KEY)
+            .await?;
+        self.triggers.lock().unwrap().insert(chat_id, trigger);
+        Ok(trigger)
+    }
+
+    /// Sets the group trigger of the chat.
+    pub async fn set(&self, chat_id: ChatId, trigger: GroupTrigger) -> Result<(), Error> {
+        self.prefs_mgr
+            .set_chat_value(&chat_id.to_string(), GROUP_TRIGGER_PREF_KEY, &trigger)
+            .await?;
+        self.triggers.lock().unwrap().insert(chat_id, trigger);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{DatabaseManager, InMemDatabaseProvider};
+
+    #[tokio::test]
+    async fn test_group_trigger_cache() {
+        let db_mgr = DatabaseManager::with_db_provider(InMemDatabaseProvider).unwrap();
+        let prefs_mgr = PreferencesManager::with_db_manager(db_mgr).await.unwrap();
+        let cache = GroupTriggerCache::new(prefs_mgr.clone());
+        let chat_id = ChatId(-100);
+        assert_eq!(cache.get(chat_id).await.unwrap(), GroupTrigger::MentionOnly);
+
+        cache.set(chat_id, GroupTrigger::All).await.unwrap();
+        assert_eq!(cache.get(chat_id).await.unwrap(), GroupTrigger::All);
+        // The trigger is saved in the preferences.
+        let cache = GroupTriggerCache::new(prefs_mgr);
+        assert_eq!(cache.get(chat_id).await.unwrap(), GroupTrigger::All);
+    }
+}
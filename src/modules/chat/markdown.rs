@@ -1,3 +1,4 @@
+use std::cmp::Reverse;
 use std::marker::PhantomData;
 
 use pulldown_cmark::{
@@ -32,6 +33,7 @@ enum Event<'a> {
 #[derive(Clone, Debug)]
 enum Tag<'a> {
     Paragraph,
+    BlockQuote,
     Heading(u32),
     CodeBlock(Option<CowStr<'a>>),
     List(Option<u64>),
@@ -48,7 +50,8 @@ impl<'a> TryFrom<CmarkTag<'a>> for Tag<'a> {
 
     fn try_from(value: CmarkTag<'a>) -> Result<Self, Self::Error> {
         let mapped = match value {
-            CmarkTag::Paragraph | CmarkTag::BlockQuote => Tag::Paragraph,
+            CmarkTag::Paragraph => Tag::Paragraph,
+            CmarkTag::BlockQuote => Tag::BlockQuote,
             CmarkTag::Heading(level, _, _) => Tag::Heading(level as _),
             CmarkTag::CodeBlock(code_block_kind) => match code_block_kind {
                 CodeBlockKind::Indented => Tag::CodeBlock(None),
@@ -127,7 +130,19 @@ struct Entity {
 
 const PARAGRAPH_MARGIN: usize = 2;
 const LIST_ITEM_MARGIN: usize = 1;
-
+/// The prefix of each line in a blockquote, since teloxide doesn't support
+/// the blockquote entity of Telegram yet.
+const QUOTE_PREFIX: &str = "| ";
+/// The indentation of each level of nested lists.
+const LIST_INDENT: &str = "   ";
+/// The markers of unordered list items, by the nesting depth.
+const BULLETS: [&str; 3] = ["•", "◦", "▪"];
+/// The delimiter of spoilers, e.g. `||hidden text||`.
+const SPOILER_DELIMITER: &str = "||";
+/// The characters to escape in MarkdownV2 text, outside of code.
+const MARKDOWN_V2_SPECIAL_CHARS: &str = "_*[]()~`>#+-=|{}.!\\";
+
+#[allow(dead_code)] // Fields are only used for debug printing.
 #[derive(Debug)]
 enum ParserError<'input> {
     /// Cannot convert the Cmark tag to our tag.
@@ -151,16 +166,23 @@ struct ParseState<'p> {
     parsed_string: ParsedString,
     utf16_offset: usize,
     prev_block_margin: usize,
+    quote_depth: usize,
+    parses_spoilers: bool,
+    /// The start offset of the spoiler that is not closed yet.
+    spoiler_start: Option<usize>,
     phantom: PhantomData<&'p str>,
 }
 
 impl<'p> ParseState<'p> {
-    fn new() -> Self {
+    fn new(parses_spoilers: bool) -> Self {
         Self {
             entity_stack: Vec::new(),
             parsed_string: ParsedString::default(),
             utf16_offset: 0,
             prev_block_margin: 0,
+            quote_depth: 0,
+            parses_spoilers,
+            spoiler_start: None,
             phantom: PhantomData,
         }
     }
@@ -196,21 +218,43 @@ impl<'p> ParseState<'p> {
     fn start<'input: 'p>(&mut self, tag: Tag<'input>) -> ParserEventResult<'input> {
         match tag {
             Tag::Paragraph => {}
+            Tag::BlockQuote => {
+                self.quote_depth += 1;
+            }
             Tag::Heading(level) => {
+                self.begin_line();
                 self.push_str(&format!("{} ", "#".repeat(level as _)));
             }
             Tag::Item => {
+                let depth = self.list_depth();
                 let top_entity_kind = self.entity_stack.last().map(|e| &e.kind);
                 let item_marker = top_entity_kind
                     .ok_or_else(|| ParserError::UnmatchedEntity(top_entity_kind.cloned(), "List"))
                     .and_then(|kind| match kind {
                         EntityKind::List(Some(start)) => Ok(format!("{}. ", start)),
-                        EntityKind::List(None) => Ok("• ".to_owned()),
+                        EntityKind::List(None) => Ok(format!(
+                            "{} ",
+                            BULLETS[depth.saturating_sub(1) % BULLETS.len()]
+                        )),
                         _ => Err(ParserError::UnmatchedEntity(Some(kind.clone()), "List")),
                     })?;
-                self.push_str(&item_marker);
+                // The marker is outdented from the contents of the item.
+                let prefix = self.line_prefix(depth.saturating_sub(1));
+                self.push_str(&format!("{}{}", prefix, item_marker));
+            }
+            Tag::List(_) if self.list_depth() > 0 => {
+                // Nested lists start on a new line of the parent item.
+                self.push_block(LIST_ITEM_MARGIN);
+                self.entity_stack.push(Entity {
+                    kind: (&tag).try_into()?,
+                    start: self.utf16_offset,
+                });
             }
             ref tag_ref => {
+                // Entities start after the prefix of the line.
+                if !matches!(tag_ref, Tag::List(_)) {
+                    self.begin_line();
+                }
                 let entity_kind = tag_ref
                     .try_into()
                     .map_err(|_| ParserError::UnexpectedTag(tag))?;
@@ -229,6 +273,10 @@ impl<'p> ParseState<'p> {
             Tag::Paragraph | Tag::Heading(_) => {
                 self.push_block(PARAGRAPH_MARGIN);
             }
+            Tag::BlockQuote => {
+                self.quote_depth = self.quote_depth.saturating_sub(1);
+                self.push_block(PARAGRAPH_MARGIN);
+            }
             Tag::CodeBlock(_) => {
                 let Entity { kind, start } = self
                     .entity_stack
@@ -263,7 +311,13 @@ impl<'p> ParseState<'p> {
                     .pop()
                     .ok_or(ParserError::UnmatchedEntity(None, "List"))?;
                 if let EntityKind::List(_) = kind {
-                    self.push_block(PARAGRAPH_MARGIN);
+                    // Nested lists are followed by the next item of the
+                    // parent list.
+                    if self.list_depth() > 0 {
+                        self.push_block(LIST_ITEM_MARGIN);
+                    } else {
+                        self.push_block(PARAGRAPH_MARGIN);
+                    }
                 } else {
                     return Err(ParserError::UnmatchedEntity(Some(kind), "List"));
                 }
@@ -325,10 +379,52 @@ impl<'p> ParseState<'p> {
     }
 
     fn text(&mut self, text: CowStr) {
-        self.push_str(&text);
+        // The lines of code blocks are kept as they are.
+        let in_code_block = self.entity_stack.last().is_some_and(|entity| {
+            matches!(
+                entity.kind,
+                EntityKind::TelegramEntityKind(MessageEntityKind::Pre { .. })
+            )
+        });
+        if in_code_block {
+            self.push_str(&text);
+            return;
+        }
+        self.begin_line();
+        if !self.parses_spoilers {
+            self.push_str(&text);
+            return;
+        }
+
+        let mut rest: &str = &text;
+        while let Some(idx) = rest.find(SPOILER_DELIMITER) {
+            self.push_str(&rest[..idx]);
+            rest = &rest[idx + SPOILER_DELIMITER.len()..];
+            // Like emphasis, spoilers can't start before or end after a
+            // whitespace, so that `a || b` is kept as is.
+            match self.spoiler_start {
+                None if !rest.starts_with(char::is_whitespace) => {
+                    self.spoiler_start = Some(self.utf16_offset);
+                }
+                Some(start)
+                    if start < self.utf16_offset
+                        && !self.parsed_string.content.ends_with(char::is_whitespace) =>
+                {
+                    self.parsed_string.entities.push(MessageEntity {
+                        kind: MessageEntityKind::Spoiler,
+                        offset: start,
+                        length: self.utf16_offset - start,
+                    });
+                    self.spoiler_start = None;
+                }
+                _ => self.push_str(SPOILER_DELIMITER),
+            }
+        }
+        self.push_str(rest);
     }
 
     fn code(&mut self, text: CowStr) {
+        self.begin_line();
         let offset = self.utf16_offset;
         self.push_str(&text);
         self.parsed_string.entities.push(MessageEntity {
@@ -342,6 +438,36 @@ impl<'p> ParseState<'p> {
         self.push_str("\n");
     }
 
+    fn list_depth(&self) -> usize {
+        self.entity_stack
+            .iter()
+            .filter(|entity| matches!(entity.kind, EntityKind::List(_)))
+            .count()
+    }
+
+    /// Returns the prefix of the lines in the current blockquotes, indented
+    /// by `indent_level` levels.
+    fn line_prefix(&self, indent_level: usize) -> String {
+        format!(
+            "{}{}",
+            QUOTE_PREFIX.repeat(self.quote_depth),
+            LIST_INDENT.repeat(indent_level)
+        )
+    }
+
+    /// Pushes the prefix if a new line is started, so that the contents
+    /// of blockquotes and list items are aligned.
+    fn begin_line(&mut self) {
+        let content = &self.parsed_string.content;
+        if !content.is_empty() && !content.ends_with('\n') {
+            return;
+        }
+        let prefix = self.line_prefix(self.list_depth());
+        if !prefix.is_empty() {
+            self.push_str(&prefix);
+        }
+    }
+
     fn push_str(&mut self, string: &str) {
         let utf16_len_inc = string.encode_utf16().count();
         self.parsed_string.content.push_str(string);
@@ -360,18 +486,25 @@ impl<'p> ParseState<'p> {
     }
 }
 
-#[allow(unused)]
 pub fn parse(content: &str) -> ParsedString {
+    parse_with_options(content, true)
+}
+
+#[allow(unused, clippy::result_large_err)]
+fn parse_with_options(content: &str, parses_spoilers: bool) -> ParsedString {
     let mut options = CmarkOptions::empty();
     options.insert(CmarkOptions::ENABLE_STRIKETHROUGH);
     let mut parser = CmarkParser::new_ext(content, options);
 
-    let result = parser.try_fold(ParseState::new(), |acc, event| {
+    let result = parser.try_fold(ParseState::new(parses_spoilers), |acc, event| {
         let mapped_event = Event::try_from(event)?;
         acc.next_state(mapped_event)
     });
 
     match result {
+        // The delimiters are dropped while parsing, parse again to keep
+        // them all as text if a spoiler is not closed.
+        Ok(state) if state.spoiler_start.is_some() => parse_with_options(content, false),
         Ok(state) => state.close(),
         Err(err) => {
             error!("Error while parsing Markdown: {:?}", err);
@@ -380,6 +513,120 @@ pub fn parse(content: &str) -> ParsedString {
     }
 }
 
+/// Parses the content of a response that is still being streamed.
+///
+/// The partial content may end in the middle of a code block, which is
+/// closed before parsing so that the code keeps its formatting.
+pub fn parse_partial(content: &str) -> ParsedString {
+    let fence_count = content
+        .lines()
+        .filter(|line| line.trim_start().starts_with("```"))
+        .count();
+    if fence_count % 2 == 0 {
+        return parse(content);
+    }
+
+    let mut closed_content = content.to_owned();
+    if !closed_content.ends_with('\n') {
+        closed_content.push('\n');
+    }
+    closed_content.push_str("```");
+    parse(&closed_content)
+}
+
+/// Escapes the text to be displayed as is with the MarkdownV2 parse mode.
+pub fn escape_markdown_v2(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        if MARKDOWN_V2_SPECIAL_CHARS.contains(ch) {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+fn markdown_v2_markups(kind: &MessageEntityKind) -> (String, String) {
+    let (open, close) = match kind {
+        MessageEntityKind::Bold => ("*", "*"),
+        MessageEntityKind::Italic => ("_", "_"),
+        MessageEntityKind::Strikethrough => ("~", "~"),
+        MessageEntityKind::Spoiler => ("||", "||"),
+        MessageEntityKind::Code => ("`", "`"),
+        MessageEntityKind::Pre { language } => {
+            return (
+                format!("```{}\n", language.as_deref().unwrap_or_default()),
+                "\n```".to_owned(),
+            );
+        }
+        MessageEntityKind::TextLink { url } => {
+            let url = url.as_str().replace('\\', "\\\\").replace(')', "\\)");
+            return ("[".to_owned(), format!("]({})", url));
+        }
+        _ => ("", ""),
+    };
+    (open.to_owned(), close.to_owned())
+}
+
+/// Renders a parsed string as MarkdownV2 text, which is an alternative to
+/// sending the entities. Overlapping entities may produce invalid markup,
+/// which Telegram rejects.
+pub fn to_markdown_v2(parsed: &ParsedString) -> String {
+    let mut entities: Vec<_> = parsed
+        .entities
+        .iter()
+        .filter(|entity| entity.length > 0)
+        .collect();
+    // The outer entities are opened first.
+    entities.sort_by_key(|entity| (entity.offset, Reverse(entity.length)));
+    let mut pending_entities = entities.into_iter().peekable();
+    let mut open_entities: Vec<(&MessageEntity, String)> = vec![];
+
+    let mut output = String::with_capacity(parsed.content.len());
+    let mut utf16_offset = 0;
+    let mut chars = parsed.content.chars();
+    loop {
+        while open_entities
+            .last()
+            .is_some_and(|(entity, _)| entity.offset + entity.length <= utf16_offset)
+        {
+            let (_, close) = open_entities.pop().unwrap();
+            output.push_str(&close);
+        }
+        while let Some(entity) = pending_entities.next_if(|entity| entity.offset <= utf16_offset) {
+            let (open, close) = markdown_v2_markups(&entity.kind);
+            output.push_str(&open);
+            open_entities.push((entity, close));
+        }
+
+        let ch = match chars.next() {
+            Some(ch) => ch,
+            None => break,
+        };
+        let in_code = open_entities.iter().any(|(entity, _)| {
+            matches!(
+                entity.kind,
+                MessageEntityKind::Code | MessageEntityKind::Pre { .. }
+            )
+        });
+        let needs_escape = if in_code {
+            ch == '`' || ch == '\\'
+        } else {
+            MARKDOWN_V2_SPECIAL_CHARS.contains(ch)
+        };
+        if needs_escape {
+            output.push('\\');
+        }
+        output.push(ch);
+        utf16_offset += ch.len_utf16();
+    }
+    // Close the entities that exceed the content.
+    while let Some((_, close)) = open_entities.pop() {
+        output.push_str(&close);
+    }
+    output
+}
+
 #[cfg(test)]
 mod tests {
     use teloxide::types::{MessageEntity, MessageEntityKind};
@@ -421,6 +668,89 @@ Next Paragraph"#;
         assert_eq!(parsed.content, expected_content);
     }
 
+    #[test]
+    fn test_parse_nested_list() {
+        let raw = r#"- item 1
+  - item 1.1
+    1. item 1.1.1
+    2. item 1.1.2
+  - item 1.2
+- item 2
+  continued
+
+End"#;
+        let expected_content = r#"• item 1
+   ◦ item 1.1
+      1. item 1.1.1
+      2. item 1.1.2
+   ◦ item 1.2
+• item 2
+   continued
+
+End"#;
+        let parsed = parse(raw);
+
+        assert_eq!(parsed.content, expected_content);
+    }
+
+    #[test]
+    fn test_parse_blockquote() {
+        let raw = r#"> Quoted **bold**
+> - item
+>
+> > Nested
+
+End"#;
+        let expected_content = r#"| Quoted bold
+
+| • item
+
+| | Nested
+
+End"#;
+        let parsed = parse(raw);
+
+        assert_eq!(parsed.content, expected_content);
+        assert!(matches!(
+            parsed.entities[0],
+            MessageEntity {
+                kind: MessageEntityKind::Bold,
+                offset: 9,
+                length: 4
+            }
+        ));
+    }
+
+    #[test]
+    fn test_spoiler() {
+        let raw = "The answer is ||**42**||, a || b, and ||unclosed";
+        let parsed = parse(raw);
+        assert_eq!(
+            parsed.content,
+            "The answer is ||42||, a || b, and ||unclosed"
+        );
+        assert_eq!(parsed.entities.len(), 1);
+
+        let raw = "The answer is ||**42**||, a || b";
+        let parsed = parse(raw);
+        assert_eq!(parsed.content, "The answer is 42, a || b");
+        assert!(matches!(
+            parsed.entities[..],
+            [
+                MessageEntity {
+                    kind: MessageEntityKind::Bold,
+                    offset: 14,
+                    length: 2
+                },
+                MessageEntity {
+                    kind: MessageEntityKind::Spoiler,
+                    offset: 14,
+                    length: 2
+                }
+            ]
+        ));
+    }
+
     #[test]
     fn test_code() {
         let raw = r#"This is a code snippet:
@@ -444,7 +774,9 @@ End"#;
                     language: Some(ref lang)
                 },
                 offset: 25,
-                length: 19
+                // Only `printf("hello\n");`, pulldown-cmark 0.9.6 leaves the
+                // newline before the closing fence out of the code.
+                length: 18
             } if lang == "c"
         ));
     }
@@ -495,4 +827,30 @@ End"#;
         assert_eq!(parsed.content, expected_content);
         assert_eq!(parsed.entities[0].length, 19);
     }
+
+    #[test]
+    fn test_to_markdown_v2() {
+        let raw = "Hi **bold *both*** and [a link](https://example.com/a_(b)), 1.5!\n\n```rust\nlet s = `x`;\n```";
+        assert_eq!(
+            to_markdown_v2(&parse(raw)),
+            "Hi *bold _both_* and [a link](https://example.com/a_(b\\)), 1\\.5\\!\n\n```rust\nlet s = \\`x\\`;\n```"
+        );
+        assert_eq!(escape_markdown_v2("a-b (c)"), "a\\-b \\(c\\)");
+    }
+
+    #[test]
+    fn test_parse_partial_code() {
+        let raw = "Code:\n```rust\nfn main() {";
+        let parsed = parse_partial(raw);
+
+        assert_eq!(parsed.content, "Code:\n\nfn main() {");
+        assert!(matches!(
+            parsed.entities[0],
+            MessageEntity {
+                kind: MessageEntityKind::Pre { .. },
+                offset: 7,
+                ..
+            }
+        ));
+    }
 }
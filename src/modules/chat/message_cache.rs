@@ -0,0 +1,154 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use teloxide::prelude::*;
+
+use crate::config::SharedConfig;
+
+const SUMMARIZE_INSTRUCTION: &str = "Summarize the group chat below. List the main topics, \
+decisions and open questions, and mention who said what when it matters. Reply with the \
+summary only.";
+
+/// A message seen in a group.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct CachedMessage {
+    pub sender: String,
+    pub text: String,
+    received_at: Instant,
+}
+
+/// Keeps the recent messages of each group in memory, so that they can be
+/// summarized even if the bot is not mentioned in them.
+#[derive(Clone)]
+pub(crate) struct GroupMessageCache {
+    chats: Arc<Mutex<HashMap<ChatId, VecDeque<CachedMessage>>>>,
+    config: SharedConfig,
+}
+
+impl GroupMessageCache {
+    pub fn new(config: SharedConfig) -> Self {
+        Self {
+            chats: Default::default(),
+            config,
+        }
+    }
+
+    /// Keeps the message if the cache is enabled and it's a text message
+    /// in a group.
+    pub fn record(&self, msg: &Message) {
+        let config = self.config.load();
+        let cache_config = match &config.group_message_cache {
+            Some(cache_config) => cache_config,
+            None => return,
+        };
+        if msg.chat.is_private() {
+            return;
+        }
+        let user = match msg.from() {
+            Some(user) if !user.is_bot => user,
+            _ => return,
+        };
+        let excluded = user
+            .username
+            .as_ref()
+            .map(|username| cache_config.excluded_usernames.contains(username))
+            .unwrap_or(false);
+        if excluded {
+            return;
+        }
+        let text = match msg.text().or_else(|| msg.caption()) {
+            Some(text) if !text.is_empty() && !text.starts_with('/') => text,
+            _ => return,
+        };
+
+        self.push(
+            msg.chat.id,
+            CachedMessage {
+                sender: user.full_name(),
+                text: text.to_owned(),
+                received_at: Instant::now(),
+            },
+            cache_config.capacity,
+        );
+    }
+
+    /// Returns the last `count` messages of the chat that are not expired.
+    pub fn recent(&self, chat_id: ChatId, count: usize) -> Vec<CachedMessage> {
+        let ttl = match &self.config.load().group_message_cache {
+            Some(cache_config) => Duration::from_secs(cache_config.ttl_minutes * 60),
+            None => return vec![],
+        };
+        let mut chats = self.chats.lock().unwrap();
+        let messages = match chats.get_mut(&chat_id) {
+            Some(messages) => messages,
+            None => return vec![],
+        };
+        while let Some(oldest) = messages.front() {
+            if oldest.received_at.elapsed() <= ttl {
+                break;
+            }
+            messages.pop_front();
+        }
+        let skipped = messages.len().saturating_sub(count);
+        messages.iter().skip(skipped).cloned().collect()
+    }
+
+    /// Drops the messages of the chat, returns the number of them.
+    pub fn forget(&self, chat_id: ChatId) -> usize {
+        self.chats
+            .lock()
+            .unwrap()
+            .remove(&chat_id)
+            .map(|messages| messages.len())
+            .unwrap_or(0)
+    }
+
+    fn push(&self, chat_id: ChatId, message: CachedMessage, capacity: usize) {
+        let mut chats = self.chats.lock().unwrap();
+        let messages = chats.entry(chat_id).or_default();
+        messages.push_back(message);
+        while messages.len() > capacity {
+            messages.pop_front();
+        }
+    }
+}
+
+/// Builds the prompt that asks the model to summarize the messages.
+pub(crate) fn summarize_prompt(messages: &[CachedMessage]) -> String {
+    let mut prompt = format!("{}\n\n", SUMMARIZE_INSTRUCTION);
+    for message in messages {
+        let _ = writeln!(&mut prompt, "{}: {}", message.sender, message.text);
+    }
+    prompt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_messages() {
+        let config = serde_json::from_str(r#"{"botToken": "", "groupMessageCache": {}}"#).unwrap();
+        let cache = GroupMessageCache::new(SharedConfig::new(config));
+        let chat_id = ChatId(-100);
+        for idx in 0..3 {
+            let message = CachedMessage {
+                sender: "Alice".to_owned(),
+                text: idx.to_string(),
+                received_at: Instant::now(),
+            };
+            cache.push(chat_id, message, 2);
+        }
+
+        let recent = cache.recent(chat_id, 10);
+        let texts: Vec<_> = recent.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, ["1", "2"]);
+        assert_eq!(cache.recent(chat_id, 1)[0].text, "2");
+        assert!(summarize_prompt(&recent).ends_with("Alice: 1\nAlice: 2\n"));
+
+        assert_eq!(cache.forget(chat_id), 2);
+        assert!(cache.recent(chat_id, 10).is_empty());
+    }
+}
@@ -1,53 +1,288 @@
 #![allow(clippy::too_many_arguments)]
 
+mod archive;
 mod braille;
+mod code_file;
+mod deep_link;
+mod degraded;
+mod directives;
+mod feedback;
+mod group_trigger;
 mod markdown;
+mod message_cache;
+mod moderation;
+mod pending_store;
+mod persona_mgr;
+mod reply_length;
+mod reply_template;
 mod session;
 mod session_mgr;
+mod web_search;
 
+use std::fmt::Write;
+use std::slice;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::Error;
 use async_openai::types::{ChatCompletionRequestMessage, ChatCompletionRequestMessageArgs, Role};
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use chrono::Utc;
 use futures::StreamExt as FuturesStreamExt;
 use teloxide::dispatching::DpHandlerDescription;
 use teloxide::dptree::di::DependencySupplier;
+use teloxide::net::Download;
 use teloxide::prelude::*;
-use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, Me};
+use teloxide::types::{
+    InlineKeyboardButton, InlineKeyboardMarkup, InputFile, Me, MessageEntity, MessageId, ParseMode,
+    PhotoSize, User, Voice,
+};
+use tokio::sync::Notify;
 
 use crate::{
-    config::SharedConfig,
+    config::{RenderMode, SharedConfig},
+    database::DatabaseManager,
     dispatcher::noop_handler,
+    event_bus::{Event, EventBus},
     module_mgr::{Command, Module},
-    modules::openai::{ChatModelResult, OpenAIClient},
-    modules::{admin::MemberManager, stats::StatsManager},
-    types::HandlerResult,
-    utils::StreamExt,
+    modules::admin::{is_admin, MemberManager, MemberRole, RoleManager},
+    modules::openai::{can_select_model, ChatModelParams, ChatModelResult, OpenAIClient},
+    modules::prefs::{
+        language_instruction, PreferencesManager, RENDER_MARKDOWN_PREF_KEY, REPLY_LANGUAGE_PREF_KEY,
+    },
+    modules::stats::{QuotaFeature, QuotaManager},
+    telemetry::hash_chat_id,
+    types::{HandlerResult, TeloxideHandler},
+    utils::{
+        auto_delete::schedule_deletion,
+        dptree_ext::{command_with_args, extract_command_args, CommandArgs},
+        i18n::user_language,
+        sender::{CoalescedEditor, RetryExt},
+        StreamExt,
+    },
 };
-use braille::BrailleProgress;
+use archive::Archive;
+use braille::{format_generation_stats, BrailleProgress};
+use code_file::{extract_code_file, CodeFile};
+use deep_link::StartPayload;
+use degraded::is_permission_error;
+pub(crate) use degraded::DegradedChats;
+use directives::Directives;
+use feedback::{feedback_buttons, Feedback};
+pub(crate) use group_trigger::{GroupTrigger, GroupTriggerCache};
+pub(crate) use message_cache::GroupMessageCache;
+use message_cache::{summarize_prompt, CachedMessage};
+use moderation::{moderate_content, ContentSource, Verdict};
+use pending_store::PendingMessageStore;
+use persona_mgr::Persona;
+pub(crate) use persona_mgr::{is_valid_persona_name, PersonaManager};
+use reply_length::{ReplyLength, REPLY_LENGTH_PREF_KEY};
+use reply_template::{context_indicator, decorate_answer};
 pub(crate) use session::Session;
 pub(crate) use session_mgr::SessionManager;
+use session_mgr::{
+    named_session_key, parse_session_key, session_key, topic_id, SessionName, DEFAULT_SESSION_NAME,
+};
+use web_search::{append_sources, render_citations, search_prompt, SearchResult};
+
+const VOICE_REPLY_PREF_KEY: &str = "VoiceReply";
+/// Whether to show how much the context holds under each answer.
+const CONTEXT_INDICATOR_PREF_KEY: &str = "ContextIndicator";
+/// The preference of groups to answer in the private chats of the senders.
+pub(crate) const PRIVATE_ANSWERS_PREF_KEY: &str = "PrivateAnswers";
+/// The system prompt of the chat, which overrides the one from config. An
+/// empty prompt means no system prompt.
+const SYSTEM_PROMPT_PREF_KEY: &str = "SystemPrompt";
+
+/// The question sent by `/continue`.
+const CONTINUE_PROMPT: &str =
+    "Continue from where you stopped, without repeating what you have already said.";
+
+/// The number of recent messages that `/summarize` summarizes by default.
+const DEFAULT_SUMMARIZE_COUNT: usize = 50;
+
+/// The progress animation stops after this many consecutive failed edits,
+/// e.g. when the message is deleted.
+const MAX_PROGRESS_EDIT_FAILURES: u32 = 3;
+/// The progress animation pauses when the stream is silent for this long,
+/// and resumes once the stream is resumed.
+const PROGRESS_STALL_THRESHOLD: Duration = Duration::from_secs(10);
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct MessageText(String);
 
+/// Overrides for requesting a single answer.
+#[derive(Clone, Debug, Default)]
+struct AnswerOptions {
+    /// `true` if the answer replaces a previous one, which skips the
+    /// response cache and is not counted as another request in stats.
+    is_regeneration: bool,
+    /// Overrides the model of the chat.
+    model: Option<String>,
+    /// Overrides the temperature of the chat.
+    temperature: Option<f32>,
+    /// The web search results in the prompt, which are cited by the answer.
+    sources: Vec<SearchResult>,
+    /// The question kept in the history instead of the prompt, e.g. the
+    /// query without the search results.
+    question: Option<String>,
+    /// The message of the previous answer, which is edited to show the new
+    /// answer instead of sending another message.
+    reused_message_id: Option<MessageId>,
+    /// The answer continued by `/continue`, which the new answer is merged
+    /// into, without keeping the question.
+    continued_answer_id: Option<i64>,
+    /// The answer regenerated by `/retry`, which is replaced with its
+    /// question once the new answer is generated.
+    retried_answer_id: Option<i64>,
+}
+
 async fn handle_chat_message(
     bot: Bot,
     me: Me,
     msg: Message,
-    chat_id: ChatId,
     session_mgr: SessionManager,
-    stats_mgr: StatsManager,
+    event_bus: EventBus,
+    member_mgr: MemberManager,
+    prefs_mgr: PreferencesManager,
+    openai_client: OpenAIClient,
+    config: SharedConfig,
+) -> bool {
+    answer_message(
+        bot,
+        me,
+        msg,
+        session_mgr,
+        event_bus,
+        member_mgr,
+        prefs_mgr,
+        openai_client,
+        config,
+        AnswerOptions::default(),
+    )
+    .await
+}
+
+/// Regenerates the answer of an edited question, in the message of the
+/// previous answer. The messages after the question are dropped from the
+/// session, since they follow the previous answer.
+async fn handle_edited_message(
+    bot: Bot,
+    me: Me,
+    msg: Message,
+    session_mgr: SessionManager,
+    event_bus: EventBus,
+    member_mgr: MemberManager,
+    prefs_mgr: PreferencesManager,
+    openai_client: OpenAIClient,
+    config: SharedConfig,
+) -> bool {
+    let is_command = msg
+        .text()
+        .or_else(|| msg.caption())
+        .map(|text| text.starts_with('/'))
+        .unwrap_or(true);
+    if is_command {
+        return false;
+    }
+    let username = msg.from().and_then(|u| u.username.clone());
+    if !member_mgr
+        .is_member_allowed(username.unwrap_or_default())
+        .await
+        .unwrap_or(false)
+    {
+        return false;
+    }
+
+    let key = session_mgr
+        .active_session_key(&msg.chat.id.to_string(), topic_id(&msg))
+        .await;
+    let answer_message_ids =
+        session_mgr.with_mut_session(key, |session| session.rollback_question(msg.id.0));
+    let mut answer_message_ids = match answer_message_ids {
+        Some(answer_message_ids) => answer_message_ids.into_iter(),
+        // The question is not answered, or is no longer in the session.
+        None => return false,
+    };
+    let reused_message_id = answer_message_ids.next().map(MessageId);
+    // The other messages of the answer (e.g. the voice) are outdated.
+    for telegram_message_id in answer_message_ids {
+        if let Err(err) = bot
+            .delete_message(msg.chat.id, MessageId(telegram_message_id))
+            .await
+        {
+            error!("Failed to revoke the previous answer: {}", err);
+        }
+    }
+
+    answer_message(
+        bot,
+        me,
+        msg,
+        session_mgr,
+        event_bus,
+        member_mgr,
+        prefs_mgr,
+        openai_client,
+        config,
+        AnswerOptions {
+            is_regeneration: true,
+            reused_message_id,
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Prepares the input of the message, e.g. transcribes the voice, and
+/// answers it.
+async fn answer_message(
+    bot: Bot,
+    me: Me,
+    msg: Message,
+    session_mgr: SessionManager,
+    event_bus: EventBus,
     member_mgr: MemberManager,
+    prefs_mgr: PreferencesManager,
     openai_client: OpenAIClient,
     config: SharedConfig,
+    mut options: AnswerOptions,
 ) -> bool {
-    let mut text = msg.text().map_or(Default::default(), |t| t.to_owned());
-    let chat_id = chat_id.to_string();
+    let mut text = msg
+        .text()
+        .or_else(|| msg.caption())
+        .map_or(Default::default(), |t| t.to_owned());
+    let chat_id = msg.chat.id.to_string();
 
     if text.starts_with('/') {
-        // Let other modules to process the command.
+        // `/ask` and `/continue` are answered like other messages, and the
+        // other commands are processed by other modules.
+        match extract_command_args(&text, "ask", me.username()) {
+            Some(question) if !question.trim().is_empty() => text = question.to_owned(),
+            Some(_) => {
+                reply_notice(&bot, &msg, "Usage: /ask <question>", &config).await;
+                return true;
+            }
+            None if extract_command_args(&text, "continue", me.username()).is_some() => {
+                let key = session_mgr
+                    .active_session_key(&chat_id, topic_id(&msg))
+                    .await;
+                match session_mgr.with_mut_session(key, |session| session.last_answer()) {
+                    Some((answer_id, _)) => options.continued_answer_id = Some(answer_id),
+                    None => {
+                        reply_notice(&bot, &msg, "There is no answer to continue.", &config).await;
+                        return true;
+                    }
+                }
+                text = CONTINUE_PROMPT.to_owned();
+            }
+            None => return false,
+        }
+    }
+    if msg.photo().is_some() && !config.load().image_input {
+        return false;
+    }
+    if msg.voice().is_some() && !config.load().voice_input {
         return false;
     }
 
@@ -60,10 +295,21 @@ async fn handle_chat_message(
         .await
         .unwrap_or(false)
     {
-        let _ = bot
-            .send_message(msg.chat.id, &config.i18n.not_allowed_prompt)
-            .reply_to_message_id(msg.id)
-            .await;
+        event_bus.publish(Event::MemberDenied {
+            chat_id: chat_id.clone(),
+            user_id: msg.from().map(|u| u.id.0),
+            username: msg.from().and_then(|u| u.username.clone()),
+        });
+        reply_notice(
+            &bot,
+            &msg,
+            &config
+                .load()
+                .i18n_strings(user_language(&msg))
+                .not_allowed_prompt,
+            &config,
+        )
+        .await;
         return true;
     }
 
@@ -78,15 +324,140 @@ async fn handle_chat_message(
     }
     text = text.trim().to_owned();
 
+    // Apply the one-off overrides, e.g. `!gpt-4o !t=0.9 <question>`.
+    let directives = Directives::parse(&text, &openai_client.available_models())
+        .map(|(directives, rest)| (directives, rest.to_owned()));
+    match directives {
+        Ok((directives, _)) if directives.is_empty() => {}
+        Ok((directives, rest)) => {
+            if directives.model.is_some()
+                && !can_select_model(msg.from(), &member_mgr, &config).await
+            {
+                reply_notice(&bot, &msg, "You are not allowed to select models.", &config).await;
+                return true;
+            }
+            if rest.is_empty() && msg.photo().is_none() && msg.voice().is_none() {
+                reply_notice(
+                    &bot,
+                    &msg,
+                    "Please send the question after the overrides.",
+                    &config,
+                )
+                .await;
+                return true;
+            }
+            options.model = directives.model.or(options.model);
+            options.temperature = directives.temperature.or(options.temperature);
+            text = rest;
+        }
+        Err(err) => {
+            reply_notice(&bot, &msg, err, &config).await;
+            return true;
+        }
+    }
+
+    let mut image_urls = vec![];
+    if let Some(photo_sizes) = msg.photo() {
+        let model = match &options.model {
+            Some(model) => model.clone(),
+            None => openai_client.chat_model(Some(&chat_id)).await,
+        };
+        if !openai_client.supports_image_input(&model) {
+            reply_notice(
+                &bot,
+                &msg,
+                format!(
+                    "The current model ({}) doesn't accept images, please switch to a vision model with /model.",
+                    model
+                ),
+                &config,
+            )
+            .await;
+            return true;
+        }
+
+        match download_photo(&bot, photo_sizes).await {
+            Ok(image_url) => image_urls.push(image_url),
+            Err(err) => {
+                error!("Failed to download the photo: {}", err);
+                reply_notice(
+                    &bot,
+                    &msg,
+                    &config
+                        .load()
+                        .i18n_strings(user_language(&msg))
+                        .api_error_prompt,
+                    &config,
+                )
+                .await;
+                return true;
+            }
+        }
+        if text.is_empty() {
+            text = "Describe this image.".to_owned();
+        }
+    }
+
+    if let Some(voice) = msg.voice() {
+        let transcription = match transcribe_voice(&bot, voice, &openai_client).await {
+            Ok(transcription) if !transcription.is_empty() => transcription,
+            Ok(_) => {
+                reply_notice(
+                    &bot,
+                    &msg,
+                    "No speech is recognized in the voice message.",
+                    &config,
+                )
+                .await;
+                return true;
+            }
+            Err(err) => {
+                error!("Failed to transcribe the voice message: {}", err);
+                reply_notice(
+                    &bot,
+                    &msg,
+                    &config
+                        .load()
+                        .i18n_strings(user_language(&msg))
+                        .api_error_prompt,
+                    &config,
+                )
+                .await;
+                return true;
+            }
+        };
+
+        if config.load().echo_transcription {
+            let res = reply_in_topic(&bot, &msg, format!("🎤 “{}”", transcription))
+                .send_retrying()
+                .await;
+            if let Err(err) = res {
+                error!("Failed to send the transcription: {}", err);
+            }
+        }
+
+        // The caption (if any) follows the transcription as a supplement.
+        text = if text.is_empty() {
+            transcription
+        } else {
+            format!("{}\n\n{}", transcription, text)
+        };
+    }
+
+    let topic_id = topic_id(&msg);
     if let Err(err) = actually_handle_chat_message(
         bot,
         Some(msg),
         text,
+        image_urls,
         chat_id,
+        topic_id,
         session_mgr,
-        stats_mgr,
+        event_bus,
+        prefs_mgr,
         openai_client,
         config,
+        options,
     )
     .await
     {
@@ -96,17 +467,239 @@ async fn handle_chat_message(
     true
 }
 
+/// Returns `true` if the text is a command that asks the model, which is
+/// limited by the quotas like other messages.
+fn is_question_command(text: &str, username: &str) -> bool {
+    let is_prompt_link = matches!(
+        extract_command_args(text, "start", username).and_then(StartPayload::parse),
+        Some(StartPayload::Prompt(_))
+    );
+    is_prompt_link
+        || ["ask", "continue", "retry", "search", "summarize"]
+            .iter()
+            .any(|cmd| extract_command_args(text, cmd, username).is_some())
+}
+
+/// Runs the handler of the command unless the sender has used up the
+/// quotas. Commands are handled before the quotas of other messages are
+/// enforced, so the commands that ask the model are wrapped with it.
+fn with_quotas(handler: TeloxideHandler) -> TeloxideHandler {
+    dptree::entry()
+        .branch(dptree::filter_async(enforce_quotas).endpoint(noop_handler))
+        .branch(handler)
+}
+
+/// Ignores the messages in chats (or topics) that the bot can't send
+/// messages to.
+async fn skip_degraded_chat(msg: Message, degraded_chats: DegradedChats) -> bool {
+    let is_command = msg.text().map(|t| t.starts_with('/')).unwrap_or(false);
+    !is_command
+        && degraded_chats.should_skip(&session_key(&msg.chat.id.to_string(), topic_id(&msg)))
+}
+
+/// Rejects the messages of users who have used up their daily quotas, and
+/// counts the media of the accepted ones.
+async fn enforce_quotas(
+    bot: Bot,
+    me: Me,
+    msg: Message,
+    quota_guard: QuotaGuard,
+    openai_client: OpenAIClient,
+    config: SharedConfig,
+) -> bool {
+    let text = msg.text().or_else(|| msg.caption()).unwrap_or_default();
+    if text.starts_with('/') && !is_question_command(text, me.username()) {
+        return false;
+    }
+    let user = match msg.from() {
+        Some(user) => user,
+        None => return false,
+    };
+
+    let mut media = vec![];
+    // The photos are rejected by the chat handler if the model doesn't
+    // accept images, so they are not counted.
+    if msg.photo().is_some()
+        && config.load().image_input
+        && openai_client.supports_image_input(&photo_model(&msg, &me, &openai_client).await)
+    {
+        media.push(QuotaFeature::Images);
+    }
+    if msg.voice().is_some() && config.load().voice_input {
+        media.push(QuotaFeature::VoiceMessages);
+    }
+
+    match quota_guard.exceeded_quota(user, media).await {
+        Some(feature) => {
+            reply_notice(&bot, &msg, quota_notice(feature), &config).await;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Checks the daily quotas of the users before asking the model.
+#[derive(Clone)]
+struct QuotaGuard {
+    member_mgr: MemberManager,
+    quota_mgr: QuotaManager,
+    role_mgr: RoleManager,
+    config: SharedConfig,
+}
+
+impl QuotaGuard {
+    /// Checks the quotas of the user for a request to the model with the
+    /// media, and counts the media if it's accepted. Returns the feature
+    /// whose quota is used up, [`None`] if the request is accepted.
+    async fn exceeded_quota(&self, user: &User, media: Vec<QuotaFeature>) -> Option<QuotaFeature> {
+        let role = self.role_mgr.role_of(user).await.unwrap_or_else(|err| {
+            error!("Failed to get the role of {}: {}", user.id, err);
+            MemberRole::default()
+        });
+        if role == MemberRole::Admin {
+            return None;
+        }
+        // Users who are not allowed are rejected by the chat handler.
+        if !self
+            .member_mgr
+            .is_member_allowed(user.username.clone().unwrap_or_default())
+            .await
+            .unwrap_or(false)
+        {
+            return None;
+        }
+
+        let mut features = vec![QuotaFeature::ChatTokens];
+        if role == MemberRole::Guest {
+            features.push(QuotaFeature::GuestMessages);
+        }
+        features.extend(media);
+
+        let quotas = self.config.load().daily_quotas;
+        for feature in &features {
+            match self
+                .quota_mgr
+                .is_exceeded(user.id.0, *feature, &quotas)
+                .await
+            {
+                Ok(true) => return Some(*feature),
+                Ok(false) => {}
+                Err(err) => error!("Failed to check the quota: {}", err),
+            }
+        }
+
+        // Chat tokens are counted once the answers are completed.
+        for feature in features {
+            if feature == QuotaFeature::ChatTokens {
+                continue;
+            }
+            if let Err(err) = self.quota_mgr.add_usage(user.id.0, feature, 1).await {
+                error!("Failed to add quota usage: {}", err);
+            }
+        }
+
+        None
+    }
+}
+
+/// The notice to the users who have used up the quota of the feature.
+fn quota_notice(feature: QuotaFeature) -> String {
+    format!(
+        "You have used up today's quota of {}, please try again tomorrow. Send /usage to see your usage.",
+        feature.name()
+    )
+}
+
+/// Returns the model that will answer the photo, i.e. the one in the
+/// overrides of the caption, or the model of the chat.
+async fn photo_model(msg: &Message, me: &Me, openai_client: &OpenAIClient) -> String {
+    let caption = msg.caption().unwrap_or_default().trim_start();
+    let caption = caption
+        .strip_prefix('@')
+        .and_then(|text| text.strip_prefix(me.username()))
+        .unwrap_or(caption)
+        .trim();
+    let model = Directives::parse(caption, &openai_client.available_models())
+        .ok()
+        .and_then(|(directives, _)| directives.model);
+    match model {
+        Some(model) => model,
+        None => {
+            openai_client
+                .chat_model(Some(&msg.chat.id.to_string()))
+                .await
+        }
+    }
+}
+
+/// Updates the degraded chats with the results of requests.
+async fn track_chat_health(
+    bot: Bot,
+    degraded_chats: DegradedChats,
+    config: SharedConfig,
+    event: Event,
+) {
+    match event {
+        Event::SendForbidden {
+            chat_id,
+            topic_id,
+            user_id,
+            reason,
+        } => {
+            // A closed topic doesn't affect the other topics of the chat.
+            let key = session_key(&chat_id, topic_id);
+            if !degraded_chats.mark(&key, reason.clone()) {
+                return;
+            }
+            warn!(
+                "Chat {} is degraded, the bot can't send messages to it: {}",
+                key, reason
+            );
+
+            let user_id = match user_id {
+                Some(user_id) if config.load().notify_user_on_send_failure => user_id,
+                _ => return,
+            };
+            let res = bot
+                .send_message(
+                    UserId(user_id),
+                    "Sorry, I don't have the permission to answer you in that group. Please ask the group admins to check my permissions.",
+                )
+                .send_retrying()
+                .await;
+            if let Err(err) = res {
+                debug!("Failed to notify the user of the send failure: {}", err);
+            }
+        }
+        Event::ChatCompleted {
+            chat_id, topic_id, ..
+        } => {
+            let key = session_key(&chat_id, topic_id);
+            if degraded_chats.clear(&key) {
+                info!("Chat {} is recovered", key);
+            }
+        }
+        _ => {}
+    }
+}
+
 async fn handle_retry_action(
     bot: Bot,
     query: CallbackQuery,
     session_mgr: SessionManager,
-    stats_mgr: StatsManager,
+    event_bus: EventBus,
+    prefs_mgr: PreferencesManager,
     openai_client: OpenAIClient,
     config: SharedConfig,
 ) -> bool {
-    if !query.data.map(|data| data == "/retry").unwrap_or(false) {
-        return false;
-    }
+    let session_name = match query
+        .data
+        .as_deref()
+        .and_then(|data| data.strip_prefix("/retry:"))
+    {
+        Some(session_name) => session_name.to_owned(),
+        None => return false,
+    };
 
     let message = query.message;
     if message.is_none() {
@@ -120,22 +713,46 @@ async fn handle_retry_action(
     }
 
     let chat_id = message.chat.id.to_string();
-    let last_message = session_mgr.swap_session_pending_message(chat_id.clone(), None);
-    if last_message.is_none() {
-        error!("Last message not found");
-        return true;
-    }
-    let last_message = last_message.unwrap();
+    let topic_id = topic_id(&message);
+    let last_message = session_mgr
+        .take_pending_message(message_session_key(&message, &session_name))
+        .await;
+    let last_message = match last_message {
+        Ok(Some(last_message)) if !last_message.is_stale() => last_message,
+        Ok(Some(_)) => {
+            let _ = bot
+                .answer_callback_query(query.id)
+                .text("The message is too old to retry, please send it again.")
+                .await;
+            return true;
+        }
+        // Already retried, or dropped with the reset of the session.
+        Ok(None) => {
+            let _ = bot
+                .answer_callback_query(query.id)
+                .text("The message is no longer available, please send it again.")
+                .await;
+            return true;
+        }
+        Err(err) => {
+            error!("Failed to take the pending message: {}", err);
+            return true;
+        }
+    };
 
     if let Err(err) = actually_handle_chat_message(
         bot,
         None,
         last_message.content,
+        vec![],
         chat_id,
+        topic_id,
         session_mgr,
-        stats_mgr,
+        event_bus,
+        prefs_mgr,
         openai_client,
         config,
+        AnswerOptions::default(),
     )
     .await
     {
@@ -145,266 +762,2881 @@ async fn handle_retry_action(
     true
 }
 
-async fn handle_show_raw_action(
+async fn handle_regenerate_action(
     bot: Bot,
     query: CallbackQuery,
     session_mgr: SessionManager,
+    event_bus: EventBus,
+    prefs_mgr: PreferencesManager,
+    quota_guard: QuotaGuard,
+    role_mgr: RoleManager,
+    openai_client: OpenAIClient,
+    config: SharedConfig,
 ) -> bool {
-    let history_msg_id: Option<i64> = query
-        .data
-        .as_ref()
-        .and_then(|data| data.strip_prefix("/show_raw:"))
-        .and_then(|id_str| id_str.parse().ok());
-    if history_msg_id.is_none() {
-        return false;
-    }
-    let history_msg_id = history_msg_id.unwrap();
+    let (history_msg_id, session_name) =
+        match parse_answer_action(query.data.as_deref(), "/regenerate:") {
+            Some(action) => action,
+            None => return false,
+        };
 
-    let message = query.message;
-    if message.is_none() {
-        return false;
+    let message = match query.message {
+        Some(message) => message,
+        None => return false,
+    };
+    // The answer is regenerated with the quota of the sender.
+    if !can_act_on_answer(&query.from, &message, &role_mgr).await {
+        let _ = bot
+            .answer_callback_query(query.id)
+            .text("Only the sender of the question can regenerate it.")
+            .await;
+        return true;
+    }
+    if let Some(feature) = quota_guard.exceeded_quota(&query.from, vec![]).await {
+        let _ = bot
+            .answer_callback_query(query.id)
+            .text(quota_notice(feature))
+            .await;
+        return true;
     }
-    let message = message.unwrap();
-    let chat_id = message.chat.id;
-
-    let history_message = session_mgr.with_mut_session(chat_id.to_string(), |session| {
-        session.get_history_message(history_msg_id)
-    });
 
-    match history_message {
-        Some(history_message) => {
+    let chat_id = message.chat.id.to_string();
+    let topic_id = topic_id(&message);
+    let question = session_mgr
+        .with_mut_session(message_session_key(&message, &session_name), |session| {
+            session.rollback_answer(history_msg_id)
+        });
+    let question = match question {
+        Some(question) => question,
+        None => {
             let _ = bot
-                .edit_message_text(chat_id, message.id, history_message.content)
+                .answer_callback_query(query.id)
+                .text("Only the latest answer can be regenerated.")
                 .await;
+            return true;
         }
-        None => {
-            let _ = bot.send_message(chat_id, "The message is stale.").await;
-        }
+    };
+
+    if let Err(err) = bot.delete_message(message.chat.id, message.id).await {
+        error!("Failed to revoke the previous answer: {}", err);
+    }
+
+    // The answer replies to the question, reply to it again so that the
+    // sender and the thread are kept.
+    if let Err(err) = actually_handle_chat_message(
+        bot,
+        message.reply_to_message().cloned(),
+        question.content,
+        vec![],
+        chat_id,
+        topic_id,
+        session_mgr,
+        event_bus,
+        prefs_mgr,
+        openai_client,
+        config,
+        AnswerOptions {
+            is_regeneration: true,
+            ..Default::default()
+        },
+    )
+    .await
+    {
+        error!("Failed to regenerate the answer: {}", err);
     }
 
     true
 }
 
-async fn actually_handle_chat_message(
+/// Regenerates the last answer of the session with the same question,
+/// optionally with another temperature.
+async fn retry_last_answer(
     bot: Bot,
-    reply_to_msg: Option<Message>,
-    content: String,
-    chat_id: String,
+    msg: Message,
+    (temperature,): (Option<f32>,),
     session_mgr: SessionManager,
-    stats_mgr: StatsManager,
+    event_bus: EventBus,
+    member_mgr: MemberManager,
+    prefs_mgr: PreferencesManager,
     openai_client: OpenAIClient,
     config: SharedConfig,
 ) -> HandlerResult {
-    // Send a progress indicator message first.
-    let progress_bar = BrailleProgress::new(1, 1, 3, Some("Thinking... 🤔".to_owned()));
-    let mut send_progress_msg = bot.send_message(chat_id.clone(), progress_bar.current_string());
-    send_progress_msg.reply_to_message_id = reply_to_msg.as_ref().map(|m| m.id);
-    let sent_progress_msg = send_progress_msg.await?;
+    let username = msg.from().and_then(|u| u.username.clone());
+    if !member_mgr
+        .is_member_allowed(username.unwrap_or_default())
+        .await
+        .unwrap_or(false)
+    {
+        reply_notice(
+            &bot,
+            &msg,
+            &config
+                .load()
+                .i18n_strings(user_language(&msg))
+                .not_allowed_prompt,
+            &config,
+        )
+        .await;
+        return Ok(());
+    }
+    if let Some(temperature) = temperature.filter(|t| !(0.0..=2.0).contains(t)) {
+        reply_in_topic(
+            &bot,
+            &msg,
+            format!(
+                "Invalid temperature {}, it must be between 0 and 2.",
+                temperature
+            ),
+        )
+        .send_retrying()
+        .await?;
+        return Ok(());
+    }
 
-    // Construct the request messages.
-    let mut msgs = session_mgr.get_history_messages(&chat_id);
-    let user_msg = ChatCompletionRequestMessageArgs::default()
-        .role(Role::User)
-        .content(content)
-        .build()
-        .unwrap();
-    msgs.push(user_msg.clone());
+    let chat_id = msg.chat.id.to_string();
+    let topic_id = topic_id(&msg);
+    let key = session_mgr.active_session_key(&chat_id, topic_id).await;
+    // The answer is kept until the new one is generated, in case it fails.
+    let last_answer = session_mgr.with_mut_session(key, |session| {
+        let (answer_id, _) = session.last_answer()?;
+        let question = session.get_question(answer_id)?;
+        Some((answer_id, question))
+    });
+    let (answer_id, question) = match last_answer {
+        Some(last_answer) => last_answer,
+        None => {
+            reply_in_topic(&bot, &msg, "There is no answer to retry.")
+                .send_retrying()
+                .await?;
+            return Ok(());
+        }
+    };
 
-    let result = stream_model_result(
-        &bot,
-        &chat_id,
-        &sent_progress_msg,
-        progress_bar,
-        msgs,
-        openai_client,
-        &config,
+    actually_handle_chat_message(
+        bot,
+        Some(msg),
+        question.content,
+        vec![],
+        chat_id,
+        topic_id,
+        session_mgr,
+        event_bus,
+        prefs_mgr,
+        openai_client,
+        config,
+        AnswerOptions {
+            is_regeneration: true,
+            temperature,
+            retried_answer_id: Some(answer_id),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Answers the query with the results of the web search, which are cited
+/// in the answer.
+async fn search_web(
+    bot: Bot,
+    msg: Message,
+    args: CommandArgs,
+    session_mgr: SessionManager,
+    event_bus: EventBus,
+    member_mgr: MemberManager,
+    prefs_mgr: PreferencesManager,
+    openai_client: OpenAIClient,
+    config: SharedConfig,
+) -> HandlerResult {
+    let web_search = match &config.load().web_search {
+        Some(web_search) => web_search.clone(),
+        None => {
+            reply_in_topic(&bot, &msg, "Web search is not enabled.")
+                .send_retrying()
+                .await?;
+            return Ok(());
+        }
+    };
+    let username = msg.from().and_then(|u| u.username.clone());
+    if !member_mgr
+        .is_member_allowed(username.unwrap_or_default())
+        .await
+        .unwrap_or(false)
+    {
+        reply_notice(
+            &bot,
+            &msg,
+            &config
+                .load()
+                .i18n_strings(user_language(&msg))
+                .not_allowed_prompt,
+            &config,
+        )
+        .await;
+        return Ok(());
+    }
+    let query = args.0.trim();
+    if query.is_empty() {
+        reply_in_topic(&bot, &msg, "Usage: /search <query>")
+            .send_retrying()
+            .await?;
+        return Ok(());
+    }
+
+    let results = match web_search::search(&web_search, query).await {
+        Ok(results) => results,
+        Err(err) => {
+            error!("Failed to search the web: {}", err);
+            reply_in_topic(
+                &bot,
+                &msg,
+                "Failed to search the web, please try again later.",
+            )
+            .send_retrying()
+            .await?;
+            return Ok(());
+        }
+    };
+    if results.is_empty() {
+        reply_in_topic(&bot, &msg, "Nothing is found on the web.")
+            .send_retrying()
+            .await?;
+        return Ok(());
+    }
+
+    let chat_id = msg.chat.id.to_string();
+    let topic_id = topic_id(&msg);
+    actually_handle_chat_message(
+        bot,
+        Some(msg),
+        search_prompt(query, &results),
+        vec![],
+        chat_id,
+        topic_id,
+        session_mgr,
+        event_bus,
+        prefs_mgr,
+        openai_client,
+        config,
+        AnswerOptions {
+            sources: results,
+            question: Some(query.to_owned()),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// The recent messages of the group that `/summarize` asks for.
+#[derive(Clone)]
+struct RecentMessages(Vec<CachedMessage>);
+
+fn collect_recent_messages(
+    msg: Message,
+    (count,): (Option<u32>,),
+    message_cache: GroupMessageCache,
+) -> RecentMessages {
+    let count = count
+        .map(|count| count as usize)
+        .unwrap_or(DEFAULT_SUMMARIZE_COUNT);
+    RecentMessages(message_cache.recent(msg.chat.id, count))
+}
+
+/// Summarizes the recent messages of the group, including the ones that
+/// don't mention the bot.
+async fn summarize_group(
+    bot: Bot,
+    msg: Message,
+    RecentMessages(messages): RecentMessages,
+    event_bus: EventBus,
+    member_mgr: MemberManager,
+    openai_client: OpenAIClient,
+    config: SharedConfig,
+) -> HandlerResult {
+    if config.load().group_message_cache.is_none() {
+        reply_in_topic(&bot, &msg, "Summarizing groups is not enabled.")
+            .send_retrying()
+            .await?;
+        return Ok(());
+    }
+    if msg.chat.is_private() {
+        reply_in_topic(&bot, &msg, "Only group chats can be summarized.")
+            .send_retrying()
+            .await?;
+        return Ok(());
+    }
+    let username = msg.from().and_then(|u| u.username.clone());
+    if !member_mgr
+        .is_member_allowed(username.clone().unwrap_or_default())
+        .await
+        .unwrap_or(false)
+    {
+        reply_notice(
+            &bot,
+            &msg,
+            &config
+                .load()
+                .i18n_strings(user_language(&msg))
+                .not_allowed_prompt,
+            &config,
+        )
+        .await;
+        return Ok(());
+    }
+    if messages.is_empty() {
+        reply_in_topic(&bot, &msg, "There are no recent messages to summarize.")
+            .send_retrying()
+            .await?;
+        return Ok(());
+    }
+
+    let progress_msg = reply_in_topic(&bot, &msg, "Summarizing... 🤔")
+        .send_retrying()
+        .await?;
+
+    // The summary is a one-off completion, which is neither kept in the
+    // session nor cached, since the prompt carries the messages of the
+    // other members.
+    let chat_id = msg.chat.id.to_string();
+    let prompt = summarize_prompt(&messages);
+    let prompt_msg = ChatCompletionRequestMessageArgs::default()
+        .role(Role::User)
+        .content(&prompt)
+        .build()?;
+    let permit = openai_client.scheduler().acquire(|_| async {}).await;
+    let result = async {
+        let stream = openai_client
+            .request_chat_model(Some(&chat_id), vec![prompt_msg], Default::default())
+            .await?;
+        stream
+            .fold(None, |_, item| async move { Some(item) })
+            .await
+            .filter(|res| !res.content.trim().is_empty())
+            .ok_or_else(|| anyhow!("Server returned empty response"))
+    }
+    .await;
+    drop(permit);
+
+    let reply_text = match result {
+        Ok(mut res) => {
+            res.prompt_tokens = openai_client.count_tokens(&res.model, &prompt);
+            res.completion_tokens = openai_client.count_tokens(&res.model, &res.content);
+            // Counted in the stats and the quota of the sender.
+            let cost = openai_client.record_usage(&res).await;
+            event_bus.publish(Event::ChatCompleted {
+                chat_id,
+                topic_id: topic_id(&msg),
+                user_id: msg.from().map(|u| u.id.0),
+                username,
+                model: res.model,
+                prompt_tokens: res.prompt_tokens,
+                completion_tokens: res.completion_tokens,
+                cost,
+                is_regeneration: false,
+            });
+            res.content
+        }
+        Err(err) => {
+            error!("Failed to summarize the group: {}", err);
+            config
+                .load()
+                .i18n_strings(user_language(&msg))
+                .api_error_prompt
+                .clone()
+        }
+    };
+    bot.edit_message_text(msg.chat.id, progress_msg.id, reply_text)
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+/// Drops the messages of the group kept for `/summarize`. Anyone in the
+/// group can do it.
+async fn forget_group_messages(
+    bot: Bot,
+    msg: Message,
+    message_cache: GroupMessageCache,
+) -> HandlerResult {
+    let count = message_cache.forget(msg.chat.id);
+    reply_in_topic(
+        &bot,
+        &msg,
+        format!("Forgot {} recent messages of this chat.", count),
+    )
+    .send_retrying()
+    .await?;
+    Ok(())
+}
+
+/// Returns `true` if the user can act on the answers to others, e.g. stop
+/// or regenerate them, which moderators and admins can do.
+async fn acts_for_others(user: &User, role_mgr: &RoleManager) -> bool {
+    role_mgr
+        .role_of(user)
+        .await
+        .map(|role| role >= MemberRole::Moderator)
+        .unwrap_or(false)
+}
+
+/// Returns `true` if the user sent the question of the answer, or can act
+/// on the answers to others. Anyone in a private chat is the asker.
+async fn can_act_on_answer(user: &User, answer_msg: &Message, role_mgr: &RoleManager) -> bool {
+    if answer_msg.chat.is_private() {
+        return true;
+    }
+    let asker_id = answer_msg
+        .reply_to_message()
+        .and_then(|question| question.from())
+        .map(|asker| asker.id);
+    asker_id == Some(user.id) || acts_for_others(user, role_mgr).await
+}
+
+async fn handle_stop_action(
+    bot: Bot,
+    query: CallbackQuery,
+    session_mgr: SessionManager,
+    role_mgr: RoleManager,
+) -> bool {
+    let key = match query
+        .data
+        .as_ref()
+        .and_then(|data| data.strip_prefix("/stop:"))
+    {
+        Some(key) => key.to_owned(),
+        None => return false,
+    };
+    let message = match &query.message {
+        Some(message) => message,
+        None => return false,
+    };
+
+    let user = &query.from;
+    let can_stop_others = acts_for_others(user, &role_mgr).await;
+    let stopped = session_mgr.stop_generation(key, message.id.0, |user_id| {
+        user_id == Some(user.id.0) || can_stop_others
+    });
+    let mut answer = bot.answer_callback_query(query.id);
+    match stopped {
+        Some(true) => {}
+        Some(false) => answer = answer.text("Only the sender of the question can stop it."),
+        None => answer = answer.text("The answer is already completed."),
+    }
+    if let Err(err) = answer.await {
+        error!("Failed to answer the callback query: {}", err);
+    }
+
+    true
+}
+
+async fn handle_new_topic_action(
+    bot: Bot,
+    query: CallbackQuery,
+    session_mgr: SessionManager,
+    role_mgr: RoleManager,
+    config: SharedConfig,
+) -> bool {
+    let session_name = match query
+        .data
+        .as_deref()
+        .and_then(|data| data.strip_prefix("/new_topic:"))
+    {
+        Some(session_name) => session_name,
+        None => return false,
+    };
+    let message = match &query.message {
+        Some(message) => message,
+        None => return false,
+    };
+
+    // The context is shared by the group, so only the sender of the
+    // question can reset it, like stopping the answer.
+    if !can_act_on_answer(&query.from, message, &role_mgr).await {
+        let _ = bot
+            .answer_callback_query(query.id)
+            .text("Only the sender of the question can start a new topic.")
+            .await;
+        return true;
+    }
+
+    session_mgr
+        .reset_session(message_session_key(message, session_name))
+        .await;
+    let reset_prompt = config
+        .load()
+        .i18n_strings(query.from.language_code.as_deref())
+        .reset_prompt
+        .clone();
+    if let Err(err) = bot.answer_callback_query(query.id).text(reset_prompt).await {
+        error!("Failed to answer the callback query: {}", err);
+    }
+
+    true
+}
+
+/// Opens the private chat with a link to continue the conversation of the
+/// group there.
+async fn handle_fork_action(
+    bot: Bot,
+    me: Me,
+    query: CallbackQuery,
+    session_mgr: SessionManager,
+) -> bool {
+    let session_name = match query
+        .data
+        .as_deref()
+        .and_then(|data| data.strip_prefix("/fork:"))
+    {
+        Some(session_name) => session_name,
+        None => return false,
+    };
+    let message = match &query.message {
+        Some(message) => message,
+        None => return false,
+    };
+
+    let key = message_session_key(message, session_name);
+    let answer = match session_mgr.request_fork(key, query.from.id.0) {
+        Ok(token) => {
+            let mut url = me.tme_url();
+            url.set_query(Some(&format!("start=fork_{}", token)));
+            bot.answer_callback_query(query.id).url(url)
+        }
+        Err(err) => {
+            error!("Failed to request a fork: {}", err);
+            bot.answer_callback_query(query.id)
+                .text("Failed to continue in private, please try again.")
+        }
+    };
+    if let Err(err) = answer.await {
+        error!("Failed to answer the callback query: {}", err);
+    }
+
+    true
+}
+
+async fn handle_feedback_action(bot: Bot, query: CallbackQuery, event_bus: EventBus) -> bool {
+    let feedback = match query.data.as_deref().and_then(Feedback::from_callback_data) {
+        Some(feedback) => feedback,
+        None => return false,
+    };
+    let message = match &query.message {
+        Some(message) => message,
+        None => return false,
+    };
+
+    event_bus.publish(Event::FeedbackReceived {
+        chat_id: message.chat.id.to_string(),
+        message_id: message.id.0,
+        user_id: query.from.id.0,
+        rating: feedback.rating,
+        model: feedback.model,
+        prompt_hash: feedback.prompt_hash,
+    });
+    if let Err(err) = bot
+        .answer_callback_query(query.id)
+        .text("Thanks for your feedback!")
+        .await
+    {
+        error!("Failed to answer the callback query: {}", err);
+    }
+
+    true
+}
+
+async fn handle_show_raw_action(
+    bot: Bot,
+    query: CallbackQuery,
+    session_mgr: SessionManager,
+    config: SharedConfig,
+) -> bool {
+    let (history_msg_id, session_name) =
+        match parse_answer_action(query.data.as_deref(), "/show_raw:") {
+            Some(action) => action,
+            None => return false,
+        };
+
+    let message = query.message;
+    if message.is_none() {
+        return false;
+    }
+    let message = message.unwrap();
+    let chat_id = message.chat.id;
+
+    let key = message_session_key(&message, &session_name);
+    let history_message =
+        session_mgr.with_mut_session(key, |session| session.get_history_message(history_msg_id));
+
+    match history_message {
+        Some(history_message) => {
+            let _ = bot
+                .edit_message_text(chat_id, message.id, history_message.content)
+                .send_retrying()
+                .await;
+        }
+        None => {
+            reply_notice(&bot, &message, "The message is stale.", &config).await;
+        }
+    }
+
+    true
+}
+
+/// Returns the key of the named session of the chat or the topic where the
+/// message is sent. The buttons of answers carry the names of their
+/// sessions, since another session may be active when they are pressed.
+fn message_session_key(message: &Message, session_name: &str) -> String {
+    let base_key = session_key(&message.chat.id.to_string(), topic_id(message));
+    named_session_key(&base_key, session_name)
+}
+
+/// Parses the data of a button on an answer, which is the `prefix`
+/// followed by the id of the history message and the session name, e.g.
+/// `/regenerate:42:work`.
+fn parse_answer_action(data: Option<&str>, prefix: &str) -> Option<(i64, String)> {
+    let (id_str, session_name) = data?.strip_prefix(prefix)?.split_once(':')?;
+    Some((id_str.parse().ok()?, session_name.to_owned()))
+}
+
+/// Downloads the largest size of the photo, and returns it as a data URL.
+async fn download_photo(bot: &Bot, photo_sizes: &[PhotoSize]) -> Result<String, Error> {
+    let photo = photo_sizes
+        .iter()
+        .max_by_key(|size| size.width * size.height)
+        .ok_or_else(|| anyhow!("The photo has no available sizes"))?;
+    let file = bot.get_file(&photo.file.id).await?;
+    let mut buf = vec![];
+    bot.download_file(&file.path, &mut buf).await?;
+
+    // Photos are always re-encoded as JPEG by Telegram.
+    Ok(format!(
+        "data:image/jpeg;base64,{}",
+        BASE64_STANDARD.encode(buf)
+    ))
+}
+
+/// Downloads the voice message and returns its transcription.
+async fn transcribe_voice(
+    bot: &Bot,
+    voice: &Voice,
+    openai_client: &OpenAIClient,
+) -> Result<String, Error> {
+    let file = bot.get_file(&voice.file.id).await?;
+    let mut buf = vec![];
+    bot.download_file(&file.path, &mut buf).await?;
+
+    // Voice messages are encoded as OGG with Opus by Telegram.
+    openai_client
+        .transcribe(&format!("{}.ogg", voice.file.unique_id), &buf)
+        .await
+}
+
+async fn actually_handle_chat_message(
+    bot: Bot,
+    reply_to_msg: Option<Message>,
+    content: String,
+    image_urls: Vec<String>,
+    chat_id: String,
+    topic_id: Option<i32>,
+    session_mgr: SessionManager,
+    event_bus: EventBus,
+    prefs_mgr: PreferencesManager,
+    openai_client: OpenAIClient,
+    config: SharedConfig,
+    options: AnswerOptions,
+) -> HandlerResult {
+    let from_user = reply_to_msg.as_ref().and_then(|m| m.from());
+    let from_user_id = from_user.map(|u| u.id.0);
+    let from_username = from_user.and_then(|u| u.username.clone());
+    let language = reply_to_msg.as_ref().and_then(user_language);
+
+    // Check the user input before sending it to the model.
+    let input_verdict = moderate_content(
+        &content,
+        config.load().moderation.input,
+        ContentSource {
+            chat_id: &chat_id,
+            username: from_username.clone(),
+            is_output: false,
+        },
+        &openai_client,
+        &event_bus,
+    )
+    .await;
+    if let Some(reply_to_msg) = &reply_to_msg {
+        match input_verdict {
+            Verdict::Blocked => {
+                let text = config
+                    .load()
+                    .i18n_strings(language)
+                    .moderation_blocked_prompt
+                    .clone();
+                reply_notice(&bot, reply_to_msg, text, &config).await;
+            }
+            Verdict::Warned => {
+                let text = config
+                    .load()
+                    .i18n_strings(language)
+                    .moderation_warning_prompt
+                    .clone();
+                reply_notice(&bot, reply_to_msg, text, &config).await;
+            }
+            Verdict::Allowed => {}
+        }
+    }
+    if input_verdict == Verdict::Blocked {
+        return Ok(());
+    }
+
+    // Send a progress indicator message first, to the private chat of the
+    // sender if the group prefers private answers.
+    let progress_bar = BrailleProgress::new(1, 1, 3, Some("Thinking... 🤔".to_owned()));
+    // Each forum topic has its own context, while the preferences and
+    // stats are still shared by the whole chat.
+    let base_key = session_key(&chat_id, topic_id);
+    let session_name = session_mgr.active_session_name(&base_key).await;
+    let session_key = named_session_key(&base_key, &session_name);
+    let stop_button = InlineKeyboardButton::callback("Stop", format!("/stop:{}", session_key));
+
+    let stop_markup = InlineKeyboardMarkup::default().append_row([stop_button]);
+    let mut reused_msg = None;
+    if let Some(message_id) = options.reused_message_id {
+        match bot
+            .edit_message_text(chat_id.clone(), message_id, progress_bar.current_string())
+            .reply_markup(stop_markup.clone())
+            .send_retrying()
+            .await
+        {
+            Ok(msg) => reused_msg = Some(msg),
+            Err(err) => warn!("Failed to reuse the previous answer: {}", err),
+        }
+    }
+    let private_chat_id = match (&reply_to_msg, from_user) {
+        (Some(msg), Some(user)) if reused_msg.is_none() && !msg.chat.is_private() => {
+            let private_answers: bool = prefs_mgr
+                .get_chat_value(&chat_id, PRIVATE_ANSWERS_PREF_KEY)
+                .await
+                .unwrap_or_default();
+            private_answers.then_some(ChatId::from(user.id))
+        }
+        _ => None,
+    };
+    let mut sent_private_msg = None;
+    if let Some(private_chat_id) = private_chat_id {
+        match bot
+            .send_message(private_chat_id, progress_bar.current_string())
+            .reply_markup(stop_markup.clone())
+            .send_retrying()
+            .await
+        {
+            Ok(sent_msg) => sent_private_msg = Some(sent_msg),
+            // Bots can't message the users who haven't started them.
+            Err(err) => debug!(
+                "Failed to answer in private, answering in the group: {}",
+                err
+            ),
+        }
+    }
+    let answers_privately = sent_private_msg.is_some();
+    let sent_progress_msg = match sent_private_msg.or(reused_msg) {
+        Some(sent_private_msg) => sent_private_msg,
+        None => {
+            let mut send_progress_msg = bot
+                .send_message(chat_id.clone(), progress_bar.current_string())
+                .reply_markup(stop_markup.clone());
+            send_progress_msg.reply_to_message_id = reply_to_msg.as_ref().map(|m| m.id);
+            send_progress_msg.message_thread_id = topic_id;
+            match send_progress_msg.send_retrying().await {
+                Ok(sent_progress_msg) => sent_progress_msg,
+                Err(err) => {
+                    if is_permission_error(&err) {
+                        event_bus.publish(Event::SendForbidden {
+                            chat_id: chat_id.clone(),
+                            topic_id,
+                            user_id: from_user_id,
+                            reason: err.to_string(),
+                        });
+                    }
+                    return Err(err.into());
+                }
+            }
+        }
+    };
+    if let (true, Some(reply_to_msg)) = (answers_privately, &reply_to_msg) {
+        let text = config
+            .load()
+            .i18n_strings(language)
+            .private_answer_prompt
+            .clone();
+        reply_notice(&bot, reply_to_msg, text, &config).await;
+    }
+
+    // The session may expire between two sweeps of the expiry task.
+    if let Some(ttl_minutes) = config.load().session_ttl_minutes {
+        let expired = session_mgr
+            .last_active(&session_key)
+            .map(|t| t.elapsed() > Duration::from_secs(ttl_minutes * 60))
+            .unwrap_or(false);
+        if expired {
+            session_mgr.reset_session(session_key.clone()).await;
+        }
+    }
+
+    // New sessions start with the system prompt of the chat, unless a
+    // persona is activated.
+    let has_system_message =
+        session_mgr.with_mut_session(session_key.clone(), |session| session.has_system_message());
+    if !has_system_message {
+        if let Some(prompt) = chat_system_prompt(&chat_id, &prefs_mgr, &config).await {
+            add_system_prompt(&session_mgr, session_key.clone(), &prompt, &openai_client).await;
+        }
+    }
+
+    // Construct the request messages.
+    let user_msg = ChatCompletionRequestMessageArgs::default()
+        .role(Role::User)
+        .content(content)
+        .build()
+        .unwrap();
+    let mut pending_msgs = vec![];
+
+    // Apply the reply length preference of this chat.
+    let reply_length: ReplyLength = prefs_mgr
+        .get_chat_value(&chat_id, REPLY_LENGTH_PREF_KEY)
+        .await
+        .unwrap_or_default();
+    if let Some(instruction) = reply_length.system_instruction() {
+        pending_msgs.push(
+            ChatCompletionRequestMessageArgs::default()
+                .role(Role::System)
+                .content(instruction)
+                .build()
+                .unwrap(),
+        );
+    }
+    let reply_language: Option<String> = prefs_mgr
+        .get_chat_value(&chat_id, REPLY_LANGUAGE_PREF_KEY)
+        .await
+        .unwrap_or_default();
+    if let Some(instruction) = reply_language.as_deref().and_then(language_instruction) {
+        pending_msgs.push(
+            ChatCompletionRequestMessageArgs::default()
+                .role(Role::System)
+                .content(instruction)
+                .build()
+                .unwrap(),
+        );
+    }
+    let persona_params = session_mgr
+        .with_mut_session(session_key.clone(), |session| {
+            session.persona().map(str::to_owned)
+        })
+        .and_then(|name| config.load().persona_params.get(&name).cloned())
+        .unwrap_or_default();
+    let params = ChatModelParams {
+        max_tokens: reply_length.max_tokens(config.load().max_tokens),
+        stop_sequences: persona_params.stop_sequences,
+        logit_bias: persona_params.logit_bias,
+        image_urls,
+        use_cache: !options.is_regeneration,
+        model: options.model,
+        temperature: options.temperature,
+    };
+
+    if config.load().inject_current_time {
+        let timezone = config.load().timezone;
+        let now = Utc::now().with_timezone(&timezone);
+        pending_msgs.push(
+            ChatCompletionRequestMessageArgs::default()
+                .role(Role::System)
+                .content(format!(
+                    "Current date and time: {} ({})",
+                    now.format("%A, %Y-%m-%d %H:%M"),
+                    timezone.name()
+                ))
+                .build()
+                .unwrap(),
+        );
+    }
+
+    pending_msgs.push(user_msg.clone());
+
+    let model = match &params.model {
+        Some(model) => model.clone(),
+        None => openai_client.chat_model(Some(&chat_id)).await,
+    };
+
+    // Evict the oldest history messages to keep the prompt in budget.
+    if let Some(max_prompt_tokens) = config.load().max_prompt_tokens {
+        let reserved_tokens = openai_client.count_prompt_tokens(&model, &pending_msgs);
+        session_mgr.with_mut_session(session_key.clone(), |session| {
+            session.trim_history_by_tokens(max_prompt_tokens.saturating_sub(reserved_tokens))
+        });
+    }
+
+    // When replying to one of the answers, continue from that answer
+    // instead of the whole session.
+    let thread_anchor_id = reply_to_msg
+        .as_ref()
+        .filter(|_| options.retried_answer_id.is_none())
+        .and_then(|msg| msg.reply_to_message())
+        .and_then(|replied_msg| {
+            session_mgr.with_mut_session(session_key.clone(), |session| {
+                session.find_history_message_id(replied_msg.id.0)
+            })
+        });
+
+    let mut msgs = match thread_anchor_id {
+        Some(anchor_id) => session_mgr.with_mut_session(session_key.clone(), |session| {
+            session.get_thread_messages(anchor_id)
+        }),
+        None => match options.retried_answer_id {
+            Some(answer_id) => session_mgr.with_mut_session(session_key.clone(), |session| {
+                session.get_history_messages_without_answer(answer_id)
+            }),
+            None => session_mgr.get_history_messages(&session_key),
+        },
+    };
+    msgs.extend(pending_msgs);
+
+    let renders_markdown: Option<bool> = prefs_mgr
+        .get_chat_value(&chat_id, RENDER_MARKDOWN_PREF_KEY)
+        .await
+        .unwrap_or_default();
+    // The preference turns rendering on or off, in the configured mode.
+    let render_mode = match (renders_markdown, config.load().default_render_mode()) {
+        (Some(false), _) => RenderMode::Plain,
+        (Some(true), RenderMode::Plain) => RenderMode::Entities,
+        (_, render_mode) => render_mode,
+    };
+
+    event_bus.publish(Event::ChatStarted {
+        chat_id: chat_id.clone(),
+        user_id: from_user_id,
+        model: model.clone(),
+    });
+
+    // Fall back to the next model when a model fails, unless the user
+    // stops the generation.
+    let mut models = vec![model];
+    for fallback_model in &config.load().fallback_models {
+        if !models.contains(fallback_model) {
+            models.push(fallback_model.clone());
+        }
+    }
+    let stop =
+        session_mgr.start_generation(session_key.clone(), sent_progress_msg.id.0, from_user_id);
+
+    // Wait for a free slot under heavy load. The message can still be
+    // stopped while it's queued.
+    let queued_prompt = config.load().i18n_strings(language).queued_prompt.clone();
+    let report_position = |position: usize| {
+        let edit = bot
+            .edit_message_text(
+                sent_progress_msg.chat.id,
+                sent_progress_msg.id,
+                queued_prompt.replace("{position}", &position.to_string()),
+            )
+            .reply_markup(stop_markup.clone());
+        async move {
+            if let Err(err) = edit.await {
+                warn!("Failed to show the queue position: {}", err);
+            }
+        }
+    };
+    let permit = tokio::select! {
+        permit = openai_client.scheduler().acquire(report_position) => Some(permit),
+        _ = stop.notified() => None,
+    };
+
+    let mut substituted_model = None;
+    let mut result = Err(anyhow!("No model is available"));
+    if permit.is_none() {
+        // Stopped while queued.
+        result = Err(GenerationStopped.into());
+        models.clear();
+    }
+    for (idx, model) in models.iter().enumerate() {
+        if idx > 0 {
+            info!("Falling back to {} for chat {}", model, chat_id);
+            substituted_model = Some(model.clone());
+        }
+        result = stream_model_result(
+            &bot,
+            &chat_id,
+            &sent_progress_msg,
+            progress_bar.clone(),
+            msgs.clone(),
+            ChatModelParams {
+                model: Some(model.clone()),
+                ..params.clone()
+            },
+            render_mode,
+            GenerationControl {
+                stop: stop.clone(),
+                reply_markup: stop_markup.clone(),
+            },
+            openai_client.clone(),
+            &config,
+        )
+        .await;
+        match &result {
+            // The model is reachable if it returns an empty answer, which
+            // is not worth another model.
+            Err(err)
+                if !err.is::<GenerationStopped>()
+                    && !err.is::<EmptyAnswer>()
+                    && idx + 1 < models.len() =>
+            {
+                error!("Failed to request the model {}: {}", model, err);
+                event_bus.publish(Event::ModelErrored {
+                    chat_id: chat_id.clone(),
+                    username: from_username.clone(),
+                    model: model.clone(),
+                    error: err.to_string(),
+                });
+            }
+            _ => break,
+        }
+    }
+    session_mgr.finish_generation(session_key.clone(), sent_progress_msg.id.0);
+    drop(permit);
+    let fallback_notice = substituted_model.map(|model| {
+        config
+            .load()
+            .i18n_strings(language)
+            .fallback_model_prompt
+            .replace("{model}", &model)
+    });
+
+    // Check the answer before keeping it.
+    let output_verdict = match &result {
+        Ok(res) => {
+            moderate_content(
+                &res.content,
+                config.load().moderation.output,
+                ContentSource {
+                    chat_id: &chat_id,
+                    username: from_username.clone(),
+                    is_output: true,
+                },
+                &openai_client,
+                &event_bus,
+            )
+            .await
+        }
+        Err(_) => Verdict::Allowed,
+    };
+
+    // Publish the result and add the reply to history.
+    let reply_result = match result {
+        Ok(res) if output_verdict == Verdict::Blocked => {
+            // The tokens are used anyway, but the answer is not kept in
+            // the history.
+            let cost = openai_client.record_usage(&res).await;
+            event_bus.publish(Event::ChatCompleted {
+                chat_id: chat_id.clone(),
+                topic_id,
+                user_id: from_user_id,
+                username: from_username,
+                model: res.model,
+                prompt_tokens: res.prompt_tokens,
+                completion_tokens: res.completion_tokens,
+                cost,
+                is_regeneration: options.is_regeneration,
+            });
+            bot.edit_message_text(
+                sent_progress_msg.chat.id,
+                sent_progress_msg.id,
+                &config
+                    .load()
+                    .i18n_strings(language)
+                    .moderation_blocked_prompt,
+            )
+            .send_retrying()
+            .await
+            .map(|_| ())
+        }
+        Ok(res) => {
+            let reply_msg = ChatCompletionRequestMessageArgs::default()
+                .role(Role::Assistant)
+                .content(&res.content)
+                .build()
+                .unwrap();
+            let reply_token_count =
+                openai_client.count_message_tokens(&res.model, slice::from_ref(&reply_msg));
+            let mut reply_history_message = session_mgr
+                .with_mut_session(session_key.clone(), |session| {
+                    session.prepare_history_message(reply_msg, reply_token_count)
+                });
+            // Private answers are not linked, since the actions and the
+            // replies in the private chat don't reach the session of the
+            // group.
+            if !answers_privately {
+                reply_history_message.telegram_message_ids = vec![sent_progress_msg.id.0];
+            }
+            // A continuation is displayed as part of the continued answer.
+            let reply_history_message_id = options
+                .continued_answer_id
+                .unwrap_or(reply_history_message.id);
+            let user_msg = match options.question {
+                Some(question) => ChatCompletionRequestMessage {
+                    content: question,
+                    ..user_msg
+                },
+                None => user_msg,
+            };
+            let user_token_count =
+                openai_client.count_message_tokens(&res.model, slice::from_ref(&user_msg));
+
+            // The question and the answer are counted ahead, since they
+            // are added to the session after the answer is shown.
+            let shows_context: bool = prefs_mgr
+                .get_chat_value(&chat_id, CONTEXT_INDICATOR_PREF_KEY)
+                .await
+                .unwrap_or_default();
+            let context_note = if shows_context {
+                let usage = session_mgr
+                    .with_mut_session(session_key.clone(), |session| session.context_usage());
+                Some(context_indicator(
+                    usage.message_count + 2,
+                    usage.tokens + user_token_count + reply_token_count,
+                ))
+            } else {
+                None
+            };
+            // Answers cut off by `maxTokens` can be continued.
+            let truncation_notice = (res.finish_reason.as_deref() == Some("length")).then(|| {
+                config
+                    .load()
+                    .i18n_strings(language)
+                    .truncated_prompt
+                    .clone()
+            });
+            let notices: Vec<&str> = [truncation_notice.as_deref(), fallback_notice.as_deref()]
+                .into_iter()
+                .flatten()
+                .collect();
+            let notice = (!notices.is_empty()).then(|| notices.join("\n"));
+
+            let regenerate_button = InlineKeyboardButton::callback(
+                "Regenerate",
+                format!("/regenerate:{}:{}", reply_history_message_id, session_name),
+            );
+            let new_topic_button = InlineKeyboardButton::callback(
+                "🧹 New topic",
+                format!("/new_topic:{}", session_name),
+            );
+            let feedback_buttons = feedback_buttons(&res.model, &user_msg.content);
+            let with_buttons = |mut buttons: Vec<InlineKeyboardButton>| {
+                if answers_privately {
+                    return InlineKeyboardMarkup::default();
+                }
+                buttons.push(new_topic_button.clone());
+                let keyboard = InlineKeyboardMarkup::default()
+                    .append_row(buttons)
+                    .append_row(feedback_buttons.clone());
+                // Lets the users in groups dig deeper without the noise.
+                if sent_progress_msg.chat.is_private() {
+                    keyboard
+                } else {
+                    keyboard.append_row([InlineKeyboardButton::callback(
+                        "💬 Continue in private",
+                        format!("/fork:{}", session_name),
+                    )])
+                }
+            };
+
+            let need_fallback = if render_mode != RenderMode::Plain {
+                let content = if options.sources.is_empty() {
+                    res.content.clone()
+                } else {
+                    render_citations(&res.content, &options.sources)
+                };
+                let content = decorate_answer(
+                    content,
+                    notice.as_deref(),
+                    context_note.as_deref(),
+                    &res.model,
+                    res.token_usage(),
+                    &config.load(),
+                );
+                let mut parsed_content = markdown::parse(&content);
+                #[cfg(debug_assertions)]
+                {
+                    debug!(
+                        "rendered Markdown contents: {}\ninto: {:#?}",
+                        res.content, parsed_content
+                    );
+                }
+                let code_file = config.load().code_file_threshold.and_then(|threshold| {
+                    extract_code_file(
+                        &mut parsed_content,
+                        threshold,
+                        &config.load().i18n_strings(language).code_file_prompt,
+                    )
+                });
+                let markdown_v2_content = (render_mode == RenderMode::MarkdownV2)
+                    .then(|| markdown::to_markdown_v2(&parsed_content));
+                let mut edit_message_text = bot.edit_message_text(
+                    sent_progress_msg.chat.id,
+                    sent_progress_msg.id,
+                    parsed_content.content,
+                );
+                if !parsed_content.entities.is_empty() || code_file.is_some() {
+                    let show_raw_button = InlineKeyboardButton::callback(
+                        "Show Raw Contents",
+                        format!("/show_raw:{}:{}", reply_history_message_id, session_name),
+                    );
+                    edit_message_text.entities = Some(parsed_content.entities);
+                    edit_message_text.reply_markup = Some(with_buttons(vec![
+                        show_raw_button,
+                        regenerate_button.clone(),
+                    ]));
+                } else {
+                    edit_message_text.reply_markup =
+                        Some(with_buttons(vec![regenerate_button.clone()]));
+                }
+                // Fall back to the entities if Telegram rejects the
+                // MarkdownV2 text.
+                let sent_markdown_v2 = match markdown_v2_content {
+                    Some(markdown_v2_content) => {
+                        let mut markdown_v2_edit = edit_message_text.clone();
+                        markdown_v2_edit.text = markdown_v2_content;
+                        markdown_v2_edit.entities = None;
+                        markdown_v2_edit.parse_mode = Some(ParseMode::MarkdownV2);
+                        match markdown_v2_edit.send_retrying().await {
+                            Ok(_) => true,
+                            Err(err) => {
+                                warn!(
+                                    "Failed to send MarkdownV2 (will fallback to entities): {}",
+                                    err
+                                );
+                                false
+                            }
+                        }
+                    }
+                    None => false,
+                };
+                let sent = if sent_markdown_v2 {
+                    Ok(())
+                } else {
+                    edit_message_text.send_retrying().await.map(|_| ())
+                };
+                if let Err(first_trial_err) = sent {
+                    // TODO: test if the error is related to Markdown before
+                    // fallback to raw contents.
+                    error!(
+                        "failed to send message (will fallback to raw contents): {}",
+                        first_trial_err
+                    );
+                    true
+                } else {
+                    // The history keeps the rendering of the answer itself,
+                    // without the notices, the sources and other decorations.
+                    let rendered_content = markdown::parse(&res.content).content;
+                    if rendered_content != res.content {
+                        reply_history_message.rendered_content = Some(rendered_content);
+                    }
+                    if let Some(code_file) = code_file {
+                        let file_msg = send_code_file(
+                            &bot,
+                            if answers_privately { None } else { topic_id },
+                            &sent_progress_msg,
+                            code_file,
+                        )
+                        .await;
+                        // Replying to the file continues the thread too.
+                        if let (false, Some(file_msg)) = (answers_privately, file_msg) {
+                            reply_history_message
+                                .telegram_message_ids
+                                .push(file_msg.id.0);
+                        }
+                    }
+                    false
+                }
+            } else {
+                true
+            };
+
+            if need_fallback {
+                let content = if options.sources.is_empty() {
+                    res.content.clone()
+                } else {
+                    append_sources(&res.content, &options.sources)
+                };
+                let content = decorate_answer(
+                    content,
+                    notice.as_deref(),
+                    context_note.as_deref(),
+                    &res.model,
+                    res.token_usage(),
+                    &config.load(),
+                );
+                bot.edit_message_text(sent_progress_msg.chat.id, sent_progress_msg.id, content)
+                    .reply_markup(with_buttons(vec![regenerate_button]))
+                    .send_retrying()
+                    .await?;
+            }
+
+            // The retried answer is replaced, unless other messages are
+            // added meanwhile.
+            if let Some(answer_id) = options.retried_answer_id {
+                let telegram_message_ids =
+                    session_mgr.with_mut_session(session_key.clone(), |session| {
+                        let (last_answer_id, telegram_message_ids) = session.last_answer()?;
+                        (last_answer_id == answer_id
+                            && session.rollback_answer(answer_id).is_some())
+                        .then_some(telegram_message_ids)
+                    });
+                for telegram_message_id in telegram_message_ids.unwrap_or_default() {
+                    if let Err(err) = bot
+                        .delete_message(chat_id.clone(), MessageId(telegram_message_id))
+                        .await
+                    {
+                        error!("Failed to revoke the previous answer: {}", err);
+                    }
+                }
+            }
+
+            let reply_history_message_id =
+                session_mgr.with_mut_session(session_key.clone(), |session| {
+                    // A continuation extends the answer in the history, unless
+                    // other messages are added meanwhile.
+                    let mut reply_history_message = match options.continued_answer_id {
+                        Some(answer_id) => {
+                            match session.extend_answer(answer_id, reply_history_message) {
+                                None => return answer_id,
+                                Some(reply_history_message) => reply_history_message,
+                            }
+                        }
+                        None => reply_history_message,
+                    };
+                    let reply_history_message_id = reply_history_message.id;
+                    let mut user_history_msg =
+                        session.prepare_history_message(user_msg, user_token_count);
+                    user_history_msg.source_message_id = reply_to_msg.as_ref().map(|msg| msg.id.0);
+                    user_history_msg.parent_id =
+                        thread_anchor_id.or_else(|| session.last_history_message_id());
+                    reply_history_message.parent_id = Some(user_history_msg.id);
+                    session.add_history_message(user_history_msg);
+                    session.add_history_message(reply_history_message);
+                    reply_history_message_id
+                });
+            session_mgr.spawn_summarization(
+                session_key.clone(),
+                from_user_id,
+                from_username.clone(),
+                openai_client.clone(),
+                event_bus.clone(),
+            );
+            session_mgr.spawn_naming(session_key.clone(), openai_client.clone());
+
+            let voice_reply: bool = prefs_mgr
+                .get_chat_value(&chat_id, VOICE_REPLY_PREF_KEY)
+                .await
+                .unwrap_or_default();
+            if voice_reply {
+                let voice_msg = send_voice_reply(
+                    &bot,
+                    if answers_privately { None } else { topic_id },
+                    &sent_progress_msg,
+                    &res.content,
+                    &openai_client,
+                )
+                .await;
+                // Replying to the voice message continues the thread too.
+                if let (false, Some(voice_msg)) = (answers_privately, voice_msg) {
+                    session_mgr.with_mut_session(session_key.clone(), |session| {
+                        session.link_telegram_message(reply_history_message_id, voice_msg.id.0)
+                    });
+                }
+            }
+
+            if output_verdict == Verdict::Allowed {
+                openai_client.cache_response(&res).await;
+            }
+            let cost = openai_client.record_usage(&res).await;
+            event_bus.publish(Event::ChatCompleted {
+                chat_id: chat_id.clone(),
+                topic_id,
+                user_id: from_user_id,
+                username: from_username,
+                model: res.model,
+                prompt_tokens: res.prompt_tokens,
+                completion_tokens: res.completion_tokens,
+                cost,
+                is_regeneration: options.is_regeneration,
+            });
+
+            if output_verdict == Verdict::Warned {
+                let text = config
+                    .load()
+                    .i18n_strings(language)
+                    .moderation_warning_prompt
+                    .clone();
+                reply_notice(&bot, &sent_progress_msg, text, &config).await;
+            }
+            if let Some(reply_to_msg) = &reply_to_msg {
+                notify_if_waited_long(&bot, reply_to_msg, &config).await;
+            }
+            Ok(())
+        }
+        Err(err) if err.is::<GenerationStopped>() => {
+            // Nothing is generated, remove the progress indicator.
+            bot.delete_message(sent_progress_msg.chat.id, sent_progress_msg.id)
+                .await
+                .map(|_| ())
+        }
+        Err(err) if err.is::<EmptyAnswer>() => {
+            // The model is reachable, so the chat is not degraded.
+            warn!("Failed to request the model: {}", err);
+            session_mgr
+                .set_pending_message(session_key, user_msg.content)
+                .await;
+            let retry_button =
+                InlineKeyboardButton::callback("Retry", format!("/retry:{}", session_name));
+            let reply_markup = InlineKeyboardMarkup::default().append_row([retry_button]);
+            bot.edit_message_text(
+                sent_progress_msg.chat.id,
+                sent_progress_msg.id,
+                &config.load().i18n_strings(language).empty_answer_prompt,
+            )
+            .reply_markup(reply_markup)
+            .send_retrying()
+            .await
+            .map(|_| ())
+        }
+        Err(err) => {
+            error!("Failed to request the model: {}", err);
+            event_bus.publish(Event::ModelErrored {
+                chat_id: chat_id.clone(),
+                username: from_username,
+                // The last model in the list is the one that failed.
+                model: models.last().cloned().unwrap_or_default(),
+                error: err.to_string(),
+            });
+            session_mgr
+                .set_pending_message(session_key, user_msg.content)
+                .await;
+            let retry_button =
+                InlineKeyboardButton::callback("Retry", format!("/retry:{}", session_name));
+            let reply_markup = InlineKeyboardMarkup::default().append_row([retry_button]);
+            bot.edit_message_text(
+                sent_progress_msg.chat.id,
+                sent_progress_msg.id,
+                &config.load().i18n_strings(language).api_error_prompt,
+            )
+            .reply_markup(reply_markup)
+            .send_retrying()
+            .await
+            .map(|_| ())
+        }
+    };
+
+    if let Err(err) = reply_result {
+        error!("Failed to edit the final message: {}", err);
+    }
+
+    Ok(())
+}
+
+/// Replies to the message, in the same forum topic if it's in one.
+fn reply_in_topic(
+    bot: &Bot,
+    msg: &Message,
+    text: impl Into<String>,
+) -> <Bot as Requester>::SendMessage {
+    let mut send_message = bot
+        .send_message(msg.chat.id, text)
+        .reply_to_message_id(msg.id);
+    send_message.message_thread_id = topic_id(msg);
+    send_message
+}
+
+/// Replies a service notice (e.g. an error), which may be deleted later to
+/// keep the chat tidy.
+async fn reply_notice(bot: &Bot, msg: &Message, text: impl Into<String>, config: &SharedConfig) {
+    match reply_in_topic(bot, msg, text).send_retrying().await {
+        Ok(sent_msg) => schedule_deletion(bot, &sent_msg, config),
+        Err(err) => error!("Failed to send the notice: {}", err),
+    }
+}
+
+/// Speaks the answer and sends it as a voice message replying to the
+/// text answer. Returns the sent voice message.
+async fn send_voice_reply(
+    bot: &Bot,
+    topic_id: Option<i32>,
+    answer_msg: &Message,
+    content: &str,
+    openai_client: &OpenAIClient,
+) -> Option<Message> {
+    let speech = match openai_client.synthesize_speech(content).await {
+        Ok(speech) => speech,
+        Err(err) => {
+            error!("Failed to synthesize the speech: {}", err);
+            return None;
+        }
+    };
+    let mut send_voice = bot
+        .send_voice(
+            answer_msg.chat.id,
+            InputFile::memory(speech).file_name("answer.ogg"),
+        )
+        .reply_to_message_id(answer_msg.id);
+    send_voice.message_thread_id = topic_id;
+    match send_voice.await {
+        Ok(voice_msg) => Some(voice_msg),
+        Err(err) => {
+            error!("Failed to send the voice reply: {}", err);
+            None
+        }
+    }
+}
+
+/// Sends the code taken out of the answer as a file replying to the text
+/// answer. Returns the sent file message.
+async fn send_code_file(
+    bot: &Bot,
+    topic_id: Option<i32>,
+    answer_msg: &Message,
+    code_file: CodeFile,
+) -> Option<Message> {
+    let caption = format!(
+        "{} ({} lines)",
+        code_file.file_name,
+        code_file.code.lines().count()
+    );
+    let mut send_document = bot
+        .send_document(
+            answer_msg.chat.id,
+            InputFile::memory(code_file.code).file_name(code_file.file_name),
+        )
+        .caption(caption)
+        .reply_to_message_id(answer_msg.id);
+    send_document.message_thread_id = topic_id;
+    match send_document.await {
+        Ok(file_msg) => Some(file_msg),
+        Err(err) => {
+            error!("Failed to send the code file: {}", err);
+            None
+        }
+    }
+}
+
+/// Mentions the sender when the answer is posted long after the question
+/// was asked, so that they don't miss it in an active group.
+async fn notify_if_waited_long(bot: &Bot, msg: &Message, config: &SharedConfig) {
+    let threshold = match config.load().long_wait_mention_threshold {
+        Some(threshold) => threshold as i64,
+        None => return,
+    };
+    if msg.chat.is_private() {
+        return;
+    }
+    let user = match msg.from() {
+        Some(user) => user,
+        None => return,
+    };
+
+    let unix_timestamp_secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    if unix_timestamp_secs - msg.date.timestamp() < threshold {
+        return;
+    }
+
+    let name = user.full_name();
+    let mention = MessageEntity::text_mention_id(user.id, 0, name.encode_utf16().count());
+    let res = reply_in_topic(
+        bot,
+        msg,
+        format!(
+            "{}, {}",
+            name,
+            config
+                .load()
+                .i18n_strings(user_language(msg))
+                .answer_ready_prompt
+        ),
     )
+    .entities([mention])
+    .send_retrying()
     .await;
+    if let Err(err) = res {
+        error!("Failed to notify the sender: {}", err);
+    }
+}
+
+/// The error of an answer that is stopped before anything is generated.
+#[derive(Debug)]
+struct GenerationStopped;
+
+impl std::fmt::Display for GenerationStopped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The generation is stopped")
+    }
+}
+
+impl std::error::Error for GenerationStopped {}
+
+/// The error of a stream that ends without any visible content.
+#[derive(Debug)]
+struct EmptyAnswer {
+    finish_reason: Option<String>,
+}
+
+impl std::fmt::Display for EmptyAnswer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Server returned empty response (finish reason: {})",
+            self.finish_reason.as_deref().unwrap_or("none")
+        )
+    }
+}
+
+impl std::error::Error for EmptyAnswer {}
+
+/// Lets the user stop the answer being streamed.
+struct GenerationControl {
+    stop: Arc<Notify>,
+    /// The keyboard with the stop button, which is kept on the progress
+    /// message.
+    reply_markup: InlineKeyboardMarkup,
+}
+
+/// Streams the answer into the message. The answer generated so far is
+/// returned if it's stopped by the user.
+#[tracing::instrument(
+    name = "chat.stream_answer",
+    skip_all,
+    fields(
+        chat.id_hash = hash_chat_id(chat_id),
+        model = params.model.as_deref(),
+        prompt_tokens = tracing::field::Empty,
+        completion_tokens = tracing::field::Empty,
+        first_token_ms = tracing::field::Empty,
+        telegram_edits = tracing::field::Empty,
+    )
+)]
+async fn stream_model_result(
+    bot: &Bot,
+    chat_id: &str,
+    editing_msg: &Message,
+    mut progress_bar: BrailleProgress,
+    msgs: Vec<ChatCompletionRequestMessage>,
+    params: ChatModelParams,
+    render_mode: RenderMode,
+    control: GenerationControl,
+    openai_client: OpenAIClient,
+    config: &SharedConfig,
+) -> Result<ChatModelResult, Error> {
+    let model = match &params.model {
+        Some(model) => model.clone(),
+        None => openai_client.chat_model(Some(chat_id)).await,
+    };
+    let prompt_tokens = openai_client.count_prompt_tokens(&model, &msgs);
+
+    let stream = openai_client
+        .request_chat_model(Some(chat_id), msgs, params)
+        .await?;
+    let mut throttled_stream = stream.throttle_buffer::<Vec<_>>(Duration::from_millis(
+        config.load().stream_throttle_interval,
+    ));
+
+    let first_token_timeout = Duration::from_secs(config.load().openai_first_token_timeout);
+    let idle_timeout = Duration::from_secs(config.load().openai_api_timeout);
+    let started_at = Instant::now();
+    let mut first_token_at: Option<Instant> = None;
+    let mut last_progress_at = Instant::now();
+    let mut last_response: Option<ChatModelResult> = None;
+    let mut edit_failures = 0;
+    // Rendering is turned off for the rest of the stream once Telegram
+    // rejects the entities of a partial response.
+    let mut renders_partial_markdown = render_mode != RenderMode::Plain;
+    let mut is_stopped = false;
+    // Edits that are sent, the others are dropped by the flood control.
+    let mut edits = 0;
+    let mut editor = CoalescedEditor::default();
+    loop {
+        // Allow a longer wait before the first token arrives, since the
+        // server may take a while to process a long prompt.
+        let has_content = last_response
+            .as_ref()
+            .map(|res| !res.content.is_empty())
+            .unwrap_or(false);
+        let timeout = if has_content {
+            idle_timeout
+        } else {
+            first_token_timeout
+        };
+
+        // Only wake up for the timeout while the animation is paused.
+        let is_animating = |last_progress_at: Instant| {
+            edit_failures < MAX_PROGRESS_EDIT_FAILURES
+                && last_progress_at.elapsed() < PROGRESS_STALL_THRESHOLD
+        };
+        let tick = if is_animating(last_progress_at) {
+            Duration::from_secs(1)
+        } else {
+            timeout.saturating_sub(last_progress_at.elapsed())
+        };
+
+        tokio::select! {
+            res = throttled_stream.next() => {
+                if res.is_none() {
+                    break;
+                }
+
+                // Since the stream item is scanned (accumulated), we only
+                // need to get the last item in the buffer and use it as
+                // the latest message content.
+                last_response = res.as_ref().unwrap().last().cloned();
+                if first_token_at.is_none()
+                    && last_response.as_ref().is_some_and(|res| !res.content.is_empty())
+                {
+                    first_token_at = Some(Instant::now());
+                    tracing::Span::current()
+                        .record("first_token_ms", started_at.elapsed().as_millis() as u64);
+                }
+
+                // Reset the timeout once the stream is resumed. Any chunk
+                // counts as progress, even if it carries no content.
+                last_progress_at = Instant::now();
+            },
+            _ = tokio::time::sleep(tick) => {
+                if last_progress_at.elapsed() >= timeout {
+                    return Err(anyhow!("Stream is timeout"));
+                }
+            }
+            // Dropping the stream cancels the request.
+            _ = control.stop.notified() => {
+                if !has_content {
+                    return Err(GenerationStopped.into());
+                }
+                is_stopped = true;
+                break;
+            }
+        }
+
+        if !is_animating(last_progress_at) {
+            continue;
+        }
+
+        progress_bar.advance_progress();
+        let content = last_response
+            .as_ref()
+            .map(|res| res.content.as_str())
+            .unwrap_or_default();
+        if let Some(template) = &config.load().progress_stats_format {
+            progress_bar.set_status(Some(format_generation_stats(
+                template,
+                started_at.elapsed(),
+                openai_client.count_tokens(&model, content),
+                first_token_at.map_or(Duration::ZERO, |at| at.elapsed()),
+            )));
+        }
+        if renders_partial_markdown && !content.is_empty() {
+            // The progress bar is appended after the content, so the
+            // offsets of the entities are still valid.
+            let parsed_content = markdown::parse_partial(content);
+            let edit = if render_mode == RenderMode::MarkdownV2 {
+                let updated_text = format!(
+                    "{}\n{}",
+                    markdown::to_markdown_v2(&parsed_content),
+                    markdown::escape_markdown_v2(&progress_bar.current_string())
+                );
+                bot.edit_message_text(editing_msg.chat.id, editing_msg.id, updated_text)
+                    .parse_mode(ParseMode::MarkdownV2)
+            } else {
+                let updated_text = format!(
+                    "{}\n{}",
+                    parsed_content.content,
+                    progress_bar.current_string()
+                );
+                bot.edit_message_text(editing_msg.chat.id, editing_msg.id, updated_text)
+                    .entities(parsed_content.entities)
+            };
+            let res = editor
+                .edit(edit.reply_markup(control.reply_markup.clone()))
+                .await;
+            match res {
+                Ok(sent) => {
+                    edits += sent as u32;
+                    edit_failures = 0;
+                    continue;
+                }
+                Err(err) => {
+                    warn!(
+                        "Failed to render the partial response (will fallback to raw contents): {}",
+                        err
+                    );
+                    renders_partial_markdown = false;
+                }
+            }
+        }
+
+        let updated_text = if content.is_empty() {
+            progress_bar.current_string()
+        } else {
+            format!("{}\n{}", content, progress_bar.current_string())
+        };
+
+        let res = editor
+            .edit(
+                bot.edit_message_text(editing_msg.chat.id, editing_msg.id, updated_text)
+                    .reply_markup(control.reply_markup.clone()),
+            )
+            .await;
+        match res {
+            Ok(sent) => {
+                edits += sent as u32;
+                edit_failures = 0;
+            }
+            Err(err) => {
+                edit_failures += 1;
+                if edit_failures == MAX_PROGRESS_EDIT_FAILURES {
+                    warn!("Progress animation is stopped after failed edits: {}", err);
+                }
+            }
+        }
+    }
+
+    tracing::Span::current().record("telegram_edits", edits);
+    if let Some(res) = &last_response {
+        debug!(
+            "Stream of {} finished with reason {:?}",
+            res.model, res.finish_reason
+        );
+    }
+    // A stream that fails before any content (e.g. with an error status)
+    // is a failure of the model, unlike a stream that ends without
+    // content, which is reported as an empty answer.
+    let stream_error = last_response
+        .as_ref()
+        .filter(|res| res.content.trim().is_empty())
+        .and_then(|res| res.error.clone());
+    if let Some(err) = stream_error {
+        return Err(anyhow!("Stream failed: {}", err));
+    }
+    if let Some(mut last_response) = last_response
+        .as_ref()
+        .filter(|res| !res.content.trim().is_empty())
+        .cloned()
+    {
+        // Cached answers cost no tokens.
+        if !last_response.cached {
+            // TODO: OpenAI currently doesn't support to give the token usage
+            // in stream mode. Therefore we need to count it locally.
+            last_response.prompt_tokens = prompt_tokens;
+            last_response.completion_tokens =
+                openai_client.count_tokens(&last_response.model, &last_response.content);
+        }
+        let span = tracing::Span::current();
+        span.record("prompt_tokens", last_response.prompt_tokens);
+        span.record("completion_tokens", last_response.completion_tokens);
+        // Partial answers are never cached.
+        if is_stopped {
+            last_response.cache_key = None;
+        }
+
+        return Ok(last_response);
+    }
+
+    Err(EmptyAnswer {
+        finish_reason: last_response.and_then(|res| res.finish_reason),
+    }
+    .into())
+}
+
+async fn reset_session(
+    bot: Bot,
+    msg: Message,
+    session_mgr: SessionManager,
+    config: SharedConfig,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.to_string();
+    session_mgr
+        .reset_session(
+            session_mgr
+                .active_session_key(&chat_id, topic_id(&msg))
+                .await,
+        )
+        .await;
+    let mut send_message = bot.send_message(
+        msg.chat.id,
+        &config.load().i18n_strings(user_language(&msg)).reset_prompt,
+    );
+    send_message.message_thread_id = topic_id(&msg);
+    if let Ok(sent_msg) = send_message.send_retrying().await {
+        schedule_deletion(&bot, &sent_msg, &config);
+    }
+    Ok(())
+}
+
+async fn new_session(
+    bot: Bot,
+    msg: Message,
+    (SessionName(name),): (SessionName,),
+    session_mgr: SessionManager,
+) -> HandlerResult {
+    let base_key = session_key(&msg.chat.id.to_string(), topic_id(&msg));
+    let reply_text = if session_mgr.has_session(&base_key, &name) {
+        format!(
+            "The session \"{}\" already exists, send /switch_session {} to switch to it.",
+            name, name
+        )
+    } else {
+        match session_mgr.switch_session(&base_key, &name).await {
+            Ok(_) => {
+                // Drop what's left of a session without context, e.g. its
+                // pending message.
+                session_mgr
+                    .reset_session(named_session_key(&base_key, &name))
+                    .await;
+                format!("Started a new session \"{}\".", name)
+            }
+            Err(err) => {
+                error!("Failed to switch the session: {}", err);
+                "Failed to start the session, internal error occurred".to_owned()
+            }
+        }
+    };
+    bot.send_message(msg.chat.id, reply_text)
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+    Ok(())
+}
+
+async fn switch_session(
+    bot: Bot,
+    msg: Message,
+    (SessionName(name),): (SessionName,),
+    session_mgr: SessionManager,
+) -> HandlerResult {
+    let base_key = session_key(&msg.chat.id.to_string(), topic_id(&msg));
+    let reply_text = if session_mgr.active_session_name(&base_key).await == name {
+        format!("The session \"{}\" is already active.", name)
+    } else if name != DEFAULT_SESSION_NAME && !session_mgr.has_session(&base_key, &name) {
+        format!(
+            "There is no session named \"{}\", send /sessions to list the sessions.",
+            name
+        )
+    } else {
+        match session_mgr.switch_session(&base_key, &name).await {
+            Ok(_) => format!("Switched to the session \"{}\".", name),
+            Err(err) => {
+                error!("Failed to switch the session: {}", err);
+                "Failed to switch the session, internal error occurred".to_owned()
+            }
+        }
+    };
+    bot.send_message(msg.chat.id, reply_text)
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+    Ok(())
+}
+
+async fn list_sessions(bot: Bot, msg: Message, session_mgr: SessionManager) -> HandlerResult {
+    let base_key = session_key(&msg.chat.id.to_string(), topic_id(&msg));
+    let active_name = session_mgr.active_session_name(&base_key).await;
+
+    let mut reply_text = "Sessions of this chat:\n".to_owned();
+    for session in session_mgr.list_sessions(&base_key, &active_name) {
+        let marker = if session.name == active_name {
+            "▶"
+        } else {
+            "•"
+        };
+        write!(&mut reply_text, "{} {}", marker, session.name)?;
+        if let Some(title) = &session.title {
+            write!(&mut reply_text, ": {}", title)?;
+        }
+        writeln!(&mut reply_text, " ({} messages)", session.message_count)?;
+    }
+    reply_text.push_str("\nSend /new_session <name> or /switch_session <name> to switch.");
+    bot.send_message(msg.chat.id, reply_text)
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+    Ok(())
+}
+
+async fn archive_session(
+    bot: Bot,
+    msg: Message,
+    session_mgr: SessionManager,
+    member_mgr: MemberManager,
+    openai_client: OpenAIClient,
+    config: SharedConfig,
+) -> HandlerResult {
+    let username = msg.from().and_then(|u| u.username.clone());
+    if !member_mgr
+        .is_member_allowed(username.unwrap_or_default())
+        .await
+        .unwrap_or(false)
+    {
+        reply_notice(
+            &bot,
+            &msg,
+            &config
+                .load()
+                .i18n_strings(user_language(&msg))
+                .not_allowed_prompt,
+            &config,
+        )
+        .await;
+        return Ok(());
+    }
+    let archive_config = match &config.load().archive {
+        Some(archive_config) => archive_config.clone(),
+        None => {
+            bot.send_message(msg.chat.id, "Archiving is not enabled.")
+                .reply_to_message_id(msg.id)
+                .send_retrying()
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let chat_id = msg.chat.id.to_string();
+    let key = session_mgr
+        .active_session_key(&chat_id, topic_id(&msg))
+        .await;
+    let msgs = session_mgr.get_raw_history_messages(&key);
+    if msgs.is_empty() {
+        bot.send_message(msg.chat.id, "There is nothing to archive.")
+            .reply_to_message_id(msg.id)
+            .send_retrying()
+            .await?;
+        return Ok(());
+    }
+
+    let chat_title = msg
+        .chat
+        .title()
+        .or_else(|| msg.chat.username())
+        .map(|title| title.to_owned())
+        .unwrap_or_else(|| chat_id.clone());
+    let model = openai_client.chat_model(Some(&chat_id)).await;
+    let mut archive = Archive::new(chat_id, chat_title, model, msgs);
+    archive.title = session_mgr.get_title(&key);
+
+    // The destinations (e.g. the paths on the server) are not shown in the
+    // chat.
+    let reply_text = match archive::export(&archive_config, &archive).await {
+        Ok(destinations) => {
+            info!(
+                "Archived chat {} to {}",
+                archive.chat_id,
+                destinations.join(", ")
+            );
+            "The conversation is archived.".to_owned()
+        }
+        Err(err) => {
+            error!("Failed to archive the session: {}", err);
+            "Failed to archive the conversation, internal error occurred".to_owned()
+        }
+    };
+    bot.send_message(msg.chat.id, reply_text)
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+/// The maximum size of the documents accepted by `/import`.
+const MAX_IMPORT_FILE_SIZE: u32 = 1024 * 1024;
+
+async fn import_session(
+    bot: Bot,
+    msg: Message,
+    session_mgr: SessionManager,
+    member_mgr: MemberManager,
+    openai_client: OpenAIClient,
+    config: SharedConfig,
+) -> HandlerResult {
+    let username = msg.from().and_then(|u| u.username.clone());
+    if !member_mgr
+        .is_member_allowed(username.unwrap_or_default())
+        .await
+        .unwrap_or(false)
+    {
+        reply_notice(
+            &bot,
+            &msg,
+            &config
+                .load()
+                .i18n_strings(user_language(&msg))
+                .not_allowed_prompt,
+            &config,
+        )
+        .await;
+        return Ok(());
+    }
 
-    // Record stats and add the reply to history.
-    let reply_result = match result {
-        Ok(res) => {
-            let reply_history_message = session_mgr.with_mut_session(chat_id.clone(), |session| {
-                session.prepare_history_message(
-                    ChatCompletionRequestMessageArgs::default()
-                        .role(Role::Assistant)
-                        .content(&res.content)
-                        .build()
-                        .unwrap(),
-                )
-            });
+    let document = match msg.reply_to_message().and_then(|m| m.document()) {
+        Some(document) => document,
+        None => {
+            bot.send_message(
+                msg.chat.id,
+                "Send the JSON file of an archived conversation, and reply /import to it.",
+            )
+            .reply_to_message_id(msg.id)
+            .send_retrying()
+            .await?;
+            return Ok(());
+        }
+    };
+    let is_json = document
+        .file_name
+        .as_deref()
+        .map(|name| name.to_lowercase().ends_with(".json"))
+        .unwrap_or(false)
+        || document
+            .mime_type
+            .as_ref()
+            .map(|mime| mime.essence_str() == "application/json")
+            .unwrap_or(false);
+    let error_text = if !is_json {
+        Some("The file is not a JSON document.".to_owned())
+    } else if document.file.size > MAX_IMPORT_FILE_SIZE {
+        Some(format!(
+            "The file is too large, the limit is {} KB.",
+            MAX_IMPORT_FILE_SIZE / 1024
+        ))
+    } else {
+        None
+    };
+    if let Some(error_text) = error_text {
+        reply_notice(&bot, &msg, error_text, &config).await;
+        return Ok(());
+    }
 
-            let need_fallback = if config.renders_markdown {
-                let parsed_content = markdown::parse(&res.content);
-                #[cfg(debug_assertions)]
-                {
-                    debug!(
-                        "rendered Markdown contents: {}\ninto: {:#?}",
-                        res.content, parsed_content
-                    );
-                }
-                let mut edit_message_text = bot.edit_message_text(
-                    chat_id.to_owned(),
-                    sent_progress_msg.id,
-                    parsed_content.content,
-                );
-                if !parsed_content.entities.is_empty() {
-                    let show_raw_button = InlineKeyboardButton::callback(
-                        "Show Raw Contents",
-                        format!("/show_raw:{}", reply_history_message.id),
-                    );
-                    edit_message_text.entities = Some(parsed_content.entities);
-                    edit_message_text.reply_markup =
-                        Some(InlineKeyboardMarkup::default().append_row([show_raw_button]));
-                }
-                if let Err(first_trial_err) = edit_message_text.await {
-                    // TODO: test if the error is related to Markdown before
-                    // fallback to raw contents.
-                    error!(
-                        "failed to send message (will fallback to raw contents): {}",
-                        first_trial_err
-                    );
-                    true
-                } else {
-                    false
-                }
-            } else {
-                true
-            };
+    let file = bot.get_file(&document.file.id).await?;
+    let mut buf = vec![];
+    bot.download_file(&file.path, &mut buf).await?;
+    // The system prompt of a group is changed by admins only, like
+    // `/system_prompt`.
+    let allows_system =
+        msg.chat.is_private() || msg.from().is_some_and(|user| is_admin(user, &config));
+    let msgs = match Archive::parse_messages(&buf, allows_system) {
+        Ok(msgs) => msgs,
+        Err(err) => {
+            reply_notice(&bot, &msg, format!("Failed to import: {}", err), &config).await;
+            return Ok(());
+        }
+    };
 
-            if need_fallback {
-                bot.edit_message_text(chat_id.to_owned(), sent_progress_msg.id, &res.content)
-                    .await?;
-            }
+    // Rebuild the session as if the messages were sent one by one, so the
+    // oldest ones are dropped beyond the conversation limit.
+    let mut session = Session::new(config.clone());
+    let model = openai_client
+        .chat_model(Some(&msg.chat.id.to_string()))
+        .await;
+    for chat_msg in msgs {
+        let token_count = openai_client.count_message_tokens(&model, slice::from_ref(&chat_msg));
+        let mut history_msg = session.prepare_history_message(chat_msg, token_count);
+        history_msg.parent_id = session.last_history_message_id();
+        session.add_history_message(history_msg);
+    }
+    let message_count = session.context_usage().message_count;
+    session_mgr.replace_session(
+        session_mgr
+            .active_session_key(&msg.chat.id.to_string(), topic_id(&msg))
+            .await,
+        session,
+    );
 
-            session_mgr.with_mut_session(chat_id.clone(), |session| {
-                let user_history_msg = session.prepare_history_message(user_msg);
-                session.add_history_message(user_history_msg);
-                session.add_history_message(reply_history_message);
-            });
+    bot.send_message(
+        msg.chat.id,
+        format!(
+            "Imported {} messages, the conversation continues from there.",
+            message_count
+        ),
+    )
+    .reply_to_message_id(msg.id)
+    .send_retrying()
+    .await?;
 
-            // TODO: maybe we need to handle the case that `reply_to_msg` is `None`.
-            if let Some(from_username) = reply_to_msg
-                .as_ref()
-                .and_then(|m| m.from())
-                .and_then(|u| u.username.as_ref())
-            {
-                let res = stats_mgr
-                    .add_usage(from_username.to_owned(), res.token_usage as _)
-                    .await;
-                if let Err(err) = res {
-                    error!("Failed to update stats: {}", err);
-                }
-            }
-            Ok(())
+    Ok(())
+}
+
+async fn set_voice_reply(
+    bot: Bot,
+    msg: Message,
+    args: CommandArgs,
+    prefs_mgr: PreferencesManager,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.to_string();
+
+    let voice_reply = match args.0.trim() {
+        "" => {
+            let current: bool = prefs_mgr
+                .get_chat_value(&chat_id, VOICE_REPLY_PREF_KEY)
+                .await?;
+            bot.send_message(
+                msg.chat.id,
+                format!(
+                    "Voice replies are {}, use \"/speak on\" or \"/speak off\" to change it",
+                    if current { "on" } else { "off" }
+                ),
+            )
+            .reply_to_message_id(msg.id)
+            .send_retrying()
+            .await?;
+            return Ok(());
+        }
+        "on" => true,
+        "off" => false,
+        _ => {
+            bot.send_message(
+                msg.chat.id,
+                "Invalid value, possible values are \"on\", \"off\"",
+            )
+            .reply_to_message_id(msg.id)
+            .send_retrying()
+            .await?;
+            return Ok(());
         }
+    };
+
+    let reply_text = match prefs_mgr
+        .set_chat_value(&chat_id, VOICE_REPLY_PREF_KEY, &voice_reply)
+        .await
+    {
+        Ok(_) if voice_reply => "Success, answers will also be spoken in this chat",
+        Ok(_) => "Success, voice replies are turned off",
         Err(err) => {
-            error!("Failed to request the model: {}", err);
-            session_mgr.swap_session_pending_message(chat_id.clone(), Some(user_msg));
-            let retry_button = InlineKeyboardButton::callback("Retry", "/retry");
-            let reply_markup = InlineKeyboardMarkup::default().append_row([retry_button]);
-            bot.edit_message_text(chat_id, sent_progress_msg.id, &config.i18n.api_error_prompt)
-                .reply_markup(reply_markup)
-                .await
-                .map(|_| ())
+            error!("Failed to set voice reply: {}", err);
+            "Failed to set voice reply, internal error occurred"
         }
     };
+    bot.send_message(msg.chat.id, reply_text)
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
 
-    if let Err(err) = reply_result {
-        error!("Failed to edit the final message: {}", err);
+    Ok(())
+}
+
+async fn set_reply_length(
+    bot: Bot,
+    msg: Message,
+    args: CommandArgs,
+    prefs_mgr: PreferencesManager,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.to_string();
+
+    let arg = args.0.trim();
+    if arg.is_empty() {
+        let current: ReplyLength = prefs_mgr
+            .get_chat_value(&chat_id, REPLY_LENGTH_PREF_KEY)
+            .await?;
+        bot.send_message(
+            msg.chat.id,
+            format!("Current reply length: {}", current.name()),
+        )
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+        return Ok(());
+    }
+
+    let reply_length: ReplyLength = match arg.parse() {
+        Ok(reply_length) => reply_length,
+        Err(_) => {
+            bot.send_message(
+                msg.chat.id,
+                "Invalid value, possible values are \"short\", \"normal\", \"detailed\"",
+            )
+            .reply_to_message_id(msg.id)
+            .send_retrying()
+            .await?;
+            return Ok(());
+        }
+    };
+
+    match prefs_mgr
+        .set_chat_value(&chat_id, REPLY_LENGTH_PREF_KEY, &reply_length)
+        .await
+    {
+        Ok(_) => {
+            bot.send_message(
+                msg.chat.id,
+                format!("Success, current reply length: {}", reply_length.name()),
+            )
+            .reply_to_message_id(msg.id)
+            .send_retrying()
+            .await?;
+        }
+        Err(err) => {
+            error!("Failed to set reply length: {}", err);
+            bot.send_message(
+                msg.chat.id,
+                "Failed to set reply length, internal error occurred",
+            )
+            .reply_to_message_id(msg.id)
+            .send_retrying()
+            .await?;
+        }
     }
 
     Ok(())
 }
 
-async fn stream_model_result(
-    bot: &Bot,
+/// Resets the session and installs the prompt of the persona as its system
+/// message.
+async fn install_persona(
+    session_mgr: &SessionManager,
+    key: String,
+    persona: &Persona,
+    openai_client: &OpenAIClient,
+) {
+    session_mgr.reset_session(key.clone()).await;
+    add_system_prompt(session_mgr, key.clone(), &persona.prompt, openai_client).await;
+    session_mgr.with_mut_session(key, |session| session.set_persona(persona.name.clone()));
+}
+
+/// Sets the prompt as the system message of the session.
+async fn add_system_prompt(
+    session_mgr: &SessionManager,
+    key: String,
+    prompt: &str,
+    openai_client: &OpenAIClient,
+) {
+    let system_msg = ChatCompletionRequestMessageArgs::default()
+        .role(Role::System)
+        .content(prompt)
+        .build()
+        .unwrap();
+    let chat_id = parse_session_key(&key).map(|(chat_id, _)| chat_id.to_string());
+    let model = openai_client.chat_model(chat_id.as_deref()).await;
+    let token_count = openai_client.count_message_tokens(&model, slice::from_ref(&system_msg));
+    session_mgr.with_mut_session(key, |session| {
+        let history_msg = session.prepare_history_message(system_msg, token_count);
+        session.add_history_message(history_msg);
+    });
+}
+
+/// Returns the system prompt that new sessions of the chat start with.
+async fn chat_system_prompt(
     chat_id: &str,
-    editing_msg: &Message,
-    mut progress_bar: BrailleProgress,
-    msgs: Vec<ChatCompletionRequestMessage>,
-    openai_client: OpenAIClient,
+    prefs_mgr: &PreferencesManager,
     config: &SharedConfig,
-) -> Result<ChatModelResult, Error> {
-    let estimated_prompt_tokens = openai_client.estimate_prompt_tokens(&msgs);
+) -> Option<String> {
+    let chat_prompt: Option<String> = prefs_mgr
+        .get_chat_value(chat_id, SYSTEM_PROMPT_PREF_KEY)
+        .await
+        .unwrap_or_default();
+    let prompt = chat_prompt.or_else(|| {
+        let config = config.load();
+        // The ids of groups are negative.
+        if chat_id.starts_with('-') {
+            config
+                .group_system_prompt
+                .clone()
+                .or_else(|| config.default_system_prompt.clone())
+        } else {
+            config.default_system_prompt.clone()
+        }
+    });
+    prompt.filter(|prompt| !prompt.trim().is_empty())
+}
+
+/// Shows or overrides the system prompt of the chat. Only admins can change
+/// it in groups.
+async fn set_system_prompt(
+    bot: Bot,
+    msg: Message,
+    args: CommandArgs,
+    session_mgr: SessionManager,
+    prefs_mgr: PreferencesManager,
+    openai_client: OpenAIClient,
+    config: SharedConfig,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.to_string();
 
-    let stream = openai_client.request_chat_model(msgs).await?;
-    let mut throttled_stream =
-        stream.throttle_buffer::<Vec<_>>(Duration::from_millis(config.stream_throttle_interval));
+    let prompt = match args.0.trim() {
+        "" => {
+            let reply_text = match chat_system_prompt(&chat_id, &prefs_mgr, &config).await {
+                Some(prompt) => format!(
+                    "The system prompt of this chat is:\n{}\n\nUse \"/system_prompt <prompt>\" to change it, \"/system_prompt off\" to turn it off or \"/system_prompt default\" to use the default one",
+                    prompt
+                ),
+                None => "This chat has no system prompt, use \"/system_prompt <prompt>\" to set one".to_owned(),
+            };
+            reply_in_topic(&bot, &msg, reply_text)
+                .send_retrying()
+                .await?;
+            return Ok(());
+        }
+        "default" => None,
+        "off" => Some(String::new()),
+        prompt => Some(prompt.to_owned()),
+    };
 
-    let mut timeout_times = 0;
-    let mut last_response = None;
-    loop {
-        tokio::select! {
-            res = throttled_stream.next() => {
-                if res.is_none() {
-                    break;
-                }
+    let is_allowed =
+        msg.chat.is_private() || msg.from().is_some_and(|user| is_admin(user, &config));
+    if !is_allowed {
+        reply_in_topic(
+            &bot,
+            &msg,
+            "Only admins can change the system prompt of a group.",
+        )
+        .send_retrying()
+        .await?;
+        return Ok(());
+    }
 
-                // Since the stream item is scanned (accumulated), we only
-                // need to get the last item in the buffer and use it as
-                // the latest message content.
-                last_response = res.as_ref().unwrap().last().cloned();
+    let reply_text = match prefs_mgr
+        .set_chat_value(&chat_id, SYSTEM_PROMPT_PREF_KEY, &prompt)
+        .await
+    {
+        Ok(_) => {
+            // Start over with the new system prompt.
+            let key = session_mgr
+                .active_session_key(&chat_id, topic_id(&msg))
+                .await;
+            session_mgr.reset_session(key.clone()).await;
+            if let Some(prompt) = chat_system_prompt(&chat_id, &prefs_mgr, &config).await {
+                add_system_prompt(&session_mgr, key, &prompt, &openai_client).await;
+            }
+            "Success, the session is reset with the new system prompt"
+        }
+        Err(err) => {
+            error!("Failed to set the system prompt: {}", err);
+            "Failed to set the system prompt, internal error occurred"
+        }
+    };
+    reply_in_topic(&bot, &msg, reply_text)
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+/// Handles `/start`, with an optional deep link payload to bootstrap the
+/// conversation.
+async fn handle_start(
+    bot: Bot,
+    msg: Message,
+    session_mgr: SessionManager,
+    event_bus: EventBus,
+    member_mgr: MemberManager,
+    persona_mgr: PersonaManager,
+    prefs_mgr: PreferencesManager,
+    openai_client: OpenAIClient,
+    config: SharedConfig,
+) -> HandlerResult {
+    let payload = msg
+        .text()
+        .and_then(|text| text.split_once(' '))
+        .and_then(|(_, payload)| StartPayload::parse(payload));
+    let username = msg.from().and_then(|u| u.username.clone());
 
-                // Reset the timeout once the stream is resumed.
-                timeout_times = 0;
+    if let Some(StartPayload::Invite(code)) = &payload {
+        let reply_text = match username {
+            _ if !config.load().invite_codes.contains(code) => {
+                "The invite code is invalid.".to_owned()
+            }
+            None => "Please set a username first, members are identified by usernames.".to_owned(),
+            Some(username) => match member_mgr.add_member(username.clone()).await {
+                Ok(added) => {
+                    if added {
+                        info!("{} joined with an invite code", username);
+                        event_bus.publish(Event::MemberAdded { username });
+                    }
+                    config
+                        .load()
+                        .i18n_strings(user_language(&msg))
+                        .start_prompt
+                        .clone()
+                }
+                Err(err) => {
+                    error!("Failed to add member: {}", err);
+                    config
+                        .load()
+                        .i18n_strings(user_language(&msg))
+                        .api_error_prompt
+                        .clone()
+                }
             },
-            _ = tokio::time::sleep(Duration::from_secs(1)) => {
-                timeout_times += 1;
-                if timeout_times >= config.openai_api_timeout {
-                    return Err(anyhow!("Stream is timeout"));
+        };
+        reply_in_topic(&bot, &msg, reply_text)
+            .send_retrying()
+            .await?;
+        return Ok(());
+    }
+
+    if payload.is_some()
+        && !member_mgr
+            .is_member_allowed(username.unwrap_or_default())
+            .await
+            .unwrap_or(false)
+    {
+        reply_notice(
+            &bot,
+            &msg,
+            &config
+                .load()
+                .i18n_strings(user_language(&msg))
+                .not_allowed_prompt,
+            &config,
+        )
+        .await;
+        return Ok(());
+    }
+
+    let chat_id = msg.chat.id.to_string();
+    let topic_id = topic_id(&msg);
+    match payload {
+        Some(StartPayload::Persona(name)) => {
+            let reply_text = match persona_mgr.get_persona(name.clone()).await? {
+                Some(persona) => {
+                    let key = session_mgr.active_session_key(&chat_id, topic_id).await;
+                    install_persona(&session_mgr, key, &persona, &openai_client).await;
+                    format!(
+                        "Persona \"{}\" is activated, send a message to start.",
+                        name
+                    )
+                }
+                None => format!("Persona \"{}\" is not found.", name),
+            };
+            reply_in_topic(&bot, &msg, reply_text)
+                .send_retrying()
+                .await?;
+        }
+        Some(StartPayload::Fork(token)) => {
+            let forked_key = match msg.from() {
+                Some(user) if msg.chat.is_private() => session_mgr.take_fork(&token, user.id.0),
+                _ => None,
+            };
+            let reply_text = match forked_key {
+                Some(forked_key) => {
+                    if session_mgr.fork_session(
+                        &forked_key,
+                        session_mgr.active_session_key(&chat_id, topic_id).await,
+                    ) {
+                        "The conversation of the group is continued here, send a message to dig deeper."
+                    } else {
+                        "The conversation of the group is empty, send a message to start."
+                    }
+                }
+                None => {
+                    "The link is expired, press \"Continue in private\" under the answer again."
+                }
+            };
+            reply_in_topic(&bot, &msg, reply_text)
+                .send_retrying()
+                .await?;
+        }
+        Some(StartPayload::Prompt(name)) => {
+            let prompt = config.load().prompt_templates.get(&name).cloned();
+            match prompt {
+                Some(prompt) => {
+                    actually_handle_chat_message(
+                        bot,
+                        Some(msg),
+                        prompt,
+                        vec![],
+                        chat_id,
+                        topic_id,
+                        session_mgr,
+                        event_bus,
+                        prefs_mgr,
+                        openai_client,
+                        config,
+                        AnswerOptions::default(),
+                    )
+                    .await?;
+                }
+                None => {
+                    reply_in_topic(&bot, &msg, format!("Prompt \"{}\" is not found.", name))
+                        .send_retrying()
+                        .await?;
                 }
             }
         }
+        _ => {
+            reply_in_topic(
+                &bot,
+                &msg,
+                &config.load().i18n_strings(user_language(&msg)).start_prompt,
+            )
+            .send_retrying()
+            .await?;
+        }
+    }
 
-        progress_bar.advance_progress();
-        let updated_text = if let Some(last_response) = &last_response {
-            format!(
-                "{}\n{}",
-                last_response.content,
-                progress_bar.current_string()
+    Ok(())
+}
+
+/// Shows how full the context of the current session is, so that users
+/// can tell when to reset it. `/context on` also shows a summary of it
+/// under each answer.
+async fn show_context_usage(
+    bot: Bot,
+    msg: Message,
+    args: CommandArgs,
+    session_mgr: SessionManager,
+    prefs_mgr: PreferencesManager,
+    openai_client: OpenAIClient,
+    config: SharedConfig,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.to_string();
+    let shows_context = match args.0.trim() {
+        "" => None,
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => {
+            bot.send_message(
+                msg.chat.id,
+                "Invalid value, possible values are \"on\", \"off\"",
             )
-        } else {
-            progress_bar.current_string()
+            .reply_to_message_id(msg.id)
+            .send_retrying()
+            .await?;
+            return Ok(());
+        }
+    };
+    if let Some(shows_context) = shows_context {
+        let reply_text = match prefs_mgr
+            .set_chat_value(&chat_id, CONTEXT_INDICATOR_PREF_KEY, &shows_context)
+            .await
+        {
+            Ok(_) if shows_context => "Success, the context usage will be shown under answers",
+            Ok(_) => "Success, the context usage is no longer shown under answers",
+            Err(err) => {
+                error!("Failed to set the context indicator: {}", err);
+                "Failed to set the context indicator, internal error occurred"
+            }
         };
-
-        let _ = bot
-            .edit_message_text(chat_id.to_owned(), editing_msg.id, updated_text)
-            .await;
+        bot.send_message(msg.chat.id, reply_text)
+            .reply_to_message_id(msg.id)
+            .send_retrying()
+            .await?;
+        return Ok(());
     }
 
-    if let Some(mut last_response) = last_response {
-        // TODO: OpenAI currently doesn't support to give the token usage
-        // in stream mode. Therefore we need to estimate it locally.
-        last_response.token_usage =
-            openai_client.estimate_tokens(&last_response.content) + estimated_prompt_tokens;
+    let (usage, title) = session_mgr.with_mut_session(
+        session_mgr
+            .active_session_key(&chat_id, topic_id(&msg))
+            .await,
+        |session| {
+            (
+                session.context_usage(),
+                session.title().map(|title| title.to_owned()),
+            )
+        },
+    );
+    let model = openai_client.chat_model(Some(&chat_id)).await;
+    let context_size = openai_client.context_size(&model);
+    let config = config.load();
 
-        return Ok(last_response);
+    let mut reply_text = String::new();
+    if let Some(title) = title {
+        writeln!(&mut reply_text, "Title: {}", title)?;
+    }
+    writeln!(
+        &mut reply_text,
+        "Messages: {} of {}",
+        usage.message_count, config.conversation_limit
+    )?;
+    writeln!(
+        &mut reply_text,
+        "Tokens: ~{} of {} ({:.0}%, context window of {})",
+        usage.tokens,
+        context_size,
+        usage.tokens as f64 * 100.0 / context_size.max(1) as f64,
+        model
+    )?;
+    if let Some(max_prompt_tokens) = config.max_prompt_tokens {
+        writeln!(
+            &mut reply_text,
+            "Prompt budget: {} tokens, older messages are dropped beyond it",
+            max_prompt_tokens
+        )?;
+    }
+    if let Some(oldest_message) = usage.oldest_message {
+        let preview: String = oldest_message.content.chars().take(50).collect();
+        let ellipsis = if preview.len() < oldest_message.content.len() {
+            "…"
+        } else {
+            ""
+        };
+        writeln!(
+            &mut reply_text,
+            "Next to be dropped: \"{}{}\"",
+            preview, ellipsis
+        )?;
+        write!(&mut reply_text, "Use /reset to start over.")?;
     }
 
-    Err(anyhow!("Server returned empty response"))
+    bot.send_message(msg.chat.id, reply_text.trim_end())
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+
+    Ok(())
 }
 
-async fn reset_session(
+/// Activates the persona in the current session.
+async fn activate_persona(
     bot: Bot,
     msg: Message,
+    args: CommandArgs,
     session_mgr: SessionManager,
+    member_mgr: MemberManager,
+    persona_mgr: PersonaManager,
+    openai_client: OpenAIClient,
     config: SharedConfig,
 ) -> HandlerResult {
-    let chat_id = msg.chat.id;
-    session_mgr.reset_session(chat_id.to_string());
-    let _ = bot.send_message(chat_id, &config.i18n.reset_prompt).await;
+    let username = msg.from().and_then(|u| u.username.clone());
+    if !member_mgr
+        .is_member_allowed(username.unwrap_or_default())
+        .await
+        .unwrap_or(false)
+    {
+        reply_notice(
+            &bot,
+            &msg,
+            &config
+                .load()
+                .i18n_strings(user_language(&msg))
+                .not_allowed_prompt,
+            &config,
+        )
+        .await;
+        return Ok(());
+    }
+
+    let name = args.0.trim();
+    if name.is_empty() {
+        reply_in_topic(
+            &bot,
+            &msg,
+            "Usage: /persona <name>, send /personas to see the available personas.",
+        )
+        .send_retrying()
+        .await?;
+        return Ok(());
+    }
+
+    let reply_text = match persona_mgr.get_persona(name.to_owned()).await? {
+        Some(persona) => {
+            let key = session_mgr
+                .active_session_key(&msg.chat.id.to_string(), topic_id(&msg))
+                .await;
+            install_persona(&session_mgr, key, &persona, &openai_client).await;
+            format!(
+                "Persona \"{}\" is activated, send a message to start.",
+                persona.name
+            )
+        }
+        None => format!("Persona \"{}\" is not found.", name),
+    };
+    reply_in_topic(&bot, &msg, reply_text)
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+async fn list_personas(bot: Bot, msg: Message, persona_mgr: PersonaManager) -> HandlerResult {
+    let personas = persona_mgr.list_personas().await?;
+    let reply_text = if personas.is_empty() {
+        "No personas are available.".to_owned()
+    } else {
+        let mut text = String::from("Available personas:\n");
+        for persona in personas {
+            let preview: String = persona.prompt.chars().take(60).collect();
+            let ellipsis = if preview.len() < persona.prompt.len() {
+                "…"
+            } else {
+                ""
+            };
+            write!(&mut text, "\n{}: {}{}", persona.name, preview, ellipsis)?;
+        }
+        write!(&mut text, "\n\nSend /persona <name> to activate one.")?;
+        text
+    };
+    reply_in_topic(&bot, &msg, reply_text)
+        .send_retrying()
+        .await?;
+
     Ok(())
 }
 
-pub(crate) struct Chat;
+pub(crate) struct Chat {
+    db_mgr: DatabaseManager,
+}
+
+impl Chat {
+    pub(crate) fn new(db_mgr: DatabaseManager) -> Self {
+        Self { db_mgr }
+    }
+}
 
 #[async_trait]
 impl Module for Chat {
     async fn register_dependency(&mut self, dep_map: &mut DependencyMap) -> Result<(), Error> {
         let config: Arc<SharedConfig> = dep_map.get();
+        let bot: Arc<Bot> = dep_map.get();
 
-        dep_map.insert(SessionManager::new(config.as_ref().clone()));
+        let event_bus: Arc<EventBus> = dep_map.get();
+
+        let prefs_mgr: Arc<PreferencesManager> = dep_map.get();
+        let pending_store = PendingMessageStore::new(self.db_mgr.clone());
+        match pending_store.restore().await {
+            Ok(0) => {}
+            Ok(count) => info!("Restored {} pending messages", count),
+            Err(err) => error!("Failed to restore the pending messages: {}", err),
+        }
+        let session_mgr = SessionManager::new(
+            config.as_ref().clone(),
+            prefs_mgr.as_ref().clone(),
+            pending_store,
+        );
+        session_mgr.start_expiry_task(bot.as_ref().clone());
+        dep_map.insert(session_mgr);
+
+        let persona_mgr =
+            PersonaManager::new(self.db_mgr.clone(), config.load().personas.clone()).await?;
+        dep_map.insert(persona_mgr);
+
+        dep_map.insert(GroupMessageCache::new(config.as_ref().clone()));
+        dep_map.insert(GroupTriggerCache::new(prefs_mgr.as_ref().clone()));
+        let member_mgr: Arc<MemberManager> = dep_map.get();
+        let quota_mgr: Arc<QuotaManager> = dep_map.get();
+        let role_mgr: Arc<RoleManager> = dep_map.get();
+        dep_map.insert(QuotaGuard {
+            member_mgr: member_mgr.as_ref().clone(),
+            quota_mgr: quota_mgr.as_ref().clone(),
+            role_mgr: role_mgr.as_ref().clone(),
+            config: config.as_ref().clone(),
+        });
+
+        let degraded_chats = DegradedChats::default();
+        let (bot, subscriber_degraded_chats, config) = (
+            bot.as_ref().clone(),
+            degraded_chats.clone(),
+            config.as_ref().clone(),
+        );
+        event_bus.subscribe(move |event| {
+            track_chat_health(
+                bot.clone(),
+                subscriber_degraded_chats.clone(),
+                config.clone(),
+                event,
+            )
+        });
+        dep_map.insert(degraded_chats);
 
         Ok(())
     }
@@ -415,22 +3647,140 @@ impl Module for Chat {
         dptree::entry()
             .branch(
                 Update::filter_message()
-                    .filter_map(|msg: Message| msg.text().map(|text| MessageText(text.to_owned())))
-                    .map(|msg: Message| msg.chat.id)
+                    .filter_map(|msg: Message| {
+                        // Photos and voices without caption are also accepted.
+                        msg.text()
+                            .or_else(|| msg.caption())
+                            .or_else(|| msg.photo().map(|_| ""))
+                            .or_else(|| msg.voice().map(|_| ""))
+                            .map(|text| MessageText(text.to_owned()))
+                    })
+                    .branch(dptree::filter_async(skip_degraded_chat).endpoint(noop_handler))
+                    .branch(dptree::filter_async(enforce_quotas).endpoint(noop_handler))
                     .branch(dptree::filter_async(handle_chat_message).endpoint(noop_handler)),
             )
+            .branch(
+                Update::filter_edited_message()
+                    .branch(dptree::filter_async(skip_degraded_chat).endpoint(noop_handler))
+                    .branch(dptree::filter_async(enforce_quotas).endpoint(noop_handler))
+                    .branch(dptree::filter_async(handle_edited_message).endpoint(noop_handler)),
+            )
             .branch(
                 Update::filter_callback_query()
                     .branch(dptree::filter_async(handle_retry_action).endpoint(noop_handler))
-                    .branch(dptree::filter_async(handle_show_raw_action).endpoint(noop_handler)),
+                    .branch(dptree::filter_async(handle_regenerate_action).endpoint(noop_handler))
+                    .branch(dptree::filter_async(handle_show_raw_action).endpoint(noop_handler))
+                    .branch(dptree::filter_async(handle_stop_action).endpoint(noop_handler))
+                    .branch(dptree::filter_async(handle_new_topic_action).endpoint(noop_handler))
+                    .branch(dptree::filter_async(handle_fork_action).endpoint(noop_handler))
+                    .branch(dptree::filter_async(handle_feedback_action).endpoint(noop_handler)),
             )
     }
 
     fn commands(&self) -> Vec<Command> {
-        vec![Command::new(
-            "reset",
-            "Reset the current session",
-            dptree::endpoint(reset_session),
-        )]
+        vec![
+            // The prompts of deep links ask the model, see
+            // `is_question_command`.
+            Command::new("start", "", with_quotas(dptree::endpoint(handle_start))).hidden(),
+            // The question is answered by the filter handler like other
+            // messages, so that it goes through the same checks (e.g. the
+            // quotas).
+            Command::new(
+                "ask",
+                "Ask a question, which also works in groups that only accept commands",
+                dptree::entry(),
+            ),
+            Command::new(
+                "continue",
+                "Continue the last answer, e.g. when it's truncated",
+                dptree::entry(),
+            ),
+            Command::new(
+                "reset",
+                "Reset the current session",
+                dptree::endpoint(reset_session),
+            ),
+            Command::new(
+                "speak",
+                "Speak the answers as voice messages (on or off)",
+                dptree::endpoint(set_voice_reply),
+            ),
+            Command::new(
+                "persona",
+                "Activate a persona in the current session",
+                dptree::endpoint(activate_persona),
+            ),
+            Command::new(
+                "personas",
+                "List the available personas",
+                dptree::endpoint(list_personas),
+            ),
+            Command::new(
+                "context",
+                "Show how full the context of the conversation is, or show it under answers (on or off)",
+                dptree::endpoint(show_context_usage),
+            ),
+            Command::new(
+                "new_session",
+                "Start another session in this chat, keeping the current one",
+                command_with_args::<(SessionName,)>("new_session").endpoint(new_session),
+            ),
+            Command::new(
+                "switch_session",
+                "Switch to another session of this chat",
+                command_with_args::<(SessionName,)>("switch_session").endpoint(switch_session),
+            ),
+            Command::new(
+                "sessions",
+                "List the sessions of this chat",
+                dptree::endpoint(list_sessions),
+            ),
+            Command::new(
+                "archive",
+                "Archive the current conversation",
+                dptree::endpoint(archive_session),
+            ),
+            Command::new(
+                "import",
+                "Continue an archived conversation (reply to its JSON file)",
+                dptree::endpoint(import_session),
+            ),
+            Command::new(
+                "length",
+                "Set the reply length (short, normal or detailed)",
+                dptree::endpoint(set_reply_length),
+            ),
+            Command::new(
+                "retry",
+                "Regenerate the last answer, optionally with another temperature",
+                with_quotas(
+                    command_with_args::<(Option<f32>,)>("retry").endpoint(retry_last_answer),
+                ),
+            ),
+            Command::new(
+                "search",
+                "Answer with the results of a web search",
+                with_quotas(dptree::endpoint(search_web)),
+            ),
+            Command::new(
+                "summarize",
+                "Summarize the recent messages of this group, optionally the last N",
+                with_quotas(
+                    command_with_args::<(Option<u32>,)>("summarize")
+                        .map(collect_recent_messages)
+                        .endpoint(summarize_group),
+                ),
+            ),
+            Command::new(
+                "forget_messages",
+                "Forget the recent messages kept for /summarize",
+                dptree::endpoint(forget_group_messages),
+            ),
+            Command::new(
+                "system_prompt",
+                "Show or change the system prompt of this chat",
+                dptree::endpoint(set_system_prompt),
+            ),
+        ]
     }
 }
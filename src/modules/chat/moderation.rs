@@ -0,0 +1,73 @@
+use crate::{
+    config::ModerationMode,
+    event_bus::{Event, EventBus},
+    modules::openai::OpenAIClient,
+};
+
+/// The decision on a moderated content.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Verdict {
+    Allowed,
+    /// The content is allowed, but the user should be warned.
+    Warned,
+    Blocked,
+}
+
+/// The origin of a moderated content.
+pub(crate) struct ContentSource<'a> {
+    pub chat_id: &'a str,
+    pub username: Option<String>,
+    /// `true` for the answers of the model.
+    pub is_output: bool,
+}
+
+/// Checks the content with the moderation endpoint in the given mode, and
+/// publishes the flagged contents. Contents are allowed if the moderation
+/// fails, so that outages of the endpoint don't take the bot down.
+pub(crate) async fn moderate_content(
+    text: &str,
+    mode: ModerationMode,
+    source: ContentSource<'_>,
+    openai_client: &OpenAIClient,
+    event_bus: &EventBus,
+) -> Verdict {
+    if mode == ModerationMode::Off || text.trim().is_empty() {
+        return Verdict::Allowed;
+    }
+
+    let categories = match openai_client.moderate(text).await {
+        Ok(categories) => categories,
+        Err(err) => {
+            error!("Failed to moderate the content: {}", err);
+            return Verdict::Allowed;
+        }
+    };
+    if categories.is_empty() {
+        return Verdict::Allowed;
+    }
+
+    let verdict = match mode {
+        ModerationMode::Block => Verdict::Blocked,
+        ModerationMode::Warn => Verdict::Warned,
+        _ => Verdict::Allowed,
+    };
+    warn!(
+        "{} in chat {} is flagged ({}): {:?}",
+        if source.is_output {
+            "Answer"
+        } else {
+            "Message"
+        },
+        source.chat_id,
+        categories.join(", "),
+        verdict
+    );
+    event_bus.publish(Event::ContentFlagged {
+        chat_id: source.chat_id.to_owned(),
+        username: source.username,
+        is_output: source.is_output,
+        categories,
+        blocked: verdict == Verdict::Blocked,
+    });
+    verdict
+}
@@ -0,0 +1,147 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use rusqlite::OptionalExtension;
+
+use crate::database::DatabaseManager;
+
+/// Pending messages older than this can't be retried, since the
+/// conversation has likely moved on.
+const PENDING_MESSAGE_TTL_SECS: i64 = 24 * 60 * 60;
+
+/// A message whose answer failed, which can be retried with the Retry
+/// button.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct PendingMessage {
+    pub content: String,
+    /// The unix timestamp when the answer failed.
+    pub created_at: i64,
+}
+
+impl PendingMessage {
+    pub fn is_stale(&self) -> bool {
+        now() - self.created_at > PENDING_MESSAGE_TTL_SECS
+    }
+}
+
+/// Stores the pending messages in the database by the session keys, so
+/// that they can be retried after restarts. Each session has at most one
+/// pending message.
+#[derive(Clone)]
+pub(crate) struct PendingMessageStore {
+    db_mgr: DatabaseManager,
+}
+
+impl PendingMessageStore {
+    pub fn new(db_mgr: DatabaseManager) -> Self {
+        Self { db_mgr }
+    }
+
+    /// Restores the pending messages kept before the restart, and returns
+    /// the number of them. The stale ones are deleted.
+    pub async fn restore(&self) -> Result<usize, Error> {
+        let before = now() - PENDING_MESSAGE_TTL_SECS;
+        self.db_mgr
+            .write(move |conn| {
+                conn.execute(
+                    "DELETE FROM pending_messages WHERE created_at < ?",
+                    (before,),
+                )?;
+                let count: i64 =
+                    conn.query_row("SELECT COUNT(*) FROM pending_messages", (), |row| {
+                        row.get(0)
+                    })?;
+                Ok(count as usize)
+            })
+            .await?
+    }
+
+    /// Keeps the message of the session, replacing the previous one.
+    pub async fn put(&self, key: String, content: String) -> Result<(), Error> {
+        let content = self.db_mgr.seal(content)?;
+        let created_at = now();
+        self.db_mgr
+            .write(move |conn| {
+                let sql = "INSERT INTO pending_messages VALUES (?1, ?2, ?3) \
+                    ON CONFLICT (session_key) DO UPDATE SET content = ?2, created_at = ?3;";
+                conn.execute(sql, (&key, &content, created_at))?;
+                Ok(())
+            })
+            .await?
+    }
+
+    /// Removes the message of the session, and returns it.
+    pub async fn take(&self, key: String) -> Result<Option<PendingMessage>, Error> {
+        let row: Option<(String, i64)> = self
+            .db_mgr
+            .write(move |conn| {
+                let tx = conn.transaction()?;
+                let sql = "SELECT content, created_at FROM pending_messages WHERE session_key = ?";
+                let row = tx
+                    .query_row(sql, (&key,), |row| Ok((row.get(0)?, row.get(1)?)))
+                    .optional()?;
+                tx.execute(
+                    "DELETE FROM pending_messages WHERE session_key = ?",
+                    (&key,),
+                )?;
+                tx.commit()?;
+                Ok::<_, Error>(row)
+            })
+            .await??;
+        match row {
+            Some((content, created_at)) => Ok(Some(PendingMessage {
+                content: self.db_mgr.unseal(content)?,
+                created_at,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes the message of the session, if any.
+    pub async fn delete(&self, key: String) -> Result<(), Error> {
+        self.db_mgr
+            .write(move |conn| {
+                conn.execute(
+                    "DELETE FROM pending_messages WHERE session_key = ?",
+                    (&key,),
+                )?;
+                Ok(())
+            })
+            .await?
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::InMemDatabaseProvider;
+
+    #[tokio::test]
+    async fn test_pending_messages() {
+        let db_mgr = DatabaseManager::with_db_provider(InMemDatabaseProvider).unwrap();
+        let store = PendingMessageStore::new(db_mgr);
+
+        store.put("1".to_owned(), "first".to_owned()).await.unwrap();
+        store
+            .put("1".to_owned(), "second".to_owned())
+            .await
+            .unwrap();
+        store.put("2".to_owned(), "other".to_owned()).await.unwrap();
+        assert_eq!(store.restore().await.unwrap(), 2);
+
+        let pending = store.take("1".to_owned()).await.unwrap().unwrap();
+        assert_eq!(pending.content, "second");
+        assert!(!pending.is_stale());
+        assert!(store.take("1".to_owned()).await.unwrap().is_none());
+
+        store.delete("2".to_owned()).await.unwrap();
+        assert_eq!(store.restore().await.unwrap(), 0);
+    }
+}
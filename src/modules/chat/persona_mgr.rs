@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use rusqlite::OptionalExtension;
+
+use crate::database::DatabaseManager;
+
+const MAX_PERSONA_NAME_LEN: usize = 32;
+
+/// A named system prompt that users can activate in their sessions.
+#[derive(Clone, Debug)]
+pub(crate) struct Persona {
+    pub name: String,
+    pub prompt: String,
+}
+
+/// Returns `true` if the name can be used in commands and deep links.
+pub(crate) fn is_valid_persona_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_PERSONA_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Stores the persona library, which is managed by admins.
+#[derive(Clone)]
+pub(crate) struct PersonaManager {
+    db_mgr: DatabaseManager,
+}
+
+impl PersonaManager {
+    /// Creates the manager, and adds the seed personas that are not in
+    /// the library yet. The seeds deleted by admins are not added again.
+    pub async fn new(
+        db_mgr: DatabaseManager,
+        seeds: HashMap<String, String>,
+    ) -> Result<Self, Error> {
+        let created_at = Self::now();
+        db_mgr
+            .write(move |conn| {
+                let sql = "INSERT OR IGNORE INTO personas SELECT ?1, ?2, ?3 WHERE NOT EXISTS (SELECT 1 FROM deleted_personas WHERE name = ?1);";
+                for (name, prompt) in seeds {
+                    if !is_valid_persona_name(&name) {
+                        warn!("Persona \"{}\" is skipped for the invalid name", name);
+                        continue;
+                    }
+                    conn.execute(sql, (&name, &prompt, created_at))?;
+                }
+                Ok::<_, Error>(())
+            })
+            .await??;
+
+        Ok(Self { db_mgr })
+    }
+
+    /// Adds the persona, or replaces the prompt if it exists.
+    pub async fn set_persona(&self, name: String, prompt: String) -> Result<(), Error> {
+        let created_at = Self::now();
+        self.db_mgr
+            .write(move |conn| {
+                let tx = conn.transaction()?;
+                let sql = "INSERT INTO personas VALUES (?1, ?2, ?3) ON CONFLICT (name) DO UPDATE SET prompt = ?2;";
+                tx.execute(sql, (&name, &prompt, created_at))?;
+                tx.execute("DELETE FROM deleted_personas WHERE name = ?", (&name,))?;
+                tx.commit()?;
+                Ok(())
+            })
+            .await?
+    }
+
+    /// Deletes the persona, returns `true` if it existed. The name is
+    /// remembered, so that a seed of the same name is not added again.
+    pub async fn delete_persona(&self, name: String) -> Result<bool, Error> {
+        let deleted_at = Self::now();
+        self.db_mgr
+            .write(move |conn| {
+                let tx = conn.transaction()?;
+                let existed = tx.execute("DELETE FROM personas WHERE name = ?", (&name,))? > 0;
+                if existed {
+                    let sql = "INSERT OR REPLACE INTO deleted_personas VALUES (?, ?)";
+                    tx.execute(sql, (&name, deleted_at))?;
+                }
+                tx.commit()?;
+                Ok(existed)
+            })
+            .await?
+    }
+
+    pub async fn get_persona(&self, name: String) -> Result<Option<Persona>, Error> {
+        self.db_mgr
+            .query(move |conn| {
+                let sql = "SELECT prompt FROM personas WHERE name = ?";
+                let prompt = conn.query_row(sql, (&name,), |row| row.get(0)).optional()?;
+                Ok(prompt.map(|prompt| Persona { name, prompt }))
+            })
+            .await?
+    }
+
+    /// Returns all the personas ordered by name.
+    pub async fn list_personas(&self) -> Result<Vec<Persona>, Error> {
+        self.db_mgr
+            .query(|conn| {
+                let sql = "SELECT name, prompt FROM personas ORDER BY name";
+                let mut stmt = conn.prepare(sql)?;
+                let rows = stmt.query_map((), |row| {
+                    Ok(Persona {
+                        name: row.get(0)?,
+                        prompt: row.get(1)?,
+                    })
+                })?;
+                Ok(rows.collect::<Result<Vec<_>, _>>()?)
+            })
+            .await?
+    }
+
+    fn now() -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::InMemDatabaseProvider;
+
+    #[test]
+    fn test_is_valid_persona_name() {
+        assert!(is_valid_persona_name("code-reviewer_2"));
+        assert!(!is_valid_persona_name(""));
+        assert!(!is_valid_persona_name("code reviewer"));
+        assert!(!is_valid_persona_name(&"a".repeat(33)));
+    }
+
+    #[tokio::test]
+    async fn test_deleted_seeds_are_not_restored() {
+        let db_mgr = DatabaseManager::with_db_provider(InMemDatabaseProvider).unwrap();
+        let seeds = HashMap::from([
+            ("coder".to_owned(), "Answer with code.".to_owned()),
+            ("poet".to_owned(), "Answer in verse.".to_owned()),
+        ]);
+        let persona_mgr = PersonaManager::new(db_mgr.clone(), seeds.clone())
+            .await
+            .unwrap();
+        assert!(persona_mgr
+            .delete_persona("coder".to_owned())
+            .await
+            .unwrap());
+
+        // Restarted with the same seeds.
+        let persona_mgr = PersonaManager::new(db_mgr, seeds).await.unwrap();
+        let names: Vec<_> = persona_mgr
+            .list_personas()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|persona| persona.name)
+            .collect();
+        assert_eq!(names, ["poet"]);
+
+        // Admins can add it back.
+        persona_mgr
+            .set_persona("coder".to_owned(), "Answer in Rust.".to_owned())
+            .await
+            .unwrap();
+        assert!(persona_mgr
+            .get_persona("coder".to_owned())
+            .await
+            .unwrap()
+            .is_some());
+    }
+}
@@ -0,0 +1,63 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+pub(crate) const REPLY_LENGTH_PREF_KEY: &str = "ReplyLength";
+
+const SHORT_REPLY_MAX_TOKENS: u16 = 256;
+
+/// The verbosity preference of a chat, which is translated into the
+/// system instructions and `max_tokens` of each request.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum ReplyLength {
+    Short,
+    #[default]
+    Normal,
+    Detailed,
+}
+
+impl ReplyLength {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReplyLength::Short => "short",
+            ReplyLength::Normal => "normal",
+            ReplyLength::Detailed => "detailed",
+        }
+    }
+
+    pub fn system_instruction(&self) -> Option<&'static str> {
+        match self {
+            ReplyLength::Short => Some(
+                "Answer as concisely as possible. Keep your replies within a few sentences.",
+            ),
+            ReplyLength::Normal => None,
+            ReplyLength::Detailed => Some(
+                "Answer in detail. Explain your reasoning thoroughly and give examples when helpful.",
+            ),
+        }
+    }
+
+    pub fn max_tokens(&self, default_max_tokens: Option<u16>) -> Option<u16> {
+        match self {
+            ReplyLength::Short => Some(
+                default_max_tokens
+                    .map(|t| t.min(SHORT_REPLY_MAX_TOKENS))
+                    .unwrap_or(SHORT_REPLY_MAX_TOKENS),
+            ),
+            ReplyLength::Normal | ReplyLength::Detailed => default_max_tokens,
+        }
+    }
+}
+
+impl FromStr for ReplyLength {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "short" => Ok(ReplyLength::Short),
+            "normal" => Ok(ReplyLength::Normal),
+            "detailed" => Ok(ReplyLength::Detailed),
+            _ => Err(()),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::config::Config;
+
+/// Adds the notice (e.g. of the fallback model), the configured prefix and
+/// suffix and the context indicator to an answer. `{model}` in the prefix
+/// and the suffix is replaced with the model that generated the answer, and
+/// `{tokens}` with the tokens used.
+pub(crate) fn decorate_answer(
+    content: String,
+    notice: Option<&str>,
+    context_indicator: Option<&str>,
+    model: &str,
+    tokens: u32,
+    config: &Config,
+) -> String {
+    let fill = |template: &str| {
+        template
+            .replace("{model}", model)
+            .replace("{tokens}", &tokens.to_string())
+    };
+    let mut parts = vec![];
+    if let Some(prefix) = &config.reply_prefix {
+        parts.push(fill(prefix));
+    }
+    parts.push(content);
+    if let Some(notice) = notice {
+        parts.push(notice.to_owned());
+    }
+    if let Some(suffix) = &config.reply_suffix {
+        parts.push(fill(suffix));
+    }
+    if let Some(context_indicator) = context_indicator {
+        parts.push(context_indicator.to_owned());
+    }
+    parts.join("\n\n")
+}
+
+/// Describes how much the context of the session holds, e.g.
+/// `ctx: 12 msgs / 3.4k tokens`.
+pub(crate) fn context_indicator(message_count: usize, tokens: u32) -> String {
+    let tokens = if tokens < 1000 {
+        tokens.to_string()
+    } else {
+        format!("{:.1}k", tokens as f64 / 1000.0)
+    };
+    format!("ctx: {} msgs / {} tokens", message_count, tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decorate_answer() {
+        let config: Config = serde_json::from_str(
+            r#"{"botToken": "", "replySuffix": "Powered by X • model: {model} • {tokens} tokens"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            decorate_answer("Hi".to_owned(), None, None, "gpt-4", 42, &config),
+            "Hi\n\nPowered by X • model: gpt-4 • 42 tokens"
+        );
+        assert_eq!(
+            decorate_answer(
+                "Hi".to_owned(),
+                Some("(by gpt-3.5)"),
+                Some("ctx: 2 msgs / 20 tokens"),
+                "gpt-3.5",
+                1,
+                &config
+            ),
+            "Hi\n\n(by gpt-3.5)\n\nPowered by X • model: gpt-3.5 • 1 tokens\n\nctx: 2 msgs / 20 tokens"
+        );
+
+        let config: Config = serde_json::from_str(r#"{"botToken": ""}"#).unwrap();
+        assert_eq!(
+            decorate_answer("Hi".to_owned(), None, None, "gpt-4", 42, &config),
+            "Hi"
+        );
+    }
+
+    #[test]
+    fn test_context_indicator() {
+        assert_eq!(context_indicator(2, 850), "ctx: 2 msgs / 850 tokens");
+        assert_eq!(context_indicator(12, 3412), "ctx: 12 msgs / 3.4k tokens");
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
 
 use async_openai::types::{ChatCompletionRequestMessage as Message, Role};
 
@@ -8,6 +9,31 @@ use crate::config::SharedConfig;
 pub struct HistoryMessage {
     pub id: i64,
     pub message: Message,
+    pub token_count: u32,
+    /// The id of the previous message in the same thread.
+    pub parent_id: Option<i64>,
+    /// The text displayed in Telegram, if it differs from the raw content
+    /// of the message, e.g. when the Markdown is rendered.
+    pub rendered_content: Option<String>,
+    /// The ids of the Telegram messages that display this message, which
+    /// are used to find the thread when users reply to them.
+    pub telegram_message_ids: Vec<i32>,
+    /// The id of the Telegram message that the user sent, for questions.
+    /// Editing the message regenerates the answer.
+    pub source_message_id: Option<i32>,
+}
+
+impl HistoryMessage {
+    /// Returns the message to send to the model as history.
+    fn prompt_message(&self, uses_rendered_content: bool) -> Message {
+        match &self.rendered_content {
+            Some(rendered_content) if uses_rendered_content => Message {
+                content: rendered_content.clone(),
+                ..self.message.clone()
+            },
+            _ => self.message.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Default)]
@@ -15,31 +41,56 @@ struct HistoryMessagePool {
     current_id: i64,
     messages: HashMap<i64, HistoryMessage>,
     deque: VecDeque<i64>,
+    telegram_message_ids: HashMap<i32, i64>,
 }
 
 impl HistoryMessagePool {
-    fn prepare_message(&mut self, message: Message) -> HistoryMessage {
+    fn prepare_message(&mut self, message: Message, token_count: u32) -> HistoryMessage {
         let (id, _) = self.current_id.overflowing_add(1);
         self.current_id = id;
 
-        HistoryMessage { id, message }
+        HistoryMessage {
+            id,
+            message,
+            token_count,
+            parent_id: None,
+            rendered_content: None,
+            telegram_message_ids: vec![],
+            source_message_id: None,
+        }
     }
 
     fn push_message(&mut self, message: HistoryMessage) {
         let id = message.id;
+        for telegram_message_id in &message.telegram_message_ids {
+            self.telegram_message_ids.insert(*telegram_message_id, id);
+        }
         self.messages.insert(id, message);
         self.deque.push_back(id);
     }
 
-    fn pop_message(&mut self) {
-        if let Some(evicted_id) = self.deque.pop_front() {
-            self.messages.remove(&evicted_id);
+    fn pop_message(&mut self) -> Option<HistoryMessage> {
+        let evicted_id = self.deque.pop_front()?;
+        let evicted = self.messages.remove(&evicted_id)?;
+        for telegram_message_id in &evicted.telegram_message_ids {
+            self.telegram_message_ids.remove(telegram_message_id);
+        }
+        Some(evicted)
+    }
+
+    fn pop_last_message(&mut self) -> Option<HistoryMessage> {
+        let last_id = self.deque.pop_back()?;
+        let last = self.messages.remove(&last_id)?;
+        for telegram_message_id in &last.telegram_message_ids {
+            self.telegram_message_ids.remove(telegram_message_id);
         }
+        Some(last)
     }
 
     fn clear(&mut self) {
         self.deque.clear();
         self.messages.clear();
+        self.telegram_message_ids.clear();
     }
 
     fn len(&self) -> usize {
@@ -53,13 +104,112 @@ impl HistoryMessagePool {
     fn iter(&self) -> impl Iterator<Item = &HistoryMessage> + '_ {
         self.deque.iter().filter_map(|id| self.messages.get(id))
     }
+
+    fn last_id(&self) -> Option<i64> {
+        self.deque.back().copied()
+    }
+
+    fn link_telegram_message(&mut self, id: i64, telegram_message_id: i32) -> bool {
+        let message = match self.messages.get_mut(&id) {
+            Some(message) => message,
+            None => return false,
+        };
+        message.telegram_message_ids.push(telegram_message_id);
+        self.telegram_message_ids.insert(telegram_message_id, id);
+        true
+    }
+
+    fn find_by_telegram_message_id(&self, telegram_message_id: i32) -> Option<i64> {
+        self.telegram_message_ids.get(&telegram_message_id).copied()
+    }
+
+    /// Appends the content of the continuation to the last message, if
+    /// it's the message with the id. Otherwise the continuation is returned
+    /// back.
+    fn extend_last_message(
+        &mut self,
+        id: i64,
+        continuation: HistoryMessage,
+    ) -> Option<HistoryMessage> {
+        if self.last_id() != Some(id) {
+            return Some(continuation);
+        }
+        let message = match self.messages.get_mut(&id) {
+            Some(message) => message,
+            None => return Some(continuation),
+        };
+
+        // The rendered content is kept only if both parts have it, since
+        // the raw and the rendered content can't be mixed. The parts are
+        // joined as they are, since the continuation may start in the middle
+        // of a word, and it starts with the whitespace otherwise.
+        message.rendered_content = match (
+            message.rendered_content.take(),
+            &continuation.rendered_content,
+        ) {
+            (Some(rendered_content), Some(rendered_continuation)) => {
+                Some(rendered_content + rendered_continuation)
+            }
+            _ => None,
+        };
+        message
+            .message
+            .content
+            .push_str(&continuation.message.content);
+        message.token_count += continuation.token_count;
+        for telegram_message_id in continuation.telegram_message_ids {
+            message.telegram_message_ids.push(telegram_message_id);
+            self.telegram_message_ids.insert(telegram_message_id, id);
+        }
+        None
+    }
+}
+
+/// How much of the context the session takes.
+#[derive(Debug, Clone)]
+pub struct ContextUsage {
+    /// The number of history messages, excluding the system message.
+    pub message_count: usize,
+    /// The tokens of the history messages and the system message.
+    pub tokens: u32,
+    /// The oldest history message, which is the next one to be dropped.
+    pub oldest_message: Option<Message>,
+}
+
+/// The evicted messages to be merged into the summary of a session.
+#[derive(Debug, Clone)]
+pub struct SummaryWork {
+    /// The summary before the messages are merged.
+    pub previous_summary: Option<String>,
+    pub messages: Vec<Message>,
+    epoch: u64,
+}
+
+/// The messages to name a session with.
+#[derive(Debug, Clone)]
+pub struct TitleWork {
+    pub messages: Vec<Message>,
+    epoch: u64,
 }
 
 #[derive(Debug)]
 pub struct Session {
-    system_message: Option<Message>,
+    system_message: Option<HistoryMessage>,
+    /// The summary of the evicted messages, and its token count.
+    summary: Option<(Message, u32)>,
     history_messages: HistoryMessagePool,
-    pending_message: Option<Message>,
+    /// The messages evicted since the last summarization.
+    evicted_messages: Vec<Message>,
+    is_summarizing: bool,
+    /// The title generated from the first messages.
+    title: Option<String>,
+    is_naming: bool,
+    /// The name of the persona installed in the session.
+    persona: Option<String>,
+    /// Increased on each reset, so that the summaries of the previous
+    /// conversation are dropped.
+    epoch: u64,
+    last_active: Instant,
     config: SharedConfig,
 }
 
@@ -67,32 +217,84 @@ impl Session {
     pub fn new(config: SharedConfig) -> Self {
         Self {
             system_message: None,
+            summary: None,
             history_messages: Default::default(),
-            pending_message: None,
+            evicted_messages: vec![],
+            is_summarizing: false,
+            title: None,
+            is_naming: false,
+            persona: None,
+            epoch: 0,
+            last_active: Instant::now(),
             config,
         }
     }
 
+    /// Returns the time when a message was last added to the session.
+    pub fn last_active(&self) -> Instant {
+        self.last_active
+    }
+
+    pub fn has_system_message(&self) -> bool {
+        self.system_message.is_some()
+    }
+
+    /// Returns `true` if the session has no context to lose.
+    pub fn is_empty(&self) -> bool {
+        self.system_message.is_none() && self.history_messages.len() == 0
+    }
+
     pub fn reset(&mut self) {
         self.system_message = None;
+        self.summary = None;
         self.history_messages.clear();
-        self.pending_message = None;
+        self.evicted_messages.clear();
+        self.is_summarizing = false;
+        self.title = None;
+        self.is_naming = false;
+        self.persona = None;
+        self.epoch += 1;
     }
 
-    pub fn prepare_history_message(&mut self, message: Message) -> HistoryMessage {
-        self.history_messages.prepare_message(message)
+    /// Returns a copy of the context for another chat. The links to the
+    /// Telegram messages are dropped, since they belong to this chat.
+    pub fn fork(&self) -> Session {
+        let mut forked = Session::new(self.config.clone());
+        forked.system_message = self.system_message.clone();
+        forked.summary = self.summary.clone();
+        forked.title = self.title.clone();
+        forked.persona = self.persona.clone();
+        forked.history_messages.current_id = self.history_messages.current_id;
+        for msg in self.history_messages.iter() {
+            forked.history_messages.push_message(HistoryMessage {
+                telegram_message_ids: vec![],
+                source_message_id: None,
+                ..msg.clone()
+            });
+        }
+        forked
+    }
+
+    pub fn prepare_history_message(
+        &mut self,
+        message: Message,
+        token_count: u32,
+    ) -> HistoryMessage {
+        self.history_messages.prepare_message(message, token_count)
     }
 
     pub fn add_history_message(&mut self, message: HistoryMessage) {
+        self.last_active = Instant::now();
+
         if matches!(message.message.role, Role::System) {
             // Replace the previous system message, we only support
             // one system message at the same time.
-            self.system_message = Some(message.message);
+            self.system_message = Some(message);
             return;
         }
 
-        if self.history_messages.len() >= (self.config.conversation_limit as usize) {
-            self.history_messages.pop_message();
+        if self.history_messages.len() >= (self.config.load().conversation_limit as usize) {
+            self.evict_oldest_message();
         }
         self.history_messages.push_message(message);
     }
@@ -103,21 +305,467 @@ impl Session {
             .map(|m| m.message.clone())
     }
 
+    /// Evicts the oldest history messages until the system message, the
+    /// summary and history messages fit in the token budget. The system
+    /// message and the summary are always kept.
+    pub fn trim_history_by_tokens(&mut self, budget: u32) {
+        let mut total_tokens = self.system_tokens()
+            + self
+                .history_messages
+                .iter()
+                .map(|m| m.token_count)
+                .sum::<u32>();
+        while total_tokens > budget {
+            match self.evict_oldest_message() {
+                Some(evicted) => total_tokens -= evicted.token_count,
+                None => break,
+            }
+        }
+    }
+
+    /// Takes the evicted messages to summarize, unless they are being
+    /// summarized or there are none.
+    pub fn take_summary_work(&mut self) -> Option<SummaryWork> {
+        if self.is_summarizing || self.evicted_messages.is_empty() {
+            return None;
+        }
+        self.is_summarizing = true;
+        Some(SummaryWork {
+            previous_summary: self.summary.as_ref().map(|(msg, _)| msg.content.clone()),
+            messages: std::mem::take(&mut self.evicted_messages),
+            epoch: self.epoch,
+        })
+    }
+
+    /// Replaces the summary with the result of the work, or leaves it as
+    /// is if the summarization failed (`None`).
+    pub fn finish_summary_work(&mut self, work: &SummaryWork, summary: Option<(Message, u32)>) {
+        if work.epoch != self.epoch {
+            return;
+        }
+        self.is_summarizing = false;
+        if summary.is_some() {
+            self.summary = summary;
+        }
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn persona(&self) -> Option<&str> {
+        self.persona.as_deref()
+    }
+
+    pub fn set_persona(&mut self, name: String) {
+        self.persona = Some(name);
+    }
+
+    /// Takes the history messages to name the session with, once it has at
+    /// least `min_messages` of them, unless it's named or being named.
+    pub fn take_title_work(&mut self, min_messages: usize) -> Option<TitleWork> {
+        if self.title.is_some() || self.is_naming || self.history_messages.len() < min_messages {
+            return None;
+        }
+        self.is_naming = true;
+        Some(TitleWork {
+            messages: self
+                .history_messages
+                .iter()
+                .map(|m| m.message.clone())
+                .collect(),
+            epoch: self.epoch,
+        })
+    }
+
+    /// Sets the title to the result of the work. The session is named again
+    /// later if the naming failed (`None`).
+    pub fn finish_title_work(&mut self, work: &TitleWork, title: Option<String>) {
+        if work.epoch != self.epoch {
+            return;
+        }
+        self.is_naming = false;
+        self.title = title;
+    }
+
+    pub fn context_usage(&self) -> ContextUsage {
+        ContextUsage {
+            message_count: self.history_messages.len(),
+            tokens: self.system_tokens()
+                + self
+                    .history_messages
+                    .iter()
+                    .map(|m| m.token_count)
+                    .sum::<u32>(),
+            oldest_message: self
+                .history_messages
+                .iter()
+                .next()
+                .map(|m| m.message.clone()),
+        }
+    }
+
+    /// Returns the history messages to send to the model.
     pub fn get_history_messages(&self) -> Vec<Message> {
+        let uses_rendered_content = self.config.load().rendered_history;
+        let msg_iter = self
+            .history_messages
+            .iter()
+            .map(|m| m.prompt_message(uses_rendered_content));
+        self.with_system_message(msg_iter)
+    }
+
+    /// Returns the history messages to send to the model, without the
+    /// given answer and the question it replies to.
+    pub fn get_history_messages_without_answer(&self, answer_id: i64) -> Vec<Message> {
+        let question_id = self
+            .history_messages
+            .get_message(&answer_id)
+            .and_then(|msg| msg.parent_id);
+        let uses_rendered_content = self.config.load().rendered_history;
+        let msg_iter = self
+            .history_messages
+            .iter()
+            .filter(|m| m.id != answer_id && Some(m.id) != question_id)
+            .map(|m| m.prompt_message(uses_rendered_content));
+        self.with_system_message(msg_iter)
+    }
+
+    /// Returns the history messages with the raw outputs of the model,
+    /// regardless of what are displayed.
+    pub fn get_raw_history_messages(&self) -> Vec<Message> {
         let msg_iter = self.history_messages.iter().map(|m| m.message.clone());
-        if let Some(sys_msg) = &self.system_message {
-            let prepend = [sys_msg.to_owned()];
-            prepend.into_iter().chain(msg_iter).collect()
-        } else {
-            msg_iter.collect()
+        self.with_system_message(msg_iter)
+    }
+
+    /// Returns the messages of the thread that ends with the given message,
+    /// by following the parents until the oldest one that still exists.
+    pub fn get_thread_messages(&self, id: i64) -> Vec<Message> {
+        let mut thread = vec![];
+        let mut next_id = Some(id);
+        let uses_rendered_content = self.config.load().rendered_history;
+        while let Some(msg) = next_id.and_then(|id| self.history_messages.get_message(&id)) {
+            thread.push(msg.prompt_message(uses_rendered_content));
+            next_id = msg.parent_id;
+        }
+        self.with_system_message(thread.into_iter().rev())
+    }
+
+    /// Returns the question that the given answer replies to.
+    pub fn get_question(&self, answer_id: i64) -> Option<Message> {
+        let question_id = self.history_messages.get_message(&answer_id)?.parent_id?;
+        self.history_messages
+            .get_message(&question_id)
+            .map(|question| question.message.clone())
+    }
+
+    /// Removes the given answer and the question it replies to, only if
+    /// the answer is the last history message. Returns the removed question.
+    pub fn rollback_answer(&mut self, id: i64) -> Option<Message> {
+        if self.history_messages.last_id() != Some(id) {
+            return None;
+        }
+        let answer = self.history_messages.pop_last_message()?;
+        match answer.parent_id {
+            Some(question_id) if self.history_messages.last_id() == Some(question_id) => self
+                .history_messages
+                .pop_last_message()
+                .map(|question| question.message),
+            _ => {
+                // The question is missing, keep the answer as is.
+                self.history_messages.push_message(answer);
+                None
+            }
+        }
+    }
+
+    /// Removes the question sent as the given Telegram message, and all the
+    /// messages after it. Returns the Telegram messages that display the
+    /// answer to the question, or `None` if the question is not found.
+    pub fn rollback_question(&mut self, source_message_id: i32) -> Option<Vec<i32>> {
+        let question_id = self
+            .history_messages
+            .iter()
+            .find(|msg| msg.source_message_id == Some(source_message_id))
+            .map(|msg| msg.id)?;
+        let mut answer_telegram_message_ids = vec![];
+        while let Some(msg) = self.history_messages.pop_last_message() {
+            if msg.parent_id == Some(question_id) && matches!(msg.message.role, Role::Assistant) {
+                answer_telegram_message_ids = msg.telegram_message_ids;
+            }
+            if msg.id == question_id {
+                break;
+            }
+        }
+        Some(answer_telegram_message_ids)
+    }
+
+    /// Returns the id of the last answer and the Telegram messages that
+    /// display it, if the last history message is an answer.
+    pub fn last_answer(&self) -> Option<(i64, Vec<i32>)> {
+        self.history_messages
+            .iter()
+            .last()
+            .filter(|msg| matches!(msg.message.role, Role::Assistant))
+            .map(|msg| (msg.id, msg.telegram_message_ids.clone()))
+    }
+
+    /// Merges the continuation into the answer, which must still be the last
+    /// history message, so that the answer stays one message in the history.
+    /// Otherwise the continuation is returned back.
+    pub fn extend_answer(
+        &mut self,
+        answer_id: i64,
+        continuation: HistoryMessage,
+    ) -> Option<HistoryMessage> {
+        self.last_active = Instant::now();
+        self.history_messages
+            .extend_last_message(answer_id, continuation)
+    }
+
+    pub fn last_history_message_id(&self) -> Option<i64> {
+        self.history_messages.last_id()
+    }
+
+    /// Records another Telegram message that displays the history message,
+    /// e.g. the voice message of an answer.
+    pub fn link_telegram_message(&mut self, id: i64, telegram_message_id: i32) -> bool {
+        self.history_messages
+            .link_telegram_message(id, telegram_message_id)
+    }
+
+    /// Returns the id of the history message displayed by the given
+    /// Telegram message.
+    pub fn find_history_message_id(&self, telegram_message_id: i32) -> Option<i64> {
+        self.history_messages
+            .find_by_telegram_message_id(telegram_message_id)
+    }
+
+    fn with_system_message<I>(&self, msg_iter: I) -> Vec<Message>
+    where
+        I: Iterator<Item = Message>,
+    {
+        let prepend = self
+            .system_message
+            .iter()
+            .map(|m| m.message.to_owned())
+            .chain(self.summary.iter().map(|(m, _)| m.to_owned()));
+        prepend.chain(msg_iter).collect()
+    }
+
+    /// Returns the tokens of the system message and the summary.
+    fn system_tokens(&self) -> u32 {
+        self.system_message
+            .as_ref()
+            .map(|m| m.token_count)
+            .unwrap_or(0)
+            + self.summary.as_ref().map(|(_, t)| *t).unwrap_or(0)
+    }
+
+    fn evict_oldest_message(&mut self) -> Option<HistoryMessage> {
+        let evicted = self.history_messages.pop_message()?;
+        // Keep the messages to be summarized if the summary is enabled.
+        if self.config.load().history_summary.is_some() {
+            self.evicted_messages.push(evicted.message.clone());
         }
+        Some(evicted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_openai::types::ChatCompletionRequestMessageArgs;
+
+    use super::*;
+
+    fn add_message(session: &mut Session, role: Role, parent_id: Option<i64>) -> i64 {
+        let msg = ChatCompletionRequestMessageArgs::default()
+            .role(role)
+            .content("content")
+            .build()
+            .unwrap();
+        let mut history_msg = session.prepare_history_message(msg, 1);
+        history_msg.parent_id = parent_id;
+        let id = history_msg.id;
+        session.add_history_message(history_msg);
+        id
+    }
+
+    #[test]
+    fn test_rollback_answer() {
+        let config = serde_json::from_str(r#"{"botToken": ""}"#).unwrap();
+        let mut session = Session::new(SharedConfig::new(config));
+        let question_1 = add_message(&mut session, Role::User, None);
+        let answer_1 = add_message(&mut session, Role::Assistant, Some(question_1));
+        let question_2 = add_message(&mut session, Role::User, Some(answer_1));
+        let answer_2 = add_message(&mut session, Role::Assistant, Some(question_2));
+
+        assert!(session.rollback_answer(answer_1).is_none());
+        assert_eq!(session.last_answer(), Some((answer_2, vec![])));
+        assert!(session.rollback_answer(answer_2).is_some());
+        assert_eq!(session.last_history_message_id(), Some(answer_1));
+        assert_eq!(session.get_history_messages().len(), 2);
+
+        add_message(&mut session, Role::User, Some(answer_1));
+        assert_eq!(session.last_answer(), None);
+    }
+
+    #[test]
+    fn test_extend_answer() {
+        let config = serde_json::from_str(r#"{"botToken": ""}"#).unwrap();
+        let mut session = Session::new(SharedConfig::new(config));
+        let question = add_message(&mut session, Role::User, None);
+        let answer = add_message(&mut session, Role::Assistant, Some(question));
+
+        let continuation = ChatCompletionRequestMessageArgs::default()
+            .role(Role::Assistant)
+            .content(" more")
+            .build()
+            .unwrap();
+        let mut continuation = session.prepare_history_message(continuation, 2);
+        continuation.telegram_message_ids = vec![42];
+        assert!(session
+            .extend_answer(answer, continuation.clone())
+            .is_none());
+        assert_eq!(session.get_history_messages().len(), 2);
+        assert_eq!(session.last_answer(), Some((answer, vec![42])));
+        assert_eq!(session.find_history_message_id(42), Some(answer));
+        let history = session.get_history_messages();
+        assert_eq!(history[1].content, "content more");
+
+        add_message(&mut session, Role::User, Some(answer));
+        assert!(session.extend_answer(answer, continuation).is_some());
+    }
+
+    #[test]
+    fn test_rollback_question() {
+        let config = serde_json::from_str(r#"{"botToken": ""}"#).unwrap();
+        let mut session = Session::new(SharedConfig::new(config));
+        let msg = ChatCompletionRequestMessageArgs::default()
+            .role(Role::User)
+            .content("question")
+            .build()
+            .unwrap();
+        let mut question = session.prepare_history_message(msg, 1);
+        question.source_message_id = Some(20);
+        let question_id = question.id;
+        session.add_history_message(question);
+        let answer = add_message(&mut session, Role::Assistant, Some(question_id));
+        assert!(session.link_telegram_message(answer, 21));
+        let follow_up = add_message(&mut session, Role::User, Some(answer));
+        add_message(&mut session, Role::Assistant, Some(follow_up));
+
+        assert_eq!(session.rollback_question(21), None);
+        assert_eq!(session.rollback_question(20), Some(vec![21]));
+        assert!(session.get_history_messages().is_empty());
+        assert_eq!(session.find_history_message_id(21), None);
     }
 
-    pub fn swap_pending_message(&mut self, msg: Option<Message>) -> Option<Message> {
-        if let Some(msg) = msg {
-            self.pending_message.replace(msg)
-        } else {
-            self.pending_message.take()
+    #[test]
+    fn test_context_usage() {
+        let config = serde_json::from_str(r#"{"botToken": ""}"#).unwrap();
+        let mut session = Session::new(SharedConfig::new(config));
+        add_message(&mut session, Role::System, None);
+        let question = add_message(&mut session, Role::User, None);
+        add_message(&mut session, Role::Assistant, Some(question));
+
+        let usage = session.context_usage();
+        assert_eq!(usage.message_count, 2);
+        assert_eq!(usage.tokens, 3);
+        assert!(matches!(usage.oldest_message.unwrap().role, Role::User));
+    }
+
+    #[test]
+    fn test_fork() {
+        let config = serde_json::from_str(r#"{"botToken": ""}"#).unwrap();
+        let mut session = Session::new(SharedConfig::new(config));
+        add_message(&mut session, Role::System, None);
+        let question = add_message(&mut session, Role::User, None);
+        let answer = add_message(&mut session, Role::Assistant, Some(question));
+        assert!(session.link_telegram_message(answer, 10));
+
+        let mut forked = session.fork();
+        assert_eq!(
+            forked.get_history_messages().len(),
+            session.get_history_messages().len()
+        );
+        assert_eq!(forked.find_history_message_id(10), None);
+        // New messages don't collide with the copied ones.
+        let next = add_message(&mut forked, Role::User, Some(answer));
+        assert!(next > answer);
+    }
+
+    #[test]
+    fn test_title_work() {
+        let config = serde_json::from_str(r#"{"botToken": ""}"#).unwrap();
+        let mut session = Session::new(SharedConfig::new(config));
+        let question = add_message(&mut session, Role::User, None);
+        assert!(session.take_title_work(2).is_none());
+        add_message(&mut session, Role::Assistant, Some(question));
+
+        let work = session.take_title_work(2).unwrap();
+        assert_eq!(work.messages.len(), 2);
+        assert!(session.take_title_work(2).is_none());
+        session.finish_title_work(&work, None);
+
+        let work = session.take_title_work(2).unwrap();
+        session.finish_title_work(&work, Some("Greetings".to_owned()));
+        assert_eq!(session.title(), Some("Greetings"));
+        assert!(session.take_title_work(2).is_none());
+        assert_eq!(session.fork().title(), Some("Greetings"));
+
+        session.reset();
+        assert_eq!(session.title(), None);
+    }
+
+    #[test]
+    fn test_rendered_history() {
+        let config = serde_json::from_str(r#"{"botToken": "", "renderedHistory": true}"#).unwrap();
+        let mut session = Session::new(SharedConfig::new(config));
+        let msg = ChatCompletionRequestMessageArgs::default()
+            .role(Role::Assistant)
+            .content("**bold**")
+            .build()
+            .unwrap();
+        let mut history_msg = session.prepare_history_message(msg, 1);
+        history_msg.rendered_content = Some("bold".to_owned());
+        history_msg.telegram_message_ids = vec![10];
+        let id = history_msg.id;
+        session.add_history_message(history_msg);
+        assert!(session.link_telegram_message(id, 11));
+
+        assert_eq!(session.get_history_messages()[0].content, "bold");
+        assert_eq!(session.get_raw_history_messages()[0].content, "**bold**");
+        assert_eq!(session.find_history_message_id(11), Some(id));
+    }
+
+    #[test]
+    fn test_summary_work() {
+        let config = r#"{"botToken": "", "conversationLimit": 2, "historySummary": {}}"#;
+        let config = serde_json::from_str(config).unwrap();
+        let mut session = Session::new(SharedConfig::new(config));
+        for _ in 0..3 {
+            add_message(&mut session, Role::User, None);
         }
+
+        let work = session.take_summary_work().unwrap();
+        assert_eq!(work.messages.len(), 1);
+        assert!(session.take_summary_work().is_none());
+
+        let summary = ChatCompletionRequestMessageArgs::default()
+            .role(Role::System)
+            .content("summary")
+            .build()
+            .unwrap();
+        session.finish_summary_work(&work, Some((summary, 5)));
+        assert_eq!(session.get_history_messages()[0].content, "summary");
+        assert_eq!(session.context_usage().tokens, 7);
+
+        // The work of a reset session is dropped.
+        add_message(&mut session, Role::User, None);
+        let work = session.take_summary_work().unwrap();
+        session.reset();
+        session.finish_summary_work(&work, None);
+        assert!(session.get_history_messages().is_empty());
     }
 }
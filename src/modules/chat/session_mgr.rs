@@ -1,36 +1,266 @@
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use async_openai::types::ChatCompletionRequestMessage as Message;
+use anyhow::Error;
+use async_openai::types::{
+    ChatCompletionRequestMessage as Message, ChatCompletionRequestMessageArgs, Role,
+};
+use futures::StreamExt;
+use teloxide::prelude::*;
+use teloxide::types::MessageKind;
+use tokio::sync::Notify;
 
+use super::pending_store::{PendingMessage, PendingMessageStore};
+use super::session::{SummaryWork, TitleWork};
 use super::Session;
-use crate::config::SharedConfig;
+use crate::config::{HistorySummaryConfig, SessionTitleConfig, SharedConfig};
+use crate::event_bus::{Event, EventBus};
+use crate::modules::openai::{ChatModelParams, OpenAIClient};
+use crate::modules::prefs::PreferencesManager;
+use crate::utils::dptree_ext::{next_word, ArgsError, CommandArg};
+
+const SUMMARY_INSTRUCTION: &str = "Summarize the conversation below concisely, keeping \
+the facts, names, decisions and open questions that later messages may refer to. \
+Merge the previous summary if there is one. Reply with the summary only.";
+const SUMMARY_PREFIX: &str = "Summary of the earlier conversation:\n";
+const TITLE_INSTRUCTION: &str = "Write a title of at most 6 words for the conversation \
+below, in the language of the conversation. Reply with the title only, without quotes.";
+/// The maximum number of tokens and characters of a title.
+const TITLE_MAX_TOKENS: u16 = 24;
+const TITLE_MAX_CHARS: usize = 64;
+
+/// How long a fork of a session can be taken after it's requested.
+const FORK_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// The name of the active session of a chat, or of a topic in the chat.
+const ACTIVE_SESSION_PREF_KEY: &str = "ActiveSession";
+/// The session that chats start with.
+pub(crate) const DEFAULT_SESSION_NAME: &str = "default";
+/// Session names are kept short, since they are put in the data of
+/// buttons, which is limited to 64 bytes.
+const SESSION_NAME_MAX_LEN: usize = 24;
+
+/// The name of a session of a chat, which consists of ASCII letters,
+/// digits, `-` and `_`, so that its length in bytes is bounded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SessionName(pub String);
+
+impl CommandArg for SessionName {
+    fn placeholder() -> String {
+        "<name>".to_owned()
+    }
+
+    fn parse(input: &mut &str) -> Result<Self, ArgsError> {
+        let word = next_word::<Self>(input)?;
+        let is_valid = word.len() <= SESSION_NAME_MAX_LEN
+            && word
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !is_valid {
+            return Err(ArgsError(format!(
+                "Invalid session name \"{}\", it should have at most {} ASCII letters, digits, \"-\" or \"_\"",
+                word, SESSION_NAME_MAX_LEN
+            )));
+        }
+        Ok(Self(word.to_owned()))
+    }
+}
+
+/// A session listed by `/sessions`.
+#[derive(Clone, Debug)]
+pub(crate) struct SessionInfo {
+    pub name: String,
+    pub title: Option<String>,
+    pub message_count: usize,
+}
 
 pub struct SessionManager {
     inner: Arc<Mutex<SessionManagerInner>>,
+    prefs_mgr: PreferencesManager,
+    pending_store: PendingMessageStore,
 }
 
 struct SessionManagerInner {
     sessions: HashMap<String, Session>,
+    /// The answers being generated, by the session keys and the ids of the
+    /// messages showing them.
+    generations: HashMap<(String, i32), Generation>,
+    /// The forks requested but not yet taken, by their tokens.
+    forks: HashMap<String, Fork>,
     config: SharedConfig,
 }
 
+/// A request to copy a session into the private chat of a user.
+struct Fork {
+    key: String,
+    user_id: u64,
+    requested_at: Instant,
+}
+
+struct Generation {
+    /// The user who asked for the answer.
+    user_id: Option<u64>,
+    stop: Arc<Notify>,
+}
+
 impl SessionManager {
-    pub fn new(config: SharedConfig) -> Self {
+    pub fn new(
+        config: SharedConfig,
+        prefs_mgr: PreferencesManager,
+        pending_store: PendingMessageStore,
+    ) -> Self {
         let inner = SessionManagerInner {
             sessions: HashMap::new(),
+            generations: HashMap::new(),
+            forks: HashMap::new(),
             config,
         };
 
         Self {
             inner: Arc::new(Mutex::new(inner)),
+            prefs_mgr,
+            pending_store,
         }
     }
 
-    pub fn reset_session(&self, key: String) {
+    /// Returns the key of the active session of the chat, or of the topic
+    /// in the chat.
+    pub async fn active_session_key(&self, chat_id: &str, topic_id: Option<i32>) -> String {
+        let base_key = session_key(chat_id, topic_id);
+        let name = self.active_session_name(&base_key).await;
+        named_session_key(&base_key, &name)
+    }
+
+    /// Returns the name of the active session of the chat or the topic
+    /// whose (unnamed) session key is `base_key`.
+    pub async fn active_session_name(&self, base_key: &str) -> String {
+        let name: Option<String> = self
+            .prefs_mgr
+            .get_chat_value(base_key, ACTIVE_SESSION_PREF_KEY)
+            .await
+            .unwrap_or_else(|err| {
+                error!("Failed to get the active session: {}", err);
+                None
+            });
+        name.unwrap_or_else(|| DEFAULT_SESSION_NAME.to_owned())
+    }
+
+    /// Makes the named session the active one, which is kept across
+    /// restarts. The session is created when a message is sent to it.
+    pub async fn switch_session(&self, base_key: &str, name: &str) -> Result<(), Error> {
+        self.prefs_mgr
+            .set_chat_value(base_key, ACTIVE_SESSION_PREF_KEY, &Some(name))
+            .await
+    }
+
+    /// Returns `true` if the named session has any context.
+    pub fn has_session(&self, base_key: &str, name: &str) -> bool {
+        let key = named_session_key(base_key, name);
+        self.with_mut_inner(|inner| {
+            inner
+                .sessions
+                .get(&key)
+                .map(|session| !session.is_empty())
+                .unwrap_or(false)
+        })
+    }
+
+    /// Lists the sessions with contexts of the chat or the topic, sorted by
+    /// their names. The active session is always listed.
+    pub fn list_sessions(&self, base_key: &str, active_name: &str) -> Vec<SessionInfo> {
+        let mut sessions = self.with_mut_inner(|inner| {
+            inner
+                .sessions
+                .iter()
+                .filter(|(_, session)| !session.is_empty())
+                .filter_map(|(key, session)| {
+                    let name = if key == base_key {
+                        DEFAULT_SESSION_NAME
+                    } else {
+                        key.strip_prefix(base_key)?.strip_prefix('#')?
+                    };
+                    Some(SessionInfo {
+                        name: name.to_owned(),
+                        title: session.title().map(|title| title.to_owned()),
+                        message_count: session.context_usage().message_count,
+                    })
+                })
+                .collect::<Vec<_>>()
+        });
+        if !sessions.iter().any(|s| s.name == active_name) {
+            sessions.push(SessionInfo {
+                name: active_name.to_owned(),
+                title: None,
+                message_count: 0,
+            });
+        }
+        sessions.sort_by(|a, b| a.name.cmp(&b.name));
+        sessions
+    }
+
+    /// Clears the context of the session, along with its pending message.
+    /// The deletion is awaited, so that it can't drop a message pended
+    /// after the reset.
+    pub async fn reset_session(&self, key: String) {
+        if let Err(err) = self.pending_store.delete(key.clone()).await {
+            error!("Failed to delete the pending message: {}", err);
+        }
         self.with_mut_session(key, |session| session.reset());
     }
 
+    /// Replaces the session wholesale, e.g. with an imported conversation.
+    pub fn replace_session(&self, key: String, session: Session) {
+        self.with_mut_inner(|inner| {
+            inner.sessions.insert(key, session);
+        });
+    }
+
+    /// Copies the session into another one, which is replaced. Returns
+    /// `false` if there's nothing to copy.
+    pub fn fork_session(&self, from_key: &str, to_key: String) -> bool {
+        self.with_mut_inner(|inner| {
+            let forked = match inner.sessions.get(from_key) {
+                Some(session) if !session.is_empty() => session.fork(),
+                _ => return false,
+            };
+            inner.sessions.insert(to_key, forked);
+            true
+        })
+    }
+
+    /// Requests a fork of the session for the user, and returns the token
+    /// to take it with [`SessionManager::take_fork`].
+    pub fn request_fork(&self, key: String, user_id: u64) -> Result<String, Error> {
+        let mut token = [0; 8];
+        openssl::rand::rand_bytes(&mut token)?;
+        let token: String = token.iter().map(|b| format!("{:02x}", b)).collect();
+        self.with_mut_inner(|inner| {
+            inner
+                .forks
+                .retain(|_, fork| fork.requested_at.elapsed() < FORK_TTL);
+            let fork = Fork {
+                key,
+                user_id,
+                requested_at: Instant::now(),
+            };
+            inner.forks.insert(token.clone(), fork);
+        });
+        Ok(token)
+    }
+
+    /// Returns the key of the session to fork, if the token is requested
+    /// by the user and not expired. Each token can be taken once.
+    pub fn take_fork(&self, token: &str, user_id: u64) -> Option<String> {
+        self.with_mut_inner(|inner| {
+            let fork = inner.forks.remove(token)?;
+            if fork.user_id != user_id || fork.requested_at.elapsed() >= FORK_TTL {
+                return None;
+            }
+            Some(fork.key)
+        })
+    }
+
     pub fn get_history_messages(&self, key: &str) -> Vec<Message> {
         self.with_mut_inner(|inner| {
             inner
@@ -41,12 +271,213 @@ impl SessionManager {
         })
     }
 
-    pub fn swap_session_pending_message(
+    pub fn get_raw_history_messages(&self, key: &str) -> Vec<Message> {
+        self.with_mut_inner(|inner| {
+            inner
+                .sessions
+                .get(key)
+                .map(|s| s.get_raw_history_messages())
+                .unwrap_or(vec![])
+        })
+    }
+
+    /// Keeps the message whose answer failed, which replaces the previous
+    /// one of the session. It's kept in the database, so that it can be
+    /// retried after restarts.
+    pub async fn set_pending_message(&self, key: String, content: String) {
+        if let Err(err) = self.pending_store.put(key, content).await {
+            error!("Failed to keep the pending message: {}", err);
+        }
+    }
+
+    /// Takes the pending message of the session to retry it.
+    pub async fn take_pending_message(&self, key: String) -> Result<Option<PendingMessage>, Error> {
+        self.pending_store.take(key).await
+    }
+
+    /// Returns the time when the session was last active, or [`None`] if
+    /// the session doesn't exist.
+    pub fn last_active(&self, key: &str) -> Option<Instant> {
+        self.with_mut_inner(|inner| inner.sessions.get(key).map(|s| s.last_active()))
+    }
+
+    /// Removes the sessions idle for longer than `ttl`, and returns the keys
+    /// of the removed sessions that had contexts.
+    pub fn evict_idle_sessions(&self, ttl: Duration) -> Vec<String> {
+        self.with_mut_inner(|inner| {
+            let mut evicted_keys = vec![];
+            inner.sessions.retain(|key, session| {
+                if session.last_active().elapsed() <= ttl {
+                    return true;
+                }
+                if !session.is_empty() {
+                    evicted_keys.push(key.clone());
+                }
+                false
+            });
+            evicted_keys
+        })
+    }
+
+    /// Starts a background task that evicts idle sessions periodically.
+    /// Sessions are kept while `sessionTtlMinutes` is not set.
+    pub fn start_expiry_task(&self, bot: Bot) {
+        let shared_config = self.with_mut_inner(|inner| inner.config.clone());
+        let session_mgr = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+
+                // Read the config on each tick, since it may be reloaded.
+                let config = shared_config.load();
+                let ttl = match config.session_ttl_minutes {
+                    Some(minutes) => Duration::from_secs(minutes * 60),
+                    None => continue,
+                };
+                let evicted_keys = session_mgr.evict_idle_sessions(ttl);
+                if !evicted_keys.is_empty() {
+                    debug!("Evicted {} idle sessions", evicted_keys.len());
+                }
+                if !config.notify_session_expiry {
+                    continue;
+                }
+                for key in evicted_keys {
+                    let (chat_id, topic_id) = match parse_session_key(&key) {
+                        Some(parsed) => parsed,
+                        None => continue,
+                    };
+                    let mut send_message = bot
+                        .send_message(chat_id, &config.i18n_strings(None).session_expired_prompt);
+                    send_message.message_thread_id = topic_id;
+                    let res = send_message.await;
+                    if let Err(err) = res {
+                        error!("Failed to notify the session expiry: {}", err);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Tracks the answer being generated in the message, and returns the
+    /// notifier that stops it.
+    pub fn start_generation(
+        &self,
+        key: String,
+        message_id: i32,
+        user_id: Option<u64>,
+    ) -> Arc<Notify> {
+        let stop = Arc::new(Notify::new());
+        self.with_mut_inner(|inner| {
+            let generation = Generation {
+                user_id,
+                stop: Arc::clone(&stop),
+            };
+            inner.generations.insert((key, message_id), generation);
+        });
+        stop
+    }
+
+    pub fn finish_generation(&self, key: String, message_id: i32) {
+        self.with_mut_inner(|inner| inner.generations.remove(&(key, message_id)));
+    }
+
+    /// Stops the answer being generated in the message if `is_allowed`
+    /// returns `true` for the user who asked for it. Returns [`None`] if
+    /// the answer is not being generated.
+    pub fn stop_generation<F>(&self, key: String, message_id: i32, is_allowed: F) -> Option<bool>
+    where
+        F: FnOnce(Option<u64>) -> bool,
+    {
+        self.with_mut_inner(|inner| {
+            let generation = inner.generations.get(&(key, message_id))?;
+            if !is_allowed(generation.user_id) {
+                return Some(false);
+            }
+            // The permit is stored if the stream is not being polled.
+            generation.stop.notify_one();
+            Some(true)
+        })
+    }
+
+    /// Summarizes the evicted messages of the session in the background, if
+    /// `historySummary` is enabled. The summary is prepended to the history
+    /// messages once it's ready. The usage is counted for the user whose
+    /// message triggers it.
+    pub fn spawn_summarization(
         &self,
         key: String,
-        msg: Option<Message>,
-    ) -> Option<Message> {
-        self.with_mut_session(key, |session| session.swap_pending_message(msg))
+        user_id: Option<u64>,
+        username: Option<String>,
+        openai_client: OpenAIClient,
+        event_bus: EventBus,
+    ) {
+        let summary_config = match &self
+            .with_mut_inner(|inner| inner.config.load())
+            .history_summary
+        {
+            Some(summary_config) => summary_config.clone(),
+            None => return,
+        };
+        let work = match self.with_mut_session(key.clone(), |session| session.take_summary_work()) {
+            Some(work) => work,
+            None => return,
+        };
+
+        let session_mgr = self.clone();
+        tokio::spawn(async move {
+            let user = (user_id, username);
+            let summary = summarize(
+                &key,
+                &work,
+                &summary_config,
+                &openai_client,
+                &event_bus,
+                user,
+            )
+            .await
+            .map_err(|err| error!("Failed to summarize the history: {}", err))
+            .ok();
+            session_mgr
+                .with_mut_session(key, |session| session.finish_summary_work(&work, summary));
+        });
+    }
+
+    /// Returns the title of the session, if it's named.
+    pub fn get_title(&self, key: &str) -> Option<String> {
+        self.with_mut_inner(|inner| {
+            inner
+                .sessions
+                .get(key)
+                .and_then(|s| s.title().map(|title| title.to_owned()))
+        })
+    }
+
+    /// Names the session in the background once it has enough messages, if
+    /// `sessionTitles` is enabled.
+    pub fn spawn_naming(&self, key: String, openai_client: OpenAIClient) {
+        let title_config = match &self
+            .with_mut_inner(|inner| inner.config.load())
+            .session_titles
+        {
+            Some(title_config) => title_config.clone(),
+            None => return,
+        };
+        let work = match self.with_mut_session(key.clone(), |session| {
+            session.take_title_work(title_config.after_messages)
+        }) {
+            Some(work) => work,
+            None => return,
+        };
+
+        let session_mgr = self.clone();
+        tokio::spawn(async move {
+            let title = name_session(&key, &work, &title_config, &openai_client)
+                .await
+                .map_err(|err| error!("Failed to name the session: {}", err))
+                .ok();
+            session_mgr.with_mut_session(key, |session| session.finish_title_work(&work, title));
+        });
     }
 
     pub fn with_mut_session<F, R>(&self, key: String, f: F) -> R
@@ -75,6 +506,245 @@ impl Clone for SessionManager {
     fn clone(&self) -> Self {
         Self {
             inner: Arc::clone(&self.inner),
+            prefs_mgr: self.prefs_mgr.clone(),
+            pending_store: self.pending_store.clone(),
         }
     }
 }
+
+async fn summarize(
+    key: &str,
+    work: &SummaryWork,
+    summary_config: &HistorySummaryConfig,
+    openai_client: &OpenAIClient,
+    event_bus: &EventBus,
+    (user_id, username): (Option<u64>, Option<String>),
+) -> Result<(Message, u32), Error> {
+    let mut prompt = SUMMARY_INSTRUCTION.to_owned();
+    if let Some(previous_summary) = &work.previous_summary {
+        prompt.push_str("\n\nPrevious summary:\n");
+        prompt.push_str(previous_summary.trim_start_matches(SUMMARY_PREFIX));
+    }
+    prompt.push_str("\n\nConversation:");
+    for msg in &work.messages {
+        prompt.push_str(&format!("\n{}: {}", msg.role, msg.content));
+    }
+    let prompt_msg = ChatCompletionRequestMessageArgs::default()
+        .role(Role::User)
+        .content(&prompt)
+        .build()?;
+
+    // Sessions of topics are billed to their chats.
+    let (chat_id, topic_id) = parse_session_key(key).map_or((None, None), |(chat_id, topic_id)| {
+        (Some(chat_id.to_string()), topic_id)
+    });
+    let params = ChatModelParams {
+        model: summary_config.model.clone(),
+        max_tokens: Some(summary_config.max_tokens),
+        ..Default::default()
+    };
+    let stream = openai_client
+        .request_chat_model(chat_id.as_deref(), vec![prompt_msg], params)
+        .await?;
+    let mut result = stream
+        .fold(None, |_, item| async move { Some(item) })
+        .await
+        .filter(|res| !res.content.trim().is_empty())
+        .ok_or_else(|| anyhow!("Server returned empty response"))?;
+    result.prompt_tokens = openai_client.count_tokens(&result.model, &prompt);
+    result.completion_tokens = openai_client.count_tokens(&result.model, &result.content);
+    let cost = openai_client.record_usage(&result).await;
+    event_bus.publish(Event::ChatCompleted {
+        chat_id: chat_id.clone().unwrap_or_else(|| key.to_owned()),
+        topic_id,
+        user_id,
+        username,
+        model: result.model.clone(),
+        prompt_tokens: result.prompt_tokens,
+        completion_tokens: result.completion_tokens,
+        cost,
+        is_regeneration: false,
+    });
+
+    let summary_msg = ChatCompletionRequestMessageArgs::default()
+        .role(Role::System)
+        .content(format!("{}{}", SUMMARY_PREFIX, result.content.trim()))
+        .build()?;
+    // The summary is sent with the model of the chat, not the summarizer.
+    let chat_model = openai_client.chat_model(chat_id.as_deref()).await;
+    let token_count =
+        openai_client.count_message_tokens(&chat_model, std::slice::from_ref(&summary_msg));
+    Ok((summary_msg, token_count))
+}
+
+async fn name_session(
+    key: &str,
+    work: &TitleWork,
+    title_config: &SessionTitleConfig,
+    openai_client: &OpenAIClient,
+) -> Result<String, Error> {
+    let mut prompt = TITLE_INSTRUCTION.to_owned();
+    prompt.push_str("\n\nConversation:");
+    for msg in &work.messages {
+        prompt.push_str(&format!("\n{}: {}", msg.role, msg.content));
+    }
+    let prompt_msg = ChatCompletionRequestMessageArgs::default()
+        .role(Role::User)
+        .content(&prompt)
+        .build()?;
+
+    let chat_id = parse_session_key(key).map(|(chat_id, _)| chat_id.to_string());
+    let params = ChatModelParams {
+        model: title_config.model.clone(),
+        max_tokens: Some(TITLE_MAX_TOKENS),
+        ..Default::default()
+    };
+    let stream = openai_client
+        .request_chat_model(chat_id.as_deref(), vec![prompt_msg], params)
+        .await?;
+    let mut result = stream
+        .fold(None, |_, item| async move { Some(item) })
+        .await
+        .ok_or_else(|| anyhow!("Server returned empty response"))?;
+    result.prompt_tokens = openai_client.count_tokens(&result.model, &prompt);
+    result.completion_tokens = openai_client.count_tokens(&result.model, &result.content);
+    openai_client.record_usage(&result).await;
+
+    clean_title(&result.content).ok_or_else(|| anyhow!("Server returned empty title"))
+}
+
+/// Takes the first line of the generated title, without the quotes and
+/// the trailing period that models tend to add.
+fn clean_title(text: &str) -> Option<String> {
+    let title = text
+        .lines()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty())?
+        .trim_start_matches("Title:")
+        .trim_matches(|c: char| c.is_whitespace() || "\"'“”«»*".contains(c))
+        .trim_end_matches('.');
+    if title.is_empty() {
+        return None;
+    }
+    Some(title.chars().take(TITLE_MAX_CHARS).collect())
+}
+
+/// Returns the forum topic that the message belongs to.
+pub(crate) fn topic_id(msg: &teloxide::types::Message) -> Option<i32> {
+    // Replies in ordinary supergroups have thread ids too, which are not
+    // topics.
+    if matches!(&msg.kind, MessageKind::Common(common) if common.is_topic_message) {
+        msg.thread_id
+    } else {
+        None
+    }
+}
+
+/// Returns the key of the session for a chat, each forum topic in the
+/// chat gets its own session.
+pub(crate) fn session_key(chat_id: &str, topic_id: Option<i32>) -> String {
+    match topic_id {
+        Some(topic_id) => format!("{}:{}", chat_id, topic_id),
+        None => chat_id.to_owned(),
+    }
+}
+
+/// Returns the key of the named session of a chat or a topic, the default
+/// session keeps the unnamed key.
+pub(crate) fn named_session_key(base_key: &str, name: &str) -> String {
+    if name == DEFAULT_SESSION_NAME {
+        base_key.to_owned()
+    } else {
+        format!("{}#{}", base_key, name)
+    }
+}
+
+pub(crate) fn parse_session_key(key: &str) -> Option<(ChatId, Option<i32>)> {
+    let key = key.split('#').next()?;
+    match key.split_once(':') {
+        Some((chat_id, topic_id)) => {
+            Some((ChatId(chat_id.parse().ok()?), Some(topic_id.parse().ok()?)))
+        }
+        None => Some((ChatId(key.parse().ok()?), None)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{DatabaseManager, InMemDatabaseProvider};
+    use crate::utils::dptree_ext::FromCommandArgs;
+
+    #[test]
+    fn test_session_key() {
+        assert_eq!(session_key("-100", None), "-100");
+        assert_eq!(session_key("-100", Some(4)), "-100:4");
+        assert_eq!(parse_session_key("-100"), Some((ChatId(-100), None)));
+        assert_eq!(parse_session_key("-100:4"), Some((ChatId(-100), Some(4))));
+        assert_eq!(parse_session_key("-100:x"), None);
+
+        assert_eq!(named_session_key("-100:4", DEFAULT_SESSION_NAME), "-100:4");
+        assert_eq!(named_session_key("-100:4", "work"), "-100:4#work");
+        assert_eq!(
+            parse_session_key("-100:4#work"),
+            Some((ChatId(-100), Some(4)))
+        );
+    }
+
+    #[test]
+    fn test_parse_session_name() {
+        assert_eq!(
+            <(SessionName,)>::parse_args("work-2"),
+            Ok((SessionName("work-2".to_owned()),))
+        );
+        assert!(<(SessionName,)>::parse_args("a".repeat(25).as_str()).is_err());
+        // Multibyte names may exceed the limit of button data in bytes.
+        assert!(<(SessionName,)>::parse_args("工作").is_err());
+        assert!(<(SessionName,)>::parse_args("work#2").is_err());
+    }
+
+    #[test]
+    fn test_clean_title() {
+        assert_eq!(
+            clean_title("\"Rust lifetimes.\"\n").as_deref(),
+            Some("Rust lifetimes")
+        );
+        assert_eq!(
+            clean_title("\nTitle: **Trip to Kyoto**").as_deref(),
+            Some("Trip to Kyoto")
+        );
+        assert_eq!(clean_title(" \"\" "), None);
+    }
+
+    #[tokio::test]
+    async fn test_stop_generation() {
+        let config = serde_json::from_str(r#"{"botToken": ""}"#).unwrap();
+        let db_mgr = DatabaseManager::with_db_provider(InMemDatabaseProvider).unwrap();
+        let prefs_mgr = PreferencesManager::with_db_manager(db_mgr.clone())
+            .await
+            .unwrap();
+        let pending_store = PendingMessageStore::new(db_mgr);
+        let session_mgr = SessionManager::new(SharedConfig::new(config), prefs_mgr, pending_store);
+        session_mgr.start_generation("-100".to_owned(), 1, Some(42));
+
+        let is_sender = |user_id| user_id == Some(42);
+        assert_eq!(
+            session_mgr.stop_generation("-100".to_owned(), 2, is_sender),
+            None
+        );
+        assert_eq!(
+            session_mgr.stop_generation("-100".to_owned(), 1, |_| false),
+            Some(false)
+        );
+        assert_eq!(
+            session_mgr.stop_generation("-100".to_owned(), 1, is_sender),
+            Some(true)
+        );
+
+        session_mgr.finish_generation("-100".to_owned(), 1);
+        assert_eq!(
+            session_mgr.stop_generation("-100".to_owned(), 1, is_sender),
+            None
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use std::fmt::Write;
+
+use anyhow::Error;
+use serde::Deserialize;
+
+use crate::{
+    config::{SearchProviderConfig, WebSearchConfig},
+    utils::http,
+};
+
+const BRAVE_SEARCH_URL: &str = "https://api.search.brave.com/res/v1/web/search";
+
+/// A result of the web search, which is cited by its number in the answer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SearchResult {
+    pub title: String,
+    pub url: String,
+    pub snippet: String,
+}
+
+#[derive(Deserialize)]
+struct BraveResponse {
+    #[serde(default)]
+    web: Option<BraveResults>,
+}
+
+#[derive(Deserialize)]
+struct BraveResults {
+    results: Vec<BraveResult>,
+}
+
+#[derive(Deserialize)]
+struct BraveResult {
+    title: String,
+    url: String,
+    #[serde(default)]
+    description: String,
+}
+
+#[derive(Deserialize)]
+struct SearxngResponse {
+    results: Vec<SearxngResult>,
+}
+
+#[derive(Deserialize)]
+struct SearxngResult {
+    title: String,
+    url: String,
+    #[serde(default)]
+    content: String,
+}
+
+/// Searches the web with the configured search API.
+pub(crate) async fn search(
+    config: &WebSearchConfig,
+    query: &str,
+) -> Result<Vec<SearchResult>, Error> {
+    let client = http::client();
+    let mut results: Vec<_> = match &config.provider {
+        SearchProviderConfig::Brave { api_key } => {
+            let resp: BraveResponse = client
+                .get(BRAVE_SEARCH_URL)
+                .query(&[("q", query), ("count", &config.max_results.to_string())])
+                .header("Accept", "application/json")
+                .header("X-Subscription-Token", api_key)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            resp.web
+                .map(|web| web.results)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|result| SearchResult {
+                    title: result.title,
+                    url: result.url,
+                    snippet: result.description,
+                })
+                .collect()
+        }
+        SearchProviderConfig::Searxng { base_url } => {
+            let resp: SearxngResponse = client
+                .get(format!("{}/search", base_url.trim_end_matches('/')))
+                .query(&[("q", query), ("format", "json")])
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            resp.results
+                .into_iter()
+                .map(|result| SearchResult {
+                    title: result.title,
+                    url: result.url,
+                    snippet: result.content,
+                })
+                .collect()
+        }
+    };
+    results.truncate(config.max_results);
+    for result in &mut results {
+        result.title = strip_tags(&result.title);
+        result.snippet = strip_tags(&result.snippet);
+    }
+    Ok(results)
+}
+
+/// Builds the prompt that asks the model to answer the query with the
+/// numbered results.
+pub(crate) fn search_prompt(query: &str, results: &[SearchResult]) -> String {
+    let mut prompt = String::from(
+        "Answer the question with the web search results below. Cite the results you use \
+        with their numbers in square brackets, e.g. [1].\n\n",
+    );
+    for (idx, result) in results.iter().enumerate() {
+        let _ = writeln!(
+            &mut prompt,
+            "[{}] {}\n{}\n{}\n",
+            idx + 1,
+            result.title,
+            result.url,
+            result.snippet
+        );
+    }
+    let _ = write!(&mut prompt, "Question: {}", query);
+    prompt
+}
+
+/// Turns the citations (e.g. `[1]`) in the Markdown answer into links to
+/// the results, and appends the list of the cited results. The code in the
+/// answer is left as is.
+pub(crate) fn render_citations(answer: &str, results: &[SearchResult]) -> String {
+    let mut rendered = String::with_capacity(answer.len());
+    let mut cited = vec![false; results.len()];
+    let mut rest = answer;
+    while let Some(start) = rest.find(['[', '`']) {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if rest.starts_with('`') {
+            // Skip to the end of the code span or block, which is closed by
+            // the same number of backticks.
+            let ticks = rest.len() - rest.trim_start_matches('`').len();
+            let end = rest[ticks..]
+                .find(&rest[..ticks])
+                .map_or(rest.len(), |end| end + 2 * ticks);
+            rendered.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+        let citation = rest[1..]
+            .find(']')
+            .map(|end| &rest[1..end + 1])
+            .and_then(|number| number.parse::<usize>().ok())
+            .filter(|number| (1..=results.len()).contains(number));
+        match citation {
+            // Existing links are left as is.
+            Some(number) if !rest[number.to_string().len() + 2..].starts_with('(') => {
+                let _ = write!(
+                    &mut rendered,
+                    "[\\[{}\\]]({})",
+                    number,
+                    results[number - 1].url
+                );
+                cited[number - 1] = true;
+                rest = &rest[number.to_string().len() + 2..];
+            }
+            _ => {
+                rendered.push('[');
+                rest = &rest[1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+
+    if cited.contains(&true) {
+        rendered.push_str("\n\n**Sources**\n");
+        for (idx, result) in results.iter().enumerate().filter(|(idx, _)| cited[*idx]) {
+            let title = result.title.replace('[', "\\[").replace(']', "\\]");
+            let _ = writeln!(&mut rendered, "{}. [{}]({})", idx + 1, title, result.url);
+        }
+    }
+    rendered
+}
+
+/// Appends the list of the results as plain text, for answers that are not
+/// rendered.
+pub(crate) fn append_sources(answer: &str, results: &[SearchResult]) -> String {
+    let mut text = format!("{}\n\nSources:\n", answer);
+    for (idx, result) in results.iter().enumerate() {
+        let _ = writeln!(&mut text, "[{}] {}", idx + 1, result.url);
+    }
+    text
+}
+
+/// Removes the HTML tags that search APIs use to highlight the matches,
+/// and decodes the entities in the text.
+fn strip_tags(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for ch in text.chars() {
+        match ch {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            ch if !in_tag => stripped.push(ch),
+            _ => {}
+        }
+    }
+    decode_entities(&stripped)
+}
+
+/// Decodes the common named entities and the numeric ones, e.g. `&amp;`
+/// and `&#39;`. Unknown entities are left as is.
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest[1..].find(';').map(|end| &rest[1..end + 1]);
+        let ch = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => {
+                let code = entity.strip_prefix('#')?;
+                let code = match code.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => code.parse().ok()?,
+                };
+                char::from_u32(code)
+            }
+        });
+        match (entity, ch) {
+            (Some(entity), Some(ch)) => {
+                decoded.push(ch);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::MessageEntityKind;
+
+    use super::super::markdown;
+    use super::*;
+
+    #[test]
+    fn test_render_citations() {
+        let results = vec![
+            SearchResult {
+                title: "Rust [Home]".to_owned(),
+                url: "https://www.rust-lang.org/".to_owned(),
+                snippet: "A language empowering everyone".to_owned(),
+            },
+            SearchResult {
+                title: "Rust Docs".to_owned(),
+                url: "https://doc.rust-lang.org/".to_owned(),
+                snippet: "The Rust Programming Language".to_owned(),
+            },
+        ];
+        assert_eq!(
+            render_citations(
+                "Rust is fast [1]. See [docs](https://docs.rs) [3].",
+                &results
+            ),
+            "Rust is fast [\\[1\\]](https://www.rust-lang.org/). See [docs](https://docs.rs) [3].\
+            \n\n**Sources**\n1. [Rust \\[Home\\]](https://www.rust-lang.org/)\n"
+        );
+        let parsed = markdown::parse(&render_citations("Rust docs [2]", &results));
+        assert_eq!(parsed.content, "Rust docs [2]\n\nSources\n2. Rust Docs");
+        assert!(matches!(
+            &parsed.entities[0].kind,
+            MessageEntityKind::TextLink { url } if url.as_str() == "https://doc.rust-lang.org/"
+        ));
+
+        assert_eq!(
+            render_citations("No citations [", &results),
+            "No citations ["
+        );
+        assert_eq!(
+            render_citations("Use `v[1]` and\n```\nv[2]\n```\n[2]", &results),
+            "Use `v[1]` and\n```\nv[2]\n```\n[\\[2\\]](https://doc.rust-lang.org/)\
+            \n\n**Sources**\n2. [Rust Docs](https://doc.rust-lang.org/)\n"
+        );
+        assert_eq!(
+            strip_tags("The <strong>Rust</strong> book"),
+            "The Rust book"
+        );
+        assert_eq!(
+            strip_tags("Tom &amp; Jerry&#39;s &lt;b&gt; &#x4e2d; &bogus; & more"),
+            "Tom & Jerry's <b> 中 &bogus; & more"
+        );
+    }
+}
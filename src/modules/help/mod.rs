@@ -0,0 +1,164 @@
+use std::fmt::Write;
+use std::sync::Arc;
+
+use anyhow::Error;
+use teloxide::prelude::*;
+
+use crate::{
+    config::{I18nStrings, SharedConfig},
+    module_mgr::{Command, Module, ModuleManager},
+    modules::admin::{MemberRole, RoleManager},
+    types::HandlerResult,
+    utils::i18n::user_language,
+};
+
+/// A command listed in the help.
+#[derive(Clone, Debug)]
+struct HelpEntry {
+    command: String,
+    description: String,
+    is_hidden: bool,
+    is_admin_only: bool,
+    /// The lowest role whose help lists the command.
+    role: MemberRole,
+}
+
+/// The commands of all the registered modules, in the order of
+/// registration.
+#[derive(Clone, Default)]
+pub(crate) struct CommandList(Arc<Vec<HelpEntry>>);
+
+impl CommandList {
+    pub fn collect(module_mgr: &mut ModuleManager) -> Self {
+        let mut entries = vec![];
+        module_mgr.with_all_modules(|m| {
+            entries.extend(m.commands().into_iter().map(|command| HelpEntry {
+                command: command.command,
+                description: command.description,
+                is_hidden: command.is_hidden,
+                is_admin_only: command.is_admin_only,
+                role: command.role,
+            }))
+        });
+        Self(Arc::new(entries))
+    }
+}
+
+/// Renders the commands available to the user. Hidden commands are never
+/// listed, except the admin commands that the role of the user can run.
+fn render_help(commands: &CommandList, role: MemberRole, strings: &I18nStrings) -> String {
+    let describe = |text: &mut String, entry: &HelpEntry| {
+        let description = strings
+            .command_descriptions
+            .get(&entry.command)
+            .unwrap_or(&entry.description);
+        let _ = writeln!(text, "/{} - {}", entry.command, description);
+    };
+
+    let mut text = format!("{}\n", strings.help_prompt);
+    for entry in commands.0.iter() {
+        if !entry.is_hidden && !entry.is_admin_only {
+            describe(&mut text, entry);
+        }
+    }
+    let mut admin_entries = commands
+        .0
+        .iter()
+        .filter(|entry| entry.is_admin_only && entry.role <= role)
+        .peekable();
+    if admin_entries.peek().is_some() {
+        let _ = write!(&mut text, "\n{}\n", strings.admin_help_prompt);
+        for entry in admin_entries {
+            describe(&mut text, entry);
+        }
+    }
+    text
+}
+
+async fn show_help(
+    bot: Bot,
+    msg: Message,
+    commands: CommandList,
+    config: SharedConfig,
+    role_mgr: RoleManager,
+) -> HandlerResult {
+    let role = match msg.from() {
+        Some(user) => role_mgr.role_of(user).await.unwrap_or_else(|err| {
+            error!("Failed to get the role of {}: {}", user.id, err);
+            MemberRole::default()
+        }),
+        None => MemberRole::default(),
+    };
+    let text = render_help(
+        &commands,
+        role,
+        config.load().i18n_strings(user_language(&msg)),
+    );
+    bot.send_message(msg.chat.id, text)
+        .reply_to_message_id(msg.id)
+        .await?;
+    Ok(())
+}
+
+pub(crate) struct Help;
+
+#[async_trait]
+impl Module for Help {
+    async fn register_dependency(&mut self, _: &mut DependencyMap) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn commands(&self) -> Vec<Command> {
+        vec![Command::new(
+            "help",
+            "Show the available commands",
+            dptree::endpoint(show_help),
+        )]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(command: &str, is_hidden: bool, role: MemberRole) -> HelpEntry {
+        HelpEntry {
+            command: command.to_owned(),
+            description: format!("About {}", command),
+            is_hidden,
+            is_admin_only: role > MemberRole::Member,
+            role,
+        }
+    }
+
+    #[test]
+    fn test_render_help() {
+        let commands = CommandList(Arc::new(vec![
+            entry("reset", false, MemberRole::Member),
+            entry("start", true, MemberRole::Member),
+            entry("add_member", true, MemberRole::Moderator),
+            entry("status", true, MemberRole::Admin),
+        ]));
+        let strings: I18nStrings =
+            serde_json::from_str(r#"{"commandDescriptions": {"reset": "Neu starten"}}"#).unwrap();
+
+        assert_eq!(
+            render_help(&commands, MemberRole::Member, &strings),
+            format!("{}\n/reset - Neu starten\n", strings.help_prompt)
+        );
+        assert_eq!(
+            render_help(&commands, MemberRole::Moderator, &strings),
+            format!(
+                "{}\n/reset - Neu starten\n\n{}\n/add_member - About add_member\n",
+                strings.help_prompt, strings.admin_help_prompt
+            )
+        );
+        assert_eq!(
+            render_help(&commands, MemberRole::Admin, &strings),
+            format!(
+                "{}\n/reset - Neu starten\n\n{}\n/add_member - About add_member\n/status - About status\n",
+                strings.help_prompt, strings.admin_help_prompt
+            )
+        );
+    }
+}
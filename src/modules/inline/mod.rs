@@ -0,0 +1,114 @@
+mod providers;
+
+use std::sync::Arc;
+
+use anyhow::Error;
+use teloxide::dispatching::DpHandlerDescription;
+use teloxide::dptree::di::DependencySupplier;
+use teloxide::prelude::*;
+use teloxide::types::{
+    InlineQueryResult, InlineQueryResultArticle, InputMessageContent, InputMessageContentText, User,
+};
+
+use crate::{
+    config::SharedConfig,
+    module_mgr::Module,
+    modules::{admin::MemberManager, chat::PersonaManager, openai::OpenAIClient},
+    types::HandlerResult,
+};
+use providers::{MemberProvider, ModelProvider, PersonaProvider};
+
+/// A candidate argument of a command, which is sent as the command
+/// message when picked.
+pub(crate) struct InlineCandidate {
+    pub title: String,
+    pub description: Option<String>,
+    pub message_text: String,
+}
+
+/// Provides the candidate arguments of a command for inline queries in
+/// the form of `<command>:<keyword>`.
+#[async_trait]
+pub(crate) trait InlineProvider: Send + Sync {
+    fn command(&self) -> &'static str;
+
+    async fn provide(&self, user: &User, keyword: &str) -> Result<Vec<InlineCandidate>, Error>;
+}
+
+#[derive(Clone)]
+struct InlineProviders(Arc<Vec<Box<dyn InlineProvider>>>);
+
+async fn handle_inline_query(
+    bot: Bot,
+    query: InlineQuery,
+    providers: InlineProviders,
+) -> HandlerResult {
+    let (command, keyword) = match query.query.split_once(':') {
+        Some((command, keyword)) => (command.trim(), keyword.trim()),
+        None => (query.query.trim(), ""),
+    };
+
+    let candidates = match providers.0.iter().find(|p| p.command() == command) {
+        Some(provider) => provider
+            .provide(&query.from, keyword)
+            .await
+            .unwrap_or_else(|err| {
+                error!("Failed to provide inline candidates: {}", err);
+                vec![]
+            }),
+        None => vec![],
+    };
+
+    let results = candidates.into_iter().enumerate().map(|(idx, candidate)| {
+        let content =
+            InputMessageContent::Text(InputMessageContentText::new(candidate.message_text));
+        let mut article = InlineQueryResultArticle::new(idx.to_string(), candidate.title, content);
+        if let Some(description) = candidate.description {
+            article = article.description(description);
+        }
+        InlineQueryResult::Article(article)
+    });
+    bot.answer_inline_query(query.id, results)
+        .is_personal(true)
+        .cache_time(0)
+        .await?;
+
+    Ok(())
+}
+
+pub(crate) struct Inline;
+
+#[async_trait]
+impl Module for Inline {
+    async fn register_dependency(&mut self, dep_map: &mut DependencyMap) -> Result<(), Error> {
+        let openai_client: Arc<OpenAIClient> = dep_map.get();
+        let member_mgr: Arc<MemberManager> = dep_map.get();
+        let persona_mgr: Arc<PersonaManager> = dep_map.get();
+        let config: Arc<SharedConfig> = dep_map.get();
+
+        let mut providers: Vec<Box<dyn InlineProvider>> = vec![
+            Box::new(ModelProvider {
+                openai_client: openai_client.as_ref().clone(),
+            }),
+            Box::new(PersonaProvider {
+                persona_mgr: persona_mgr.as_ref().clone(),
+            }),
+        ];
+        for command in ["del_member", "ban_member", "unban_member"] {
+            providers.push(Box::new(MemberProvider {
+                command,
+                member_mgr: member_mgr.as_ref().clone(),
+                config: config.as_ref().clone(),
+            }));
+        }
+        dep_map.insert(InlineProviders(Arc::new(providers)));
+
+        Ok(())
+    }
+
+    fn filter_handler(
+        &self,
+    ) -> Handler<'static, DependencyMap, HandlerResult, DpHandlerDescription> {
+        Update::filter_inline_query().endpoint(handle_inline_query)
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::Error;
+use teloxide::types::User;
+
+use super::{InlineCandidate, InlineProvider};
+use crate::{
+    config::SharedConfig,
+    modules::{
+        admin::{is_admin, MemberManager},
+        chat::PersonaManager,
+        openai::OpenAIClient,
+    },
+};
+
+const MEMBER_CANDIDATES_LIMIT: u64 = 20;
+
+/// Provides the available models for the `/model` command.
+pub(crate) struct ModelProvider {
+    pub openai_client: OpenAIClient,
+}
+
+#[async_trait]
+impl InlineProvider for ModelProvider {
+    fn command(&self) -> &'static str {
+        "model"
+    }
+
+    async fn provide(&self, _user: &User, keyword: &str) -> Result<Vec<InlineCandidate>, Error> {
+        let keyword = keyword.to_lowercase();
+        Ok(self
+            .openai_client
+            .available_models()
+            .into_iter()
+            .filter(|model| model.to_lowercase().contains(&keyword))
+            .map(|model| InlineCandidate {
+                description: None,
+                message_text: format!("/model {}", model),
+                title: model,
+            })
+            .collect())
+    }
+}
+
+/// Provides the personas for the `/persona` command.
+pub(crate) struct PersonaProvider {
+    pub persona_mgr: PersonaManager,
+}
+
+#[async_trait]
+impl InlineProvider for PersonaProvider {
+    fn command(&self) -> &'static str {
+        "persona"
+    }
+
+    async fn provide(&self, _user: &User, keyword: &str) -> Result<Vec<InlineCandidate>, Error> {
+        let keyword = keyword.to_lowercase();
+        Ok(self
+            .persona_mgr
+            .list_personas()
+            .await?
+            .into_iter()
+            .filter(|persona| persona.name.to_lowercase().contains(&keyword))
+            .map(|persona| InlineCandidate {
+                description: Some(persona.prompt),
+                message_text: format!("/persona {}", persona.name),
+                title: persona.name,
+            })
+            .collect())
+    }
+}
+
+/// Provides the members for the admin commands that take a username.
+/// Nothing is provided for non-admin users.
+pub(crate) struct MemberProvider {
+    pub command: &'static str,
+    pub member_mgr: MemberManager,
+    pub config: SharedConfig,
+}
+
+#[async_trait]
+impl InlineProvider for MemberProvider {
+    fn command(&self) -> &'static str {
+        self.command
+    }
+
+    async fn provide(&self, user: &User, keyword: &str) -> Result<Vec<InlineCandidate>, Error> {
+        if !is_admin(user, &self.config) {
+            return Ok(vec![]);
+        }
+
+        let members = self
+            .member_mgr
+            .search_members(keyword.to_owned(), MEMBER_CANDIDATES_LIMIT)
+            .await?;
+        Ok(members
+            .into_iter()
+            .map(|member| InlineCandidate {
+                description: member.disabled.then(|| "Disabled".to_owned()),
+                message_text: format!("/{} {}", self.command, member.username),
+                title: member.username,
+            })
+            .collect())
+    }
+}
@@ -3,6 +3,8 @@
 pub(crate) mod admin;
 pub(crate) mod chat;
 pub(crate) mod config;
+pub(crate) mod help;
+pub(crate) mod inline;
 pub(crate) mod openai;
 pub(crate) mod prefs;
 pub(crate) mod stats;
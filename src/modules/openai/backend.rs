@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+
+use anyhow::Error;
+use async_openai::error::OpenAIError;
+use async_openai::types::{CreateChatCompletionRequest, CreateChatCompletionStreamResponse};
+use async_openai::Client;
+use futures::{stream, Stream, StreamExt};
+use reqwest_eventsource::{Error as EventSourceError, Event, RequestBuilderExt};
+use serde_json::Value;
+
+use super::vision::attach_images;
+use crate::{
+    config::{CompatibleProviderConfig, Config},
+    utils::http,
+};
+
+const ORGANIZATION_HEADER: &str = "OpenAI-Organization";
+const PROJECT_HEADER: &str = "OpenAI-Project";
+
+pub(crate) type ChatCompletionResponseStream =
+    Pin<Box<dyn Stream<Item = Result<CreateChatCompletionStreamResponse, OpenAIError>> + Send>>;
+
+/// A service that serves the chat model.
+#[async_trait]
+pub(crate) trait ChatBackend: Send + Sync {
+    /// Sends the request and streams the response. `image_urls` are
+    /// attached to the last user message.
+    async fn chat(
+        &self,
+        req: CreateChatCompletionRequest,
+        image_urls: &[String],
+    ) -> Result<ChatCompletionResponseStream, Error>;
+}
+
+/// Returns the headers that attribute the requests sent directly to the
+/// OpenAI API to the configured organization and project.
+pub(crate) fn account_headers(config: &Config) -> Vec<(&'static str, String)> {
+    let mut headers = vec![];
+    if let Some(organization) = &config.openai_organization {
+        headers.push((ORGANIZATION_HEADER, organization.clone()));
+    }
+    if let Some(project) = &config.openai_project {
+        headers.push((PROJECT_HEADER, project.clone()));
+    }
+    headers
+}
+
+/// The OpenAI API, with a key from the key pool.
+pub(crate) struct OpenAIBackend {
+    pub client: Client,
+    /// See [`account_headers`].
+    pub headers: Vec<(&'static str, String)>,
+}
+
+#[async_trait]
+impl ChatBackend for OpenAIBackend {
+    async fn chat(
+        &self,
+        req: CreateChatCompletionRequest,
+        image_urls: &[String],
+    ) -> Result<ChatCompletionResponseStream, Error> {
+        // `async-openai` sends the organization header, but not the project
+        // one, so requests with projects are sent directly too.
+        let has_project = self.headers.iter().any(|(name, _)| *name == PROJECT_HEADER);
+        if image_urls.is_empty() && !has_project {
+            return Ok(self.client.chat().create_stream(req).await?);
+        }
+
+        // The multimodal content format is not supported by `async-openai`
+        // yet, so the request body is patched and sent directly.
+        let mut body = serde_json::to_value(req)?;
+        if !image_urls.is_empty() {
+            attach_images(&mut body, image_urls)?;
+        }
+        stream_chat_completions(
+            self.client.api_base(),
+            Some(self.client.api_key()),
+            &self.headers,
+            body,
+        )
+    }
+}
+
+/// A self-hosted server with an OpenAI-compatible API, e.g. Ollama,
+/// LM Studio or vLLM.
+pub(crate) struct CompatibleBackend {
+    base_url: String,
+    api_key: Option<String>,
+    model_names: HashMap<String, String>,
+}
+
+impl CompatibleBackend {
+    pub fn new(config: &CompatibleProviderConfig) -> Self {
+        Self {
+            base_url: config.base_url.trim_end_matches('/').to_owned(),
+            api_key: config.api_key.clone(),
+            model_names: config.model_names.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl ChatBackend for CompatibleBackend {
+    async fn chat(
+        &self,
+        req: CreateChatCompletionRequest,
+        image_urls: &[String],
+    ) -> Result<ChatCompletionResponseStream, Error> {
+        let mut body = serde_json::to_value(req)?;
+        // The models are usually named differently by these servers.
+        if let Some(model_name) = body["model"]
+            .as_str()
+            .and_then(|model| self.model_names.get(model))
+        {
+            body["model"] = Value::String(model_name.clone());
+        }
+        if !image_urls.is_empty() {
+            attach_images(&mut body, image_urls)?;
+        }
+        stream_chat_completions(&self.base_url, self.api_key.as_deref(), &[], body)
+    }
+}
+
+/// Parses a chunk of the stream. Some servers omit the fields that are not
+/// used by the bot, which are filled with defaults. Errors reported in
+/// the stream are turned into stream errors.
+fn parse_stream_chunk(data: &str) -> Result<CreateChatCompletionStreamResponse, OpenAIError> {
+    let mut chunk: Value = serde_json::from_str(data).map_err(OpenAIError::JSONDeserialize)?;
+    if let Some(err) = chunk.get("error") {
+        let message = err["message"]
+            .as_str()
+            .map_or(err.to_string(), str::to_owned);
+        return Err(OpenAIError::StreamError(message));
+    }
+
+    let defaults = [
+        ("object", Value::from("chat.completion.chunk")),
+        ("created", Value::from(0)),
+        ("model", Value::from("")),
+        ("choices", Value::Array(vec![])),
+    ];
+    if let Some(fields) = chunk.as_object_mut() {
+        for (key, value) in defaults {
+            if fields.get(key).filter(|value| !value.is_null()).is_none() {
+                fields.insert(key.to_owned(), value);
+            }
+        }
+    }
+    serde_json::from_value(chunk).map_err(OpenAIError::JSONDeserialize)
+}
+
+/// Posts the request body to the chat completions endpoint of `api_base`
+/// with the extra headers, and streams the response.
+pub(crate) fn stream_chat_completions(
+    api_base: &str,
+    api_key: Option<&str>,
+    headers: &[(&'static str, String)],
+    mut body: Value,
+) -> Result<ChatCompletionResponseStream, Error> {
+    body["stream"] = Value::Bool(true);
+    let mut req = http::streaming_client()
+        .post(format!("{}/chat/completions", api_base))
+        .json(&body);
+    if let Some(api_key) = api_key {
+        req = req.bearer_auth(api_key);
+    }
+    for (name, value) in headers {
+        req = req.header(*name, value);
+    }
+    let event_source = req.eventsource()?;
+
+    // Stop at the end marker or the first error, otherwise the event
+    // source will keep reconnecting.
+    Ok(
+        stream::unfold(Some(event_source), |event_source| async move {
+            let mut event_source = event_source?;
+            loop {
+                match event_source.next().await? {
+                    Ok(Event::Open) => continue,
+                    Ok(Event::Message(message)) if message.data == "[DONE]" => {
+                        event_source.close();
+                        return None;
+                    }
+                    Ok(Event::Message(message)) => {
+                        let item = parse_stream_chunk(&message.data);
+                        return Some((item, Some(event_source)));
+                    }
+                    // Some servers close the stream without the end marker.
+                    Err(EventSourceError::StreamEnded) => {
+                        event_source.close();
+                        return None;
+                    }
+                    Err(err) => {
+                        event_source.close();
+                        return Some((Err(OpenAIError::StreamError(err.to_string())), None));
+                    }
+                }
+            }
+        })
+        .boxed(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stream_chunk() {
+        let chunk =
+            parse_stream_chunk(r#"{"choices":[{"index":0,"delta":{"content":"Hi"}}]}"#).unwrap();
+        assert_eq!(chunk.choices[0].delta.content.as_deref(), Some("Hi"));
+
+        let err = parse_stream_chunk(r#"{"error":{"message":"model not found"}}"#).unwrap_err();
+        assert!(matches!(err, OpenAIError::StreamError(msg) if msg == "model not found"));
+    }
+}
@@ -0,0 +1,404 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Error;
+use async_openai::Client;
+use chrono::Utc;
+use openssl::sha::sha256;
+
+use crate::{
+    config::SharedConfig,
+    database::DatabaseManager,
+    event_bus::{Event, EventBus},
+};
+
+/// The status of a key in the pool, which is safe to display since the
+/// key is masked.
+#[derive(Clone, Debug)]
+pub(crate) struct KeyStatus {
+    pub masked_key: String,
+    /// The spend of the current month in USD.
+    pub spend: f64,
+    pub monthly_budget: Option<f64>,
+}
+
+impl KeyStatus {
+    /// Returns the ratio of spend to budget, or `None` if the key has
+    /// no budget.
+    pub fn budget_usage(&self) -> Option<f64> {
+        self.monthly_budget
+            .filter(|budget| *budget > 0.0)
+            .map(|budget| self.spend / budget)
+    }
+}
+
+struct PooledKey {
+    client: Client,
+    /// Identifies the key in the database, see [`key_id`].
+    key_id: String,
+    masked_key: String,
+    monthly_budget: Option<f64>,
+}
+
+struct KeyPoolState {
+    month: String,
+    spends: Vec<f64>,
+    alerted: Vec<bool>,
+    exceeded: Vec<bool>,
+}
+
+struct KeyPoolInner {
+    keys: Vec<PooledKey>,
+    state: Mutex<KeyPoolState>,
+    db_mgr: DatabaseManager,
+    event_bus: EventBus,
+    config: SharedConfig,
+}
+
+/// A pool of API keys, which distributes the requests to the key with
+/// the least spend in the current month, and warns when a key approaches
+/// its monthly budget.
+#[derive(Clone)]
+pub(crate) struct KeyPool {
+    inner: Arc<KeyPoolInner>,
+}
+
+impl KeyPool {
+    pub async fn new(
+        db_mgr: DatabaseManager,
+        event_bus: EventBus,
+        config: SharedConfig,
+    ) -> Result<Self, Error> {
+        // Keys are fixed once the pool is created.
+        let keys_config = config.load();
+        let keys: Vec<_> = if keys_config.openai_api_keys.is_empty() {
+            vec![(keys_config.openai_api_key.clone(), None)]
+        } else {
+            keys_config
+                .openai_api_keys
+                .iter()
+                .map(|key| (key.key.clone(), key.monthly_budget))
+                .collect()
+        };
+        let keys: Vec<_> = keys
+            .into_iter()
+            .map(|(key, monthly_budget)| {
+                let mut client = Client::new().with_api_key(&key);
+                if let Some(organization) = &keys_config.openai_organization {
+                    client = client.with_org_id(organization);
+                }
+                PooledKey {
+                    client,
+                    key_id: key_id(&key),
+                    masked_key: mask_key(&key),
+                    monthly_budget,
+                }
+            })
+            .collect();
+
+        Self::migrate_masked_key_ids(&db_mgr, &keys).await?;
+        let month = current_month(&config);
+        let mut spends = vec![];
+        for key in &keys {
+            spends.push(Self::query_spend(&db_mgr, &key.key_id, &month).await?);
+        }
+        let alerted = vec![false; keys.len()];
+        let exceeded = vec![false; keys.len()];
+
+        Ok(Self {
+            inner: Arc::new(KeyPoolInner {
+                keys,
+                state: Mutex::new(KeyPoolState {
+                    month,
+                    spends,
+                    alerted,
+                    exceeded,
+                }),
+                db_mgr,
+                event_bus,
+                config,
+            }),
+        })
+    }
+
+    /// Picks a key for the next request, returns its index and client.
+    pub fn pick(&self) -> (usize, Client) {
+        let mut state = self.inner.state.lock().unwrap();
+        self.roll_month_if_needed(&mut state);
+
+        let keys = &self.inner.keys;
+        let within_budget = |idx: &usize| match keys[*idx].monthly_budget {
+            Some(budget) => state.spends[*idx] < budget,
+            None => true,
+        };
+        let least_spent = |a: &usize, b: &usize| state.spends[*a].total_cmp(&state.spends[*b]);
+
+        let idx = (0..keys.len())
+            .filter(within_budget)
+            .min_by(least_spent)
+            .unwrap_or_else(|| {
+                warn!("All API keys have exceeded their monthly budgets");
+                (0..keys.len()).min_by(least_spent).unwrap()
+            });
+        (idx, keys[idx].client.clone())
+    }
+
+    /// Adds the spend to the key, and warns if the key approaches its
+    /// monthly budget.
+    pub async fn record_spend(&self, idx: usize, cost: f64) {
+        if cost <= 0.0 {
+            return;
+        }
+
+        let month = {
+            let mut state = self.inner.state.lock().unwrap();
+            self.roll_month_if_needed(&mut state);
+            state.spends[idx] += cost;
+
+            let key = &self.inner.keys[idx];
+            if let Some(budget) = key.monthly_budget {
+                let threshold = budget * self.inner.config.load().key_budget_alert_threshold;
+                if state.spends[idx] >= threshold && !state.alerted[idx] {
+                    state.alerted[idx] = true;
+                    warn!(
+                        "API key {} has spent ${:.2} of its ${:.2} monthly budget",
+                        key.masked_key, state.spends[idx], budget
+                    );
+                }
+                if state.spends[idx] >= budget && !state.exceeded[idx] {
+                    state.exceeded[idx] = true;
+                    self.inner.event_bus.publish(Event::QuotaExceeded {
+                        masked_key: key.masked_key.clone(),
+                        spend: state.spends[idx],
+                        monthly_budget: budget,
+                    });
+                }
+            }
+
+            state.month.clone()
+        };
+
+        let key_id = self.inner.keys[idx].key_id.clone();
+        self.save_month_spend(key_id, month, cost).await;
+    }
+
+    /// Adds the spend to the OpenAI-compatible provider of the base URL,
+    /// which is kept apart from the keys since it has no budget.
+    pub async fn record_provider_spend(&self, base_url: &str, cost: f64) {
+        if cost <= 0.0 {
+            return;
+        }
+        let month = {
+            let mut state = self.inner.state.lock().unwrap();
+            self.roll_month_if_needed(&mut state);
+            state.month.clone()
+        };
+        let key_id = format!("provider:{}", base_url);
+        self.save_month_spend(key_id, month, cost).await;
+    }
+
+    async fn save_month_spend(&self, key_id: String, month: String, cost: f64) {
+        let res = self
+            .inner
+            .db_mgr
+            .enqueue_work(move |conn| {
+                let sql = "INSERT OR REPLACE INTO api_key_spend VALUES (?, ?, COALESCE((SELECT cost FROM api_key_spend WHERE key_id = ? AND month = ?), 0) + ?);";
+                let res = conn.execute(sql, (&key_id, &month, &key_id, &month, cost));
+                if let Err(err) = res {
+                    error!("Failed to record key spend: {}", err);
+                }
+            })
+            .await;
+        if let Err(err) = res {
+            error!("Failed to record key spend: {}", err);
+        }
+    }
+
+    /// Returns the masked keys and their clients.
+    pub fn clients(&self) -> Vec<(String, Client)> {
+        self.inner
+            .keys
+            .iter()
+            .map(|key| (key.masked_key.clone(), key.client.clone()))
+            .collect()
+    }
+
+    pub fn statuses(&self) -> Vec<KeyStatus> {
+        let mut state = self.inner.state.lock().unwrap();
+        self.roll_month_if_needed(&mut state);
+
+        self.inner
+            .keys
+            .iter()
+            .zip(state.spends.iter())
+            .map(|(key, spend)| KeyStatus {
+                masked_key: key.masked_key.clone(),
+                spend: *spend,
+                monthly_budget: key.monthly_budget,
+            })
+            .collect()
+    }
+
+    fn roll_month_if_needed(&self, state: &mut KeyPoolState) {
+        let month = current_month(&self.inner.config);
+        if state.month != month {
+            state.month = month;
+            state.spends.iter_mut().for_each(|spend| *spend = 0.0);
+            state
+                .alerted
+                .iter_mut()
+                .for_each(|alerted| *alerted = false);
+            state
+                .exceeded
+                .iter_mut()
+                .for_each(|exceeded| *exceeded = false);
+        }
+    }
+
+    /// Moves the spends recorded under the masked keys, which were used to
+    /// identify the keys, to the ids of the keys. The masked keys shared by
+    /// several keys in the pool are ambiguous, and left as they are.
+    async fn migrate_masked_key_ids(
+        db_mgr: &DatabaseManager,
+        keys: &[PooledKey],
+    ) -> Result<(), Error> {
+        let ids: Vec<_> = keys
+            .iter()
+            .filter(|key| {
+                keys.iter()
+                    .filter(|other| other.masked_key == key.masked_key)
+                    .count()
+                    == 1
+            })
+            .map(|key| (key.masked_key.clone(), key.key_id.clone()))
+            .collect();
+        db_mgr
+            .write(move |conn| {
+                for (masked_key, key_id) in ids {
+                    let sql = "UPDATE OR IGNORE api_key_spend SET key_id = ? WHERE key_id = ?";
+                    conn.execute(sql, (key_id, masked_key))?;
+                }
+                Ok::<_, rusqlite::Error>(())
+            })
+            .await??;
+        Ok(())
+    }
+
+    async fn query_spend(
+        db_mgr: &DatabaseManager,
+        key_id: &str,
+        month: &str,
+    ) -> Result<f64, Error> {
+        let key_id = key_id.to_owned();
+        let month = month.to_owned();
+        let spend = db_mgr
+            .query(move |conn| {
+                let sql = "SELECT cost FROM api_key_spend WHERE key_id = ? AND month = ?";
+                conn.query_row(sql, (key_id, month), |row| row.get(0))
+                    .unwrap_or(0.0)
+            })
+            .await?;
+        Ok(spend)
+    }
+}
+
+fn current_month(config: &SharedConfig) -> String {
+    Utc::now()
+        .with_timezone(&config.load().timezone)
+        .format("%Y-%m")
+        .to_string()
+}
+
+/// Returns the id of the key in the database, which is a hash of the
+/// whole key, since the masked keys of different keys may be the same.
+fn key_id(key: &str) -> String {
+    let digest = sha256(key.as_bytes());
+    let hash: String = digest[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("sha256:{}", hash)
+}
+
+/// Masks the key so that only the prefix and the last 4 characters
+/// are shown.
+fn mask_key(key: &str) -> String {
+    let chars: Vec<_> = key.chars().collect();
+    if chars.len() <= 8 {
+        return "*".repeat(chars.len());
+    }
+    let prefix: String = chars[..3].iter().collect();
+    let suffix: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}...{}", prefix, suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::InMemDatabaseProvider;
+
+    #[test]
+    fn test_mask_key() {
+        assert_eq!(mask_key("sk-abcdefghijklmnopqrstuvwxyz"), "sk-...wxyz");
+        assert_eq!(mask_key("short"), "*****");
+    }
+
+    #[test]
+    fn test_key_id() {
+        let id = key_id("sk-abcdefghijklmnopqrstuvwxyz");
+        assert_eq!(id, key_id("sk-abcdefghijklmnopqrstuvwxyz"));
+        assert_ne!(id, key_id("sk-zyxwvutsrqponmlkjihgfedwxyz"));
+        assert!(!id.contains("wxyz"));
+    }
+
+    #[tokio::test]
+    async fn test_record_provider_spend() {
+        let config =
+            serde_json::from_str(r#"{"botToken": "", "openaiAPIKey": "sk-test"}"#).unwrap();
+        let config = SharedConfig::new(config);
+        let db_mgr = DatabaseManager::with_db_provider(InMemDatabaseProvider).unwrap();
+        let key_pool = KeyPool::new(db_mgr.clone(), EventBus::new(), config.clone())
+            .await
+            .unwrap();
+
+        key_pool.record_spend(0, 0.25).await;
+        key_pool
+            .record_provider_spend("http://localhost:11434/v1", 0.5)
+            .await;
+        assert_eq!(key_pool.statuses()[0].spend, 0.25);
+
+        let month = current_month(&config);
+        let spend = KeyPool::query_spend(&db_mgr, "provider:http://localhost:11434/v1", &month)
+            .await
+            .unwrap();
+        assert_eq!(spend, 0.5);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_masked_key_ids() {
+        let config = serde_json::from_str(
+            r#"{"botToken": "", "openaiAPIKey": "sk-abcdefghijklmnopqrstuvwxyz"}"#,
+        )
+        .unwrap();
+        let config = SharedConfig::new(config);
+        let db_mgr = DatabaseManager::with_db_provider(InMemDatabaseProvider).unwrap();
+        let month = current_month(&config);
+        let sql_month = month.clone();
+        db_mgr
+            .write(move |conn| {
+                let sql = "INSERT INTO api_key_spend VALUES ('sk-...wxyz', ?, 1.5)";
+                conn.execute(sql, (sql_month,))
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        let key_pool = KeyPool::new(db_mgr.clone(), EventBus::new(), config)
+            .await
+            .unwrap();
+        assert_eq!(key_pool.statuses()[0].spend, 1.5);
+        let spend = KeyPool::query_spend(&db_mgr, "sk-...wxyz", &month)
+            .await
+            .unwrap();
+        assert_eq!(spend, 0.0);
+    }
+}
@@ -0,0 +1,520 @@
+mod backend;
+mod key_pool;
+mod moderation;
+mod openai_client;
+mod response_cache;
+mod sampling;
+mod scheduler;
+mod speech;
+mod stream_dump;
+mod tokenizer;
+mod vision;
+
+use std::sync::Arc;
+
+use anyhow::Error;
+use teloxide::dispatching::DpHandlerDescription;
+use teloxide::dptree::di::DependencySupplier;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, User};
+
+use crate::{
+    config::{ProviderConfig, SharedConfig},
+    conversation::{Conversation, ConversationManager},
+    database::DatabaseManager,
+    dispatcher::noop_handler,
+    event_bus::EventBus,
+    module_mgr::{Command, Module},
+    modules::{
+        admin::{is_admin, MemberManager},
+        prefs::PreferencesManager,
+    },
+    types::HandlerResult,
+    utils::{auto_delete::schedule_deletion, dptree_ext::CommandArgs, i18n::user_language},
+};
+pub(crate) use openai_client::{
+    model_button_id, ChatModelParams, ChatModelResult, OpenAIClient, CHAT_MODEL_PREF_KEY,
+};
+pub(crate) use sampling::SAMPLING_PREF_KEY;
+use sampling::{parameter_presets, SamplingParams};
+pub(crate) use scheduler::CompletionScheduler;
+
+pub(crate) async fn is_allowed_member(
+    user: &User,
+    member_mgr: &MemberManager,
+    config: &SharedConfig,
+) -> bool {
+    if is_admin(user, config) {
+        return true;
+    }
+
+    member_mgr
+        .is_member_allowed(user.username.clone().unwrap_or_default())
+        .await
+        .unwrap_or(false)
+}
+
+pub(crate) async fn can_select_model(
+    user: Option<&User>,
+    member_mgr: &MemberManager,
+    config: &SharedConfig,
+) -> bool {
+    let user = match user {
+        Some(user) => user,
+        None => return false,
+    };
+
+    if config.load().model_selection_admin_only && !is_admin(user, config) {
+        return false;
+    }
+    is_allowed_member(user, member_mgr, config).await
+}
+
+fn make_models_keyboard(models: &[String], current_model: &str) -> InlineKeyboardMarkup {
+    models
+        .iter()
+        .fold(InlineKeyboardMarkup::default(), |keyboard, model| {
+            let title = if model == current_model {
+                format!("✓ {}", model)
+            } else {
+                model.to_owned()
+            };
+            keyboard.append_row([InlineKeyboardButton::callback(
+                title,
+                format!("/model:{}", model_button_id(model)),
+            )])
+        })
+}
+
+async fn switch_model(
+    chat_id: &str,
+    model: &str,
+    openai_client: &OpenAIClient,
+    prefs_mgr: &PreferencesManager,
+) -> String {
+    if !openai_client.is_model_available(model) {
+        return "The model is not available.".to_owned();
+    }
+
+    match prefs_mgr
+        .set_chat_value(chat_id, CHAT_MODEL_PREF_KEY, &Some(model.to_owned()))
+        .await
+    {
+        Ok(_) => format!("Success, current model: {}", model),
+        Err(err) => {
+            error!("Failed to set chat model: {}", err);
+            "Failed to switch model, internal error occurred".to_owned()
+        }
+    }
+}
+
+async fn show_models(
+    bot: Bot,
+    msg: Message,
+    args: CommandArgs,
+    openai_client: OpenAIClient,
+    prefs_mgr: PreferencesManager,
+    member_mgr: MemberManager,
+    config: SharedConfig,
+) -> HandlerResult {
+    if !can_select_model(msg.from(), &member_mgr, &config).await {
+        let sent_msg = bot
+            .send_message(msg.chat.id, "You are not allowed to switch models")
+            .reply_to_message_id(msg.id)
+            .await?;
+        schedule_deletion(&bot, &sent_msg, &config);
+        return Ok(());
+    }
+
+    let chat_id = msg.chat.id.to_string();
+
+    // Switch directly if the model is given, e.g. picked from inline query.
+    let model = args.0.trim();
+    if !model.is_empty() {
+        let reply_text = switch_model(&chat_id, model, &openai_client, &prefs_mgr).await;
+        bot.send_message(msg.chat.id, reply_text)
+            .reply_to_message_id(msg.id)
+            .await?;
+        return Ok(());
+    }
+
+    let current_model = openai_client.chat_model(Some(&chat_id)).await;
+    let keyboard = make_models_keyboard(&openai_client.available_models(), &current_model);
+    bot.send_message(
+        msg.chat.id,
+        format!(
+            "Current model: {}\nSelect a model for this chat:",
+            current_model
+        ),
+    )
+    .reply_markup(keyboard)
+    .reply_to_message_id(msg.id)
+    .await?;
+
+    Ok(())
+}
+
+async fn handle_select_model_action(
+    bot: Bot,
+    query: CallbackQuery,
+    openai_client: OpenAIClient,
+    prefs_mgr: PreferencesManager,
+    member_mgr: MemberManager,
+    config: SharedConfig,
+) -> bool {
+    let model_id = query
+        .data
+        .as_ref()
+        .and_then(|data| data.strip_prefix("/model:"));
+    let model_id = match model_id {
+        Some(model_id) => model_id.to_owned(),
+        None => return false,
+    };
+
+    let message = match query.message {
+        Some(message) => message,
+        None => return false,
+    };
+
+    if !can_select_model(Some(&query.from), &member_mgr, &config).await {
+        let _ = bot
+            .answer_callback_query(query.id)
+            .text("You are not allowed to switch models")
+            .await;
+        return true;
+    }
+
+    let chat_id = message.chat.id.to_string();
+    let reply_text = match openai_client.find_model_by_button_id(&model_id) {
+        Some(model) => switch_model(&chat_id, &model, &openai_client, &prefs_mgr).await,
+        None => "The model is not available.".to_owned(),
+    };
+    let _ = bot
+        .edit_message_text(message.chat.id, message.id, reply_text)
+        .await;
+    let _ = bot.answer_callback_query(query.id).await;
+
+    true
+}
+
+fn make_presets_keyboard(
+    presets: &[(String, SamplingParams)],
+    current_params: &SamplingParams,
+) -> InlineKeyboardMarkup {
+    presets.iter().fold(
+        InlineKeyboardMarkup::default(),
+        |keyboard, (name, params)| {
+            let title = if params == current_params {
+                format!("✓ {}", name)
+            } else {
+                name.to_owned()
+            };
+            keyboard.append_row([InlineKeyboardButton::callback(
+                title,
+                format!("/preset:{}", name),
+            )])
+        },
+    )
+}
+
+async fn apply_preset(
+    chat_id: &str,
+    name: &str,
+    prefs_mgr: &PreferencesManager,
+    config: &SharedConfig,
+) -> String {
+    let params = match parameter_presets(&config.load().parameter_presets)
+        .into_iter()
+        .find(|(n, _)| n == name)
+    {
+        Some((_, params)) => params,
+        None => return format!("Unknown preset \"{}\".", name),
+    };
+
+    match prefs_mgr
+        .set_chat_value(chat_id, SAMPLING_PREF_KEY, &params)
+        .await
+    {
+        Ok(_) => format!("Success, current preset: {}\n{}", name, params),
+        Err(err) => {
+            error!("Failed to set sampling params: {}", err);
+            "Failed to apply the preset, internal error occurred".to_owned()
+        }
+    }
+}
+
+async fn show_presets(
+    bot: Bot,
+    msg: Message,
+    args: CommandArgs,
+    openai_client: OpenAIClient,
+    prefs_mgr: PreferencesManager,
+    member_mgr: MemberManager,
+    config: SharedConfig,
+) -> HandlerResult {
+    let allowed = match msg.from() {
+        Some(user) => is_allowed_member(user, &member_mgr, &config).await,
+        None => false,
+    };
+    if !allowed {
+        let sent_msg = bot
+            .send_message(
+                msg.chat.id,
+                &config
+                    .load()
+                    .i18n_strings(user_language(&msg))
+                    .not_allowed_prompt,
+            )
+            .reply_to_message_id(msg.id)
+            .await?;
+        schedule_deletion(&bot, &sent_msg, &config);
+        return Ok(());
+    }
+
+    let chat_id = msg.chat.id.to_string();
+
+    let name = args.0.trim();
+    if !name.is_empty() {
+        let reply_text = apply_preset(&chat_id, name, &prefs_mgr, &config).await;
+        bot.send_message(msg.chat.id, reply_text)
+            .reply_to_message_id(msg.id)
+            .await?;
+        return Ok(());
+    }
+
+    let current_params = openai_client.chat_sampling_params(&chat_id).await;
+    let keyboard = make_presets_keyboard(
+        &parameter_presets(&config.load().parameter_presets),
+        &current_params,
+    );
+    bot.send_message(
+        msg.chat.id,
+        format!(
+            "Settings of this chat:\n{}\n\nSelect a preset for this chat:",
+            current_params
+        ),
+    )
+    .reply_markup(keyboard)
+    .reply_to_message_id(msg.id)
+    .await?;
+
+    Ok(())
+}
+
+async fn handle_select_preset_action(
+    bot: Bot,
+    query: CallbackQuery,
+    prefs_mgr: PreferencesManager,
+    member_mgr: MemberManager,
+    config: SharedConfig,
+) -> bool {
+    let name = query
+        .data
+        .as_ref()
+        .and_then(|data| data.strip_prefix("/preset:"));
+    let name = match name {
+        Some(name) => name.to_owned(),
+        None => return false,
+    };
+
+    let message = match query.message {
+        Some(message) => message,
+        None => return false,
+    };
+
+    if !is_allowed_member(&query.from, &member_mgr, &config).await {
+        let _ = bot
+            .answer_callback_query(query.id)
+            .text("You are not allowed to change the settings")
+            .await;
+        return true;
+    }
+
+    let chat_id = message.chat.id.to_string();
+    let reply_text = apply_preset(&chat_id, &name, &prefs_mgr, &config).await;
+    let _ = bot
+        .edit_message_text(message.chat.id, message.id, reply_text)
+        .await;
+    let _ = bot.answer_callback_query(query.id).await;
+
+    true
+}
+
+/// The state of an ongoing `/settings` conversation.
+#[derive(Clone, Copy)]
+struct SettingsState;
+
+const SETTINGS_USAGE: &str = "Send \"<setting> <value>\" to change a setting, \"<setting> default\" to restore it, \"reset\" to restore all settings, or \"done\" to finish.";
+
+async fn show_settings(
+    bot: Bot,
+    msg: Message,
+    openai_client: OpenAIClient,
+    member_mgr: MemberManager,
+    conversation_mgr: ConversationManager,
+    config: SharedConfig,
+) -> HandlerResult {
+    let user = match msg.from() {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+    if !is_allowed_member(user, &member_mgr, &config).await {
+        let sent_msg = bot
+            .send_message(
+                msg.chat.id,
+                &config
+                    .load()
+                    .i18n_strings(user_language(&msg))
+                    .not_allowed_prompt,
+            )
+            .reply_to_message_id(msg.id)
+            .await?;
+        schedule_deletion(&bot, &sent_msg, &config);
+        return Ok(());
+    }
+
+    let params = openai_client
+        .chat_sampling_params(&msg.chat.id.to_string())
+        .await;
+    bot.send_message(
+        msg.chat.id,
+        format!("Settings of this chat:\n{}\n\n{}", params, SETTINGS_USAGE),
+    )
+    .reply_to_message_id(msg.id)
+    .await?;
+
+    conversation_mgr.start_conversation(
+        msg.chat.id,
+        Some(user.id),
+        SettingsState,
+        dptree::filter(|msg: Message| {
+            msg.text()
+                .map(|text| !text.starts_with('/'))
+                .unwrap_or(false)
+        })
+        .endpoint(handle_settings_input),
+    );
+
+    Ok(())
+}
+
+async fn handle_settings_input(
+    bot: Bot,
+    msg: Message,
+    conversation: Conversation<SettingsState>,
+    openai_client: OpenAIClient,
+    prefs_mgr: PreferencesManager,
+) -> HandlerResult {
+    // Mentions are required to talk to the bot in groups, skip them.
+    let words: Vec<_> = msg
+        .text()
+        .unwrap_or_default()
+        .split_whitespace()
+        .filter(|word| !word.starts_with('@'))
+        .collect();
+
+    let chat_id = msg.chat.id.to_string();
+    let mut params = openai_client.chat_sampling_params(&chat_id).await;
+    let result = match words.as_slice() {
+        ["done"] => {
+            conversation.end();
+            bot.send_message(msg.chat.id, "Settings are saved.")
+                .reply_to_message_id(msg.id)
+                .await?;
+            return Ok(());
+        }
+        ["reset"] => {
+            params = Default::default();
+            Ok(())
+        }
+        [name, "default"] => params.set(name, None),
+        [name, value] => match value.parse() {
+            Ok(value) => params.set(name, Some(value)),
+            Err(_) => Err(format!("\"{}\" is not a valid number", value)),
+        },
+        _ => Err(SETTINGS_USAGE.to_owned()),
+    };
+
+    let reply_text = match result {
+        Ok(_) => match prefs_mgr
+            .set_chat_value(&chat_id, SAMPLING_PREF_KEY, &params)
+            .await
+        {
+            Ok(_) => format!("Updated:\n{}", params),
+            Err(err) => {
+                error!("Failed to set sampling params: {}", err);
+                "Failed to update settings, internal error occurred".to_owned()
+            }
+        },
+        Err(err_msg) => err_msg,
+    };
+    bot.send_message(msg.chat.id, reply_text)
+        .reply_to_message_id(msg.id)
+        .await?;
+
+    Ok(())
+}
+
+pub(crate) struct OpenAI {
+    db_mgr: DatabaseManager,
+}
+
+impl OpenAI {
+    pub(crate) fn new(db_mgr: DatabaseManager) -> Self {
+        Self { db_mgr }
+    }
+}
+
+#[async_trait]
+impl Module for OpenAI {
+    async fn register_dependency(&mut self, dep_map: &mut DependencyMap) -> Result<(), Error> {
+        let prefs_mgr: Arc<PreferencesManager> = dep_map.get();
+        let event_bus: Arc<EventBus> = dep_map.get();
+        let config: Arc<SharedConfig> = dep_map.get();
+
+        let openai_client = OpenAIClient::new(
+            self.db_mgr.clone(),
+            prefs_mgr.as_ref().clone(),
+            event_bus.as_ref().clone(),
+            config.as_ref().clone(),
+        )
+        .await?;
+        // Models of other providers can't be listed with the OpenAI API.
+        if config.load().validate_models && matches!(config.load().provider, ProviderConfig::OpenAI)
+        {
+            openai_client.validate_models().await?;
+        }
+        dep_map.insert(openai_client.scheduler().clone());
+        dep_map.insert(openai_client);
+
+        Ok(())
+    }
+
+    fn filter_handler(
+        &self,
+    ) -> Handler<'static, DependencyMap, HandlerResult, DpHandlerDescription> {
+        Update::filter_callback_query()
+            .branch(dptree::filter_async(handle_select_model_action).endpoint(noop_handler))
+            .branch(dptree::filter_async(handle_select_preset_action).endpoint(noop_handler))
+    }
+
+    fn commands(&self) -> Vec<Command> {
+        vec![
+            Command::new(
+                "model",
+                "Switch the model used in this chat",
+                dptree::endpoint(show_models),
+            ),
+            Command::new(
+                "settings",
+                "Adjust the sampling parameters of this chat",
+                dptree::endpoint(show_settings),
+            ),
+            Command::new(
+                "preset",
+                "Apply a parameter preset (creative, precise, balanced...) to this chat",
+                dptree::endpoint(show_presets),
+            ),
+        ]
+    }
+}
@@ -0,0 +1,34 @@
+use anyhow::Error;
+use async_openai::types::{Category, CreateModerationRequestArgs, ModerationInput};
+use async_openai::Client;
+
+/// Classifies the text with the moderation endpoint, and returns the
+/// flagged categories (empty if the text is not flagged).
+pub(crate) async fn moderate_text(client: &Client, text: &str) -> Result<Vec<String>, Error> {
+    let req = CreateModerationRequestArgs::default()
+        .input(ModerationInput::String(text.to_owned()))
+        .build()?;
+    let resp = client.moderations().create(req).await?;
+    Ok(resp
+        .results
+        .iter()
+        .filter(|result| result.flagged)
+        .flat_map(|result| flagged_categories(&result.categories))
+        .map(str::to_owned)
+        .collect())
+}
+
+fn flagged_categories(categories: &Category) -> Vec<&'static str> {
+    [
+        ("hate", categories.hate),
+        ("hate/threatening", categories.hate_threatening),
+        ("self-harm", categories.self_harm),
+        ("sexual", categories.sexual),
+        ("sexual/minors", categories.sexual_minors),
+        ("violence", categories.violence),
+        ("violence/graphic", categories.violence_graphic),
+    ]
+    .into_iter()
+    .filter_map(|(name, flagged)| flagged.then_some(name))
+    .collect()
+}
@@ -0,0 +1,543 @@
+use std::collections::{HashMap, HashSet};
+use std::pin::Pin;
+
+use anyhow::Error;
+use async_openai::types::{
+    AudioInput, ChatCompletionRequestMessage, CreateChatCompletionRequestArgs,
+    CreateTranscriptionRequestArgs, Stop,
+};
+use futures::{future, stream, Stream, StreamExt};
+
+use super::backend::{account_headers, ChatBackend, CompatibleBackend, OpenAIBackend};
+use super::key_pool::{KeyPool, KeyStatus};
+use super::moderation::moderate_text;
+use super::response_cache::{cache_key, ResponseCache};
+use super::sampling::{SamplingParams, SAMPLING_PREF_KEY};
+use super::scheduler::CompletionScheduler;
+use super::speech::create_speech;
+use super::vision::IMAGE_TOKENS_ESTIMATE;
+use super::{stream_dump::StreamDump, tokenizer};
+use crate::{
+    config::{ProviderConfig, SharedConfig},
+    database::DatabaseManager,
+    event_bus::EventBus,
+    modules::prefs::PreferencesManager,
+    telemetry::hash_chat_id,
+    utils::http,
+};
+
+pub(crate) const CHAT_MODEL_PREF_KEY: &str = "ChatModel";
+
+pub(crate) type ChatModelStream = Pin<Box<dyn Stream<Item = ChatModelResult> + Send>>;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct ChatModelResult {
+    pub content: String,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub model: String,
+    /// The backend that served the request, whose spend the cost is
+    /// recorded under.
+    pub served_by: ServedBy,
+    /// `true` if the answer is served from the response cache.
+    pub cached: bool,
+    /// The key to cache the answer with, if the request can be cached.
+    pub cache_key: Option<String>,
+    /// Why the model stopped, e.g. `stop`, `length` or `content_filter`.
+    /// [`None`] if the stream ends without a reason.
+    pub finish_reason: Option<String>,
+    /// The error that ended the stream, if any.
+    pub error: Option<String>,
+}
+
+/// The backend that served a chat completion.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) enum ServedBy {
+    /// The OpenAI API, with the key of the index in the pool.
+    Key(usize),
+    /// The OpenAI-compatible provider of the base URL.
+    Provider(String),
+    /// The response cache, which costs nothing.
+    #[default]
+    Cache,
+}
+
+impl ChatModelResult {
+    pub fn token_usage(&self) -> u32 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+/// Returns a short id of the model, which is stable across releases. The
+/// ids are put in the data of buttons instead of the names of the models,
+/// which may exceed its limit of 64 bytes.
+pub(crate) fn model_button_id(model: &str) -> String {
+    let hash = model.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// Per-request parameters that override the defaults from config.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ChatModelParams {
+    pub model: Option<String>,
+    pub max_tokens: Option<u16>,
+    pub stop_sequences: Option<Vec<String>>,
+    pub logit_bias: Option<HashMap<String, i32>>,
+    /// URLs (or data URLs) of images attached to the last user message,
+    /// which requires a vision model.
+    pub image_urls: Vec<String>,
+    /// Serves the request from the response cache if it's enabled.
+    pub use_cache: bool,
+    /// Overrides the temperature of the chat.
+    pub temperature: Option<f32>,
+}
+
+#[derive(Clone)]
+pub(crate) struct OpenAIClient {
+    key_pool: KeyPool,
+    prefs_mgr: PreferencesManager,
+    response_cache: ResponseCache,
+    scheduler: CompletionScheduler,
+    config: SharedConfig,
+}
+
+impl OpenAIClient {
+    pub(crate) async fn new(
+        db_mgr: DatabaseManager,
+        prefs_mgr: PreferencesManager,
+        event_bus: EventBus,
+        config: SharedConfig,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            key_pool: KeyPool::new(db_mgr.clone(), event_bus, config.clone()).await?,
+            prefs_mgr,
+            response_cache: ResponseCache::new(db_mgr),
+            scheduler: CompletionScheduler::new(config.load().max_concurrent_completions),
+            config,
+        })
+    }
+
+    /// Returns the scheduler that limits the concurrent chat completions.
+    pub(crate) fn scheduler(&self) -> &CompletionScheduler {
+        &self.scheduler
+    }
+
+    #[tracing::instrument(
+        name = "openai.request",
+        skip_all,
+        fields(
+            chat.id_hash = chat_id.map(hash_chat_id),
+            model = tracing::field::Empty,
+            prompt_tokens = tracing::field::Empty,
+            max_tokens = tracing::field::Empty,
+            cached = false,
+        )
+    )]
+    pub(crate) async fn request_chat_model(
+        &self,
+        chat_id: Option<&str>,
+        msgs: Vec<ChatCompletionRequestMessage>,
+        params: ChatModelParams,
+    ) -> Result<ChatModelStream, Error> {
+        let (key_index, client) = self.key_pool.pick();
+        let model = match params.model {
+            Some(model) => model,
+            None => self.chat_model(chat_id).await,
+        };
+
+        // Make sure the answer can fit in the rest of the context window.
+        let prompt_tokens = self.count_prompt_tokens(&model, &msgs)
+            + params.image_urls.len() as u32 * IMAGE_TOKENS_ESTIMATE;
+        let prompt_tokens = prompt_tokens as usize;
+        let available_tokens = tokenizer::context_size(&model).saturating_sub(prompt_tokens);
+        if available_tokens == 0 {
+            return Err(anyhow!("The prompt exceeds the context window"));
+        }
+        let available_tokens = available_tokens.min(u16::MAX as usize) as u16;
+        let max_tokens = params
+            .max_tokens
+            .or(self.config.load().max_tokens)
+            .map(|t| t.min(available_tokens))
+            .unwrap_or(available_tokens);
+        let span = tracing::Span::current();
+        span.record("model", model.as_str());
+        span.record("prompt_tokens", prompt_tokens);
+        span.record("max_tokens", max_tokens);
+        let mut stream_dump = self.config.load().stream_dump_dir.as_ref().and_then(|dir| {
+            StreamDump::create(dir, chat_id.unwrap_or("unknown"), &model)
+                .map_err(|err| error!("Failed to create stream dump: {}", err))
+                .ok()
+        });
+
+        let sampling = match chat_id {
+            Some(chat_id) => self.chat_sampling_params(chat_id).await,
+            None => SamplingParams::default(),
+        }
+        .or(self.default_sampling_params());
+        let sampling = SamplingParams {
+            temperature: params.temperature.or(sampling.temperature),
+            ..sampling
+        };
+
+        let mut req_args = CreateChatCompletionRequestArgs::default();
+        req_args.model(&model).max_tokens(max_tokens).messages(msgs);
+        if let Some(temperature) = sampling.temperature {
+            req_args.temperature(temperature);
+        }
+        if let Some(top_p) = sampling.top_p {
+            req_args.top_p(top_p);
+        }
+        if let Some(presence_penalty) = sampling.presence_penalty {
+            req_args.presence_penalty(presence_penalty);
+        }
+        if let Some(frequency_penalty) = sampling.frequency_penalty {
+            req_args.frequency_penalty(frequency_penalty);
+        }
+
+        let stop_sequences = params
+            .stop_sequences
+            .unwrap_or_else(|| self.config.load().stop_sequences.clone());
+        if !stop_sequences.is_empty() {
+            req_args.stop(Stop::StringArray(stop_sequences));
+        }
+
+        let logit_bias = params
+            .logit_bias
+            .unwrap_or_else(|| self.config.load().logit_bias.clone());
+        if !logit_bias.is_empty() {
+            req_args.logit_bias(
+                logit_bias
+                    .into_iter()
+                    .map(|(token, bias)| (token, bias.into()))
+                    .collect::<HashMap<_, _>>(),
+            );
+        }
+
+        let req = req_args.build()?;
+
+        // Images are not part of the request yet, so the requests with
+        // images are never cached.
+        let cache_key = match &self.config.load().response_cache {
+            Some(cache_config) if params.use_cache && params.image_urls.is_empty() => {
+                let key = cache_key(&req)?;
+                let ttl_secs = cache_config.ttl_minutes * 60;
+                match self.response_cache.get(&key, ttl_secs).await {
+                    Ok(Some(content)) => {
+                        debug!("Serving the answer from the response cache");
+                        tracing::Span::current().record("cached", true);
+                        let res = ChatModelResult {
+                            content,
+                            model,
+                            cached: true,
+                            ..Default::default()
+                        };
+                        return Ok(stream::once(future::ready(res)).boxed());
+                    }
+                    Ok(None) => {}
+                    Err(err) => error!("Failed to query the response cache: {}", err),
+                }
+                Some(key)
+            }
+            _ => None,
+        };
+
+        let (backend, served_by): (Box<dyn ChatBackend>, _) = match &self.config.load().provider {
+            ProviderConfig::OpenAI => (
+                Box::new(OpenAIBackend {
+                    client,
+                    headers: account_headers(&self.config.load()),
+                }),
+                ServedBy::Key(key_index),
+            ),
+            ProviderConfig::OpenAICompatible(provider) => (
+                Box::new(CompatibleBackend::new(provider)),
+                ServedBy::Provider(provider.base_url.clone()),
+            ),
+        };
+        let stream = backend.chat(req, &params.image_urls).await?;
+        Ok(stream
+            .inspect(move |item| {
+                if let Some(stream_dump) = stream_dump.as_mut() {
+                    stream_dump.record(item);
+                }
+            })
+            .scan(
+                ChatModelResult {
+                    model,
+                    served_by,
+                    cache_key,
+                    ..Default::default()
+                },
+                |acc, cur| {
+                    if let Err(err) = &cur {
+                        warn!("Error in the stream of {}: {}", acc.model, err);
+                        acc.error = Some(err.to_string());
+                    }
+                    let choice = cur.as_ref().ok().and_then(|resp| resp.choices.first());
+                    if let Some(content) = choice.and_then(|choice| choice.delta.content.as_ref()) {
+                        acc.content.push_str(content);
+                    }
+                    if let Some(finish_reason) =
+                        choice.and_then(|choice| choice.finish_reason.as_ref())
+                    {
+                        acc.finish_reason = Some(finish_reason.clone());
+                    }
+                    future::ready(Some(acc.clone()))
+                },
+            )
+            .boxed())
+    }
+
+    /// Transcribes the audio with the transcription model. `file_name` is
+    /// used to name the temporary file, and its extension tells the API
+    /// the format of the audio.
+    pub(crate) async fn transcribe(&self, file_name: &str, audio: &[u8]) -> Result<String, Error> {
+        // The API client only uploads files from the disk.
+        let path = std::env::temp_dir().join(format!("telegpt-{}", file_name));
+        tokio::fs::write(&path, audio).await?;
+
+        let (_, client) = self.key_pool.pick();
+        let req = CreateTranscriptionRequestArgs::default()
+            .file(AudioInput { path: path.clone() })
+            .model(&self.config.load().transcription_model)
+            .build()?;
+        let res = client.audio().transcribe(req).await;
+
+        if let Err(err) = tokio::fs::remove_file(&path).await {
+            warn!("Failed to remove the temporary audio file: {}", err);
+        }
+        Ok(res?.text.trim().to_owned())
+    }
+
+    /// Generates the speech of the text with the TTS model, in OGG with Opus.
+    pub(crate) async fn synthesize_speech(&self, text: &str) -> Result<Vec<u8>, Error> {
+        let (_, client) = self.key_pool.pick();
+        let config = self.config.load();
+        create_speech(
+            &client,
+            &account_headers(&config),
+            &config.tts_model,
+            &config.tts_voice,
+            text,
+        )
+        .await
+    }
+
+    /// Classifies the text with the moderation endpoint of OpenAI, and
+    /// returns the flagged categories (empty if the text is not flagged).
+    pub(crate) async fn moderate(&self, text: &str) -> Result<Vec<String>, Error> {
+        let (_, client) = self.key_pool.pick();
+        moderate_text(&client, text).await
+    }
+
+    /// Records the usage of a finished request into the spend of the backend
+    /// that served it, and returns the estimated cost in USD.
+    pub(crate) async fn record_usage(&self, res: &ChatModelResult) -> f64 {
+        let cost = self
+            .config
+            .load()
+            .model_pricing
+            .get(&res.model)
+            .map(|pricing| pricing.cost(res.prompt_tokens, res.completion_tokens))
+            .unwrap_or(0.0);
+        match &res.served_by {
+            ServedBy::Key(idx) => self.key_pool.record_spend(*idx, cost).await,
+            ServedBy::Provider(base_url) => {
+                self.key_pool.record_provider_spend(base_url, cost).await
+            }
+            ServedBy::Cache => {}
+        }
+        cost
+    }
+
+    /// Caches the answer if it's requested with the cache enabled.
+    pub(crate) async fn cache_response(&self, res: &ChatModelResult) {
+        let ttl_minutes = self
+            .config
+            .load()
+            .response_cache
+            .as_ref()
+            .map(|cache_config| cache_config.ttl_minutes);
+        if let (Some(ttl_minutes), Some(key)) = (ttl_minutes, &res.cache_key) {
+            let res = self
+                .response_cache
+                .put(key.clone(), res.content.clone(), ttl_minutes * 60)
+                .await;
+            if let Err(err) = res {
+                error!("Failed to cache the response: {}", err);
+            }
+        }
+    }
+
+    /// Drops all the cached answers, and returns the number of them.
+    pub(crate) async fn clear_response_cache(&self) -> Result<usize, Error> {
+        self.response_cache.clear().await
+    }
+
+    pub(crate) fn key_statuses(&self) -> Vec<KeyStatus> {
+        self.key_pool.statuses()
+    }
+
+    /// Returns the sampling parameters overridden by the given chat.
+    pub(crate) async fn chat_sampling_params(&self, chat_id: &str) -> SamplingParams {
+        self.prefs_mgr
+            .get_chat_value(chat_id, SAMPLING_PREF_KEY)
+            .await
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn default_sampling_params(&self) -> SamplingParams {
+        let config = self.config.load();
+        SamplingParams {
+            temperature: Some(config.temperature),
+            top_p: config.top_p,
+            presence_penalty: config.presence_penalty,
+            frequency_penalty: config.frequency_penalty,
+        }
+    }
+
+    /// Returns the model used by the given chat, which is either the
+    /// per-chat override or the default model in config.
+    pub(crate) async fn chat_model(&self, chat_id: Option<&str>) -> String {
+        if let Some(chat_id) = chat_id {
+            let chat_model: Option<String> = self
+                .prefs_mgr
+                .get_chat_value(chat_id, CHAT_MODEL_PREF_KEY)
+                .await
+                .unwrap_or_default();
+            if let Some(chat_model) = chat_model.filter(|m| self.is_model_available(m)) {
+                return chat_model;
+            }
+        }
+        self.config.load().openai_gpt_model.clone()
+    }
+
+    pub(crate) fn available_models(&self) -> Vec<String> {
+        let config = self.config.load();
+        if config.available_models.is_empty() {
+            vec![config.openai_gpt_model.clone()]
+        } else {
+            config.available_models.clone()
+        }
+    }
+
+    pub(crate) fn is_model_available(&self, model: &str) -> bool {
+        self.available_models().iter().any(|m| m == model)
+    }
+
+    /// Returns the available model with the id, see [`model_button_id`].
+    pub(crate) fn find_model_by_button_id(&self, id: &str) -> Option<String> {
+        self.available_models()
+            .into_iter()
+            .find(|model| model_button_id(model) == id)
+    }
+
+    /// Returns `true` if the model accepts images.
+    pub(crate) fn supports_image_input(&self, model: &str) -> bool {
+        let model = tokenizer::base_model(model);
+        self.config
+            .load()
+            .vision_models
+            .iter()
+            .any(|prefix| model.starts_with(prefix.as_str()))
+    }
+
+    /// Checks that the chat service is reachable with the credentials.
+    pub(crate) async fn check_connectivity(&self) -> Result<(), Error> {
+        match &self.config.load().provider {
+            ProviderConfig::OpenAI => {
+                let (_, client) = self.key_pool.pick();
+                client.models().list().await?;
+            }
+            ProviderConfig::OpenAICompatible(provider) => {
+                let url = format!("{}/models", provider.base_url.trim_end_matches('/'));
+                let mut req = http::client().get(url);
+                if let Some(api_key) = &provider.api_key {
+                    req = req.bearer_auth(api_key);
+                }
+                req.send().await?.error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the configured models are accessible to every key in
+    /// the pool. Returns an error if the default model is not accessible.
+    pub(crate) async fn validate_models(&self) -> Result<(), Error> {
+        for (masked_key, client) in self.key_pool.clients() {
+            let accessible_models: HashSet<_> = match client.models().list().await {
+                Ok(resp) => resp.data.into_iter().map(|model| model.id).collect(),
+                Err(err) => {
+                    warn!("Failed to list models with key {}: {}", masked_key, err);
+                    continue;
+                }
+            };
+
+            let default_model = &self.config.load().openai_gpt_model;
+            if !accessible_models.contains(default_model) {
+                return Err(anyhow!(
+                    "The model \"{}\" (`openaiGptModel`) is not accessible with key {}",
+                    default_model,
+                    masked_key
+                ));
+            }
+            for model in self.available_models() {
+                if !accessible_models.contains(&model) {
+                    warn!(
+                        "The model \"{}\" is not accessible with key {}",
+                        model, masked_key
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts the tokens of the text with the tokenizer of the model.
+    pub(crate) fn count_tokens(&self, model: &str, text: &str) -> u32 {
+        tokenizer::count_tokens(model, text)
+            .map(|t| t as _)
+            .unwrap_or_else(|| self.estimate_tokens(text))
+    }
+
+    /// Counts the tokens of the prompt of a request with the tokenizer of
+    /// the model, which are the tokens of the messages and the priming of
+    /// the reply.
+    pub(crate) fn count_prompt_tokens(
+        &self,
+        model: &str,
+        msgs: &[ChatCompletionRequestMessage],
+    ) -> u32 {
+        self.count_message_tokens(model, msgs) + tokenizer::REPLY_PRIMING_TOKENS as u32
+    }
+
+    /// Counts the tokens of the messages with the tokenizer of the model,
+    /// including the overhead of the chat format.
+    pub(crate) fn count_message_tokens(
+        &self,
+        model: &str,
+        msgs: &[ChatCompletionRequestMessage],
+    ) -> u32 {
+        tokenizer::count_message_tokens(model, msgs)
+            .map(|t| t as _)
+            .unwrap_or_else(|| {
+                msgs.iter()
+                    .map(|msg| self.estimate_tokens(&msg.content))
+                    .sum()
+            })
+    }
+
+    /// Returns the size of context window of the model.
+    pub(crate) fn context_size(&self, model: &str) -> u32 {
+        tokenizer::context_size(model) as _
+    }
+
+    /// A rough estimation used when there is no tokenizer for the model.
+    fn estimate_tokens(&self, text: &str) -> u32 {
+        let tokens =
+            tokenizer::estimate_tokens(text) * self.config.load().token_estimate_multiplier;
+        tokens.ceil() as _
+    }
+}
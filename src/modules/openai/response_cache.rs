@@ -0,0 +1,132 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use async_openai::types::CreateChatCompletionRequest;
+use openssl::sha::sha256;
+use rusqlite::OptionalExtension;
+
+use crate::database::DatabaseManager;
+
+/// Caches the answers of the chat model in the database, keyed by the
+/// hash of the whole request, so that identical prompts are answered
+/// without calling the API again.
+#[derive(Clone)]
+pub(crate) struct ResponseCache {
+    db_mgr: DatabaseManager,
+}
+
+impl ResponseCache {
+    pub fn new(db_mgr: DatabaseManager) -> Self {
+        Self { db_mgr }
+    }
+
+    /// Returns the cached answer of the key if it's not older than `ttl_secs`.
+    pub async fn get(&self, key: &str, ttl_secs: u64) -> Result<Option<String>, Error> {
+        let key = self.db_mgr.lookup_key(key.to_owned())?;
+        let min_created_at = unix_timestamp_secs() - ttl_secs as i64;
+        let content: Option<String> = self
+            .db_mgr
+            .query(move |conn| {
+                let sql =
+                    "SELECT content FROM response_cache WHERE cache_key = ? AND created_at >= ?";
+                conn.query_row(sql, (key, min_created_at), |row| row.get(0))
+                    .optional()
+            })
+            .await??;
+        content
+            .map(|content| self.db_mgr.unseal(content))
+            .transpose()
+    }
+
+    /// Caches the answer of the key, and drops the entries older than
+    /// `ttl_secs` along the way.
+    pub async fn put(&self, key: String, content: String, ttl_secs: u64) -> Result<(), Error> {
+        let key = self.db_mgr.lookup_key(key)?;
+        let content = self.db_mgr.seal(content)?;
+        let now = unix_timestamp_secs();
+        self.db_mgr
+            .enqueue_work(move |conn| {
+                let res = conn
+                    .execute(
+                        "DELETE FROM response_cache WHERE created_at < ?",
+                        (now - ttl_secs as i64,),
+                    )
+                    .and_then(|_| {
+                        conn.execute(
+                            "INSERT OR REPLACE INTO response_cache VALUES (?, ?, ?)",
+                            (key, content, now),
+                        )
+                    });
+                if let Err(err) = res {
+                    error!("Failed to cache the response: {}", err);
+                }
+            })
+            .await
+    }
+
+    /// Drops all the cached answers, and returns the number of them.
+    pub async fn clear(&self) -> Result<usize, Error> {
+        let count = self
+            .db_mgr
+            .write(|conn| conn.execute("DELETE FROM response_cache", ()))
+            .await??;
+        Ok(count)
+    }
+}
+
+/// Derives the cache key of the request, which is the SHA-256 hash of it,
+/// so that the prompts are not stored in the keys. The whitespaces in the
+/// messages are normalized, so that trivially different prompts share the
+/// answer.
+pub(crate) fn cache_key(req: &CreateChatCompletionRequest) -> Result<String, Error> {
+    let mut req = req.clone();
+    for msg in &mut req.messages {
+        msg.content = msg.content.split_whitespace().collect::<Vec<_>>().join(" ");
+    }
+    let digest = sha256(serde_json::to_string(&req)?.as_bytes());
+    Ok(digest.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+fn unix_timestamp_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use async_openai::types::{
+        ChatCompletionRequestMessageArgs, CreateChatCompletionRequestArgs, Role,
+    };
+
+    use super::*;
+
+    fn request(model: &str, content: &str) -> CreateChatCompletionRequest {
+        CreateChatCompletionRequestArgs::default()
+            .model(model)
+            .messages(vec![ChatCompletionRequestMessageArgs::default()
+                .role(Role::User)
+                .content(content)
+                .build()
+                .unwrap()])
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let key = cache_key(&request("gpt-3.5-turbo", "Hello, world!")).unwrap();
+        assert_eq!(key.len(), 64);
+        assert!(!key.contains("Hello"));
+        assert_eq!(
+            key,
+            cache_key(&request("gpt-3.5-turbo", "  Hello,\n world! ")).unwrap()
+        );
+        assert_ne!(key, cache_key(&request("gpt-4", "Hello, world!")).unwrap());
+        assert_ne!(
+            key,
+            cache_key(&request("gpt-3.5-turbo", "Hello world!")).unwrap()
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::ParameterPreset;
+
+pub(crate) const SAMPLING_PREF_KEY: &str = "SamplingParams";
+
+/// The presets that are available without config. The penalties are set
+/// explicitly, so a preset fully replaces the parameters of the chat.
+const BUILTIN_PRESETS: [(&str, SamplingParams); 3] = [
+    (
+        "creative",
+        SamplingParams {
+            temperature: Some(1.2),
+            top_p: Some(1.0),
+            presence_penalty: Some(0.6),
+            frequency_penalty: Some(0.3),
+        },
+    ),
+    (
+        "balanced",
+        SamplingParams {
+            temperature: Some(0.7),
+            top_p: Some(1.0),
+            presence_penalty: Some(0.0),
+            frequency_penalty: Some(0.0),
+        },
+    ),
+    (
+        "precise",
+        SamplingParams {
+            temperature: Some(0.2),
+            top_p: Some(0.9),
+            presence_penalty: Some(0.0),
+            frequency_penalty: Some(0.0),
+        },
+    ),
+];
+
+const PARAM_NAMES: [&str; 4] = [
+    "temperature",
+    "top_p",
+    "presence_penalty",
+    "frequency_penalty",
+];
+
+/// Sampling parameters of a chat. Parameters with `None` value fall back
+/// to the defaults from config.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SamplingParams {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+}
+
+impl SamplingParams {
+    /// Sets the parameter of the given name, `None` to restore the default
+    /// value. Returns an error message if the name or value is invalid.
+    pub fn set(&mut self, name: &str, value: Option<f32>) -> Result<(), String> {
+        let (field, range) = match name {
+            "temperature" => (&mut self.temperature, 0.0..=2.0),
+            "top_p" => (&mut self.top_p, 0.0..=1.0),
+            "presence_penalty" => (&mut self.presence_penalty, -2.0..=2.0),
+            "frequency_penalty" => (&mut self.frequency_penalty, -2.0..=2.0),
+            _ => {
+                return Err(format!(
+                    "Unknown setting \"{}\", available settings: {}",
+                    name,
+                    PARAM_NAMES.join(", ")
+                ))
+            }
+        };
+
+        if let Some(value) = value {
+            if !range.contains(&value) {
+                return Err(format!(
+                    "The value of {} must be between {} and {}",
+                    name,
+                    range.start(),
+                    range.end()
+                ));
+            }
+        }
+        *field = value;
+        Ok(())
+    }
+
+    /// Returns the parameters with the unset ones filled from `defaults`.
+    pub fn or(self, defaults: SamplingParams) -> Self {
+        Self {
+            temperature: self.temperature.or(defaults.temperature),
+            top_p: self.top_p.or(defaults.top_p),
+            presence_penalty: self.presence_penalty.or(defaults.presence_penalty),
+            frequency_penalty: self.frequency_penalty.or(defaults.frequency_penalty),
+        }
+    }
+}
+
+impl From<ParameterPreset> for SamplingParams {
+    fn from(preset: ParameterPreset) -> Self {
+        Self {
+            temperature: preset.temperature,
+            top_p: preset.top_p,
+            presence_penalty: preset.presence_penalty,
+            frequency_penalty: preset.frequency_penalty,
+        }
+    }
+}
+
+/// Returns the presets that can be applied with `/preset`, the built-in
+/// ones first and then the custom ones by name.
+pub(crate) fn parameter_presets(
+    custom: &HashMap<String, ParameterPreset>,
+) -> Vec<(String, SamplingParams)> {
+    let mut presets: Vec<_> = BUILTIN_PRESETS
+        .iter()
+        .map(|(name, params)| {
+            let params = custom
+                .get(*name)
+                .map(|preset| (*preset).into())
+                .unwrap_or(*params);
+            (name.to_string(), params)
+        })
+        .collect();
+    let mut custom: Vec<_> = custom
+        .iter()
+        .filter(|(name, _)| !BUILTIN_PRESETS.iter().any(|(n, _)| n == name))
+        .map(|(name, preset)| (name.clone(), (*preset).into()))
+        .collect();
+    custom.sort_by(|(a, _), (b, _)| a.cmp(b));
+    presets.extend(custom);
+    presets
+}
+
+impl Display for SamplingParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let values = [
+            self.temperature,
+            self.top_p,
+            self.presence_penalty,
+            self.frequency_penalty,
+        ];
+        for (idx, (name, value)) in PARAM_NAMES.iter().zip(values).enumerate() {
+            if idx > 0 {
+                writeln!(f)?;
+            }
+            match value {
+                Some(value) => write!(f, "{}: {}", name, value)?,
+                None => write!(f, "{}: default", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_params() {
+        let mut params = SamplingParams::default();
+        assert!(params.set("temperature", Some(1.2)).is_ok());
+        assert!(params.set("top_p", Some(1.5)).is_err());
+        assert!(params.set("unknown", Some(0.0)).is_err());
+        assert_eq!(params.temperature, Some(1.2));
+        assert_eq!(params.top_p, None);
+
+        let defaults = SamplingParams {
+            temperature: Some(0.6),
+            top_p: Some(0.9),
+            ..Default::default()
+        };
+        let merged = params.or(defaults);
+        assert_eq!(merged.temperature, Some(1.2));
+        assert_eq!(merged.top_p, Some(0.9));
+
+        assert!(params.set("temperature", None).is_ok());
+        assert_eq!(params.temperature, None);
+    }
+
+    #[test]
+    fn test_parameter_presets() {
+        let custom = HashMap::from([
+            (
+                "precise".to_owned(),
+                ParameterPreset {
+                    temperature: Some(0.0),
+                    ..Default::default()
+                },
+            ),
+            ("coding".to_owned(), ParameterPreset::default()),
+        ]);
+        let presets = parameter_presets(&custom);
+        let names: Vec<_> = presets.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["creative", "balanced", "precise", "coding"]);
+        assert_eq!(
+            presets[2].1,
+            SamplingParams {
+                temperature: Some(0.0),
+                ..Default::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// How often the position of a queued request is checked.
+const POSITION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Limits the number of completions running at the same time. Requests
+/// beyond the limit wait in a FIFO queue.
+#[derive(Clone)]
+pub(crate) struct CompletionScheduler {
+    /// [`None`] if the completions are not limited.
+    semaphore: Option<Arc<Semaphore>>,
+    /// The tickets of the waiting requests, in the order of arrival.
+    queue: Arc<Mutex<VecDeque<u64>>>,
+    next_ticket: Arc<AtomicU64>,
+}
+
+/// A slot to run a completion, which is released when dropped.
+pub(crate) struct CompletionPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+/// Removes the ticket from the queue when the request stops waiting,
+/// including when it's cancelled.
+struct QueueTicket {
+    ticket: u64,
+    queue: Arc<Mutex<VecDeque<u64>>>,
+}
+
+impl Drop for QueueTicket {
+    fn drop(&mut self) {
+        self.queue
+            .lock()
+            .unwrap()
+            .retain(|ticket| *ticket != self.ticket);
+    }
+}
+
+impl CompletionScheduler {
+    pub fn new(max_concurrent_completions: Option<usize>) -> Self {
+        Self {
+            semaphore: max_concurrent_completions.map(|max| Arc::new(Semaphore::new(max.max(1)))),
+            queue: Default::default(),
+            next_ticket: Default::default(),
+        }
+    }
+
+    /// Waits for a free slot. While waiting, `report_position` is called
+    /// with the 1-based position in the queue whenever it changes.
+    pub async fn acquire<F, Fut>(&self, mut report_position: F) -> CompletionPermit
+    where
+        F: FnMut(usize) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let semaphore = match &self.semaphore {
+            Some(semaphore) => Arc::clone(semaphore),
+            None => return CompletionPermit { _permit: None },
+        };
+        // Don't jump the queue even if a slot is just released.
+        if self.queue.lock().unwrap().is_empty() {
+            if let Ok(permit) = Arc::clone(&semaphore).try_acquire_owned() {
+                return CompletionPermit {
+                    _permit: Some(permit),
+                };
+            }
+        }
+
+        let ticket = QueueTicket {
+            ticket: self.next_ticket.fetch_add(1, Ordering::Relaxed),
+            queue: Arc::clone(&self.queue),
+        };
+        self.queue.lock().unwrap().push_back(ticket.ticket);
+
+        // The semaphore is fair, so the permits are granted in the order
+        // of the queue.
+        let acquire = semaphore.acquire_owned();
+        tokio::pin!(acquire);
+        let mut reported_position = None;
+        loop {
+            if let Some(position) = self.position(ticket.ticket) {
+                if reported_position != Some(position) {
+                    reported_position = Some(position);
+                    report_position(position).await;
+                }
+            }
+            tokio::select! {
+                permit = &mut acquire => {
+                    return CompletionPermit {
+                        // The semaphore is never closed.
+                        _permit: permit.ok(),
+                    };
+                }
+                _ = tokio::time::sleep(POSITION_CHECK_INTERVAL) => {}
+            }
+        }
+    }
+
+    /// Returns the number of waiting requests.
+    pub fn queue_len(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    fn position(&self, ticket: u64) -> Option<usize> {
+        self.queue
+            .lock()
+            .unwrap()
+            .iter()
+            .position(|t| *t == ticket)
+            .map(|idx| idx + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_queue_position() {
+        let scheduler = CompletionScheduler::new(Some(1));
+        let first = scheduler.acquire(|_| async {}).await;
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let waiting = {
+            let scheduler = scheduler.clone();
+            tokio::spawn(async move {
+                scheduler
+                    .acquire(|position| {
+                        let _ = tx.send(position);
+                        async {}
+                    })
+                    .await
+            })
+        };
+        assert_eq!(rx.recv().await, Some(1));
+        assert_eq!(scheduler.queue_len(), 1);
+
+        drop(first);
+        let _second = waiting.await.unwrap();
+        assert_eq!(scheduler.queue_len(), 0);
+    }
+}
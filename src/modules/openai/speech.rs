@@ -0,0 +1,41 @@
+use anyhow::Error;
+use async_openai::Client;
+use serde_json::json;
+
+use crate::utils::http;
+
+/// The maximum length of the input accepted by the speech API.
+const MAX_INPUT_CHARS: usize = 4096;
+
+/// Generates the speech of the text in OGG with Opus, which can be sent
+/// as a Telegram voice message directly. Texts longer than the limit of
+/// the API are truncated.
+///
+/// The speech API is not supported by `async-openai` yet, so the request
+/// is sent directly.
+pub(crate) async fn create_speech(
+    client: &Client,
+    headers: &[(&'static str, String)],
+    model: &str,
+    voice: &str,
+    text: &str,
+) -> Result<Vec<u8>, Error> {
+    let input: String = text.chars().take(MAX_INPUT_CHARS).collect();
+    let mut req = http::client()
+        .post(format!("{}/audio/speech", client.api_base()))
+        .bearer_auth(client.api_key());
+    for (name, value) in headers {
+        req = req.header(*name, value);
+    }
+    let resp = req
+        .json(&json!({
+            "model": model,
+            "voice": voice,
+            "input": input,
+            "response_format": "opus",
+        }))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(resp.bytes().await?.to_vec())
+}
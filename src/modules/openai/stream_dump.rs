@@ -0,0 +1,68 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use async_openai::error::OpenAIError;
+use async_openai::types::CreateChatCompletionStreamResponse;
+
+/// Writes the raw stream deltas of a chat into a local file, which helps
+/// investigating the cases where the accumulated content ends up empty or
+/// truncated.
+pub(crate) struct StreamDump {
+    file: File,
+}
+
+impl StreamDump {
+    pub fn create<P>(dir: P, chat_id: &str, model: &str) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(format!("{}.log", chat_id)))?;
+
+        let unix_timestamp_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        writeln!(
+            file,
+            "=== request at {} (model: {}) ===",
+            unix_timestamp_secs, model
+        )?;
+
+        Ok(Self { file })
+    }
+
+    pub fn record(&mut self, item: &Result<CreateChatCompletionStreamResponse, OpenAIError>) {
+        let res = match item {
+            Ok(resp) => {
+                if resp.choices.is_empty() {
+                    writeln!(self.file, "[empty choices] id={:?}", resp.id)
+                } else {
+                    resp.choices.iter().try_for_each(|choice| {
+                        writeln!(
+                            self.file,
+                            "[delta] index={} role={:?} content={:?} finish_reason={:?}",
+                            choice.index,
+                            choice.delta.role,
+                            choice.delta.content,
+                            choice.finish_reason
+                        )
+                    })
+                }
+            }
+            Err(err) => writeln!(self.file, "[error] {}", err),
+        };
+
+        if let Err(err) = res {
+            error!("Failed to write stream dump: {}", err);
+        }
+    }
+}
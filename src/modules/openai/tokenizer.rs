@@ -0,0 +1,155 @@
+use async_openai::types::ChatCompletionRequestMessage;
+use tiktoken_rs::{tokenizer::Tokenizer, CoreBPE};
+
+fn with_bpe<F, R>(model: &str, f: F) -> Option<R>
+where
+    F: FnOnce(&CoreBPE) -> R,
+{
+    let bpe = match tiktoken_rs::tokenizer::get_tokenizer(base_model(model))? {
+        Tokenizer::O200kBase => tiktoken_rs::o200k_base_singleton(),
+        Tokenizer::Cl100kBase => tiktoken_rs::cl100k_base_singleton(),
+        Tokenizer::P50kBase => tiktoken_rs::p50k_base_singleton(),
+        Tokenizer::P50kEdit => tiktoken_rs::p50k_edit_singleton(),
+        Tokenizer::R50kBase | Tokenizer::Gpt2 => tiktoken_rs::r50k_base_singleton(),
+    };
+    let bpe = bpe.lock();
+    Some(f(&bpe))
+}
+
+/// Returns the base model of a fine-tuned model, e.g. `gpt-3.5-turbo`
+/// for `ft:gpt-3.5-turbo:my-org:custom-suffix:id`, or the model itself
+/// if it's not fine-tuned.
+pub(crate) fn base_model(model: &str) -> &str {
+    if let Some(rest) = model.strip_prefix("ft:") {
+        return rest.split(':').next().unwrap_or(rest);
+    }
+    // Legacy fine-tuned models, e.g. `davinci:ft-my-org-2023-01-01`.
+    if let Some((base, _)) = model.split_once(":ft-") {
+        return base;
+    }
+    model
+}
+
+/// Returns the size of context window of the given model.
+pub(crate) fn context_size(model: &str) -> usize {
+    tiktoken_rs::model::get_context_size(base_model(model))
+}
+
+/// Counts the tokens of the text. Returns `None` if there is no tokenizer
+/// for the model.
+pub(crate) fn count_tokens(model: &str, text: &str) -> Option<usize> {
+    with_bpe(model, |bpe| bpe.encode_with_special_tokens(text).len())
+}
+
+/// The tokens that every reply is primed with, i.e.
+/// `<|start|>assistant<|message|>`, which are counted once per request.
+pub(crate) const REPLY_PRIMING_TOKENS: usize = 3;
+
+/// Counts the tokens of the messages, including the overhead of the chat
+/// format of each message, but not [`REPLY_PRIMING_TOKENS`], so that the
+/// counts of messages add up. Returns `None` if there is no tokenizer for
+/// the model.
+pub(crate) fn count_message_tokens(
+    model: &str,
+    msgs: &[ChatCompletionRequestMessage],
+) -> Option<usize> {
+    // See: https://github.com/openai/openai-cookbook/blob/main/examples/How_to_count_tokens_with_tiktoken.ipynb
+    let (tokens_per_message, tokens_per_name): (i64, i64) =
+        if base_model(model).starts_with("gpt-3.5") {
+            (4, -1)
+        } else {
+            (3, 1)
+        };
+
+    with_bpe(model, |bpe| {
+        let mut num_tokens: i64 = 0;
+        for msg in msgs {
+            num_tokens += tokens_per_message;
+            num_tokens += bpe.encode_with_special_tokens(&msg.role.to_string()).len() as i64;
+            num_tokens += bpe.encode_with_special_tokens(&msg.content).len() as i64;
+            if let Some(name) = &msg.name {
+                num_tokens += bpe.encode_with_special_tokens(name).len() as i64;
+                num_tokens += tokens_per_name;
+            }
+        }
+        num_tokens.max(0) as usize
+    })
+}
+
+/// Estimates the tokens of the text by the scripts of its characters,
+/// which is used when there is no tokenizer for the model.
+pub(crate) fn estimate_tokens(text: &str) -> f64 {
+    text.chars()
+        .map(|c| match c {
+            // Whitespaces are mostly merged into the adjacent words.
+            c if c.is_whitespace() => 0.1,
+            // Punctuations are usually separate tokens, which makes code
+            // denser than prose.
+            c if c.is_ascii_punctuation() => 0.5,
+            // About 4 characters per token for English words.
+            c if c.is_ascii() => 0.25,
+            // Chinese, Japanese and Korean characters are often a token
+            // each, or even more.
+            '\u{1100}'..='\u{11FF}'
+            | '\u{2E80}'..='\u{9FFF}'
+            | '\u{AC00}'..='\u{D7AF}'
+            | '\u{F900}'..='\u{FAFF}'
+            | '\u{FF00}'..='\u{FFEF}'
+            | '\u{20000}'..='\u{2FFFF}' => 1.0,
+            // Other letters (e.g. Cyrillic, Greek and accented Latin) are
+            // split into shorter pieces than English words.
+            _ => 0.5,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use async_openai::types::Role;
+
+    use super::*;
+
+    #[test]
+    fn test_count_tokens() {
+        assert_eq!(count_tokens("gpt-3.5-turbo", "hello world"), Some(2));
+        assert!(count_tokens("gpt-3.5-turbo", "你好，世界").unwrap() > 2);
+        assert_eq!(count_tokens("unknown-model", "hello world"), None);
+    }
+
+    #[test]
+    fn test_count_message_tokens() {
+        let msg = |role, content: &str| ChatCompletionRequestMessage {
+            role,
+            content: content.to_owned(),
+            name: None,
+        };
+        let msgs = [
+            msg(Role::System, "Be brief."),
+            msg(Role::User, "hello world"),
+        ];
+        let total = count_message_tokens("gpt-4", &msgs).unwrap();
+        let sum = count_message_tokens("gpt-4", &msgs[..1]).unwrap()
+            + count_message_tokens("gpt-4", &msgs[1..]).unwrap();
+        assert_eq!(total, sum);
+        // 3 tokens of the format, 1 of the role and 2 of the content.
+        assert_eq!(count_message_tokens("gpt-4", &msgs[1..]), Some(6));
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens("你好世界"), 4.0);
+        assert_eq!(estimate_tokens("a(b);"), 2.0);
+        assert!((estimate_tokens("hello world") - 2.6).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_base_model() {
+        assert_eq!(base_model("gpt-4"), "gpt-4");
+        assert_eq!(
+            base_model("ft:gpt-3.5-turbo-0613:my-org:custom:7p4lURel"),
+            "gpt-3.5-turbo-0613"
+        );
+        assert_eq!(base_model("davinci:ft-my-org-2023-01-01"), "davinci");
+        assert_eq!(context_size("ft:gpt-4-0613:my-org::abc"), 8192);
+    }
+}
@@ -0,0 +1,26 @@
+use anyhow::Error;
+use serde_json::{json, Value};
+
+/// A rough estimation of the tokens taken by an image.
+pub(crate) const IMAGE_TOKENS_ESTIMATE: u32 = 765;
+
+/// Attaches the images to the last user message of the request body, in
+/// the multimodal content format.
+pub(crate) fn attach_images(body: &mut Value, image_urls: &[String]) -> Result<(), Error> {
+    let last_user_msg = body["messages"]
+        .as_array_mut()
+        .and_then(|msgs| msgs.iter_mut().rev().find(|msg| msg["role"] == "user"))
+        .ok_or_else(|| anyhow!("No user message to attach the images to"))?;
+    let mut content = vec![json!({
+        "type": "text",
+        "text": last_user_msg["content"].take(),
+    })];
+    content.extend(image_urls.iter().map(|url| {
+        json!({
+            "type": "image_url",
+            "image_url": { "url": url },
+        })
+    }));
+    last_user_msg["content"] = Value::Array(content);
+    Ok(())
+}
@@ -1,9 +1,24 @@
+mod panel;
 mod prefs_mgr;
 
 use anyhow::Error;
+use teloxide::dispatching::DpHandlerDescription;
 use teloxide::prelude::*;
 
-use crate::{database::DatabaseManager, module_mgr::Module};
+use crate::{
+    config::{RenderMode, SharedConfig},
+    database::DatabaseManager,
+    dispatcher::noop_handler,
+    module_mgr::{Command, Module},
+    modules::admin::MemberManager,
+    modules::openai::{
+        can_select_model, is_allowed_member, OpenAIClient, CHAT_MODEL_PREF_KEY, SAMPLING_PREF_KEY,
+    },
+    types::HandlerResult,
+    utils::{auto_delete::schedule_deletion, i18n::user_language},
+};
+pub(crate) use panel::{language_instruction, RENDER_MARKDOWN_PREF_KEY, REPLY_LANGUAGE_PREF_KEY};
+use panel::{Action, Page, PanelState};
 pub(crate) use prefs_mgr::PreferencesManager;
 
 pub(crate) struct Prefs {
@@ -16,6 +31,176 @@ impl Prefs {
     }
 }
 
+async fn load_panel_state(
+    chat_id: &str,
+    prefs_mgr: &PreferencesManager,
+    openai_client: &OpenAIClient,
+    config: &SharedConfig,
+) -> Result<PanelState, Error> {
+    let renders_markdown: Option<bool> = prefs_mgr
+        .get_chat_value(chat_id, RENDER_MARKDOWN_PREF_KEY)
+        .await?;
+    Ok(PanelState {
+        renders_markdown: renders_markdown
+            .unwrap_or(config.load().default_render_mode() != RenderMode::Plain),
+        language: prefs_mgr
+            .get_chat_value(chat_id, REPLY_LANGUAGE_PREF_KEY)
+            .await?,
+        model: openai_client.chat_model(Some(chat_id)).await,
+        temperature: openai_client
+            .chat_sampling_params(chat_id)
+            .await
+            .temperature,
+    })
+}
+
+async fn show_prefs(
+    bot: Bot,
+    msg: Message,
+    prefs_mgr: PreferencesManager,
+    openai_client: OpenAIClient,
+    member_mgr: MemberManager,
+    config: SharedConfig,
+) -> HandlerResult {
+    let allowed = match msg.from() {
+        Some(user) => is_allowed_member(user, &member_mgr, &config).await,
+        None => false,
+    };
+    if !allowed {
+        let sent_msg = bot
+            .send_message(
+                msg.chat.id,
+                &config
+                    .load()
+                    .i18n_strings(user_language(&msg))
+                    .not_allowed_prompt,
+            )
+            .reply_to_message_id(msg.id)
+            .await?;
+        schedule_deletion(&bot, &sent_msg, &config);
+        return Ok(());
+    }
+
+    let chat_id = msg.chat.id.to_string();
+    let state = load_panel_state(&chat_id, &prefs_mgr, &openai_client, &config).await?;
+    let (text, keyboard) =
+        panel::render_page(Page::Main, &state, &openai_client.available_models());
+    bot.send_message(msg.chat.id, text)
+        .reply_markup(keyboard)
+        .reply_to_message_id(msg.id)
+        .await?;
+
+    Ok(())
+}
+
+/// Applies the action to the preferences, and returns the page to show
+/// after it.
+async fn apply_action(
+    action: Action,
+    chat_id: &str,
+    state: &PanelState,
+    prefs_mgr: &PreferencesManager,
+    openai_client: &OpenAIClient,
+) -> Result<Page, Error> {
+    match action {
+        Action::Open(page) => return Ok(page),
+        Action::ToggleMarkdown => {
+            prefs_mgr
+                .set_chat_value(
+                    chat_id,
+                    RENDER_MARKDOWN_PREF_KEY,
+                    &Some(!state.renders_markdown),
+                )
+                .await?
+        }
+        Action::SetLanguage(language) => {
+            prefs_mgr
+                .set_chat_value(chat_id, REPLY_LANGUAGE_PREF_KEY, &language)
+                .await?
+        }
+        Action::SetModel(model_id) => {
+            let model = openai_client
+                .find_model_by_button_id(&model_id)
+                .ok_or_else(|| anyhow!("The model is not available"))?;
+            prefs_mgr
+                .set_chat_value(chat_id, CHAT_MODEL_PREF_KEY, &Some(model))
+                .await?
+        }
+        Action::SetTemperature(temperature) => {
+            let mut params = openai_client.chat_sampling_params(chat_id).await;
+            params
+                .set("temperature", temperature)
+                .map_err(|err| anyhow!(err))?;
+            prefs_mgr
+                .set_chat_value(chat_id, SAMPLING_PREF_KEY, &params)
+                .await?
+        }
+    }
+    Ok(Page::Main)
+}
+
+async fn handle_prefs_action(
+    bot: Bot,
+    query: CallbackQuery,
+    prefs_mgr: PreferencesManager,
+    openai_client: OpenAIClient,
+    member_mgr: MemberManager,
+    config: SharedConfig,
+) -> bool {
+    let action = match query.data.as_deref().and_then(Action::parse) {
+        Some(action) => action,
+        None => return false,
+    };
+    let message = match query.message {
+        Some(message) => message,
+        None => return false,
+    };
+
+    let allowed = match &action {
+        Action::SetModel(_) => can_select_model(Some(&query.from), &member_mgr, &config).await,
+        _ => is_allowed_member(&query.from, &member_mgr, &config).await,
+    };
+    if !allowed {
+        let _ = bot
+            .answer_callback_query(query.id)
+            .text("You are not allowed to change the preferences")
+            .await;
+        return true;
+    }
+
+    let chat_id = message.chat.id.to_string();
+    let result = async {
+        let state = load_panel_state(&chat_id, &prefs_mgr, &openai_client, &config).await?;
+        let page = apply_action(action, &chat_id, &state, &prefs_mgr, &openai_client).await?;
+        let state = load_panel_state(&chat_id, &prefs_mgr, &openai_client, &config).await?;
+        Ok::<_, Error>(panel::render_page(
+            page,
+            &state,
+            &openai_client.available_models(),
+        ))
+    }
+    .await;
+
+    match result {
+        Ok((text, keyboard)) => {
+            let _ = bot
+                .edit_message_text(message.chat.id, message.id, text)
+                .reply_markup(keyboard)
+                .await;
+            let _ = bot.answer_callback_query(query.id).await;
+        }
+        Err(err) => {
+            error!("Failed to update the preferences: {}", err);
+            let _ = bot
+                .answer_callback_query(query.id)
+                .text(format!("Failed to update the preferences: {}", err))
+                .await;
+        }
+    }
+
+    true
+}
+
 #[async_trait]
 impl Module for Prefs {
     async fn register_dependency(&mut self, dep_map: &mut DependencyMap) -> Result<(), Error> {
@@ -23,4 +208,19 @@ impl Module for Prefs {
         dep_map.insert(prefs_mgr);
         Ok(())
     }
+
+    fn filter_handler(
+        &self,
+    ) -> Handler<'static, DependencyMap, HandlerResult, DpHandlerDescription> {
+        Update::filter_callback_query()
+            .branch(dptree::filter_async(handle_prefs_action).endpoint(noop_handler))
+    }
+
+    fn commands(&self) -> Vec<Command> {
+        vec![Command::new(
+            "prefs",
+            "Show the preferences panel of this chat",
+            dptree::endpoint(show_prefs),
+        )]
+    }
 }
@@ -0,0 +1,215 @@
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+use crate::modules::openai::model_button_id;
+
+pub(crate) const RENDER_MARKDOWN_PREF_KEY: &str = "RenderMarkdown";
+pub(crate) const REPLY_LANGUAGE_PREF_KEY: &str = "ReplyLanguage";
+
+const ACTION_PREFIX: &str = "/prefs:";
+
+const LANGUAGES: [(&str, &str); 7] = [
+    ("en", "English"),
+    ("zh", "中文"),
+    ("es", "Español"),
+    ("fr", "Français"),
+    ("de", "Deutsch"),
+    ("ja", "日本語"),
+    ("ru", "Русский"),
+];
+
+const TEMPERATURES: [f32; 5] = [0.0, 0.5, 0.7, 1.0, 1.5];
+
+/// Returns the system instruction that makes the model reply in the
+/// language of the given code.
+pub(crate) fn language_instruction(code: &str) -> Option<String> {
+    language_name(code).map(|name| format!("Always reply in {}.", name))
+}
+
+fn language_name(code: &str) -> Option<&'static str> {
+    LANGUAGES
+        .iter()
+        .find(|(c, _)| *c == code)
+        .map(|(_, name)| *name)
+}
+
+/// The current values of the preferences shown in the panel.
+pub(crate) struct PanelState {
+    pub renders_markdown: bool,
+    pub language: Option<String>,
+    pub model: String,
+    pub temperature: Option<f32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Page {
+    Main,
+    Language,
+    Model,
+    Temperature,
+}
+
+/// An action triggered by a button in the panel.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Action {
+    Open(Page),
+    ToggleMarkdown,
+    SetLanguage(Option<String>),
+    /// Selects the model by its id, see [`model_button_id`].
+    SetModel(String),
+    SetTemperature(Option<f32>),
+}
+
+impl Action {
+    pub fn parse(data: &str) -> Option<Self> {
+        let action = data.strip_prefix(ACTION_PREFIX)?;
+        let (name, value) = match action.split_once(':') {
+            Some((name, value)) => (name, Some(value)),
+            None => (action, None),
+        };
+        let action = match (name, value) {
+            ("main", None) => Action::Open(Page::Main),
+            ("markdown", None) => Action::ToggleMarkdown,
+            ("language", None) => Action::Open(Page::Language),
+            ("language", Some("default")) => Action::SetLanguage(None),
+            ("language", Some(code)) => Action::SetLanguage(Some(code.to_owned())),
+            ("model", None) => Action::Open(Page::Model),
+            ("model", Some(model_id)) => Action::SetModel(model_id.to_owned()),
+            ("temperature", None) => Action::Open(Page::Temperature),
+            ("temperature", Some("default")) => Action::SetTemperature(None),
+            ("temperature", Some(value)) => Action::SetTemperature(Some(value.parse().ok()?)),
+            _ => return None,
+        };
+        Some(action)
+    }
+}
+
+fn button(title: impl Into<String>, action: &str) -> InlineKeyboardButton {
+    InlineKeyboardButton::callback(title, format!("{}{}", ACTION_PREFIX, action))
+}
+
+fn checked(title: &str, is_checked: bool) -> String {
+    if is_checked {
+        format!("✓ {}", title)
+    } else {
+        title.to_owned()
+    }
+}
+
+/// Returns the text and keyboard of the given page.
+pub(crate) fn render_page(
+    page: Page,
+    state: &PanelState,
+    models: &[String],
+) -> (String, InlineKeyboardMarkup) {
+    let back_row = [button("« Back", "main")];
+    match page {
+        Page::Main => {
+            let language = state
+                .language
+                .as_deref()
+                .and_then(language_name)
+                .unwrap_or("Auto");
+            let temperature = state
+                .temperature
+                .map_or("Default".to_owned(), |t| t.to_string());
+            let keyboard = InlineKeyboardMarkup::default()
+                .append_row([button(
+                    format!(
+                        "Markdown: {}",
+                        if state.renders_markdown { "On" } else { "Off" }
+                    ),
+                    "markdown",
+                )])
+                .append_row([button(format!("Language: {}", language), "language")])
+                .append_row([button(format!("Model: {}", state.model), "model")])
+                .append_row([button(
+                    format!("Temperature: {}", temperature),
+                    "temperature",
+                )]);
+            ("Preferences of this chat:".to_owned(), keyboard)
+        }
+        Page::Language => {
+            let keyboard = LANGUAGES
+                .iter()
+                .fold(
+                    InlineKeyboardMarkup::default().append_row([button(
+                        checked("Auto", state.language.is_none()),
+                        "language:default",
+                    )]),
+                    |keyboard, (code, name)| {
+                        let is_checked = state.language.as_deref() == Some(*code);
+                        keyboard.append_row([button(
+                            checked(name, is_checked),
+                            &format!("language:{}", code),
+                        )])
+                    },
+                )
+                .append_row(back_row);
+            ("Select the language of answers:".to_owned(), keyboard)
+        }
+        Page::Model => {
+            let keyboard = models
+                .iter()
+                .fold(InlineKeyboardMarkup::default(), |keyboard, model| {
+                    keyboard.append_row([button(
+                        checked(model, *model == state.model),
+                        &format!("model:{}", model_button_id(model)),
+                    )])
+                })
+                .append_row(back_row);
+            ("Select a model for this chat:".to_owned(), keyboard)
+        }
+        Page::Temperature => {
+            let values_row: Vec<_> = TEMPERATURES
+                .iter()
+                .map(|t| {
+                    button(
+                        checked(&t.to_string(), state.temperature == Some(*t)),
+                        &format!("temperature:{}", t),
+                    )
+                })
+                .collect();
+            let keyboard = InlineKeyboardMarkup::default()
+                .append_row(values_row)
+                .append_row([button(
+                    checked("Default", state.temperature.is_none()),
+                    "temperature:default",
+                )])
+                .append_row(back_row);
+            (
+                "Select the temperature, higher values make answers more random:".to_owned(),
+                keyboard,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_action() {
+        assert_eq!(Action::parse("/prefs:main"), Some(Action::Open(Page::Main)));
+        assert_eq!(
+            Action::parse("/prefs:language:zh"),
+            Some(Action::SetLanguage(Some("zh".to_owned())))
+        );
+        let model_id = model_button_id("ft:gpt-3.5-turbo:my-org:custom-suffix:abc123");
+        assert_eq!(model_id.len(), 16);
+        assert_eq!(
+            Action::parse(&format!("/prefs:model:{}", model_id)),
+            Some(Action::SetModel(model_id))
+        );
+        assert_eq!(
+            Action::parse("/prefs:temperature:0.7"),
+            Some(Action::SetTemperature(Some(0.7)))
+        );
+        assert_eq!(
+            Action::parse("/prefs:temperature:default"),
+            Some(Action::SetTemperature(None))
+        );
+        assert_eq!(Action::parse("/prefs:temperature:hot"), None);
+        assert_eq!(Action::parse("/model:gpt-4"), None);
+    }
+}
@@ -12,16 +12,6 @@ pub(crate) struct PreferencesManager {
 
 impl PreferencesManager {
     pub async fn with_db_manager(db_mgr: DatabaseManager) -> Result<Self, Error> {
-        // Initialize the database table before returning.
-        let ok = db_mgr.query(|conn| {
-            let sql = "CREATE TABLE IF NOT EXISTS preferences (pref_key TEXT NOT NULL PRIMARY KEY, value TEXT);";
-            conn.execute(sql, ()).unwrap();
-            true
-        }).await?;
-        if !ok {
-            return Err(anyhow!("Failed to initialize database table"));
-        }
-
         Ok(Self { db_mgr })
     }
 
@@ -30,7 +20,7 @@ impl PreferencesManager {
         V: Serialize,
     {
         let key = key.to_owned();
-        let serialized_value = serde_json::to_string(value)?;
+        let serialized_value = self.db_mgr.seal(serde_json::to_string(value)?)?;
 
         self.db_mgr
             .enqueue_work(move |conn| {
@@ -57,20 +47,37 @@ impl PreferencesManager {
         V: DeserializeOwned + Default + Send + Debug + 'static,
     {
         let key = key.to_owned();
-        let value = self
+        let value_str = self
             .db_mgr
             .query(move |conn| {
                 let sql = "SELECT value FROM preferences WHERE pref_key = ?";
-                let value_str = conn.query_row(sql, (key,), |row| row.get(0) as Result<String, _>);
-                if let Ok(value_str) = value_str {
-                    serde_json::from_str(&value_str)
-                } else {
-                    Ok(V::default())
-                }
+                conn.query_row(sql, (key,), |row| row.get(0) as Result<String, _>)
+                    .ok()
             })
+            .await?;
+
+        match value_str {
+            Some(value_str) => Ok(serde_json::from_str(&self.db_mgr.unseal(value_str)?)?),
+            None => Ok(V::default()),
+        }
+    }
+
+    pub async fn set_chat_value<V>(&self, chat_id: &str, key: &str, value: &V) -> Result<(), Error>
+    where
+        V: Serialize,
+    {
+        self.set_value(&Self::chat_scoped_key(chat_id, key), value)
             .await
-            .and_then(|res| res.map_err(|err| anyhow!(err)))?;
+    }
+
+    pub async fn get_chat_value<V>(&self, chat_id: &str, key: &str) -> Result<V, Error>
+    where
+        V: DeserializeOwned + Default + Send + Debug + 'static,
+    {
+        self.get_value(&Self::chat_scoped_key(chat_id, key)).await
+    }
 
-        Ok(value)
+    fn chat_scoped_key(chat_id: &str, key: &str) -> String {
+        format!("{}@{}", key, chat_id)
     }
 }
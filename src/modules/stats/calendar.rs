@@ -0,0 +1,95 @@
+use chrono::{Datelike, NaiveDate, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// Returns the date in the timezone of the unix timestamp.
+pub(crate) fn local_date(timestamp: i64, timezone: &Tz) -> NaiveDate {
+    Utc.timestamp_opt(timestamp, 0)
+        .single()
+        .unwrap_or_default()
+        .with_timezone(timezone)
+        .date_naive()
+}
+
+/// Returns the unix timestamp when the hour of the timestamp starts in the
+/// timezone. The offsets of a few timezones are not whole hours, e.g. +5:30,
+/// so their hours don't start with the hours in UTC.
+pub(crate) fn local_hour_start(timestamp: i64, timezone: &Tz) -> i64 {
+    let offset = Utc
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .unwrap_or_default()
+        .with_timezone(timezone)
+        .offset()
+        .fix()
+        .local_minus_utc() as i64;
+    (timestamp + offset).div_euclid(3600) * 3600 - offset
+}
+
+/// Returns the current date in the timezone.
+pub(crate) fn local_today(timezone: &Tz) -> NaiveDate {
+    Utc::now().with_timezone(timezone).date_naive()
+}
+
+/// Returns the unix timestamp when the date starts in the timezone.
+pub(crate) fn local_midnight(date: NaiveDate, timezone: &Tz) -> i64 {
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+    // Midnight is skipped by the daylight saving time in a few timezones,
+    // the day starts an hour later then.
+    timezone
+        .from_local_datetime(&midnight)
+        .earliest()
+        .or_else(|| {
+            timezone
+                .from_local_datetime(&(midnight + chrono::Duration::hours(1)))
+                .earliest()
+        })
+        .map_or_else(
+            || Utc.from_utc_datetime(&midnight).timestamp(),
+            |time| time.timestamp(),
+        )
+}
+
+/// Returns the first date of the month of the date.
+pub(crate) fn month_start(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).unwrap()
+}
+
+/// Returns the number of days since the unix epoch of the date, which
+/// identifies the date in the database.
+pub(crate) fn day_number(date: NaiveDate) -> i64 {
+    (date - NaiveDate::from_ymd_opt(1970, 1, 1).unwrap()).num_days()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_day() {
+        let timezone: Tz = "Asia/Shanghai".parse().unwrap();
+        // 2023-03-01 20:00:00 UTC is 2023-03-02 04:00:00 in Shanghai.
+        let date = local_date(1677700800, &timezone);
+        assert_eq!(date, NaiveDate::from_ymd_opt(2023, 3, 2).unwrap());
+        // 2023-03-01 16:00:00 UTC.
+        assert_eq!(local_midnight(date, &timezone), 1677686400);
+        assert_eq!(
+            day_number(date),
+            day_number(local_date(1677686400, &Tz::UTC)) + 1
+        );
+
+        let timezone: Tz = "Asia/Kolkata".parse().unwrap();
+        // 2023-03-01 18:45:00 UTC is 2023-03-02 00:15:00 in Kolkata, whose
+        // hour starts at 18:30:00 UTC.
+        assert_eq!(local_hour_start(1677696300, &timezone), 1677695400);
+        assert_eq!(local_hour_start(1677696300, &Tz::UTC), 1677693600);
+
+        let timezone: Tz = "America/New_York".parse().unwrap();
+        let date = NaiveDate::from_ymd_opt(2023, 3, 12).unwrap();
+        // The daylight saving time starts at 2 AM, so the offset is -5.
+        assert_eq!(local_midnight(date, &timezone), 1678597200);
+        assert_eq!(
+            month_start(date),
+            NaiveDate::from_ymd_opt(2023, 3, 1).unwrap()
+        );
+    }
+}
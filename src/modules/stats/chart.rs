@@ -0,0 +1,96 @@
+//! A minimal bar chart renderer that encodes the image as PNG.
+//!
+//! The chart has no text, the labels are expected to be sent along with
+//! the image (e.g. as the caption of the photo).
+
+const WIDTH: usize = 640;
+const HEIGHT: usize = 320;
+const MARGIN: usize = 20;
+const GRID_LINES: usize = 4;
+
+const BACKGROUND_COLOR: [u8; 3] = [0xff, 0xff, 0xff];
+const GRID_COLOR: [u8; 3] = [0xe0, 0xe0, 0xe0];
+const AXIS_COLOR: [u8; 3] = [0x80, 0x80, 0x80];
+const BAR_COLOR: [u8; 3] = [0x2a, 0x9d, 0xf4];
+
+struct Canvas {
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new() -> Self {
+        Self {
+            pixels: BACKGROUND_COLOR.repeat(WIDTH * HEIGHT),
+        }
+    }
+
+    fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: [u8; 3]) {
+        for row in y..(y + height).min(HEIGHT) {
+            for col in x..(x + width).min(WIDTH) {
+                let offset = (row * WIDTH + col) * 3;
+                self.pixels[offset..offset + 3].copy_from_slice(&color);
+            }
+        }
+    }
+
+    fn encode_png(&self) -> Vec<u8> {
+        let mut png = vec![];
+        let mut encoder = png::Encoder::new(&mut png, WIDTH as u32, HEIGHT as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        // Encoding into memory can't fail with the size and the format.
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&self.pixels).unwrap();
+        writer.finish().unwrap();
+        png
+    }
+}
+
+/// Renders the values as a bar chart, and returns the PNG data.
+pub(crate) fn render_bar_chart(values: &[i64]) -> Vec<u8> {
+    let mut canvas = Canvas::new();
+    let plot_width = WIDTH - MARGIN * 2;
+    let plot_height = HEIGHT - MARGIN * 2;
+
+    for idx in 0..GRID_LINES {
+        let y = MARGIN + plot_height * idx / GRID_LINES;
+        canvas.fill_rect(MARGIN, y, plot_width, 1, GRID_COLOR);
+    }
+
+    let max_value = values.iter().copied().max().unwrap_or(0).max(1);
+    if !values.is_empty() {
+        let slot_width = plot_width / values.len();
+        let bar_width = (slot_width * 4 / 5).max(1);
+        for (idx, value) in values.iter().enumerate() {
+            let bar_height = (plot_height as i64 * (*value).max(0) / max_value) as usize;
+            let x = MARGIN + slot_width * idx + (slot_width - bar_width) / 2;
+            let y = MARGIN + plot_height - bar_height;
+            canvas.fill_rect(x, y, bar_width, bar_height, BAR_COLOR);
+        }
+    }
+
+    canvas.fill_rect(MARGIN, MARGIN + plot_height, plot_width, 1, AXIS_COLOR);
+    canvas.encode_png()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_bar_chart() {
+        let png = render_bar_chart(&[0, 10, 5, 20]);
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR"));
+        assert_eq!(&png[16..24], &[0, 0, 2, 0x80, 0, 0, 1, 0x40]);
+        // The IEND chunk has a well-known CRC.
+        assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(pixels[..3], BACKGROUND_COLOR);
+        // The bottom of the highest bar.
+        let offset = ((HEIGHT - MARGIN - 1) * WIDTH + WIDTH - MARGIN - 75) * 3;
+        assert_eq!(pixels[offset..offset + 3], BAR_COLOR);
+    }
+}
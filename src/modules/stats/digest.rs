@@ -0,0 +1,164 @@
+use std::fmt::Write;
+
+use anyhow::Error;
+use chrono::NaiveDate;
+use teloxide::prelude::*;
+use teloxide::types::Chat;
+
+use super::calendar::{local_midnight, local_today};
+use super::stats_mgr::{StatsManager, UsageDigest};
+use crate::{config::SharedConfig, scheduler::Scheduler, utils::sender::RetryExt};
+
+/// Returns the name of the chat to show in the digest.
+fn chat_name(chat: &Chat) -> Option<String> {
+    chat.title()
+        .map(|title| title.to_owned())
+        .or_else(|| chat.username().map(|username| format!("@{}", username)))
+        .or_else(|| chat.first_name().map(|first_name| first_name.to_owned()))
+}
+
+/// Renders the digest of the date. `chat_names` are the names of the top
+/// chats in order, and the chat ids are shown for chats without names.
+fn render_digest(date: NaiveDate, digest: &UsageDigest, chat_names: &[Option<String>]) -> String {
+    let mut text = String::new();
+    let _ = writeln!(&mut text, "Usage on {}:", date.format("%Y-%m-%d"));
+    let _ = writeln!(&mut text, "Requests: {}", digest.requests);
+    let _ = writeln!(
+        &mut text,
+        "Tokens: {} (~${:.4})",
+        digest.usage.tokens, digest.usage.cost
+    );
+    if !digest.top_chats.is_empty() {
+        let _ = writeln!(&mut text, "\nTop chats:");
+        for (idx, (chat_id, requests, tokens)) in digest.top_chats.iter().enumerate() {
+            let name = chat_names
+                .get(idx)
+                .cloned()
+                .flatten()
+                .unwrap_or_else(|| chat_id.clone());
+            let _ = writeln!(
+                &mut text,
+                "{}. {} - {} requests, {} tokens",
+                idx + 1,
+                name,
+                requests,
+                tokens
+            );
+        }
+    }
+    if !digest.top_users.is_empty() {
+        let _ = writeln!(&mut text, "\nTop users:");
+        for (idx, (user_id, usage)) in digest.top_users.iter().enumerate() {
+            let _ = writeln!(
+                &mut text,
+                "{}. {} - {} tokens (~${:.4})",
+                idx + 1,
+                user_id,
+                usage.tokens,
+                usage.cost
+            );
+        }
+    }
+    text.trim_end().to_owned()
+}
+
+/// Posts the usage of yesterday (in the configured timezone) to the chat
+/// in `dailyDigest`.
+async fn post_daily_digest(
+    bot: Bot,
+    stats_mgr: StatsManager,
+    config: SharedConfig,
+) -> Result<(), Error> {
+    let (daily_digest, timezone) = {
+        let config = config.load();
+        match &config.daily_digest {
+            Some(daily_digest) => (daily_digest.clone(), config.timezone),
+            None => return Ok(()),
+        }
+    };
+
+    let today = local_today(&timezone);
+    let yesterday = today - chrono::Duration::days(1);
+    let digest = stats_mgr
+        .query_usage_digest(
+            local_midnight(yesterday, &timezone),
+            local_midnight(today, &timezone),
+            daily_digest.top_limit,
+        )
+        .await?;
+
+    // The names are only for display, chats that can't be fetched (e.g.
+    // the bot has left) are shown with their ids.
+    let mut chat_names = vec![];
+    for (chat_id, _, _) in &digest.top_chats {
+        let name = match chat_id.parse() {
+            Ok(chat_id) => bot.get_chat(ChatId(chat_id)).await.ok(),
+            Err(_) => None,
+        };
+        chat_names.push(name.as_ref().and_then(chat_name));
+    }
+
+    let text = render_digest(yesterday, &digest, &chat_names);
+    bot.send_message(ChatId(daily_digest.chat_id), text)
+        .send_retrying()
+        .await?;
+    Ok(())
+}
+
+/// Schedules the daily digest, which is posted while `dailyDigest` is set.
+pub(crate) fn schedule_daily_digest(
+    scheduler: &Scheduler,
+    bot: Bot,
+    stats_mgr: StatsManager,
+    config: SharedConfig,
+) {
+    let schedule_config = config.clone();
+    scheduler.daily(
+        "daily digest",
+        move || {
+            let config = schedule_config.load();
+            let time = config.daily_digest.as_ref()?.time_of_day()?;
+            Some((time, config.timezone))
+        },
+        move || {
+            let (bot, stats_mgr, config) = (bot.clone(), stats_mgr.clone(), config.clone());
+            async move {
+                if let Err(err) = post_daily_digest(bot, stats_mgr, config).await {
+                    error!("Failed to post the daily digest: {}", err);
+                }
+            }
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::stats::stats_mgr::Usage;
+
+    #[test]
+    fn test_render_digest() {
+        let digest = UsageDigest {
+            requests: 12,
+            usage: Usage {
+                tokens: 3000,
+                cost: 0.5,
+            },
+            top_chats: vec![("-100".to_owned(), 10, 2500), ("42".to_owned(), 2, 500)],
+            top_users: vec![(
+                "alice".to_owned(),
+                Usage {
+                    tokens: 3000,
+                    cost: 0.5,
+                },
+            )],
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+        assert_eq!(
+            render_digest(date, &digest, &[Some("Group".to_owned()), None]),
+            "Usage on 2024-05-01:\nRequests: 12\nTokens: 3000 (~$0.5000)\n\n\
+            Top chats:\n1. Group - 10 requests, 2500 tokens\n2. 42 - 2 requests, 500 tokens\n\n\
+            Top users:\n1. alice - 3000 tokens (~$0.5000)"
+        );
+    }
+}
@@ -1,16 +1,33 @@
+mod calendar;
+mod chart;
+mod digest;
+mod quota;
 mod stats_mgr;
 
 use std::fmt::Write;
+use std::sync::Arc;
 
 use anyhow::Error;
+use teloxide::dptree::di::DependencySupplier;
 use teloxide::prelude::*;
+use teloxide::types::{InputFile, MessageEntity};
 
 use crate::{
+    config::SharedConfig,
     database::DatabaseManager,
+    event_bus::{Event, EventBus},
     module_mgr::{Command, Module},
+    modules::admin::{check_role, MemberRole, RoleManager},
+    scheduler::Scheduler,
     types::HandlerResult,
+    utils::{
+        dptree_ext::{command_with_args, CommandArgs},
+        sender::RetryExt,
+    },
 };
-pub(crate) use stats_mgr::StatsManager;
+use calendar::local_date;
+pub(crate) use quota::{QuotaFeature, QuotaManager};
+pub(crate) use stats_mgr::{ChatReport, FeedbackReport, ModerationReport, StatsManager};
 
 pub(crate) struct Stats {
     db_mgr: DatabaseManager,
@@ -22,38 +39,375 @@ impl Stats {
     }
 }
 
-async fn handle_show_stats(bot: Bot, msg: Message, stats_mgr: StatsManager) -> HandlerResult {
+const DEFAULT_DETAIL_DAYS: u32 = 7;
+const TOP_USERS_LIMIT: u32 = 5;
+const DEFAULT_CHART_DAYS: u32 = 30;
+const MAX_CHART_DAYS: u32 = 365;
+
+async fn handle_show_stats(
+    bot: Bot,
+    msg: Message,
+    args: CommandArgs,
+    stats_mgr: StatsManager,
+    role_mgr: RoleManager,
+    config: SharedConfig,
+) -> HandlerResult {
+    let mut args_iter = args.0.split_whitespace();
+    match args_iter.next() {
+        Some("detail") => {
+            let days = args_iter
+                .next()
+                .and_then(|days_str| days_str.parse().ok())
+                .filter(|days| *days > 0)
+                .unwrap_or(DEFAULT_DETAIL_DAYS);
+            // The top users are only shown to admins, since they reveal the
+            // usage of other users.
+            let shows_top_users = check_role(&msg, &role_mgr, MemberRole::Admin).await;
+            return handle_show_stats_detail(bot, msg, days, shows_top_users, stats_mgr, config)
+                .await;
+        }
+        Some("today") => {
+            let since = stats_mgr.days_ago_timestamp(1);
+            return handle_show_period_stats(bot, msg, "today", since, stats_mgr).await;
+        }
+        Some("month") => {
+            let since = stats_mgr.month_start_timestamp();
+            return handle_show_period_stats(bot, msg, "this month", since, stats_mgr).await;
+        }
+        _ => {}
+    }
+
     let mut reply_text = String::new();
     if let Some(from_username) = msg.from().and_then(|u| u.username.as_ref()) {
         let user_usage = stats_mgr
             .query_usage(Some(from_username.to_owned()))
             .await?;
-        writeln!(&mut reply_text, "Your token usage: {}", user_usage)?;
+        writeln!(
+            &mut reply_text,
+            "Your token usage: {} (~${:.4})",
+            user_usage.tokens, user_usage.cost
+        )?;
     }
     let total_usage = stats_mgr.query_usage(None).await?;
-    write!(&mut reply_text, "Total token usage: {}", total_usage)?;
+    write!(
+        &mut reply_text,
+        "Total token usage: {} (~${:.4})",
+        total_usage.tokens, total_usage.cost
+    )?;
+
+    bot.send_message(msg.chat.id, reply_text)
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+/// Shows the usage since the start of the period, in the configured
+/// timezone.
+async fn handle_show_period_stats(
+    bot: Bot,
+    msg: Message,
+    period: &str,
+    since: i64,
+    stats_mgr: StatsManager,
+) -> HandlerResult {
+    let mut reply_text = String::new();
+    if let Some(from_username) = msg.from().and_then(|u| u.username.as_ref()) {
+        let user_usage = stats_mgr
+            .query_usage_since(Some(from_username.to_owned()), since)
+            .await?;
+        writeln!(
+            &mut reply_text,
+            "Your token usage {}: {} (~${:.4})",
+            period, user_usage.tokens, user_usage.cost
+        )?;
+    }
+    let total_usage = stats_mgr.query_usage_since(None, since).await?;
+    write!(
+        &mut reply_text,
+        "Total token usage {}: {} (~${:.4})",
+        period, total_usage.tokens, total_usage.cost
+    )?;
+
+    bot.send_message(msg.chat.id, reply_text)
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+async fn handle_show_stats_detail(
+    bot: Bot,
+    msg: Message,
+    days: u32,
+    shows_top_users: bool,
+    stats_mgr: StatsManager,
+    config: SharedConfig,
+) -> HandlerResult {
+    let daily_usage = stats_mgr.query_daily_usage(days).await?;
+    let model_usage = stats_mgr.query_model_usage(days).await?;
+
+    let mut table = String::new();
+    writeln!(&mut table, "Usage in the last {} days:", days)?;
+    let timezone = config.load().timezone;
+    for (day, tokens) in &daily_usage {
+        let day = local_date(*day, &timezone).format("%Y-%m-%d");
+        writeln!(&mut table, "{:<12}{:>10}", day, tokens)?;
+    }
+    if shows_top_users {
+        let top_users = stats_mgr.query_top_users(days, TOP_USERS_LIMIT).await?;
+        writeln!(&mut table, "\nTop users:")?;
+        for (idx, (user_id, tokens)) in top_users.iter().enumerate() {
+            let user = format!("{}. {}", idx + 1, user_id);
+            writeln!(&mut table, "{:<20}{:>10}", user, tokens)?;
+        }
+    }
+    writeln!(&mut table, "\nUsage by model:")?;
+    for (model, tokens) in &model_usage {
+        writeln!(&mut table, "{:<20}{:>10}", model, tokens)?;
+    }
+    let table = table.trim_end();
+
+    let entity = MessageEntity::pre(None, 0, table.encode_utf16().count());
+    bot.send_message(msg.chat.id, table)
+        .entities([entity])
+        .reply_to_message_id(msg.id)
+        .send_retrying()
+        .await?;
+
+    Ok(())
+}
+
+async fn handle_usage_chart(
+    bot: Bot,
+    msg: Message,
+    (days,): (Option<u32>,),
+    stats_mgr: StatsManager,
+    config: SharedConfig,
+) -> HandlerResult {
+    let days = days
+        .filter(|days| *days > 0)
+        .unwrap_or(DEFAULT_CHART_DAYS)
+        .min(MAX_CHART_DAYS);
+    let series = stats_mgr.query_daily_series(days).await?;
+
+    let timezone = config.load().timezone;
+    let format_day = |day: i64| local_date(day, &timezone).format("%Y-%m-%d").to_string();
+    let tokens: Vec<_> = series.iter().map(|(_, tokens)| *tokens).collect();
+    let caption = format!(
+        "Token usage from {} to {}\nTotal: {}, peak: {} per day",
+        series
+            .first()
+            .map(|(day, _)| format_day(*day))
+            .unwrap_or_default(),
+        series
+            .last()
+            .map(|(day, _)| format_day(*day))
+            .unwrap_or_default(),
+        tokens.iter().sum::<i64>(),
+        tokens.iter().max().copied().unwrap_or(0),
+    );
+
+    let chart = chart::render_bar_chart(&tokens);
+    bot.send_photo(msg.chat.id, InputFile::memory(chart).file_name("usage.png"))
+        .caption(caption)
+        .reply_to_message_id(msg.id)
+        .await?;
+
+    Ok(())
+}
+
+/// Shows the usage of the sender today on each budgeted feature.
+async fn handle_show_usage(
+    bot: Bot,
+    msg: Message,
+    quota_mgr: QuotaManager,
+    role_mgr: RoleManager,
+    config: SharedConfig,
+) -> HandlerResult {
+    let user = match msg.from() {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+    let user_id = user.id.0;
+
+    let mut features = QuotaFeature::ALL.to_vec();
+    if role_mgr.role_of(user).await? == MemberRole::Guest {
+        features.push(QuotaFeature::GuestMessages);
+    }
+
+    let quotas = config.load().daily_quotas;
+    let mut reply_text = String::from("Your usage today:");
+    for feature in features {
+        let usage = quota_mgr.query_usage(user_id, feature).await?;
+        let limit = feature
+            .limit(&quotas)
+            .map_or("unlimited".to_owned(), |limit| limit.to_string());
+        write!(
+            &mut reply_text,
+            "\n{}: {} / {}",
+            feature.name(),
+            usage,
+            limit
+        )?;
+    }
 
     bot.send_message(msg.chat.id, reply_text)
         .reply_to_message_id(msg.id)
-        .send()
+        .send_retrying()
         .await?;
 
     Ok(())
 }
 
+async fn record_event(stats_mgr: StatsManager, quota_mgr: QuotaManager, event: Event) {
+    let res = match event {
+        Event::ChatCompleted {
+            chat_id,
+            user_id,
+            username,
+            model,
+            prompt_tokens,
+            completion_tokens,
+            cost,
+            is_regeneration,
+            ..
+        } => {
+            let tokens = (prompt_tokens + completion_tokens) as _;
+            if let Some(user_id) = user_id {
+                let res = quota_mgr
+                    .add_usage(user_id, QuotaFeature::ChatTokens, tokens as _)
+                    .await;
+                if let Err(err) = res {
+                    error!("Failed to add quota usage: {}", err);
+                }
+            }
+            let res = stats_mgr
+                .log_request(
+                    chat_id,
+                    username.clone().unwrap_or_default(),
+                    tokens,
+                    true,
+                    is_regeneration,
+                )
+                .await;
+            // TODO: maybe we need to handle the case that the user is unknown.
+            match (res, username) {
+                (Ok(_), Some(username)) => {
+                    stats_mgr
+                        .add_usage(
+                            username,
+                            model,
+                            prompt_tokens as _,
+                            completion_tokens as _,
+                            cost,
+                        )
+                        .await
+                }
+                (res, _) => res,
+            }
+        }
+        Event::ModelErrored {
+            chat_id, username, ..
+        } => {
+            stats_mgr
+                .log_request(chat_id, username.unwrap_or_default(), 0, false, false)
+                .await
+        }
+        Event::ContentFlagged {
+            chat_id,
+            username,
+            is_output,
+            categories,
+            blocked,
+        } => {
+            stats_mgr
+                .log_moderation(
+                    chat_id,
+                    username.unwrap_or_default(),
+                    is_output,
+                    categories,
+                    blocked,
+                )
+                .await
+        }
+        Event::FeedbackReceived {
+            chat_id,
+            message_id,
+            user_id,
+            rating,
+            model,
+            prompt_hash,
+        } => {
+            stats_mgr
+                .log_feedback(
+                    chat_id,
+                    message_id,
+                    user_id.to_string(),
+                    rating,
+                    model,
+                    prompt_hash,
+                )
+                .await
+        }
+        _ => Ok(()),
+    };
+    if let Err(err) = res {
+        error!("Failed to update stats: {}", err);
+    }
+}
+
 #[async_trait]
 impl Module for Stats {
     async fn register_dependency(&mut self, dep_map: &mut DependencyMap) -> Result<(), Error> {
-        let stats_mgr = StatsManager::with_db_manager(self.db_mgr.clone()).await?;
+        let event_bus: Arc<EventBus> = dep_map.get();
+        let scheduler: Arc<Scheduler> = dep_map.get();
+        let bot: Arc<Bot> = dep_map.get();
+
+        let config: Arc<SharedConfig> = dep_map.get();
+
+        let stats_mgr =
+            StatsManager::with_db_manager(self.db_mgr.clone(), config.as_ref().clone()).await?;
+        let quota_mgr = QuotaManager::new(self.db_mgr.clone(), config.as_ref().clone()).await?;
+        let (subscriber_stats_mgr, subscriber_quota_mgr) = (stats_mgr.clone(), quota_mgr.clone());
+        event_bus.subscribe(move |event| {
+            record_event(
+                subscriber_stats_mgr.clone(),
+                subscriber_quota_mgr.clone(),
+                event,
+            )
+        });
+        stats_mgr.start_pruning_task();
+        digest::schedule_daily_digest(
+            &scheduler,
+            bot.as_ref().clone(),
+            stats_mgr.clone(),
+            config.as_ref().clone(),
+        );
         dep_map.insert(stats_mgr);
+        dep_map.insert(quota_mgr);
         Ok(())
     }
 
     fn commands(&self) -> Vec<Command> {
-        vec![Command::new(
-            "stats",
-            "Show the token usage and other stats",
-            dptree::endpoint(handle_show_stats),
-        )]
+        vec![
+            Command::new(
+                "stats",
+                "Show the token usage and other stats (use \"today\", \"month\" or \"detail\" for reports)",
+                dptree::endpoint(handle_show_stats),
+            ),
+            Command::new(
+                "usage",
+                "Show your usage and quotas today",
+                dptree::endpoint(handle_show_usage),
+            ),
+            Command::new(
+                "usage_chart",
+                "Show the daily token usage as a chart",
+                command_with_args::<(Option<u32>,)>("usage_chart").endpoint(handle_usage_chart),
+            ),
+        ]
     }
 }
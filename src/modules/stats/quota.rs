@@ -0,0 +1,121 @@
+use anyhow::Error;
+use rusqlite::OptionalExtension;
+
+use super::calendar::{day_number, local_today};
+use crate::{
+    config::{DailyQuotas, SharedConfig},
+    database::DatabaseManager,
+};
+
+/// A feature that is budgeted separately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum QuotaFeature {
+    ChatTokens,
+    Images,
+    VoiceMessages,
+    /// The messages of guests, which is not listed in [`QuotaFeature::ALL`]
+    /// since other users are not limited by it.
+    GuestMessages,
+}
+
+impl QuotaFeature {
+    pub const ALL: [QuotaFeature; 3] = [
+        QuotaFeature::ChatTokens,
+        QuotaFeature::Images,
+        QuotaFeature::VoiceMessages,
+    ];
+
+    fn key(&self) -> &'static str {
+        match self {
+            QuotaFeature::ChatTokens => "chat_tokens",
+            QuotaFeature::Images => "images",
+            QuotaFeature::VoiceMessages => "voice_messages",
+            QuotaFeature::GuestMessages => "guest_messages",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            QuotaFeature::ChatTokens => "chat tokens",
+            QuotaFeature::Images => "images",
+            QuotaFeature::VoiceMessages => "voice messages",
+            QuotaFeature::GuestMessages => "messages",
+        }
+    }
+
+    pub fn limit(&self, quotas: &DailyQuotas) -> Option<u64> {
+        match self {
+            QuotaFeature::ChatTokens => quotas.chat_tokens,
+            QuotaFeature::Images => quotas.images,
+            QuotaFeature::VoiceMessages => quotas.voice_messages,
+            QuotaFeature::GuestMessages => quotas.guest_messages,
+        }
+    }
+}
+
+/// Tracks the daily usage of each user on the budgeted features. The
+/// usage is reset at midnight in the configured timezone.
+#[derive(Clone)]
+pub(crate) struct QuotaManager {
+    db_mgr: DatabaseManager,
+    config: SharedConfig,
+}
+
+impl QuotaManager {
+    pub async fn new(db_mgr: DatabaseManager, config: SharedConfig) -> Result<Self, Error> {
+        Ok(Self { db_mgr, config })
+    }
+
+    pub async fn add_usage(
+        &self,
+        user_id: u64,
+        feature: QuotaFeature,
+        amount: u64,
+    ) -> Result<(), Error> {
+        let day = self.today();
+        self.db_mgr
+            .enqueue_work(move |conn| {
+                let sql = "INSERT INTO quota_usage VALUES (?1, ?2, ?3, ?4) \
+                    ON CONFLICT (user_id, feature, day) DO UPDATE SET amount = amount + excluded.amount;";
+                let res = conn.execute(sql, (user_id as i64, feature.key(), day, amount as i64));
+                if let Err(err) = res {
+                    error!("Failed to add quota usage: {}", err);
+                }
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Returns the usage of the user on the feature today (in the configured timezone).
+    pub async fn query_usage(&self, user_id: u64, feature: QuotaFeature) -> Result<u64, Error> {
+        let day = self.today();
+        self.db_mgr
+            .query(move |conn| {
+                let sql =
+                    "SELECT amount FROM quota_usage WHERE user_id = ? AND feature = ? AND day = ?";
+                let amount: Option<i64> = conn
+                    .query_row(sql, (user_id as i64, feature.key(), day), |row| row.get(0))
+                    .optional()?;
+                Ok(amount.unwrap_or(0) as u64)
+            })
+            .await?
+    }
+
+    /// Returns `true` if the user has used up the quota of the feature.
+    pub async fn is_exceeded(
+        &self,
+        user_id: u64,
+        feature: QuotaFeature,
+        quotas: &DailyQuotas,
+    ) -> Result<bool, Error> {
+        match feature.limit(quotas) {
+            Some(limit) => Ok(self.query_usage(user_id, feature).await? >= limit),
+            None => Ok(false),
+        }
+    }
+
+    fn today(&self) -> i64 {
+        day_number(local_today(&self.config.load().timezone))
+    }
+}
@@ -1,42 +1,132 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::Error;
-use rusqlite::{Connection as SqliteConnection, OptionalExtension};
+use rusqlite::{types::FromSql, Connection as SqliteConnection, OptionalExtension, Row};
 
-use crate::database::DatabaseManager;
+use super::calendar::{local_date, local_hour_start, local_midnight, local_today, month_start};
+use crate::{config::SharedConfig, database::DatabaseManager};
+
+/// The accumulated usage of a user or the whole bot.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Usage {
+    pub tokens: i64,
+    /// The estimated cost in USD.
+    pub cost: f64,
+}
+
+impl Usage {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            tokens: row.get::<_, Option<i64>>(0)?.unwrap_or(0),
+            cost: row.get::<_, Option<f64>>(1)?.unwrap_or(0.0),
+        })
+    }
+}
+
+/// The activities of a chat in a period.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ChatReport {
+    pub requests: i64,
+    pub failed_requests: i64,
+    pub active_users: i64,
+    pub tokens: i64,
+    /// Users with the most requests, and their request counts.
+    pub top_askers: Vec<(String, i64)>,
+}
+
+/// The usage of the whole bot in a period.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct UsageDigest {
+    pub requests: i64,
+    pub usage: Usage,
+    /// Chats with the most requests, and their request and token counts.
+    pub top_chats: Vec<(String, i64, i64)>,
+    /// Users with the most tokens, and their usage.
+    pub top_users: Vec<(String, Usage)>,
+}
+
+/// The flagged contents in a period.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ModerationReport {
+    pub flagged_inputs: i64,
+    pub flagged_outputs: i64,
+    pub blocked: i64,
+    /// Flagged categories and their counts, in descending order.
+    pub categories: Vec<(String, i64)>,
+    /// Users with the most flagged messages, and their counts.
+    pub top_users: Vec<(String, i64)>,
+}
+
+/// The ratings of answers in a period.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FeedbackReport {
+    pub thumbs_up: i64,
+    pub thumbs_down: i64,
+    /// Models with their thumbs up and down counts, in descending order of
+    /// the total count.
+    pub models: Vec<(String, i64, i64)>,
+}
+
+/// The token usage of a user in an hour.
+#[derive(Clone, Debug)]
+pub(crate) struct UsageRecord {
+    pub user_id: String,
+    /// The unix timestamp when the hour starts in the configured timezone.
+    pub time: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub tokens: i64,
+    pub cost: f64,
+}
 
 #[derive(Clone)]
 pub(crate) struct StatsManager {
     db_mgr: DatabaseManager,
+    config: SharedConfig,
 }
 
 impl StatsManager {
-    pub async fn with_db_manager(db_mgr: DatabaseManager) -> Result<Self, Error> {
-        // Initialize the database table before returning.
-        let ok = db_mgr.query(|conn| {
-            let sql = "CREATE TABLE IF NOT EXISTS token_usage (user_id TEXT NOT NULL, time INTEGER NOT NULL, tokens INTEGER NOT NULL, PRIMARY KEY (user_id, time));";
-            conn.execute(sql, ()).unwrap();
-            true
-        }).await?;
-        if !ok {
-            return Err(anyhow!("Failed to initialize database table"));
-        }
-
-        Ok(Self { db_mgr })
+    pub async fn with_db_manager(
+        db_mgr: DatabaseManager,
+        config: SharedConfig,
+    ) -> Result<Self, Error> {
+        Ok(Self { db_mgr, config })
     }
 
-    pub async fn add_usage(&self, user_id: String, tokens: i64) -> Result<(), Error> {
+    pub async fn add_usage(
+        &self,
+        user_id: String,
+        model: String,
+        prompt_tokens: i64,
+        completion_tokens: i64,
+        cost: f64,
+    ) -> Result<(), Error> {
         let now = SystemTime::now();
         let unix_timestamp = now.duration_since(UNIX_EPOCH).unwrap();
-        let hour_grouped_timestamp_secs: i64 = (unix_timestamp.as_secs() / 3600 * 3600) as _;
+        // Grouped by the hours in the timezone, so that the hours are not
+        // split by the days in it.
+        let hour_grouped_timestamp_secs =
+            local_hour_start(unix_timestamp.as_secs() as _, &self.config.load().timezone);
 
         self.db_mgr.enqueue_work(move |conn| {
-            let sql = "INSERT OR REPLACE INTO token_usage VALUES (?, ?, COALESCE((SELECT tokens FROM token_usage WHERE user_id = ? AND time = ?), 0) + ?);";
+            let sql = "INSERT INTO token_usage (user_id, time, tokens, prompt_tokens, completion_tokens, cost) VALUES (?1, ?2, ?3 + ?4, ?3, ?4, ?5) \
+                ON CONFLICT (user_id, time) DO UPDATE SET tokens = tokens + excluded.tokens, prompt_tokens = prompt_tokens + excluded.prompt_tokens, \
+                completion_tokens = completion_tokens + excluded.completion_tokens, cost = cost + excluded.cost;";
             let mut stmt = conn.prepare(sql).unwrap();
 
-            let user_id = &user_id;
             let time = hour_grouped_timestamp_secs;
-            let updated_rows = stmt.execute((user_id, time, user_id, time, tokens)).unwrap_or(0);
+            let updated_rows = stmt.execute((&user_id, time, prompt_tokens, completion_tokens, cost)).unwrap_or(0);
+            if updated_rows != 1 {
+                error!("Unexpected updated rows: {}", updated_rows);
+            }
+
+            let sql = "INSERT OR REPLACE INTO model_usage VALUES (?, ?, COALESCE((SELECT tokens FROM model_usage WHERE model = ? AND time = ?), 0) + ?);";
+            let mut stmt = conn.prepare(sql).unwrap();
+
+            let model = &model;
+            let tokens = prompt_tokens + completion_tokens;
+            let updated_rows = stmt.execute((model, time, model, time, tokens)).unwrap_or(0);
             if updated_rows != 1 {
                 error!("Unexpected updated rows: {}", updated_rows);
             }
@@ -45,7 +135,191 @@ impl StatsManager {
         Ok(())
     }
 
-    pub async fn query_usage(&self, user_id: Option<String>) -> Result<i64, Error> {
+    /// Records a request to the model, which is used for the per-chat
+    /// reports.
+    pub async fn log_request(
+        &self,
+        chat_id: String,
+        user_id: String,
+        tokens: i64,
+        succeeded: bool,
+        regenerated: bool,
+    ) -> Result<(), Error> {
+        let unix_timestamp_secs: i64 = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as _;
+
+        self.db_mgr
+            .enqueue_work(move |conn| {
+                let sql = "INSERT INTO request_log (chat_id, user_id, time, tokens, succeeded, regenerated) \
+                    VALUES (?, ?, ?, ?, ?, ?);";
+                let res = conn.execute(
+                    sql,
+                    (
+                        chat_id,
+                        user_id,
+                        unix_timestamp_secs,
+                        tokens,
+                        succeeded,
+                        regenerated,
+                    ),
+                );
+                if let Err(err) = res {
+                    error!("Failed to log request: {}", err);
+                }
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records a content flagged by the moderation, for admins to review.
+    pub async fn log_moderation(
+        &self,
+        chat_id: String,
+        user_id: String,
+        is_output: bool,
+        categories: Vec<String>,
+        blocked: bool,
+    ) -> Result<(), Error> {
+        let unix_timestamp_secs: i64 = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as _;
+
+        self.db_mgr
+            .enqueue_work(move |conn| {
+                let sql = "INSERT INTO moderation_log VALUES (?, ?, ?, ?, ?, ?);";
+                let res = conn.execute(
+                    sql,
+                    (
+                        chat_id,
+                        user_id,
+                        unix_timestamp_secs,
+                        is_output,
+                        categories.join(","),
+                        blocked,
+                    ),
+                );
+                if let Err(err) = res {
+                    error!("Failed to log moderation: {}", err);
+                }
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records the rating of an answer, replacing the previous rating of the
+    /// same user.
+    pub async fn log_feedback(
+        &self,
+        chat_id: String,
+        message_id: i32,
+        user_id: String,
+        rating: i8,
+        model: String,
+        prompt_hash: String,
+    ) -> Result<(), Error> {
+        let unix_timestamp_secs: i64 = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as _;
+
+        self.db_mgr
+            .enqueue_work(move |conn| {
+                let sql = "INSERT OR REPLACE INTO feedback VALUES (?, ?, ?, ?, ?, ?, ?);";
+                let res = conn.execute(
+                    sql,
+                    (
+                        chat_id,
+                        message_id,
+                        user_id,
+                        rating,
+                        model,
+                        prompt_hash,
+                        unix_timestamp_secs,
+                    ),
+                );
+                if let Err(err) = res {
+                    error!("Failed to log feedback: {}", err);
+                }
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Returns the report of ratings in the last `days` days.
+    pub async fn query_feedback_report(&self, days: u32) -> Result<FeedbackReport, Error> {
+        let since = self.days_ago_timestamp(days);
+        self.db_mgr
+            .query(move |conn| {
+                let sql = "SELECT model, COALESCE(SUM(rating > 0), 0) AS up, COALESCE(SUM(rating < 0), 0) AS down \
+                    FROM feedback WHERE time >= ? GROUP BY model ORDER BY up + down DESC, model";
+                let mut stmt = conn.prepare(sql)?;
+                let rows = stmt.query_map((since,), |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })?;
+                let models = rows.collect::<Result<Vec<(String, i64, i64)>, _>>()?;
+
+                Ok(FeedbackReport {
+                    thumbs_up: models.iter().map(|(_, up, _)| up).sum(),
+                    thumbs_down: models.iter().map(|(_, _, down)| down).sum(),
+                    models,
+                })
+            })
+            .await?
+    }
+
+    /// Returns the report of flagged contents in the last `days` days.
+    pub async fn query_moderation_report(
+        &self,
+        days: u32,
+        top_limit: u32,
+    ) -> Result<ModerationReport, Error> {
+        let since = self.days_ago_timestamp(days);
+        self.db_mgr
+            .query(move |conn| {
+                let sql = "SELECT COALESCE(SUM(1 - is_output), 0), COALESCE(SUM(is_output), 0), COALESCE(SUM(blocked), 0) \
+                    FROM moderation_log WHERE time >= ?";
+                let (flagged_inputs, flagged_outputs, blocked) =
+                    conn.query_row(sql, (since,), |row| {
+                        Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                    })?;
+
+                let sql = "SELECT categories FROM moderation_log WHERE time >= ?";
+                let mut stmt = conn.prepare(sql)?;
+                let rows = stmt.query_map((since,), |row| row.get::<_, String>(0))?;
+                let mut category_counts: HashMap<String, i64> = HashMap::new();
+                for categories in rows {
+                    for category in categories?.split(',').filter(|c| !c.is_empty()) {
+                        *category_counts.entry(category.to_owned()).or_default() += 1;
+                    }
+                }
+                let mut categories: Vec<_> = category_counts.into_iter().collect();
+                categories.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+                let sql = "SELECT user_id, COUNT(*) AS flags FROM moderation_log WHERE time >= ?1 \
+                    GROUP BY user_id ORDER BY flags DESC LIMIT ?2";
+                let mut stmt = conn.prepare(sql)?;
+                let rows =
+                    stmt.query_map((since, top_limit), |row| Ok((row.get(0)?, row.get(1)?)))?;
+                let top_users = rows.collect::<Result<Vec<_>, _>>()?;
+
+                Ok(ModerationReport {
+                    flagged_inputs,
+                    flagged_outputs,
+                    blocked,
+                    categories,
+                    top_users,
+                })
+            })
+            .await?
+    }
+
+    pub async fn query_usage(&self, user_id: Option<String>) -> Result<Usage, Error> {
         let usage = self
             .db_mgr
             .query(|conn| {
@@ -59,7 +333,7 @@ impl StatsManager {
                     Ok(usage) => usage,
                     Err(err) => {
                         error!("Failed to query usage: {}", err);
-                        0
+                        Usage::default()
                     }
                 }
             })
@@ -67,20 +341,377 @@ impl StatsManager {
 
         Ok(usage)
     }
+
+    /// Deletes the usage recorded before the timestamp, and returns the
+    /// number of deleted token usage rows. The token usage is rolled up
+    /// into the monthly totals (in UTC) of the users first, so that the
+    /// all-time usage is kept.
+    pub async fn prune_usage(&self, before: i64) -> Result<usize, Error> {
+        self.db_mgr
+            .write(move |conn| {
+                let tx = conn.transaction()?;
+                let sql = "INSERT INTO usage_monthly (user_id, month, tokens, prompt_tokens, completion_tokens, cost) \
+                    SELECT user_id, strftime('%Y-%m', time, 'unixepoch'), SUM(tokens), SUM(prompt_tokens), SUM(completion_tokens), SUM(cost) \
+                    FROM token_usage WHERE time < ?1 GROUP BY 1, 2 \
+                    ON CONFLICT (user_id, month) DO UPDATE SET tokens = tokens + excluded.tokens, prompt_tokens = prompt_tokens + excluded.prompt_tokens, \
+                    completion_tokens = completion_tokens + excluded.completion_tokens, cost = cost + excluded.cost;";
+                tx.execute(sql, (before,))?;
+                let pruned = tx.execute("DELETE FROM token_usage WHERE time < ?", (before,))?;
+                tx.execute("DELETE FROM model_usage WHERE time < ?", (before,))?;
+                tx.execute("DELETE FROM request_log WHERE time < ?", (before,))?;
+                tx.commit()?;
+                Ok::<_, Error>(pruned)
+            })
+            .await?
+    }
+
+    /// Starts a background task that prunes the usage older than
+    /// `statsRetentionDays` periodically.
+    pub fn start_pruning_task(&self) {
+        let stats_mgr = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+            loop {
+                interval.tick().await;
+
+                // Read the config on each tick, since it may be reloaded.
+                let retention_days = match stats_mgr.config.load().stats_retention_days {
+                    Some(days) => days,
+                    None => continue,
+                };
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+                let before = now.as_secs() as i64 - retention_days as i64 * 24 * 60 * 60;
+                match stats_mgr.prune_usage(before).await {
+                    Ok(0) => {}
+                    Ok(pruned) => info!("Pruned {} rows of usage", pruned),
+                    Err(err) => error!("Failed to prune the usage: {}", err),
+                }
+            }
+        });
+    }
+
+    /// Returns all the recorded token usage, ordered by time.
+    pub async fn export_usage(&self) -> Result<Vec<UsageRecord>, Error> {
+        self.db_mgr
+            .query(|conn| {
+                let sql = "SELECT user_id, time, prompt_tokens, completion_tokens, tokens, cost FROM token_usage ORDER BY time, user_id";
+                let mut stmt = conn.prepare(sql)?;
+                let records = stmt
+                    .query_map((), |row| {
+                        Ok(UsageRecord {
+                            user_id: row.get(0)?,
+                            time: row.get(1)?,
+                            prompt_tokens: row.get(2)?,
+                            completion_tokens: row.get(3)?,
+                            tokens: row.get(4)?,
+                            cost: row.get(5)?,
+                        })
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(records)
+            })
+            .await?
+    }
+
+    /// Returns the usage since the timestamp, of the user or the whole bot.
+    pub async fn query_usage_since(
+        &self,
+        user_id: Option<String>,
+        since: i64,
+    ) -> Result<Usage, Error> {
+        self.db_mgr
+            .query(move |conn| {
+                let usage = match user_id {
+                    Some(user_id) => {
+                        let sql = "SELECT SUM(tokens), SUM(cost) FROM token_usage WHERE user_id = ? AND time >= ?";
+                        conn.query_row(sql, (user_id, since), Usage::from_row)
+                    }
+                    None => {
+                        let sql = "SELECT SUM(tokens), SUM(cost) FROM token_usage WHERE time >= ?";
+                        conn.query_row(sql, (since,), Usage::from_row)
+                    }
+                };
+                Ok(usage?)
+            })
+            .await?
+    }
+
+    /// Returns the usage of each day (in the configured timezone) in the
+    /// last `days` days, ordered by time. Days without usage are not
+    /// included.
+    pub async fn query_daily_usage(&self, days: u32) -> Result<Vec<(i64, i64)>, Error> {
+        let since = self.days_ago_timestamp(days);
+        // The usage is grouped by hours, which are regrouped by the days in
+        // the timezone.
+        let hourly_usage: Vec<(i64, i64)> = self
+            .db_mgr
+            .query(move |conn| {
+                let sql = "SELECT time, SUM(tokens) FROM token_usage WHERE time >= ? GROUP BY time ORDER BY time";
+                Self::query_pairs(conn, sql, since)
+            })
+            .await??;
+
+        let timezone = self.config.load().timezone;
+        let mut daily_usage: Vec<(i64, i64)> = vec![];
+        for (hour, tokens) in hourly_usage {
+            let day = local_midnight(local_date(hour, &timezone), &timezone);
+            match daily_usage.last_mut() {
+                Some((last_day, last_tokens)) if *last_day == day => *last_tokens += tokens,
+                _ => daily_usage.push((day, tokens)),
+            }
+        }
+        Ok(daily_usage)
+    }
+
+    /// Returns the usage of each day (in the configured timezone) in the
+    /// last `days` days, ordered by time. Unlike `query_daily_usage`, days
+    /// without usage are included with zero tokens.
+    pub async fn query_daily_series(&self, days: u32) -> Result<Vec<(i64, i64)>, Error> {
+        let daily_usage = self.query_daily_usage(days).await?;
+        let timezone = self.config.load().timezone;
+        let first_date = local_today(&timezone) - chrono::Duration::days(days as i64 - 1);
+        let series = (0..days as i64)
+            .map(|idx| {
+                // Days are not always 24 hours long with the daylight saving
+                // time.
+                let date = first_date + chrono::Duration::days(idx);
+                let day = local_midnight(date, &timezone);
+                let tokens = daily_usage
+                    .iter()
+                    .find(|(d, _)| *d == day)
+                    .map(|(_, tokens)| *tokens)
+                    .unwrap_or(0);
+                (day, tokens)
+            })
+            .collect();
+        Ok(series)
+    }
+
+    /// Returns the users with the most usage in the last `days` days.
+    pub async fn query_top_users(
+        &self,
+        days: u32,
+        limit: u32,
+    ) -> Result<Vec<(String, i64)>, Error> {
+        let since = self.days_ago_timestamp(days);
+        self.db_mgr
+            .query(move |conn| {
+                let sql = "SELECT user_id, SUM(tokens) AS total FROM token_usage WHERE time >= ?1 GROUP BY user_id ORDER BY total DESC LIMIT ?2";
+                let mut stmt = conn.prepare(sql)?;
+                let rows = stmt.query_map((since, limit), |row| Ok((row.get(0)?, row.get(1)?)))?;
+                Ok(rows.collect::<Result<Vec<_>, _>>()?)
+            })
+            .await?
+    }
+
+    /// Returns the report of the given chat in the last `days` days.
+    pub async fn query_chat_report(
+        &self,
+        chat_id: String,
+        days: u32,
+        top_limit: u32,
+    ) -> Result<ChatReport, Error> {
+        let since = self.days_ago_timestamp(days);
+        self.db_mgr
+            .query(move |conn| {
+                // Regenerated answers only count for the tokens, since they
+                // answer the same questions again.
+                let sql = "SELECT COALESCE(SUM(1 - regenerated), 0), COALESCE(SUM(1 - succeeded), 0), COUNT(DISTINCT user_id), COALESCE(SUM(tokens), 0) \
+                    FROM request_log WHERE chat_id = ?1 AND time >= ?2";
+                let (requests, failed_requests, active_users, tokens) =
+                    conn.query_row(sql, (&chat_id, since), |row| {
+                        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                    })?;
+
+                let sql = "SELECT user_id, SUM(1 - regenerated) AS requests FROM request_log WHERE chat_id = ?1 AND time >= ?2 \
+                    GROUP BY user_id ORDER BY requests DESC LIMIT ?3";
+                let mut stmt = conn.prepare(sql)?;
+                let rows = stmt.query_map((&chat_id, since, top_limit), |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?;
+                let top_askers = rows.collect::<Result<Vec<_>, _>>()?;
+
+                Ok(ChatReport {
+                    requests,
+                    failed_requests,
+                    active_users,
+                    tokens,
+                    top_askers,
+                })
+            })
+            .await?
+    }
+
+    /// Returns the usage of the whole bot between the timestamps.
+    pub async fn query_usage_digest(
+        &self,
+        since: i64,
+        until: i64,
+        top_limit: u32,
+    ) -> Result<UsageDigest, Error> {
+        self.db_mgr
+            .query(move |conn| {
+                let sql = "SELECT COALESCE(SUM(1 - regenerated), 0) FROM request_log WHERE time >= ?1 AND time < ?2";
+                let requests = conn.query_row(sql, (since, until), |row| row.get(0))?;
+                let sql = "SELECT SUM(tokens), SUM(cost) FROM token_usage WHERE time >= ?1 AND time < ?2";
+                let usage = conn.query_row(sql, (since, until), Usage::from_row)?;
+
+                let sql = "SELECT chat_id, SUM(1 - regenerated) AS requests, SUM(tokens) FROM request_log WHERE time >= ?1 AND time < ?2 \
+                    GROUP BY chat_id ORDER BY requests DESC LIMIT ?3";
+                let mut stmt = conn.prepare(sql)?;
+                let rows = stmt.query_map((since, until, top_limit), |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })?;
+                let top_chats = rows.collect::<Result<Vec<_>, _>>()?;
+
+                let sql = "SELECT user_id, SUM(tokens) AS total, SUM(cost) FROM token_usage WHERE time >= ?1 AND time < ?2 \
+                    GROUP BY user_id ORDER BY total DESC LIMIT ?3";
+                let mut stmt = conn.prepare(sql)?;
+                let rows = stmt.query_map((since, until, top_limit), |row| {
+                    Ok((
+                        row.get(0)?,
+                        Usage {
+                            tokens: row.get(1)?,
+                            cost: row.get(2)?,
+                        },
+                    ))
+                })?;
+                let top_users = rows.collect::<Result<Vec<_>, _>>()?;
+
+                Ok(UsageDigest {
+                    requests,
+                    usage,
+                    top_chats,
+                    top_users,
+                })
+            })
+            .await?
+    }
+
+    /// Returns the usage of each model in the last `days` days.
+    pub async fn query_model_usage(&self, days: u32) -> Result<Vec<(String, i64)>, Error> {
+        let since = self.days_ago_timestamp(days);
+        self.db_mgr
+            .query(move |conn| {
+                let sql = "SELECT model, SUM(tokens) AS total FROM model_usage WHERE time >= ? GROUP BY model ORDER BY total DESC";
+                Self::query_pairs(conn, sql, since)
+            })
+            .await?
+    }
 }
 
 impl StatsManager {
-    fn query_usage_of_user(conn: &mut SqliteConnection, user_id: &str) -> Result<i64, Error> {
-        let sql = "SELECT SUM(tokens) FROM token_usage WHERE user_id = ?";
+    /// Returns when the day `days - 1` days ago starts in the configured
+    /// timezone, so that the last `days` days include today.
+    pub fn days_ago_timestamp(&self, days: u32) -> i64 {
+        let timezone = self.config.load().timezone;
+        let date = local_today(&timezone) - chrono::Duration::days(days.saturating_sub(1) as i64);
+        local_midnight(date, &timezone)
+    }
+
+    /// Returns when this month starts in the configured timezone.
+    pub fn month_start_timestamp(&self) -> i64 {
+        let timezone = self.config.load().timezone;
+        local_midnight(month_start(local_today(&timezone)), &timezone)
+    }
+
+    fn query_pairs<K>(
+        conn: &mut SqliteConnection,
+        sql: &str,
+        since: i64,
+    ) -> Result<Vec<(K, i64)>, Error>
+    where
+        K: FromSql,
+    {
+        let mut stmt = conn.prepare(sql)?;
+        let rows = stmt.query_map((since,), |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn query_usage_of_user(conn: &mut SqliteConnection, user_id: &str) -> Result<Usage, Error> {
+        let sql = "SELECT SUM(tokens), SUM(cost) FROM (SELECT tokens, cost FROM token_usage WHERE user_id = ?1 \
+            UNION ALL SELECT tokens, cost FROM usage_monthly WHERE user_id = ?1)";
         let result = conn
-            .query_row(sql, (user_id,), |row| row.get(0))
+            .query_row(sql, (user_id,), Usage::from_row)
             .optional()?;
-        Ok(result.unwrap_or(0))
+        Ok(result.unwrap_or_default())
     }
 
-    fn query_total_usage(conn: &mut SqliteConnection) -> Result<i64, Error> {
-        let sql = "SELECT SUM(tokens) FROM token_usage";
-        let result = conn.query_row(sql, (), |row| row.get(0)).optional()?;
-        Ok(result.unwrap_or(0))
+    fn query_total_usage(conn: &mut SqliteConnection) -> Result<Usage, Error> {
+        let sql = "SELECT SUM(tokens), SUM(cost) FROM (SELECT tokens, cost FROM token_usage \
+            UNION ALL SELECT tokens, cost FROM usage_monthly)";
+        let result = conn.query_row(sql, (), Usage::from_row).optional()?;
+        Ok(result.unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::InMemDatabaseProvider;
+
+    #[tokio::test]
+    async fn test_prune_usage() {
+        let config = SharedConfig::new(serde_json::from_str(r#"{"botToken": ""}"#).unwrap());
+        let db_mgr = DatabaseManager::with_db_provider(InMemDatabaseProvider).unwrap();
+        let stats_mgr = StatsManager::with_db_manager(db_mgr.clone(), config)
+            .await
+            .unwrap();
+
+        // 2024-01-01 and 2024-01-02 in UTC, on both sides of the cutoff.
+        let (old, cutoff, new) = (1704067200, 1704153600, 1704157200);
+        db_mgr
+            .write(move |conn| {
+                for time in [old, old + 3600, new] {
+                    let sql = "INSERT INTO token_usage (user_id, time, tokens, prompt_tokens, completion_tokens, cost) VALUES ('alice', ?, 30, 10, 20, 0.5)";
+                    conn.execute(sql, (time,))?;
+                    let sql = "INSERT INTO model_usage VALUES ('gpt-4', ?, 30)";
+                    conn.execute(sql, (time,))?;
+                    let sql = "INSERT INTO request_log (chat_id, user_id, time, tokens, succeeded) VALUES ('1', 'alice', ?, 30, 1)";
+                    conn.execute(sql, (time,))?;
+                }
+                Ok::<_, rusqlite::Error>(())
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(stats_mgr.prune_usage(cutoff).await.unwrap(), 2);
+        // Pruning again doesn't roll up the usage twice.
+        assert_eq!(stats_mgr.prune_usage(cutoff).await.unwrap(), 0);
+
+        let records = stats_mgr.export_usage().await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].time, new);
+
+        // The pruned usage is kept in the monthly totals.
+        let usage = stats_mgr
+            .query_usage(Some("alice".to_owned()))
+            .await
+            .unwrap();
+        assert_eq!(usage.tokens, 90);
+        assert!((usage.cost - 1.5).abs() < 1e-9);
+        let monthly: (String, i64, i64, i64) = db_mgr
+            .query(|conn| {
+                let sql = "SELECT month, tokens, prompt_tokens, completion_tokens FROM usage_monthly WHERE user_id = 'alice'";
+                conn.query_row(sql, (), |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                })
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(monthly, ("2024-01".to_owned(), 60, 20, 40));
+
+        let counts: (i64, i64) = db_mgr
+            .query(|conn| {
+                let sql =
+                    "SELECT (SELECT COUNT(*) FROM model_usage), (SELECT COUNT(*) FROM request_log)";
+                conn.query_row(sql, (), |row| Ok((row.get(0)?, row.get(1)?)))
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(counts, (1, 1));
     }
 }
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Buckets are pruned once there are more than this many of them.
+const MAX_IDLE_BUCKETS: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum BucketKey {
+    User(u64),
+    Chat(i64),
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+    notified: bool,
+}
+
+impl Bucket {
+    fn new(capacity: f64, now: Instant) -> Self {
+        Self {
+            tokens: capacity,
+            updated_at: now,
+            notified: false,
+        }
+    }
+
+    fn refill(&mut self, capacity: f64, now: Instant) {
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * capacity / 60.0).min(capacity);
+        self.updated_at = now;
+    }
+}
+
+/// The result of [`RateLimiter::check`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RateLimitResult {
+    Allowed,
+    /// The message is rejected, `notify` is `true` for the first rejected
+    /// message since the last allowed one.
+    Limited {
+        notify: bool,
+    },
+}
+
+/// Limits the messages of each user and each group with token buckets,
+/// which are refilled continuously up to the limit per minute.
+#[derive(Clone, Default)]
+pub(crate) struct RateLimiter {
+    buckets: Arc<Mutex<HashMap<BucketKey, Bucket>>>,
+    overrides: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+impl RateLimiter {
+    /// Sets the limit per minute of the member (0 for unlimited), or
+    /// removes the override if `limit` is `None`.
+    pub fn set_override(&self, username: String, limit: Option<u32>) {
+        let mut overrides = self.overrides.lock().unwrap();
+        match limit {
+            Some(limit) => overrides.insert(username, limit),
+            None => overrides.remove(&username),
+        };
+    }
+
+    pub fn get_override(&self, username: &str) -> Option<u32> {
+        self.overrides.lock().unwrap().get(username).copied()
+    }
+
+    /// Consumes a token from the buckets of the user and the group. The
+    /// group bucket is skipped for private chats and members with an
+    /// override.
+    pub fn check(
+        &self,
+        user_id: u64,
+        chat_id: Option<i64>,
+        limit: u32,
+        has_override: bool,
+    ) -> RateLimitResult {
+        if limit == 0 {
+            return RateLimitResult::Allowed;
+        }
+        self.check_at(user_id, chat_id, limit, has_override, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        user_id: u64,
+        chat_id: Option<i64>,
+        limit: u32,
+        has_override: bool,
+        now: Instant,
+    ) -> RateLimitResult {
+        let capacity = limit as f64;
+        let mut keys = vec![BucketKey::User(user_id)];
+        if let (Some(chat_id), false) = (chat_id, has_override) {
+            keys.push(BucketKey::Chat(chat_id));
+        }
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_IDLE_BUCKETS {
+            buckets.retain(|_, bucket| {
+                bucket.refill(capacity, now);
+                bucket.tokens < capacity
+            });
+        }
+
+        for key in &keys {
+            let bucket = buckets
+                .entry(*key)
+                .or_insert_with(|| Bucket::new(capacity, now));
+            bucket.refill(capacity, now);
+            if bucket.tokens < 1.0 {
+                let notify = !bucket.notified;
+                bucket.notified = true;
+                return RateLimitResult::Limited { notify };
+            }
+        }
+
+        for key in &keys {
+            let bucket = buckets.get_mut(key).unwrap();
+            bucket.tokens -= 1.0;
+            bucket.notified = false;
+        }
+        RateLimitResult::Allowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::default();
+        let now = Instant::now();
+        for _ in 0..2 {
+            assert_eq!(
+                limiter.check_at(1, None, 2, false, now),
+                RateLimitResult::Allowed
+            );
+        }
+        assert_eq!(
+            limiter.check_at(1, None, 2, false, now),
+            RateLimitResult::Limited { notify: true }
+        );
+        assert_eq!(
+            limiter.check_at(1, None, 2, false, now),
+            RateLimitResult::Limited { notify: false }
+        );
+        // Another user in a private chat is not affected.
+        assert_eq!(
+            limiter.check_at(2, None, 2, false, now),
+            RateLimitResult::Allowed
+        );
+
+        // One token is refilled in 30 seconds.
+        let later = now + Duration::from_secs(30);
+        assert_eq!(
+            limiter.check_at(1, None, 2, false, later),
+            RateLimitResult::Allowed
+        );
+        assert_eq!(
+            limiter.check_at(1, None, 2, false, later),
+            RateLimitResult::Limited { notify: true }
+        );
+    }
+
+    #[test]
+    fn test_chat_bucket() {
+        let limiter = RateLimiter::default();
+        let now = Instant::now();
+        assert_eq!(
+            limiter.check_at(1, Some(-100), 1, false, now),
+            RateLimitResult::Allowed
+        );
+        assert_eq!(
+            limiter.check_at(2, Some(-100), 1, false, now),
+            RateLimitResult::Limited { notify: true }
+        );
+        assert_eq!(
+            limiter.check_at(3, Some(-100), 1, true, now),
+            RateLimitResult::Allowed
+        );
+    }
+}
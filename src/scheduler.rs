@@ -0,0 +1,135 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+/// How often the jobs are checked.
+const TICK_INTERVAL: Duration = Duration::from_secs(30);
+
+type Schedule = Box<dyn Fn() -> Option<(NaiveTime, Tz)> + Send + Sync>;
+type Task = Box<dyn Fn() -> BoxFuture<'static, ()> + Send + Sync>;
+
+struct DailyJob {
+    name: String,
+    schedule: Schedule,
+    task: Task,
+    /// The local time of the last check, in the timezone of the schedule.
+    last_checked: Option<NaiveDateTime>,
+}
+
+impl DailyJob {
+    /// Returns `true` if the time of the job has come since the last check.
+    fn check(&mut self) -> bool {
+        let (time, timezone) = match (self.schedule)() {
+            Some(schedule) => schedule,
+            None => {
+                self.last_checked = None;
+                return false;
+            }
+        };
+        let now = Utc::now().with_timezone(&timezone).naive_local();
+        match self.last_checked.replace(now) {
+            Some(last_checked) => is_due(last_checked, now, time),
+            None => false,
+        }
+    }
+}
+
+/// Returns `true` if the time of day is passed between the two checks.
+fn is_due(last_checked: NaiveDateTime, now: NaiveDateTime, time: NaiveTime) -> bool {
+    let mut next_run = last_checked.date().and_time(time);
+    if next_run <= last_checked {
+        next_run += chrono::Duration::days(1);
+    }
+    next_run <= now
+}
+
+/// Runs the jobs of the modules at times of day, like cron. The jobs of all
+/// the modules are checked by one background task, which stops when the
+/// scheduler is dropped.
+#[derive(Clone, Default)]
+pub(crate) struct Scheduler {
+    jobs: Arc<Mutex<Vec<DailyJob>>>,
+}
+
+impl Scheduler {
+    /// Creates a scheduler and starts checking its jobs.
+    pub fn start() -> Self {
+        let scheduler = Self::default();
+        let jobs = Arc::downgrade(&scheduler.jobs);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(TICK_INTERVAL);
+            loop {
+                interval.tick().await;
+
+                let jobs = match jobs.upgrade() {
+                    Some(jobs) => jobs,
+                    None => break,
+                };
+                let due_tasks: Vec<_> = jobs
+                    .lock()
+                    .unwrap()
+                    .iter_mut()
+                    .filter_map(|job| {
+                        if !job.check() {
+                            return None;
+                        }
+                        info!("Running the scheduled job \"{}\"", job.name);
+                        Some((job.task)())
+                    })
+                    .collect();
+                for task in due_tasks {
+                    tokio::spawn(task);
+                }
+            }
+        });
+        scheduler
+    }
+
+    /// Runs the task every day at the time (in the timezone) returned by
+    /// `schedule`, which is called on each check so that the config can be
+    /// reloaded. The job is paused while `schedule` returns [`None`]. Runs
+    /// missed while the bot is not running are skipped.
+    pub fn daily<S, F, Fut>(&self, name: &str, schedule: S, task: F)
+    where
+        S: Fn() -> Option<(NaiveTime, Tz)> + Send + Sync + 'static,
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        self.jobs.lock().unwrap().push(DailyJob {
+            name: name.to_owned(),
+            schedule: Box::new(schedule),
+            task: Box::new(move || task().boxed()),
+            last_checked: None,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    #[test]
+    fn test_is_due() {
+        let at = |day: u32, hour: u32, min: u32| {
+            NaiveDate::from_ymd_opt(2024, 5, day)
+                .unwrap()
+                .and_hms_opt(hour, min, 0)
+                .unwrap()
+        };
+        let nine = NaiveTime::from_hms_opt(9, 0, 0).unwrap();
+        let midnight = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
+
+        assert!(is_due(at(1, 8, 59), at(1, 9, 0), nine));
+        assert!(!is_due(at(1, 9, 0), at(1, 9, 1), nine));
+        assert!(!is_due(at(1, 8, 0), at(1, 8, 30), nine));
+        // The time is passed across midnight.
+        assert!(is_due(at(1, 23, 59), at(2, 0, 1), midnight));
+        assert!(is_due(at(1, 10, 0), at(2, 9, 30), nine));
+    }
+}
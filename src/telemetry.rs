@@ -0,0 +1,133 @@
+//! Exports the traces of the request lifecycles (updates, OpenAI requests,
+//! Telegram edits and database queries) with OTLP.
+//!
+//! Spans are always created, and only exported after [`init_tracing`] is
+//! called with the `tracing` config. Chat ids are hashed with
+//! [`hash_chat_id`] before they are recorded.
+
+use std::sync::{Arc, OnceLock};
+
+use anyhow::Error;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rand::rand_bytes;
+use openssl::sign::Signer;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    trace::{self, Sampler},
+    Resource,
+};
+use teloxide::dispatching::DpHandlerDescription;
+use teloxide::dptree::di::DependencySupplier;
+use teloxide::dptree::HandlerDescription;
+use teloxide::prelude::*;
+use tracing::Instrument;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+use crate::{config::TracingConfig, types::HandlerResult};
+
+/// The key of the HMAC of chat ids, see [`hash_chat_id`].
+static CHAT_ID_HASH_KEY: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// Flushes the pending spans when dropped.
+pub struct TracingGuard;
+
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        opentelemetry::global::shutdown_tracer_provider();
+    }
+}
+
+/// Installs the OTLP exporter as the global subscriber of spans. Keep the
+/// guard until the bot is stopped.
+///
+/// This must be called in a Tokio runtime, and only once per process.
+pub fn init_tracing(config: &TracingConfig) -> Result<TracingGuard, Error> {
+    if let Some(hash_key) = &config.hash_key {
+        if CHAT_ID_HASH_KEY.set(hash_key.as_bytes().to_vec()).is_err() {
+            warn!("Chat ids are hashed before `tracing.hashKey` is set");
+        }
+    }
+    let exporter = opentelemetry_otlp::new_exporter()
+        .http()
+        .with_endpoint(&config.otlp_endpoint);
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio)));
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(
+            trace::config()
+                .with_sampler(sampler)
+                .with_resource(Resource::new(vec![KeyValue::new(
+                    "service.name",
+                    config.service_name.clone(),
+                )])),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+    tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()?;
+    Ok(TracingGuard)
+}
+
+/// Hashes the chat id with HMAC-SHA256, so that the traces can be
+/// correlated without revealing the chats. The key is `tracing.hashKey`,
+/// or a random one of the process if it's not set, since the ids are too
+/// few to resist brute force without a secret.
+pub(crate) fn hash_chat_id(chat_id: &str) -> String {
+    let key = CHAT_ID_HASH_KEY.get_or_init(|| {
+        let mut key = vec![0; 32];
+        rand_bytes(&mut key).expect("Failed to generate the hash key");
+        key
+    });
+    let digest = PKey::hmac(key)
+        .and_then(|pkey| {
+            let mut signer = Signer::new(MessageDigest::sha256(), &pkey)?;
+            signer.update(chat_id.as_bytes())?;
+            signer.sign_to_vec()
+        })
+        .expect("Failed to hash the chat id");
+    digest[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Wraps the handling of each update in a root span, which the spans of
+/// the handlers are nested in.
+pub(crate) fn trace_update(
+    handler: Handler<'static, DependencyMap, HandlerResult, DpHandlerDescription>,
+) -> Handler<'static, DependencyMap, HandlerResult, DpHandlerDescription> {
+    dptree::from_fn_with_description(
+        DpHandlerDescription::entry(),
+        |deps: DependencyMap, cont| {
+            let update: Arc<Update> = deps.get();
+            let span = tracing::info_span!(
+                "telegram.update",
+                update.id = update.id,
+                update.kind = update_kind(&update),
+                chat.id_hash = update
+                    .chat()
+                    .map(|chat| hash_chat_id(&chat.id.to_string()))
+                    .as_deref(),
+            );
+            cont(deps).instrument(span)
+        },
+    )
+    .chain(handler)
+}
+
+fn update_kind(update: &Update) -> &'static str {
+    use teloxide::types::UpdateKind;
+
+    match &update.kind {
+        UpdateKind::Message(_) => "message",
+        UpdateKind::EditedMessage(_) => "edited_message",
+        UpdateKind::CallbackQuery(_) => "callback_query",
+        UpdateKind::InlineQuery(_) => "inline_query",
+        UpdateKind::ChosenInlineResult(_) => "chosen_inline_result",
+        _ => "other",
+    }
+}
@@ -0,0 +1,131 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::Error;
+use futures::future::BoxFuture;
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// A request received by the mock server.
+pub(crate) struct Request {
+    pub path: String,
+    pub content_type: String,
+    pub body: Vec<u8>,
+}
+
+/// A response of the mock server.
+pub(crate) enum Response {
+    Json(u16, Value),
+    /// Server-sent events, each item is the data of an event.
+    EventStream(Vec<String>),
+}
+
+type Handler = Arc<dyn Fn(Request) -> BoxFuture<'static, Response> + Send + Sync>;
+
+/// A minimal HTTP/1.1 server, which closes the connection after each
+/// response. It's stopped when dropped.
+pub(crate) struct MockServer {
+    addr: SocketAddr,
+    accept_task: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Listens on a random local port.
+    pub async fn start<F>(handler: F) -> Result<Self, Error>
+    where
+        F: Fn(Request) -> BoxFuture<'static, Response> + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let handler: Handler = Arc::new(handler);
+        let accept_task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = Arc::clone(&handler);
+                tokio::spawn(async move {
+                    if let Err(err) = handle_connection(stream, handler).await {
+                        debug!("Mock server connection failed: {}", err);
+                    }
+                });
+            }
+        });
+        Ok(Self { addr, accept_task })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+    }
+}
+
+async fn handle_connection(stream: TcpStream, handler: Handler) -> Result<(), Error> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let path = request_line
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| anyhow!("Invalid request line: {}", request_line))?
+        .to_owned();
+
+    let mut content_length = 0;
+    let mut content_type = String::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.trim().parse()?,
+                "content-type" => content_type = value.trim().to_owned(),
+                _ => {}
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+
+    let response = handler(Request {
+        path,
+        content_type,
+        body,
+    })
+    .await;
+
+    let mut stream = reader.into_inner();
+    match response {
+        Response::Json(status, value) => {
+            let body = value.to_string();
+            let head = format!(
+                "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                body.len()
+            );
+            stream.write_all(head.as_bytes()).await?;
+            stream.write_all(body.as_bytes()).await?;
+        }
+        Response::EventStream(events) => {
+            let head =
+                "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n";
+            stream.write_all(head.as_bytes()).await?;
+            for data in events {
+                stream
+                    .write_all(format!("data: {}\n\n", data).as_bytes())
+                    .await?;
+                stream.flush().await?;
+            }
+        }
+    }
+    stream.shutdown().await?;
+    Ok(())
+}
@@ -0,0 +1,63 @@
+//! Mock servers to test the bot end-to-end without live keys, enabled by
+//! the `test-harness` feature.
+//!
+//! [`MockTelegram`] serves the Bot API: it feeds the updates to the bot and
+//! records the methods called by it. [`MockOpenAI`] streams the queued
+//! replies of the chat model. Run the bot against both of them with the
+//! config from [`mock_config`]:
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! use std::time::Duration;
+//!
+//! use serde_json::json;
+//! use telegpt_core::{app::App, testing::*};
+//!
+//! let telegram = MockTelegram::start().await?;
+//! let openai = MockOpenAI::start().await?;
+//! openai.push_reply(&["Hello", ", Alice!"]);
+//!
+//! let config = mock_config(&telegram, &openai, json!({ "adminUsernames": ["alice"] }))?;
+//! let bot = tokio::spawn(App::new(config).await?.run());
+//!
+//! telegram.send_text(1, "alice", "Hi");
+//! let answer = telegram
+//!     .wait_for(Duration::from_secs(5), |req| req.params["text"] == "Hello, Alice!")
+//!     .await;
+//! assert!(answer.is_some());
+//! bot.abort();
+//! # Ok(())
+//! # }
+//! ```
+
+mod http;
+mod openai;
+mod telegram;
+
+use anyhow::Error;
+use serde_json::{json, Value};
+
+use crate::config::{Config, SharedConfig};
+pub use openai::MockOpenAI;
+pub use telegram::{MockTelegram, TelegramRequest, BOT_USERNAME, BOT_USER_ID};
+
+/// Builds the config that connects the bot to the mock servers, with an
+/// in-memory database. The fields of `overrides` (a JSON object in the
+/// format of the config file) replace the defaults.
+pub fn mock_config(
+    telegram: &MockTelegram,
+    openai: &MockOpenAI,
+    overrides: Value,
+) -> Result<SharedConfig, Error> {
+    let mut config = json!({
+        "botToken": "123456:mock",
+        "telegramApiUrl": telegram.api_url(),
+        "provider": { "type": "openaiCompatible", "baseUrl": openai.base_url() },
+        "openaiGptModel": "mock-model",
+    });
+    if let (Some(config), Value::Object(overrides)) = (config.as_object_mut(), overrides) {
+        config.extend(overrides);
+    }
+    let config: Config = serde_json::from_value(config)?;
+    Ok(SharedConfig::new(config))
+}
@@ -0,0 +1,116 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Error;
+use futures::FutureExt;
+use serde_json::{json, Value};
+
+use super::http::{MockServer, Request, Response};
+
+enum MockReply {
+    /// The chunks of the reply, and the finish reason.
+    Stream(Vec<String>, String),
+    Error(u16, String),
+}
+
+#[derive(Default)]
+struct OpenAIState {
+    replies: VecDeque<MockReply>,
+    requests: Vec<Value>,
+}
+
+/// A mock OpenAI-compatible server, which streams the queued replies to
+/// the chat completion requests in order.
+///
+/// Requests without a queued reply fail with status 500.
+pub struct MockOpenAI {
+    server: MockServer,
+    state: Arc<Mutex<OpenAIState>>,
+}
+
+impl MockOpenAI {
+    pub async fn start() -> Result<Self, Error> {
+        let state = Arc::new(Mutex::new(OpenAIState::default()));
+        let server = {
+            let state = Arc::clone(&state);
+            MockServer::start(move |req| {
+                let response = handle_request(req, &state);
+                async move { response }.boxed()
+            })
+            .await?
+        };
+        Ok(Self { server, state })
+    }
+
+    /// The URL to set as the `baseUrl` of the `openaiCompatible` provider.
+    pub fn base_url(&self) -> String {
+        format!("{}/v1", self.server.url())
+    }
+
+    /// Queues a reply, which is streamed in the given chunks.
+    pub fn push_reply(&self, chunks: &[&str]) {
+        self.push_reply_with_finish_reason(chunks, "stop");
+    }
+
+    /// Queues a reply that finishes with the reason, e.g. `length` for a
+    /// truncated reply.
+    pub fn push_reply_with_finish_reason(&self, chunks: &[&str], finish_reason: &str) {
+        let chunks = chunks.iter().map(|chunk| chunk.to_string()).collect();
+        self.state
+            .lock()
+            .unwrap()
+            .replies
+            .push_back(MockReply::Stream(chunks, finish_reason.to_owned()));
+    }
+
+    /// Queues a failed request, e.g. to test the fallback models.
+    pub fn push_error(&self, status: u16, message: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .replies
+            .push_back(MockReply::Error(status, message.to_owned()));
+    }
+
+    /// Returns the bodies of the chat completion requests so far.
+    pub fn requests(&self) -> Vec<Value> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+fn handle_request(req: Request, state: &Mutex<OpenAIState>) -> Response {
+    if !req.path.ends_with("/chat/completions") {
+        return Response::Json(404, json!({ "error": { "message": "Not found" } }));
+    }
+    let body: Value = serde_json::from_slice(&req.body).unwrap_or(Value::Null);
+    let model = body["model"].as_str().unwrap_or_default().to_owned();
+
+    let mut state = state.lock().unwrap();
+    state.requests.push(body);
+    match state.replies.pop_front() {
+        Some(MockReply::Stream(chunks, finish_reason)) => {
+            let mut events: Vec<_> = chunks
+                .into_iter()
+                .map(|chunk| stream_chunk(&model, json!({ "content": chunk }), None))
+                .collect();
+            events.push(stream_chunk(&model, json!({}), Some(&finish_reason)));
+            events.push("[DONE]".to_owned());
+            Response::EventStream(events)
+        }
+        Some(MockReply::Error(status, message)) => {
+            Response::Json(status, json!({ "error": { "message": message } }))
+        }
+        None => Response::Json(500, json!({ "error": { "message": "No reply is queued" } })),
+    }
+}
+
+fn stream_chunk(model: &str, delta: Value, finish_reason: Option<&str>) -> String {
+    json!({
+        "id": "chatcmpl-mock",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": model,
+        "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+    })
+    .to_string()
+}
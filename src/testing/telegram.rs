@@ -0,0 +1,299 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Error;
+use futures::FutureExt;
+use serde_json::{json, Value};
+use tokio::sync::Notify;
+
+use super::http::{MockServer, Request, Response};
+
+/// The user id of the mock bot.
+pub const BOT_USER_ID: u64 = 10000;
+/// The username of the mock bot.
+pub const BOT_USERNAME: &str = "telegpt_test_bot";
+
+/// How long `getUpdates` waits for new updates before returning none.
+const LONG_POLL_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// A call of a Bot API method.
+#[derive(Clone, Debug)]
+pub struct TelegramRequest {
+    /// The name of the method, e.g. `sendMessage`.
+    pub method: String,
+    /// The parameters sent as JSON. Empty for requests with files, which
+    /// are sent as multipart forms.
+    pub params: Value,
+}
+
+#[derive(Default)]
+struct TelegramState {
+    updates: VecDeque<Value>,
+    next_update_id: i64,
+    next_message_id: i64,
+    requests: Vec<TelegramRequest>,
+}
+
+/// A mock Bot API server, which feeds the updates to the bot and records
+/// the methods called by it.
+///
+/// Methods that send or edit messages return a message with the text in
+/// the request, and the other methods return `true`.
+pub struct MockTelegram {
+    server: MockServer,
+    state: Arc<Mutex<TelegramState>>,
+    updates_notify: Arc<Notify>,
+    requests_notify: Arc<Notify>,
+}
+
+impl MockTelegram {
+    pub async fn start() -> Result<Self, Error> {
+        let state = Arc::new(Mutex::new(TelegramState {
+            next_update_id: 1,
+            next_message_id: 1,
+            ..Default::default()
+        }));
+        let updates_notify = Arc::new(Notify::new());
+        let requests_notify = Arc::new(Notify::new());
+
+        let server = {
+            let state = Arc::clone(&state);
+            let updates_notify = Arc::clone(&updates_notify);
+            let requests_notify = Arc::clone(&requests_notify);
+            MockServer::start(move |req| {
+                handle_request(
+                    req,
+                    Arc::clone(&state),
+                    Arc::clone(&updates_notify),
+                    Arc::clone(&requests_notify),
+                )
+                .boxed()
+            })
+            .await?
+        };
+
+        Ok(Self {
+            server,
+            state,
+            updates_notify,
+            requests_notify,
+        })
+    }
+
+    /// The URL to set as `telegramApiUrl`.
+    pub fn api_url(&self) -> String {
+        self.server.url()
+    }
+
+    /// Queues an update, whose `update_id` is assigned by the server.
+    pub fn push_update(&self, mut update: Value) {
+        let mut state = self.state.lock().unwrap();
+        update["update_id"] = json!(state.next_update_id);
+        state.next_update_id += 1;
+        state.updates.push_back(update);
+        drop(state);
+        self.updates_notify.notify_waiters();
+    }
+
+    /// Queues a text message from the user, and returns its message id.
+    /// Positive chat ids are private chats, and negative ones are groups.
+    pub fn send_text(&self, chat_id: i64, username: &str, text: &str) -> i64 {
+        let message_id = self.next_message_id();
+        self.push_update(json!({
+            "message": {
+                "message_id": message_id,
+                "date": 0,
+                "chat": chat(chat_id),
+                "from": user(username),
+                "text": text,
+                "entities": command_entities(text),
+            }
+        }));
+        message_id
+    }
+
+    /// Queues an edit of the text message sent by the user.
+    pub fn edit_text(&self, chat_id: i64, username: &str, message_id: i64, text: &str) {
+        self.push_update(json!({
+            "edited_message": {
+                "message_id": message_id,
+                "date": 0,
+                "edit_date": 1,
+                "chat": chat(chat_id),
+                "from": user(username),
+                "text": text,
+                "entities": command_entities(text),
+            }
+        }));
+    }
+
+    /// Queues a press of the inline button with the callback data, under
+    /// the message sent by the bot.
+    pub fn press_button(&self, chat_id: i64, username: &str, message_id: i64, data: &str) {
+        self.push_update(json!({
+            "callback_query": {
+                "id": format!("{}", message_id),
+                "from": user(username),
+                "message": {
+                    "message_id": message_id,
+                    "date": 0,
+                    "chat": chat(chat_id),
+                    "from": bot_user(),
+                    "text": "",
+                },
+                "chat_instance": "0",
+                "data": data,
+            }
+        }));
+    }
+
+    /// Returns the methods called so far, in order.
+    pub fn requests(&self) -> Vec<TelegramRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Waits until a method matching the predicate is called, and returns
+    /// it, or `None` if it's not called within the timeout.
+    pub async fn wait_for<F>(&self, timeout: Duration, predicate: F) -> Option<TelegramRequest>
+    where
+        F: Fn(&TelegramRequest) -> bool,
+    {
+        let find = || self.requests().into_iter().find(|req| predicate(req));
+        tokio::time::timeout(timeout, async {
+            loop {
+                let notified = self.requests_notify.notified();
+                if let Some(req) = find() {
+                    return req;
+                }
+                notified.await;
+            }
+        })
+        .await
+        .ok()
+    }
+
+    fn next_message_id(&self) -> i64 {
+        let mut state = self.state.lock().unwrap();
+        let message_id = state.next_message_id;
+        state.next_message_id += 1;
+        message_id
+    }
+}
+
+async fn handle_request(
+    req: Request,
+    state: Arc<Mutex<TelegramState>>,
+    updates_notify: Arc<Notify>,
+    requests_notify: Arc<Notify>,
+) -> Response {
+    // The path is `/bot<token>/<method>`, and teloxide capitalizes the
+    // methods (e.g. `SendMessage`).
+    let method = req.path.rsplit('/').next().unwrap_or_default();
+    let mut chars = method.chars();
+    let method: String = chars
+        .next()
+        .map(|first| first.to_ascii_lowercase())
+        .into_iter()
+        .chain(chars)
+        .collect();
+    let params: Value = if req.content_type.starts_with("application/json") {
+        serde_json::from_slice(&req.body).unwrap_or(Value::Null)
+    } else {
+        json!({})
+    };
+
+    let result = match method.as_str() {
+        "getMe" => bot_user(),
+        "getUpdates" => {
+            let notified = updates_notify.notified();
+            if state.lock().unwrap().updates.is_empty() {
+                let _ = tokio::time::timeout(LONG_POLL_TIMEOUT, notified).await;
+            }
+            let updates: Vec<_> = state.lock().unwrap().updates.drain(..).collect();
+            Value::Array(updates)
+        }
+        _ => {
+            let result = if method.starts_with("send") || method.starts_with("edit") {
+                let mut state = state.lock().unwrap();
+                let message_id = match params.get("message_id").and_then(Value::as_i64) {
+                    Some(message_id) => message_id,
+                    None => {
+                        state.next_message_id += 1;
+                        state.next_message_id - 1
+                    }
+                };
+                json!({
+                    "message_id": message_id,
+                    "date": 0,
+                    "chat": chat(chat_id(&params["chat_id"])),
+                    "from": bot_user(),
+                    "text": params["text"].as_str().unwrap_or_default(),
+                })
+            } else {
+                json!(true)
+            };
+            state
+                .lock()
+                .unwrap()
+                .requests
+                .push(TelegramRequest { method, params });
+            requests_notify.notify_waiters();
+            result
+        }
+    };
+    Response::Json(200, json!({ "ok": true, "result": result }))
+}
+
+fn bot_user() -> Value {
+    json!({
+        "id": BOT_USER_ID,
+        "is_bot": true,
+        "first_name": "TeleGPT",
+        "username": BOT_USERNAME,
+        "can_join_groups": true,
+        "can_read_all_group_messages": true,
+        "supports_inline_queries": true,
+    })
+}
+
+fn chat(chat_id: i64) -> Value {
+    if chat_id > 0 {
+        json!({ "id": chat_id, "type": "private", "first_name": "User" })
+    } else {
+        json!({ "id": chat_id, "type": "supergroup", "title": "Group" })
+    }
+}
+
+/// Chat ids are sent either as numbers or as strings.
+fn chat_id(value: &Value) -> i64 {
+    match value {
+        Value::String(chat_id) => chat_id.parse().unwrap_or_default(),
+        value => value.as_i64().unwrap_or_default(),
+    }
+}
+
+fn user(username: &str) -> Value {
+    json!({
+        "id": user_id(username),
+        "is_bot": false,
+        "first_name": username,
+        "username": username,
+    })
+}
+
+/// Derives a stable user id from the username.
+fn user_id(username: &str) -> u64 {
+    username.bytes().fold(1, |id: u64, byte| {
+        id.wrapping_mul(31).wrapping_add(byte as u64)
+    }) % 1_000_000_000
+}
+
+/// Marks the leading command, which Telegram does for real messages.
+fn command_entities(text: &str) -> Value {
+    if !text.starts_with('/') {
+        return json!([]);
+    }
+    let length = text.find(' ').unwrap_or(text.len());
+    json!([{ "type": "bot_command", "offset": 0, "length": length }])
+}
@@ -1,11 +1,9 @@
-#![doc(hidden)]
-
 use anyhow::Error;
-use teloxide::dispatching::DefaultKey;
 use teloxide::dispatching::DpHandlerDescription;
 use teloxide::prelude::*;
 
-pub(crate) type HandlerResult = Result<(), Error>;
-pub(crate) type TeloxideHandler =
-    Handler<'static, DependencyMap, HandlerResult, DpHandlerDescription>;
-pub(crate) type TeloxideDispatcher = Dispatcher<Bot, Error, DefaultKey>;
+/// The result type of the handlers.
+pub type HandlerResult = Result<(), Error>;
+/// The handler type used by modules, see [`Module`](crate::Module).
+pub type TeloxideHandler = Handler<'static, DependencyMap, HandlerResult, DpHandlerDescription>;
+pub(crate) type TeloxideDispatcher = Dispatcher<Bot, Error, ChatId>;
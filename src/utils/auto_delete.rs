@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+use teloxide::prelude::*;
+
+use crate::config::SharedConfig;
+
+/// Deletes the bot's message after `serviceMessageTtl` seconds, if it's
+/// configured and the message is in a group. This is used for the error
+/// notices and other service messages that are useless after being read.
+pub(crate) fn schedule_deletion(bot: &Bot, msg: &Message, config: &SharedConfig) {
+    let ttl = match config.load().service_message_ttl {
+        Some(ttl) if !msg.chat.is_private() => Duration::from_secs(ttl),
+        _ => return,
+    };
+
+    let (bot, chat_id, message_id) = (bot.clone(), msg.chat.id, msg.id);
+    tokio::spawn(async move {
+        tokio::time::sleep(ttl).await;
+        // The message may have been deleted by others.
+        if let Err(err) = bot.delete_message(chat_id, message_id).await {
+            debug!("Failed to delete the service message: {}", err);
+        }
+    });
+}
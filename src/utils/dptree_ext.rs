@@ -1,5 +1,7 @@
 use teloxide::prelude::*;
-use teloxide::types::Me;
+use teloxide::types::{Me, MessageKind};
+
+use crate::types::TeloxideHandler;
 
 pub trait HandlerExt {
     fn post_chain(self, next: Self) -> Self;
@@ -29,7 +31,7 @@ where
     }
 }
 
-fn extract_command_args<'i>(input: &'i str, cmd: &str, username: &str) -> Option<&'i str> {
+pub fn extract_command_args<'i>(input: &'i str, cmd: &str, username: &str) -> Option<&'i str> {
     let pat = format!("/{}", cmd);
     input.strip_prefix(&pat).and_then(|rest| {
         if rest.is_empty() {
@@ -45,7 +47,7 @@ fn extract_command_args<'i>(input: &'i str, cmd: &str, username: &str) -> Option
             return Some(args);
         }
 
-        return args.strip_prefix(' ');
+        args.strip_prefix(' ')
     })
 }
 
@@ -59,9 +61,186 @@ pub fn command_filter(cmd: String) -> impl Fn(Message, Me) -> Option<CommandArgs
     }
 }
 
+/// An error in the arguments of a command, which is shown to the user.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArgsError(pub String);
+
+/// A value that can be parsed from the arguments of a command.
+pub trait CommandArg: Sized {
+    /// The placeholder of the argument in the usage, e.g. `<number>`.
+    fn placeholder() -> String;
+
+    /// Parses the argument from the start of `input`, and advances `input`
+    /// past it.
+    fn parse(input: &mut &str) -> Result<Self, ArgsError>;
+}
+
+/// Takes the next whitespace-separated word of `input`, or reports the
+/// missing argument `T`.
+pub fn next_word<'i, T: CommandArg>(input: &mut &'i str) -> Result<&'i str, ArgsError> {
+    let trimmed = input.trim_start();
+    if trimmed.is_empty() {
+        return Err(ArgsError(format!("Missing argument {}", T::placeholder())));
+    }
+    let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+    let (word, rest) = trimmed.split_at(end);
+    *input = rest;
+    Ok(word)
+}
+
+impl CommandArg for String {
+    fn placeholder() -> String {
+        "<text>".to_owned()
+    }
+
+    fn parse(input: &mut &str) -> Result<Self, ArgsError> {
+        next_word::<Self>(input).map(str::to_owned)
+    }
+}
+
+macro_rules! impl_number_arg {
+    ($($ty:ty),*) => {
+        $(
+            impl CommandArg for $ty {
+                fn placeholder() -> String {
+                    "<number>".to_owned()
+                }
+
+                fn parse(input: &mut &str) -> Result<Self, ArgsError> {
+                    let word = next_word::<Self>(input)?;
+                    word.parse()
+                        .map_err(|_| ArgsError(format!("Invalid number \"{}\"", word)))
+                }
+            }
+        )*
+    };
+}
+
+impl_number_arg!(u32, u64, i64, f32);
+
+impl CommandArg for bool {
+    fn placeholder() -> String {
+        "<yes|no>".to_owned()
+    }
+
+    fn parse(input: &mut &str) -> Result<Self, ArgsError> {
+        match next_word::<Self>(input)? {
+            "yes" | "on" | "true" | "1" => Ok(true),
+            "no" | "off" | "false" | "0" => Ok(false),
+            word => Err(ArgsError(format!(
+                "Invalid value \"{}\", possible values are \"yes\", \"no\"",
+                word
+            ))),
+        }
+    }
+}
+
+/// An argument that can be omitted at the end of the arguments.
+impl<T: CommandArg> CommandArg for Option<T> {
+    fn placeholder() -> String {
+        format!("[{}]", T::placeholder().trim_matches(['<', '>']))
+    }
+
+    fn parse(input: &mut &str) -> Result<Self, ArgsError> {
+        if input.trim().is_empty() {
+            return Ok(None);
+        }
+        T::parse(input).map(Some)
+    }
+}
+
+/// The rest of the arguments, which may contain whitespaces.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rest(pub String);
+
+impl CommandArg for Rest {
+    fn placeholder() -> String {
+        "<text...>".to_owned()
+    }
+
+    fn parse(input: &mut &str) -> Result<Self, ArgsError> {
+        let rest = input.trim();
+        if rest.is_empty() {
+            return Err(ArgsError(format!(
+                "Missing argument {}",
+                Self::placeholder()
+            )));
+        }
+        *input = "";
+        Ok(Self(rest.to_owned()))
+    }
+}
+
+/// The arguments of a command, which are tuples of [`CommandArg`]s.
+pub trait FromCommandArgs: Sized {
+    /// Returns the placeholders of the arguments, e.g. `<text> <number>`.
+    fn usage() -> String;
+
+    fn parse_args(input: &str) -> Result<Self, ArgsError>;
+}
+
+macro_rules! impl_from_command_args {
+    ($($arg:ident),+) => {
+        impl<$($arg: CommandArg),+> FromCommandArgs for ($($arg,)+) {
+            fn usage() -> String {
+                [$($arg::placeholder()),+].join(" ")
+            }
+
+            fn parse_args(mut input: &str) -> Result<Self, ArgsError> {
+                let args = ($($arg::parse(&mut input)?,)+);
+                if !input.trim().is_empty() {
+                    return Err(ArgsError("Too many arguments".to_owned()));
+                }
+                Ok(args)
+            }
+        }
+    };
+}
+
+impl_from_command_args!(A);
+impl_from_command_args!(A, B);
+impl_from_command_args!(A, B, C);
+
+/// Parses the arguments of the command `cmd` into `A`, which is injected
+/// into the following handlers, e.g.:
+///
+/// ```ignore
+/// command_with_args::<(String, u32)>("set_quota").endpoint(set_quota)
+/// ```
+///
+/// The usage of the command is replied if the arguments are invalid, so
+/// the senders should be authorized before the arguments are parsed.
+pub fn command_with_args<A>(cmd: &'static str) -> TeloxideHandler
+where
+    A: FromCommandArgs + Clone + Send + Sync + 'static,
+{
+    dptree::filter_map_async(
+        move |bot: Bot, msg: Message, args: CommandArgs| async move {
+            match A::parse_args(&args.0) {
+                Ok(parsed) => Some(parsed),
+                Err(err) => {
+                    let text = format!("{}\nUsage: /{} {}", err.0, cmd, A::usage());
+                    let mut send_message = bot
+                        .send_message(msg.chat.id, text)
+                        .reply_to_message_id(msg.id);
+                    // Reply in the forum topic of the command.
+                    if matches!(&msg.kind, MessageKind::Common(common) if common.is_topic_message) {
+                        send_message.message_thread_id = msg.thread_id;
+                    }
+                    let res = send_message.await;
+                    if let Err(err) = res {
+                        error!("Failed to reply the usage: {}", err);
+                    }
+                    None
+                }
+            }
+        },
+    )
+}
+
 #[cfg(test)]
 mod tests {
-    use super::extract_command_args;
+    use super::*;
 
     #[test]
     fn test_extract_command_args() {
@@ -70,14 +249,8 @@ mod tests {
             extract_command_args("/test", "test", username),
             Some("")
         ));
-        assert!(matches!(
-            extract_command_args("/test1", "test", username),
-            None
-        ));
-        assert!(matches!(
-            extract_command_args("/test@otherbot", "test", username),
-            None
-        ));
+        assert!(extract_command_args("/test1", "test", username).is_none());
+        assert!(extract_command_args("/test@otherbot", "test", username).is_none());
         assert!(matches!(
             extract_command_args("/test@mybot", "test", username),
             Some("")
@@ -86,13 +259,36 @@ mod tests {
             extract_command_args("/test@mybot arg1 arg2", "test", username),
             Some("arg1 arg2")
         ));
-        assert!(matches!(
-            extract_command_args("/test@mybotarg", "test", username),
-            None
-        ));
+        assert!(extract_command_args("/test@mybotarg", "test", username).is_none());
         assert!(matches!(
             extract_command_args("/test arg1 arg2", "test", username),
             Some("arg1 arg2")
         ));
     }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            <(String, u32)>::parse_args(" alice  5 "),
+            Ok(("alice".to_owned(), 5))
+        );
+        assert_eq!(
+            <(String, Option<u32>)>::parse_args("alice"),
+            Ok(("alice".to_owned(), None))
+        );
+        assert_eq!(
+            <(String, Rest)>::parse_args("coder You are a coder."),
+            Ok(("coder".to_owned(), Rest("You are a coder.".to_owned())))
+        );
+        assert_eq!(
+            <(String, u32)>::parse_args("alice"),
+            Err(ArgsError("Missing argument <number>".to_owned()))
+        );
+        assert_eq!(
+            <(u32,)>::parse_args("five"),
+            Err(ArgsError("Invalid number \"five\"".to_owned()))
+        );
+        assert!(<(bool,)>::parse_args("yes no").is_err());
+        assert_eq!(<(String, Option<u32>)>::usage(), "<text> [number]");
+    }
 }
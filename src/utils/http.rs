@@ -0,0 +1,34 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// The timeout of establishing the connections.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// The timeout of whole requests, from connecting to reading the body.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Returns the shared HTTP client for requests other than the OpenAI
+/// client, e.g. web searches and archive uploads. Reusing it keeps the
+/// connections alive, and every request is bounded by the timeouts.
+pub(crate) fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .expect("Failed to build the HTTP client")
+    })
+}
+
+/// Returns the shared HTTP client for streamed responses, which only has
+/// the connect timeout, since the streams are bounded by the timeouts
+/// between the tokens instead.
+pub(crate) fn streaming_client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .build()
+            .expect("Failed to build the HTTP client")
+    })
+}
@@ -0,0 +1,7 @@
+use teloxide::types::Message;
+
+/// Returns the IETF language tag of the sender, which is used to pick the
+/// strings in their language.
+pub(crate) fn user_language(msg: &Message) -> Option<&str> {
+    msg.from().and_then(|user| user.language_code.as_deref())
+}
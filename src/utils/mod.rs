@@ -1,6 +1,10 @@
 #![doc(hidden)]
 
+pub(crate) mod auto_delete;
 pub(crate) mod dptree_ext;
+pub(crate) mod http;
+pub(crate) mod i18n;
+pub(crate) mod sender;
 pub(crate) mod stream_ext;
 
 #[allow(unused_imports)]
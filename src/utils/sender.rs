@@ -0,0 +1,84 @@
+//! Sends messages with the flood control of Telegram in mind.
+//!
+//! Telegram answers too frequent requests (mostly the edits of a streamed
+//! answer) with `429 Too Many Requests` and the time to wait. Requests are
+//! sent with [`RetryExt::send_retrying`] to wait and resend them, and the
+//! progress edits go through [`CoalescedEditor`] to drop the outdated ones.
+
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+use teloxide::payloads::EditMessageText;
+use teloxide::prelude::*;
+use teloxide::requests::{JsonRequest, Output};
+use teloxide::RequestError;
+
+/// How many times a request is resent after being rate limited.
+const MAX_RETRIES: usize = 3;
+
+/// Requests are not resent if Telegram asks to wait longer than this, the
+/// user would have lost patience by then anyway.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+pub(crate) trait RetryExt: Request<Err = RequestError> {
+    /// Sends the request, and resends it after the time that Telegram asks
+    /// to wait when it's rate limited.
+    fn send_retrying(self) -> BoxFuture<'static, Result<Output<Self>, RequestError>>;
+}
+
+impl<R> RetryExt for R
+where
+    R: Request<Err = RequestError> + Send + Sync + 'static,
+    Output<R>: Send,
+{
+    fn send_retrying(self) -> BoxFuture<'static, Result<Output<Self>, RequestError>> {
+        Box::pin(async move {
+            let mut retries = 0;
+            loop {
+                match self.send_ref().await {
+                    Err(RequestError::RetryAfter(retry_after))
+                        if retries < MAX_RETRIES && retry_after <= MAX_RETRY_AFTER =>
+                    {
+                        retries += 1;
+                        warn!("Rate limited by Telegram, retrying after {:?}", retry_after);
+                        tokio::time::sleep(retry_after).await;
+                    }
+                    res => return res,
+                }
+            }
+        })
+    }
+}
+
+/// Edits a message repeatedly (e.g. with the partial answer), without
+/// waiting for the flood control. After Telegram asks to wait, the edits
+/// are skipped until then, so that the next edit carries the latest text
+/// instead of the outdated ones being sent in turn.
+#[derive(Default)]
+pub(crate) struct CoalescedEditor {
+    paused_until: Option<Instant>,
+}
+
+impl CoalescedEditor {
+    /// Sends the edit, returns `false` if it's skipped.
+    pub async fn edit(&mut self, req: JsonRequest<EditMessageText>) -> Result<bool, RequestError> {
+        if let Some(paused_until) = self.paused_until {
+            if Instant::now() < paused_until {
+                return Ok(false);
+            }
+            self.paused_until = None;
+        }
+        match req.await {
+            Ok(_) => Ok(true),
+            Err(RequestError::RetryAfter(retry_after)) => {
+                debug!(
+                    "Edits are paused for {:?} by the flood control",
+                    retry_after
+                );
+                self.paused_until = Some(Instant::now() + retry_after);
+                Ok(false)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
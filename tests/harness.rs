@@ -0,0 +1,737 @@
+//! End-to-end tests with the mock servers, run with
+//! `cargo test --features test-harness`.
+
+#![cfg(feature = "test-harness")]
+
+use std::time::Duration;
+
+use serde_json::json;
+use telegpt_core::{app::App, testing::*};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Waits until the tokens of the answers to the user are counted, which is
+/// done after the answers are sent.
+async fn wait_for_token_usage(telegram: &MockTelegram, chat_id: i64, username: &str) {
+    for _ in 0..50 {
+        let message_id = telegram.send_text(chat_id, username, "/usage");
+        let reply = telegram
+            .wait_for(TIMEOUT, |req| {
+                req.params["reply_to_message_id"] == message_id
+            })
+            .await
+            .unwrap();
+        if !reply.params["text"]
+            .as_str()
+            .unwrap_or_default()
+            .contains("chat tokens: 0 /")
+        {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("The token usage is not counted");
+}
+
+#[tokio::test]
+async fn test_stream_answer() {
+    let telegram = MockTelegram::start().await.unwrap();
+    let openai = MockOpenAI::start().await.unwrap();
+    openai.push_reply(&["Hello", ", Alice!"]);
+
+    let config = mock_config(&telegram, &openai, json!({ "adminUsernames": ["alice"] })).unwrap();
+    let bot = tokio::spawn(App::new(config).await.unwrap().run());
+
+    telegram.send_text(1, "alice", "Hi");
+    let answer = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "editMessageText" && req.params["text"] == "Hello, Alice!"
+        })
+        .await;
+    assert!(answer.is_some());
+    let messages = openai.requests()[0]["messages"].clone();
+    assert_eq!(
+        messages.as_array().unwrap().last().unwrap()["content"],
+        "Hi"
+    );
+
+    bot.abort();
+}
+
+#[tokio::test]
+async fn test_fallback_model() {
+    let telegram = MockTelegram::start().await.unwrap();
+    let openai = MockOpenAI::start().await.unwrap();
+    openai.push_error(500, "The model is overloaded");
+    openai.push_reply(&["Fallback answer"]);
+
+    let config = mock_config(
+        &telegram,
+        &openai,
+        json!({ "adminUsernames": ["alice"], "fallbackModels": ["backup-model"] }),
+    )
+    .unwrap();
+    let bot = tokio::spawn(App::new(config).await.unwrap().run());
+
+    telegram.send_text(1, "alice", "Hi");
+    let answer = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "editMessageText"
+                && req.params["text"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .starts_with("Fallback answer")
+        })
+        .await;
+    assert!(answer.is_some());
+    assert_eq!(openai.requests()[1]["model"], "backup-model");
+
+    bot.abort();
+}
+
+#[tokio::test]
+async fn test_empty_answer_without_fallback() {
+    let telegram = MockTelegram::start().await.unwrap();
+    let openai = MockOpenAI::start().await.unwrap();
+    openai.push_reply(&[]);
+    openai.push_reply(&["Fallback answer"]);
+
+    let config = mock_config(
+        &telegram,
+        &openai,
+        json!({ "adminUsernames": ["alice"], "fallbackModels": ["backup-model"] }),
+    )
+    .unwrap();
+    let bot = tokio::spawn(App::new(config).await.unwrap().run());
+
+    // The model is reachable, so the empty answer is reported instead of
+    // falling back.
+    telegram.send_text(1, "alice", "Hi");
+    let notice = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "editMessageText"
+                && req.params["text"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .starts_with("The model returned an empty answer")
+        })
+        .await;
+    assert!(notice.is_some());
+    assert_eq!(openai.requests().len(), 1);
+
+    bot.abort();
+}
+
+#[tokio::test]
+async fn test_command_routing() {
+    let telegram = MockTelegram::start().await.unwrap();
+    let openai = MockOpenAI::start().await.unwrap();
+
+    let config = mock_config(&telegram, &openai, json!({ "adminUsernames": ["alice"] })).unwrap();
+    let bot = tokio::spawn(App::new(config).await.unwrap().run());
+
+    telegram.send_text(1, "alice", "/reset");
+    let reply = telegram
+        .wait_for(TIMEOUT, |req| req.method == "sendMessage")
+        .await;
+    assert!(reply.is_some());
+    assert!(openai.requests().is_empty());
+
+    bot.abort();
+}
+
+#[tokio::test]
+async fn test_admin_command_usage_hidden() {
+    let telegram = MockTelegram::start().await.unwrap();
+    let openai = MockOpenAI::start().await.unwrap();
+
+    let config = mock_config(&telegram, &openai, json!({ "adminUsernames": ["alice"] })).unwrap();
+    let bot = tokio::spawn(App::new(config).await.unwrap().run());
+
+    // The sender is rejected before the missing arguments are replied.
+    telegram.send_text(1, "mallory", "/set_role");
+    let reply = telegram
+        .wait_for(TIMEOUT, |req| req.method == "sendMessage")
+        .await
+        .unwrap();
+    assert_eq!(
+        reply.params["text"],
+        "You don't have the right to execute admin commands!"
+    );
+
+    bot.abort();
+}
+
+#[tokio::test]
+async fn test_empty_answer() {
+    let telegram = MockTelegram::start().await.unwrap();
+    let openai = MockOpenAI::start().await.unwrap();
+    openai.push_reply(&[" ", "\n"]);
+
+    let config = mock_config(&telegram, &openai, json!({ "adminUsernames": ["alice"] })).unwrap();
+    let bot = tokio::spawn(App::new(config).await.unwrap().run());
+
+    telegram.send_text(1, "alice", "Hi");
+    let notice = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "editMessageText"
+                && req.params["text"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .starts_with("The model returned an empty answer")
+        })
+        .await;
+    assert!(notice.is_some());
+
+    bot.abort();
+}
+
+#[tokio::test]
+async fn test_continue_truncated_answer() {
+    let telegram = MockTelegram::start().await.unwrap();
+    let openai = MockOpenAI::start().await.unwrap();
+    openai.push_reply_with_finish_reason(&["Once upon"], "length");
+    // The continuation starts with the space it needs.
+    openai.push_reply(&[" a time."]);
+    openai.push_reply(&["The end."]);
+
+    let config = mock_config(&telegram, &openai, json!({ "adminUsernames": ["alice"] })).unwrap();
+    let bot = tokio::spawn(App::new(config).await.unwrap().run());
+
+    telegram.send_text(1, "alice", "Tell a story");
+    let truncated = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "editMessageText"
+                && req.params["text"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .contains("send /continue for more")
+        })
+        .await;
+    assert!(truncated.is_some());
+
+    telegram.send_text(1, "alice", "/continue");
+    let continued = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "editMessageText"
+                && req.params["text"].as_str().map(str::trim) == Some("a time.")
+        })
+        .await;
+    assert!(continued.is_some());
+    let messages = openai.requests()[1]["messages"].clone();
+    let messages = messages.as_array().unwrap();
+    assert_eq!(messages[messages.len() - 2]["content"], "Once upon");
+
+    // The continuation is merged into the answer, and the instruction to
+    // continue is not kept.
+    telegram.send_text(1, "alice", "And then?");
+    let answered = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "editMessageText" && req.params["text"] == "The end."
+        })
+        .await;
+    assert!(answered.is_some());
+    let messages = openai.requests()[2]["messages"].clone();
+    let contents: Vec<_> = messages
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|msg| msg["content"].as_str().unwrap_or_default().to_owned())
+        .collect();
+    assert_eq!(
+        &contents[contents.len() - 3..],
+        ["Tell a story", "Once upon a time.", "And then?"]
+    );
+
+    bot.abort();
+}
+
+#[tokio::test]
+async fn test_import_requires_member() {
+    let telegram = MockTelegram::start().await.unwrap();
+    let openai = MockOpenAI::start().await.unwrap();
+
+    let config = mock_config(&telegram, &openai, json!({ "adminUsernames": ["alice"] })).unwrap();
+    let bot = tokio::spawn(App::new(config).await.unwrap().run());
+
+    telegram.send_text(1, "alice", "/set_public off");
+    let set_private = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "sendMessage" && req.params["text"] == "Success, current status: false"
+        })
+        .await;
+    assert!(set_private.is_some());
+
+    telegram.send_text(-100, "mallory", "/import");
+    let reply = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "sendMessage" && req.params["chat_id"] == -100
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        reply.params["text"],
+        "Sadly, you are not allowed to use this bot currently."
+    );
+
+    bot.abort();
+}
+
+#[tokio::test]
+async fn test_summarize_outside_session() {
+    let telegram = MockTelegram::start().await.unwrap();
+    let openai = MockOpenAI::start().await.unwrap();
+    openai.push_reply(&["Bob greeted."]);
+    openai.push_reply(&["Hello!"]);
+
+    let config = mock_config(
+        &telegram,
+        &openai,
+        json!({ "groupMessageCache": {}, "dailyQuotas": { "chatTokens": 1 } }),
+    )
+    .unwrap();
+    let bot = tokio::spawn(App::new(config).await.unwrap().run());
+
+    telegram.send_text(-100, "bob", "Good morning, everyone");
+    telegram.send_text(-100, "alice", "/summarize");
+    let summary = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "editMessageText" && req.params["text"] == "Bob greeted."
+        })
+        .await;
+    assert!(summary.is_some());
+
+    // The summary is not kept in the session of the group.
+    telegram.send_text(-100, "carol", "/ask Hi");
+    let answer = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "editMessageText" && req.params["text"] == "Hello!"
+        })
+        .await;
+    assert!(answer.is_some());
+    let messages = openai.requests()[1]["messages"].clone();
+    assert!(!messages.to_string().contains("Good morning"));
+
+    // The summary is counted in the quota of the sender.
+    telegram.send_text(-100, "alice", "/summarize");
+    let rejected = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.params["text"]
+                .as_str()
+                .unwrap_or_default()
+                .starts_with("You have used up today's quota")
+        })
+        .await;
+    assert!(rejected.is_some());
+    assert_eq!(openai.requests().len(), 2);
+
+    bot.abort();
+}
+
+#[tokio::test]
+async fn test_edit_over_quota() {
+    let telegram = MockTelegram::start().await.unwrap();
+    let openai = MockOpenAI::start().await.unwrap();
+    openai.push_reply(&["Hello!"]);
+    openai.push_reply(&["Hello again!"]);
+
+    let config = mock_config(
+        &telegram,
+        &openai,
+        json!({ "dailyQuotas": { "chatTokens": 1 } }),
+    )
+    .unwrap();
+    let bot = tokio::spawn(App::new(config).await.unwrap().run());
+
+    let message_id = telegram.send_text(1, "bob", "Hi");
+    let answer = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "editMessageText" && req.params["text"] == "Hello!"
+        })
+        .await;
+    assert!(answer.is_some());
+    wait_for_token_usage(&telegram, 1, "bob").await;
+
+    // The answer used up the quota, so the edit is not answered again.
+    telegram.edit_text(1, "bob", message_id, "Hi there");
+    let rejected = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.params["text"]
+                .as_str()
+                .unwrap_or_default()
+                .starts_with("You have used up today's quota")
+        })
+        .await;
+    assert!(rejected.is_some());
+    assert_eq!(openai.requests().len(), 1);
+
+    bot.abort();
+}
+
+#[tokio::test]
+async fn test_system_prompt_after_expiry() {
+    let telegram = MockTelegram::start().await.unwrap();
+    let openai = MockOpenAI::start().await.unwrap();
+    openai.push_reply(&["Hello!"]);
+    openai.push_reply(&["Hello again!"]);
+
+    let config = mock_config(
+        &telegram,
+        &openai,
+        json!({
+            "adminUsernames": ["alice"],
+            "defaultSystemPrompt": "Be brief.",
+            "sessionTtlMinutes": 0,
+        }),
+    )
+    .unwrap();
+    let bot = tokio::spawn(App::new(config).await.unwrap().run());
+
+    telegram.send_text(1, "alice", "Hi");
+    let answer = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "editMessageText" && req.params["text"] == "Hello!"
+        })
+        .await;
+    assert!(answer.is_some());
+
+    // The session is expired, and starts again with the system prompt.
+    telegram.send_text(1, "alice", "Hi again");
+    let answer = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "editMessageText" && req.params["text"] == "Hello again!"
+        })
+        .await;
+    assert!(answer.is_some());
+    let messages = openai.requests()[1]["messages"].clone();
+    assert_eq!(messages[0]["role"], "system");
+    assert_eq!(messages[0]["content"], "Be brief.");
+    assert_eq!(messages.as_array().unwrap().len(), 2);
+
+    bot.abort();
+}
+
+#[tokio::test]
+async fn test_rendered_history_without_notices() {
+    let telegram = MockTelegram::start().await.unwrap();
+    let openai = MockOpenAI::start().await.unwrap();
+    openai.push_reply_with_finish_reason(&["**Once** upon"], "length");
+    openai.push_reply(&["The end."]);
+
+    let config = mock_config(
+        &telegram,
+        &openai,
+        json!({ "adminUsernames": ["alice"], "rendersMarkdown": true, "renderedHistory": true }),
+    )
+    .unwrap();
+    let bot = tokio::spawn(App::new(config).await.unwrap().run());
+
+    telegram.send_text(1, "alice", "Tell a story");
+    let truncated = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "editMessageText"
+                && req.params["text"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .contains("send /continue for more")
+        })
+        .await;
+    assert!(truncated.is_some());
+
+    // The answer is sent back as rendered, without the notice.
+    telegram.send_text(1, "alice", "And then?");
+    let answered = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "editMessageText" && req.params["text"] == "The end."
+        })
+        .await;
+    assert!(answered.is_some());
+    let messages = openai.requests()[1]["messages"].clone();
+    let messages = messages.as_array().unwrap();
+    assert_eq!(messages[messages.len() - 2]["content"], "Once upon");
+
+    bot.abort();
+}
+
+#[tokio::test]
+async fn test_persona_params() {
+    let telegram = MockTelegram::start().await.unwrap();
+    let openai = MockOpenAI::start().await.unwrap();
+    openai.push_reply(&["fn main() {}"]);
+    openai.push_reply(&["Hello!"]);
+
+    let config = mock_config(
+        &telegram,
+        &openai,
+        json!({
+            "adminUsernames": ["alice"],
+            "stopSequences": ["END"],
+            "personas": {"coder": "Answer with code."},
+            "personaParams": {
+                "coder": {"stopSequences": ["```"], "logitBias": {"50256": -100}},
+            },
+        }),
+    )
+    .unwrap();
+    let bot = tokio::spawn(App::new(config).await.unwrap().run());
+
+    telegram.send_text(1, "alice", "/persona coder");
+    let reply = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "sendMessage"
+                && req.params["text"]
+                    .as_str()
+                    .is_some_and(|text| text.starts_with("Persona \"coder\" is activated"))
+        })
+        .await;
+    assert!(reply.is_some());
+
+    telegram.send_text(1, "alice", "Hi");
+    let answer = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "editMessageText" && req.params["text"] == "fn main() {}"
+        })
+        .await;
+    assert!(answer.is_some());
+    let request = openai.requests()[0].clone();
+    assert_eq!(request["messages"][0]["content"], "Answer with code.");
+    assert_eq!(request["stop"], json!(["```"]));
+    assert_eq!(request["logit_bias"]["50256"], -100);
+
+    // The defaults apply again once the session is reset.
+    telegram.send_text(1, "alice", "/reset");
+    telegram.send_text(1, "alice", "Hi");
+    let answer = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "editMessageText" && req.params["text"] == "Hello!"
+        })
+        .await;
+    assert!(answer.is_some());
+    let request = openai.requests()[1].clone();
+    assert_eq!(request["stop"], json!(["END"]));
+    assert!(request.get("logit_bias").is_none());
+
+    bot.abort();
+}
+
+#[tokio::test]
+async fn test_stats_detail_top_users() {
+    let telegram = MockTelegram::start().await.unwrap();
+    let openai = MockOpenAI::start().await.unwrap();
+
+    let config = mock_config(&telegram, &openai, json!({ "adminUsernames": ["alice"] })).unwrap();
+    let bot = tokio::spawn(App::new(config).await.unwrap().run());
+
+    telegram.send_text(2, "bob", "/stats detail");
+    let reply = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "sendMessage" && req.params["chat_id"] == 2
+        })
+        .await
+        .unwrap();
+    let text = reply.params["text"].as_str().unwrap();
+    assert!(text.contains("Usage by model:"));
+    assert!(!text.contains("Top users:"));
+
+    telegram.send_text(1, "alice", "/stats detail");
+    let reply = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "sendMessage" && req.params["chat_id"] == 1
+        })
+        .await
+        .unwrap();
+    assert!(reply.params["text"]
+        .as_str()
+        .unwrap()
+        .contains("Top users:"));
+
+    bot.abort();
+}
+
+#[tokio::test]
+async fn test_history_summary_usage() {
+    let telegram = MockTelegram::start().await.unwrap();
+    let openai = MockOpenAI::start().await.unwrap();
+    openai.push_reply(&["Hello!"]);
+    openai.push_reply(&["Hello again!"]);
+    openai.push_reply(&["Bob greeted the assistant."]);
+
+    let config = mock_config(
+        &telegram,
+        &openai,
+        json!({
+            "adminUsernames": ["alice"],
+            "conversationLimit": 2,
+            "historySummary": { "model": "gpt-summary" },
+        }),
+    )
+    .unwrap();
+    let bot = tokio::spawn(App::new(config).await.unwrap().run());
+
+    telegram.send_text(2, "bob", "Hi");
+    telegram
+        .wait_for(TIMEOUT, |req| req.params["text"] == "Hello!")
+        .await
+        .unwrap();
+    telegram.send_text(2, "bob", "Hi again");
+    telegram
+        .wait_for(TIMEOUT, |req| req.params["text"] == "Hello again!")
+        .await
+        .unwrap();
+    for _ in 0..50 {
+        if openai.requests().len() == 3 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert_eq!(openai.requests()[2]["model"], "gpt-summary");
+
+    // The summary is counted like other requests.
+    telegram.send_text(1, "alice", "/stats detail");
+    let reply = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "sendMessage" && req.params["chat_id"] == 1
+        })
+        .await
+        .unwrap();
+    assert!(reply.params["text"]
+        .as_str()
+        .unwrap()
+        .contains("gpt-summary"));
+
+    bot.abort();
+}
+
+#[tokio::test]
+async fn test_retry_keeps_answer_on_failure() {
+    let telegram = MockTelegram::start().await.unwrap();
+    let openai = MockOpenAI::start().await.unwrap();
+    openai.push_reply(&["Hello!"]);
+    openai.push_error(400, "Bad request");
+    openai.push_reply(&["Hi there!"]);
+
+    let config = mock_config(&telegram, &openai, json!({ "adminUsernames": ["alice"] })).unwrap();
+    let bot = tokio::spawn(App::new(config).await.unwrap().run());
+
+    telegram.send_text(1, "alice", "Hi");
+    telegram
+        .wait_for(TIMEOUT, |req| req.params["text"] == "Hello!")
+        .await
+        .unwrap();
+
+    // The failed retry keeps the answer, so it can be retried again.
+    telegram.send_text(1, "alice", "/retry");
+    telegram
+        .wait_for(TIMEOUT, |req| {
+            req.params["text"] == "Hmm, something went wrong..."
+        })
+        .await
+        .unwrap();
+    telegram.send_text(1, "alice", "/retry");
+    telegram
+        .wait_for(TIMEOUT, |req| req.params["text"] == "Hi there!")
+        .await
+        .unwrap();
+
+    let requests = openai.requests();
+    assert_eq!(requests.len(), 3);
+    let msgs = requests[2]["messages"].as_array().unwrap();
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0]["content"], "Hi");
+
+    bot.abort();
+}
+
+#[tokio::test]
+async fn test_regenerate_over_quota() {
+    let telegram = MockTelegram::start().await.unwrap();
+    let openai = MockOpenAI::start().await.unwrap();
+    openai.push_reply(&["Hello!"]);
+    openai.push_reply(&["Hello again!"]);
+
+    let config = mock_config(
+        &telegram,
+        &openai,
+        json!({ "dailyQuotas": { "chatTokens": 1 } }),
+    )
+    .unwrap();
+    let bot = tokio::spawn(App::new(config).await.unwrap().run());
+
+    telegram.send_text(1, "bob", "Hi");
+    let answer = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.params["reply_markup"]
+                .to_string()
+                .contains("/regenerate:")
+        })
+        .await
+        .unwrap();
+    let data = answer.params["reply_markup"]["inline_keyboard"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .flat_map(|row| row.as_array().unwrap())
+        .filter_map(|button| button["callback_data"].as_str())
+        .find(|data| data.starts_with("/regenerate:"))
+        .unwrap()
+        .to_owned();
+    let message_id = answer.params["message_id"].as_i64().unwrap();
+    wait_for_token_usage(&telegram, 1, "bob").await;
+
+    // The answer used up the quota, so it's not regenerated.
+    telegram.press_button(1, "bob", message_id, &data);
+    let rejected = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.method == "answerCallbackQuery"
+                && req.params["text"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .starts_with("You have used up today's quota")
+        })
+        .await;
+    assert!(rejected.is_some());
+    assert_eq!(openai.requests().len(), 1);
+
+    bot.abort();
+}
+
+#[tokio::test]
+async fn test_prompt_link_over_quota() {
+    let telegram = MockTelegram::start().await.unwrap();
+    let openai = MockOpenAI::start().await.unwrap();
+    openai.push_reply(&["Hello!"]);
+    openai.push_reply(&["Good morning!"]);
+
+    let config = mock_config(
+        &telegram,
+        &openai,
+        json!({
+            "dailyQuotas": { "chatTokens": 1 },
+            "promptTemplates": { "daily": "Plan my day" },
+        }),
+    )
+    .unwrap();
+    let bot = tokio::spawn(App::new(config).await.unwrap().run());
+
+    telegram.send_text(1, "bob", "Hi");
+    telegram
+        .wait_for(TIMEOUT, |req| req.params["text"] == "Hello!")
+        .await
+        .unwrap();
+    wait_for_token_usage(&telegram, 1, "bob").await;
+
+    // The answer used up the quota, so the prompt is not asked.
+    telegram.send_text(1, "bob", "/start prompt_daily");
+    let rejected = telegram
+        .wait_for(TIMEOUT, |req| {
+            req.params["text"]
+                .as_str()
+                .unwrap_or_default()
+                .starts_with("You have used up today's quota")
+        })
+        .await;
+    assert!(rejected.is_some());
+    assert_eq!(openai.requests().len(), 1);
+
+    bot.abort();
+}